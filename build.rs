@@ -1,37 +1,16 @@
-use std::process::Command;
-use std::fs::read_dir;
+use std::path::PathBuf;
 
-fn main() {
-
-    let dirs = read_dir("./shared/shaders").unwrap();
-    for file_name in dirs {
-        match file_name {
-            Ok(name) => {
-
-                let original_name = name
-                    .file_name()
-                    .to_str()
-                    .unwrap()
-                    .to_string();
+use fujiya_shaders::{embed_shaders, ShaderCompiler};
 
-                if !original_name.contains('.') { continue; }
-
-                let format = original_name.split(".").collect::<Vec<&str>>()[1];
-                let name = original_name.split(".").collect::<Vec<&str>>()[0];
-                let out_name = format!("{}-{}.spv", name, format);
+fn main() {
 
-                if format == "frag" || format == "vert" {
-                    let _ = Command::new("glslc")
-                        .arg(format!("./shaders/{}", original_name))
-                        .arg("-o")
-                        .arg(format!("./shaders/spv/{}", out_name))
-                        .spawn()
-                        .unwrap()
-                        .wait();
-                }
-            },
+    let out_file = PathBuf::from(std::env::var("OUT_DIR").unwrap()).join("shaders.rs");
 
-            _ => {}
+    // Все шейдеры из shared/shaders компилируются в SPIR-V и встраиваются в бинарник
+    if let Err(errors) = embed_shaders("./shared/shaders", &out_file, &ShaderCompiler::new()) {
+        for err in &errors {
+            eprintln!("error: {}", err);
         }
+        panic!("{} shader(s) failed to compile", errors.len());
     }
-}
\ No newline at end of file
+}
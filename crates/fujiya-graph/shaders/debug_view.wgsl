@@ -0,0 +1,44 @@
+// Отладочный вид: копия выхода пасса с выбором канала, линейной глубиной и экспозицией
+
+struct DebugViewConstants {
+    // Маска канала, если выбран один канал
+    channels: vec4<f32>,
+    // В ступенях, множитель 2^exposure
+    exposure: f32,
+    near: f32,
+    far: f32,
+    // 1 - линеаризовать глубину, 2 - показать один канал в оттенках серого
+    flags: u32,
+}
+
+var<immediate> constants: DebugViewConstants;
+
+@group(0) @binding(0) var source_tex: texture_2d<f32>;
+@group(0) @binding(1) var output_tex: texture_storage_2d<rgba16float, write>;
+
+@compute @workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(output_tex);
+
+    if (id.x >= size.x || id.y >= size.y) {
+        return;
+    }
+
+    var value = textureLoad(source_tex, id.xy, 0);
+
+    if ((constants.flags & 1u) != 0u) {
+        // Работает и для reversed-Z, если near и far поменяны местами
+        let near = constants.near;
+        let far = constants.far;
+        let depth = near * far / (far - value.r * (far - near));
+        let normalized = (depth - min(near, far)) / abs(far - near);
+        value = vec4<f32>(vec3<f32>(normalized), 1.0);
+    }
+
+    if ((constants.flags & 2u) != 0u) {
+        value = vec4<f32>(vec3<f32>(dot(value, constants.channels)), 1.0);
+    }
+
+    let color = value.rgb * exp2(constants.exposure);
+    textureStore(output_tex, id.xy, vec4<f32>(color, 1.0));
+}
@@ -0,0 +1,519 @@
+use std::{collections::HashMap, error::Error, path::Path};
+
+use ash::vk::{self, CommandBuffer};
+use bytemuck::{Pod, Zeroable};
+use fujiya_render::{
+    AccessType, BarrierBatch, ComputePipelineBuilder, DescriptorPool, DescriptorPoolBuilder, DescriptorSet,
+    DescriptorSetLayout, DescriptorSetLayoutBuilder, DescriptorSetWriter, GPUImage, ImageDesc, RenderContext,
+    CompiledShader, RenderPipeline, ShaderCompiler, ShaderError, ShaderLanguage, ShaderProgramBuilder
+};
+
+use crate::{ExecuteError, GraphResource, ImageHandle, PassDesc, Pool, RenderGraph, Transients};
+
+/// WGSL source of the debug view, embedded so the binary doesn't need the crate sources
+pub const DEBUG_VIEW_SHADER: &str = include_str!("../shaders/debug_view.wgsl");
+pub const DEBUG_VIEW_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+pub fn compile_debug_view_shader() -> Result<CompiledShader, ShaderError> {
+    ShaderCompiler::new().compile_source(Path::new("debug_view.wgsl"), DEBUG_VIEW_SHADER, ShaderLanguage::Wgsl)
+}
+
+/// Workgroup size of [`DEBUG_VIEW_SHADER`]
+const GROUP_SIZE: u32 = 8;
+
+///
+/// Channels shown by the debug view, a single channel is shown in grayscale
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DebugChannel {
+    #[default]
+    Rgb,
+    R,
+    G,
+    B,
+    A,
+}
+
+impl DebugChannel {
+
+    pub fn mask(self) -> [f32; 4] {
+        match self {
+            Self::Rgb => [1.0, 1.0, 1.0, 0.0],
+            Self::R => [1.0, 0.0, 0.0, 0.0],
+            Self::G => [0.0, 1.0, 0.0, 0.0],
+            Self::B => [0.0, 0.0, 1.0, 0.0],
+            Self::A => [0.0, 0.0, 0.0, 1.0],
+        }
+    }
+}
+
+/// Push constants of [`DEBUG_VIEW_SHADER`]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Default, Pod, Zeroable)]
+pub struct DebugViewConstants {
+    pub channels: [f32; 4],
+    pub exposure: f32,
+    pub near: f32,
+    pub far: f32,
+    /// 1 linearizes depth, 2 shows a single channel
+    pub flags: u32,
+}
+
+///
+/// Output of a pass shown fullscreen instead of the frame, see [`RenderGraph::set_debug_view`]
+///
+/// # Example:
+///
+/// ```ignore
+/// graph.set_debug_view(Some(
+///     DebugView::new("GBuffer")
+///         .with_output("depth")
+///         .with_depth_range(0.1, 1000.0)
+/// ));
+/// ```
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct DebugView {
+    pub pass: String,
+    /// Name of an image the pass writes, the first written image if `None`
+    pub output: Option<String>,
+    pub channel: DebugChannel,
+    /// `(near, far)` of the projection to linearize depth images, swapped for reversed Z
+    pub depth_range: Option<(f32, f32)>,
+    /// In stops, the color is multiplied by `2^exposure`
+    pub exposure: f32,
+}
+
+impl DebugView {
+
+    pub fn new(pass: impl Into<String>) -> Self {
+        Self { pass: pass.into(), output: None, channel: DebugChannel::Rgb, depth_range: None, exposure: 0.0 }
+    }
+
+    pub fn with_output(mut self, output: impl Into<String>) -> Self {
+        self.output = Some(output.into());
+        self
+    }
+
+    pub fn with_channel(mut self, channel: DebugChannel) -> Self {
+        self.channel = channel;
+        self
+    }
+
+    pub fn with_depth_range(mut self, near: f32, far: f32) -> Self {
+        self.depth_range = Some((near, far));
+        self
+    }
+
+    pub fn with_exposure(mut self, exposure: f32) -> Self {
+        self.exposure = exposure;
+        self
+    }
+
+    /// Whether switching to `other` changes the image that is shown
+    fn same_source(&self, other: &Self) -> bool {
+        self.pass == other.pass && self.output == other.output
+    }
+
+    /// Depth is linearized only for depth images
+    pub fn constants(&self, is_depth: bool) -> DebugViewConstants {
+        let mut constants = DebugViewConstants { channels: self.channel.mask(), exposure: self.exposure, ..Default::default() };
+
+        if let (true, Some((near, far))) = (is_depth, self.depth_range) {
+            constants.near = near;
+            constants.far = far;
+            constants.flags |= 1;
+        }
+
+        if self.channel != DebugChannel::Rgb {
+            constants.flags |= 2;
+        }
+
+        constants
+    }
+
+    ///
+    /// Pass index and image the view shows
+    ///
+    /// `None` if no pass has the name or it writes no matching image.
+    ///
+    pub fn resolve(&self, passes: &[&PassDesc], images: &Pool<GPUImage>) -> Option<(usize, ImageHandle)> {
+        let index = passes.iter().position(|pass| *pass.name == *self.pass)?;
+
+        let image = passes[index].writes.iter().find_map(|resource| match resource {
+            GraphResource::Image(handle) => match &self.output {
+                Some(output) if images.name(*handle) != Some(output.as_str()) => None,
+                _ => Some(*handle),
+            },
+            _ => None,
+        })?;
+
+        Some((index, image))
+    }
+}
+
+///
+/// GPU objects of the debug view, created on the first frame it is shown
+///
+pub struct DebugViewRenderer {
+    pub pipeline: RenderPipeline,
+    pub set_layout: DescriptorSetLayout,
+    pub pool: DescriptorPool,
+    /// One per frame in flight
+    pub sets: Vec<DescriptorSet>,
+    /// Copy of the shown output, the size of the source
+    pub image: Option<GPUImage>,
+    pub image_accesses: Vec<AccessType>,
+    /// Whether `image` holds the output of the frame being recorded
+    pub captured: bool,
+}
+
+impl DebugViewRenderer {
+
+    pub fn new(device: &ash::Device, frames_in_flight: usize) -> Result<Self, Box<dyn Error>> {
+
+        let spv = compile_debug_view_shader()?.spv;
+        let program = ShaderProgramBuilder::new()
+            .with_device(device)
+            .with_compute_shader(spv)
+            .try_build()?;
+
+        let bindings = [
+            vk::DescriptorSetLayoutBinding::default()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE),
+            vk::DescriptorSetLayoutBinding::default()
+                .binding(1)
+                .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE),
+        ];
+
+        let set_layout = DescriptorSetLayoutBuilder::new()
+            .with_device(device)
+            .with_bindings(&bindings)
+            .build();
+
+        let pipeline = ComputePipelineBuilder::new()
+            .with_device(device)
+            .with_shader_program(&program)
+            .add_set_layout(set_layout.raw)
+            .add_push_constants::<DebugViewConstants>(0)
+            .build();
+
+        program.destroy(device);
+
+        let frames = frames_in_flight as u32;
+        let pool_sizes = [
+            vk::DescriptorPoolSize { ty: vk::DescriptorType::SAMPLED_IMAGE, descriptor_count: frames },
+            vk::DescriptorPoolSize { ty: vk::DescriptorType::STORAGE_IMAGE, descriptor_count: frames },
+        ];
+
+        let pool = DescriptorPoolBuilder::new()
+            .with_device(device)
+            .with_pool_sizes(&pool_sizes)
+            .with_max_sets(frames)
+            .build();
+
+        let sets = pool.allocate(device, &vec![set_layout.raw; frames_in_flight])?;
+
+        Ok(Self { pipeline, set_layout, pool, sets, image: None, image_accesses: vec![], captured: false })
+    }
+
+    ///
+    /// Copy `source` into the debug image right after the pass that wrote it
+    ///
+    /// The source stays readable by the passes after it, its last accesses are updated.
+    ///
+    #[allow(clippy::too_many_arguments)]
+    pub fn capture(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: CommandBuffer,
+        frame: usize,
+        view: &DebugView,
+        resource: GraphResource,
+        source: &GPUImage,
+        states: &mut HashMap<GraphResource, Vec<AccessType>>,
+        transients: &mut Transients
+    ) {
+        let Some(image) = &self.image else {
+            return;
+        };
+
+        let read = AccessType::ComputeShaderReadSampledImage;
+        let previous = states.get(&resource).cloned().unwrap_or_default();
+
+        let mut barriers = BarrierBatch::new();
+        barriers.add_image(source.raw, source.subresource_range(), &previous, &[read], previous.is_empty());
+        barriers.add_image(image.raw, image.subresource_range(), &self.image_accesses, &[AccessType::ComputeShaderWrite], true);
+        barriers.record(device, command_buffer);
+
+        RenderGraph::note_recorded_access(states, transients, resource, read);
+
+        let set = self.sets[frame];
+        DescriptorSetWriter::new()
+            .write_sampled_image(0, source.view, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .write_storage_image(1, image.view)
+            .update(device, &set);
+
+        let constants = view.constants(source.desc.aspect().contains(vk::ImageAspectFlags::DEPTH));
+        let extent = image.desc.extent;
+
+        self.pipeline.bind(device, command_buffer);
+        set.bind(device, command_buffer, self.pipeline.bind_point, self.pipeline.raw_layout, 0);
+        self.pipeline.push_constants(device, command_buffer, 0, &constants);
+        unsafe {
+            device.cmd_dispatch(command_buffer, extent.width.div_ceil(GROUP_SIZE), extent.height.div_ceil(GROUP_SIZE), 1);
+        }
+
+        self.image_accesses = vec![AccessType::ComputeShaderWrite];
+        self.captured = true;
+    }
+
+    ///
+    /// Stretch the captured image over the whole swapchain image
+    ///
+    /// Returns the access the swapchain image is left in.
+    ///
+    pub fn blit(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: CommandBuffer,
+        swapchain_image: vk::Image,
+        swapchain_range: vk::ImageSubresourceRange,
+        swapchain_extent: vk::Extent2D,
+        previous: &[AccessType]
+    ) -> Option<AccessType> {
+
+        let image = self.image.as_ref().filter(|_| self.captured)?;
+
+        let mut barriers = BarrierBatch::new();
+        barriers.add_image(image.raw, image.subresource_range(), &self.image_accesses, &[AccessType::TransferRead], false);
+        barriers.add_image(swapchain_image, swapchain_range, previous, &[AccessType::TransferWrite], true);
+        barriers.record(device, command_buffer);
+
+        let layers = vk::ImageSubresourceLayers::default()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .layer_count(1);
+
+        let corner = |extent: vk::Extent2D| vk::Offset3D { x: extent.width as i32, y: extent.height as i32, z: 1 };
+
+        let region = vk::ImageBlit::default()
+            .src_subresource(layers)
+            .src_offsets([vk::Offset3D::default(), corner(image.desc.extent)])
+            .dst_subresource(layers)
+            .dst_offsets([vk::Offset3D::default(), corner(swapchain_extent)]);
+
+        unsafe {
+            device.cmd_blit_image(
+                command_buffer,
+                image.raw,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                swapchain_image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[region],
+                vk::Filter::LINEAR
+            );
+        }
+
+        self.image_accesses = vec![AccessType::TransferRead];
+        self.captured = false;
+
+        Some(AccessType::TransferWrite)
+    }
+
+    /// None of the frames that used it may be in flight
+    pub fn destroy(&mut self, device: &ash::Device) {
+        if let Some(image) = self.image.take() {
+            image.destroy(device);
+        }
+
+        self.pool.destroy(device);
+        self.pipeline.destroy(device);
+        unsafe { device.destroy_descriptor_set_layout(self.set_layout.raw, None) };
+    }
+}
+
+impl RenderGraph {
+
+    ///
+    /// Show the output of a pass fullscreen instead of the frame, `None` to turn it off
+    ///
+    /// The output is copied right after the pass, later passes don't change what is shown.
+    /// The image must be sampled, the graph adds the usage to transient and temporal images.
+    /// Changing only the channel, depth range or exposure doesn't recompile the graph.
+    ///
+    pub fn set_debug_view(&mut self, view: Option<DebugView>) {
+        let same_source = match (&self.debug_view, &view) {
+            (Some(old), Some(new)) => old.same_source(new),
+            (None, None) => true,
+            _ => false,
+        };
+
+        if !same_source {
+            self.schedule = None;
+        }
+        self.debug_view = view;
+    }
+
+    pub fn debug_view(&self) -> Option<&DebugView> {
+        self.debug_view.as_ref()
+    }
+
+    pub(crate) fn resolve_debug_view(&self) -> Option<(usize, ImageHandle)> {
+        let passes = self.nodes.iter().map(|pass| &pass.desc).collect::<Vec<_>>();
+        self.debug_view.as_ref()?.resolve(&passes, &self.resources.images)
+    }
+
+    /// Called on compile, the shown image is read by a compute shader
+    pub(crate) fn plan_debug_view(&mut self) {
+        if let Some((_, handle)) = self.resolve_debug_view() {
+            self.note_extra_access(GraphResource::Image(handle), AccessType::ComputeShaderReadSampledImage);
+        }
+    }
+
+    ///
+    /// Pass index and image to capture this frame, creating what the debug view needs
+    ///
+    /// A view that can't be shown is turned off with a warning.
+    ///
+    pub(crate) fn prepare_debug_view(&mut self, ctx: &RenderContext) -> Result<Option<(usize, ImageHandle)>, ExecuteError> {
+
+        let Some(view) = self.debug_view.clone() else {
+            return Ok(None);
+        };
+
+        let Some((index, handle)) = self.resolve_debug_view() else {
+            log::warn!("Debug view is turned off, pass {:?} writes no image {:?}", view.pass, view.output);
+            self.set_debug_view(None);
+            return Ok(None);
+        };
+
+        // Появится после выделения транзиентов
+        let Some(source) = self.resources.images.get(handle) else {
+            return Ok(None);
+        };
+
+        let extent = source.desc.extent;
+        let usage = ctx.window_manager.caps.supported_usage_flags;
+        let unsupported = if !usage.contains(vk::ImageUsageFlags::TRANSFER_DST) {
+            Some("the swapchain can't be copied to")
+        } else if !source.desc.usage.contains(vk::ImageUsageFlags::SAMPLED) {
+            Some("the image is not sampled")
+        } else if source.desc.aspect().contains(vk::ImageAspectFlags::STENCIL) {
+            Some("depth-stencil images are not supported")
+        } else {
+            None
+        };
+
+        if let Some(reason) = unsupported {
+            log::warn!("Debug view of pass {:?} is turned off, {}", view.pass, reason);
+            self.set_debug_view(None);
+            return Ok(None);
+        }
+
+        let device = ctx.graphics_device.raw_device();
+
+        if self.debug_renderer.is_none() {
+            match DebugViewRenderer::new(device, self.sync.len()) {
+                Ok(renderer) => self.debug_renderer = Some(renderer),
+                Err(err) => {
+                    log::error!("Debug view is turned off, {}", err);
+                    self.set_debug_view(None);
+                    return Ok(None);
+                }
+            }
+        }
+
+        let renderer = self.debug_renderer.as_mut().unwrap();
+
+        if renderer.image.as_ref().map(|image| image.desc.extent) != Some(extent) {
+            // Старое изображение может использоваться любым кадром в полёте
+            let fences = self.sync.iter().map(|sync| sync.fence).collect::<Vec<_>>();
+            unsafe { device.wait_for_fences(&fences, true, u64::MAX)? };
+
+            if let Some(image) = renderer.image.take() {
+                image.destroy(device);
+            }
+
+            let desc = ImageDesc::new_2d(DEBUG_VIEW_FORMAT, extent)
+                .with_usage(vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_SRC);
+
+            let memory_prop = &ctx.graphics_device.phys_dev.phys_info.memory_prop;
+            renderer.image = Some(GPUImage::new(device, memory_prop, desc)?);
+            renderer.image_accesses.clear();
+        }
+
+        renderer.captured = false;
+        Ok(Some((index, handle)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fujiya_render::ImageDesc;
+
+    use super::*;
+
+    #[test]
+    fn resolves_pass_outputs_by_name() {
+        let mut graph = RenderGraph::new();
+        let extent = vk::Extent2D { width: 64, height: 64 };
+        let hdr = graph.create_image("hdr", ImageDesc::new_2d(vk::Format::R16G16B16A16_SFLOAT, extent));
+        let depth = graph.create_image("depth", ImageDesc::new_2d(vk::Format::D32_SFLOAT, extent));
+
+        graph.add_pass("GBuffer")
+            .write(hdr)
+            .access(depth, AccessType::DepthStencilAttachmentWrite)
+            .execute(|_, _, _| Ok(()));
+
+        graph.set_debug_view(Some(DebugView::new("GBuffer")));
+        assert_eq!(graph.resolve_debug_view(), Some((0, hdr)));
+
+        graph.set_debug_view(Some(DebugView::new("GBuffer").with_output("depth")));
+        assert_eq!(graph.resolve_debug_view(), Some((0, depth)));
+
+        graph.set_debug_view(Some(DebugView::new("GBuffer").with_output("normals")));
+        assert_eq!(graph.resolve_debug_view(), None);
+
+        graph.set_debug_view(Some(DebugView::new("Lighting")));
+        assert_eq!(graph.resolve_debug_view(), None);
+    }
+
+    #[test]
+    fn only_source_changes_recompile() {
+        let mut graph = RenderGraph::new();
+        graph.compile().unwrap();
+
+        graph.set_debug_view(Some(DebugView::new("GBuffer")));
+        assert!(graph.schedule.is_none());
+        graph.compile().unwrap();
+
+        graph.set_debug_view(Some(DebugView::new("GBuffer").with_channel(DebugChannel::A).with_exposure(2.0)));
+        assert!(graph.schedule.is_some());
+    }
+
+    #[test]
+    fn packs_push_constants() {
+        let view = DebugView::new("GBuffer").with_channel(DebugChannel::G).with_depth_range(0.1, 100.0).with_exposure(-1.0);
+
+        assert_eq!(view.constants(false), DebugViewConstants {
+            channels: [0.0, 1.0, 0.0, 0.0],
+            exposure: -1.0,
+            flags: 2,
+            ..Default::default()
+        });
+        assert_eq!(view.constants(true).flags, 3);
+        assert_eq!((view.constants(true).near, view.constants(true).far), (0.1, 100.0));
+        assert_eq!(std::mem::size_of::<DebugViewConstants>(), 32);
+    }
+
+    #[test]
+    fn debug_view_shader_compiles() {
+        let shader = compile_debug_view_shader().unwrap();
+        assert!(!shader.spv.is_empty());
+    }
+}
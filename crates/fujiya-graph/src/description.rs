@@ -0,0 +1,915 @@
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt,
+    fs,
+    io,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::Arc,
+    time::SystemTime
+};
+
+use ash::vk;
+use fujiya_render::{
+    AccessType, ComputePipelineBuilder, DescriptorPool, DescriptorPoolBuilder, DescriptorSet, DescriptorSetWriter,
+    ImageDesc, PipelineReflection, RenderContext, RenderPipeline, ShaderCompiler, ShaderProgramBuilder, ShaderReflection
+};
+use serde::Deserialize;
+
+use crate::{GraphError, GraphResource, ImageHandle, PipelineHandle, RenderGraph, RenderGraphResource};
+
+/// Name of the swapchain image in a description
+pub const SWAPCHAIN: &str = "swapchain";
+
+/// Added to the name of a temporal image to read the previous frame, e.g. `"taa.history"`
+pub const HISTORY_SUFFIX: &str = ".history";
+
+///
+/// Frame structure loaded from a RON file, see [`GraphDescriptionLoader`]
+///
+/// Passes run in the order their reads and writes require, as passes added in code do.
+///
+/// # Example:
+///
+/// ```ron
+/// (
+///     images: [
+///         (name: "hdr", format: "R16G16B16A16_SFLOAT"),
+///         (name: "bloom", format: "R16G16B16A16_SFLOAT", size: Scale(0.5)),
+///         (name: "taa", format: "R16G16B16A16_SFLOAT", temporal: true),
+///     ],
+///     passes: [
+///         (name: "scene", kind: Custom("scene"), writes: ["hdr"]),
+///         (name: "bloom", kind: Compute(shader: "bloom.wgsl", params: [0.8]), reads: ["hdr"], writes: ["bloom"], feature: Some("bloom")),
+///         (name: "taa", kind: Compute(shader: "taa.wgsl"), reads: ["hdr", "taa.history"], writes: ["taa"]),
+///         (name: "compose", kind: Custom("compose"), reads: ["taa", "bloom"], writes: ["swapchain"]),
+///     ],
+/// )
+/// ```
+///
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct GraphDescription {
+    #[serde(default)]
+    pub images: Vec<ImageDescription>,
+    pub passes: Vec<PassDescription>,
+}
+
+///
+/// Image created by the graph, transient unless `temporal` is set
+///
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ImageDescription {
+    pub name: String,
+    /// Name of a [`vk::Format`], e.g. `"R16G16B16A16_SFLOAT"`
+    pub format: String,
+    #[serde(default)]
+    pub size: ImageSize,
+    #[serde(default)]
+    pub temporal: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+pub enum ImageSize {
+    /// The size of the swapchain
+    #[default]
+    Swapchain,
+    /// The size of the swapchain multiplied by the factor
+    Scale(f32),
+    Fixed(u32, u32),
+}
+
+impl ImageSize {
+
+    pub fn extent(&self, swapchain: vk::Extent2D) -> vk::Extent2D {
+        let scale = |size: u32, factor: f32| ((size as f32 * factor) as u32).max(1);
+
+        match *self {
+            Self::Swapchain => swapchain,
+            Self::Scale(factor) => vk::Extent2D { width: scale(swapchain.width, factor), height: scale(swapchain.height, factor) },
+            Self::Fixed(width, height) => vk::Extent2D { width, height },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct PassDescription {
+    pub name: String,
+    pub kind: PassKind,
+    #[serde(default)]
+    pub reads: Vec<String>,
+    #[serde(default)]
+    pub writes: Vec<String>,
+    /// See [`crate::PassBuilder::with_feature`]
+    #[serde(default)]
+    pub feature: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub enum PassKind {
+    ///
+    /// Compute shader over the first written image
+    ///
+    /// Reads are bound as sampled images to bindings `0..` of set 0, writes as storage
+    /// images right after them. `params` are the push constants, a `f32` each.
+    ///
+    Compute {
+        /// Relative to the description file
+        shader: String,
+        #[serde(default)]
+        params: Vec<f32>,
+    },
+    /// Rust closure registered with [`GraphDescriptionLoader::with_custom_pass`]
+    Custom(String),
+}
+
+///
+/// Error of loading a [`GraphDescription`]
+///
+#[derive(Debug)]
+pub enum DescriptionError {
+    Io(PathBuf, io::Error),
+    Parse(ron::error::SpannedError),
+    UnknownFormat {
+        image: String,
+        format: String,
+    },
+    /// Two images, passes or an image and an imported resource have the same name
+    DuplicateName(String),
+    UnknownResource {
+        pass: String,
+        resource: String,
+    },
+    UnknownCustomPass {
+        pass: String,
+        custom: String,
+    },
+    InvalidPass {
+        pass: String,
+        reason: &'static str,
+    },
+    /// The shader doesn't compile or doesn't match the resources of the pass
+    Shader {
+        pass: String,
+        error: String,
+    },
+    /// The passes of the description don't form a valid graph with the other passes
+    Graph(GraphError),
+    Vulkan(vk::Result),
+}
+
+impl fmt::Display for DescriptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(path, err) => write!(f, "can't read {:?}, {}", path, err),
+            Self::Parse(err) => write!(f, "{}", err),
+            Self::UnknownFormat { image, format } => write!(f, "image {:?} has unknown format {:?}", image, format),
+            Self::DuplicateName(name) => write!(f, "name {:?} is used twice", name),
+            Self::UnknownResource { pass, resource } => write!(f, "pass {:?} uses unknown resource {:?}", pass, resource),
+            Self::UnknownCustomPass { pass, custom } => write!(f, "pass {:?} runs custom pass {:?} that is not registered", pass, custom),
+            Self::InvalidPass { pass, reason } => write!(f, "pass {:?} is invalid, {}", pass, reason),
+            Self::Shader { pass, error } => write!(f, "shader of pass {:?}: {}", pass, error),
+            Self::Graph(err) => write!(f, "{}", err),
+            Self::Vulkan(result) => write!(f, "{}", result),
+        }
+    }
+}
+
+impl Error for DescriptionError {}
+
+impl From<vk::Result> for DescriptionError {
+    fn from(result: vk::Result) -> Self {
+        Self::Vulkan(result)
+    }
+}
+
+impl GraphDescription {
+
+    pub fn from_ron(source: &str) -> Result<Self, DescriptionError> {
+        ron::from_str(source).map_err(DescriptionError::Parse)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, DescriptionError> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path).map_err(|err| DescriptionError::Io(path.to_path_buf(), err))?;
+        Self::from_ron(&source)
+    }
+
+    ///
+    /// Check the names against each other, `imports` and the registered custom passes
+    ///
+    /// Shaders are checked when the description is applied.
+    ///
+    pub fn check(&self, imports: &HashMap<String, GraphResource>, custom_passes: &[&str]) -> Result<(), DescriptionError> {
+
+        // Имя ресурса -> является ли он изображением
+        let mut resources: HashMap<String, bool> = imports.iter()
+            .map(|(name, resource)| (name.clone(), matches!(resource, GraphResource::Image(_))))
+            .collect();
+
+        if resources.insert(SWAPCHAIN.to_owned(), false).is_some() {
+            return Err(DescriptionError::DuplicateName(SWAPCHAIN.to_owned()));
+        }
+
+        for image in &self.images {
+            if parse_format(&image.format).is_none() {
+                return Err(DescriptionError::UnknownFormat { image: image.name.clone(), format: image.format.clone() });
+            }
+
+            let mut names = vec![image.name.clone()];
+            if image.temporal {
+                names.push(format!("{}{}", image.name, HISTORY_SUFFIX));
+            }
+
+            for name in names {
+                if resources.insert(name.clone(), true).is_some() {
+                    return Err(DescriptionError::DuplicateName(name));
+                }
+            }
+        }
+
+        let mut passes = HashSet::new();
+
+        for pass in &self.passes {
+            if !passes.insert(pass.name.as_str()) {
+                return Err(DescriptionError::DuplicateName(pass.name.clone()));
+            }
+
+            for resource in pass.reads.iter().chain(&pass.writes) {
+                let Some(is_image) = resources.get(resource) else {
+                    return Err(DescriptionError::UnknownResource { pass: pass.name.clone(), resource: resource.clone() });
+                };
+
+                if matches!(pass.kind, PassKind::Compute { .. }) && !is_image {
+                    return Err(DescriptionError::InvalidPass { pass: pass.name.clone(), reason: "compute passes use only images" });
+                }
+            }
+
+            match &pass.kind {
+                PassKind::Compute { .. } if pass.writes.is_empty() => {
+                    return Err(DescriptionError::InvalidPass { pass: pass.name.clone(), reason: "compute passes write at least one image" });
+                },
+                PassKind::Custom(custom) if !custom_passes.contains(&custom.as_str()) => {
+                    return Err(DescriptionError::UnknownCustomPass { pass: pass.name.clone(), custom: custom.clone() });
+                },
+                _ => {},
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// [`vk::Format`] by its name, only formats usable for render targets
+pub fn parse_format(name: &str) -> Option<vk::Format> {
+    let format = match name {
+        "R8_UNORM" => vk::Format::R8_UNORM,
+        "R8G8_UNORM" => vk::Format::R8G8_UNORM,
+        "R8G8B8A8_UNORM" => vk::Format::R8G8B8A8_UNORM,
+        "R8G8B8A8_SRGB" => vk::Format::R8G8B8A8_SRGB,
+        "B8G8R8A8_UNORM" => vk::Format::B8G8R8A8_UNORM,
+        "B8G8R8A8_SRGB" => vk::Format::B8G8R8A8_SRGB,
+        "A2B10G10R10_UNORM_PACK32" => vk::Format::A2B10G10R10_UNORM_PACK32,
+        "B10G11R11_UFLOAT_PACK32" => vk::Format::B10G11R11_UFLOAT_PACK32,
+        "R16_SFLOAT" => vk::Format::R16_SFLOAT,
+        "R16G16_SFLOAT" => vk::Format::R16G16_SFLOAT,
+        "R16G16B16A16_SFLOAT" => vk::Format::R16G16B16A16_SFLOAT,
+        "R32_SFLOAT" => vk::Format::R32_SFLOAT,
+        "R32G32_SFLOAT" => vk::Format::R32G32_SFLOAT,
+        "R32G32B32A32_SFLOAT" => vk::Format::R32G32B32A32_SFLOAT,
+        "R32_UINT" => vk::Format::R32_UINT,
+        "R32G32_UINT" => vk::Format::R32G32_UINT,
+        "R32G32B32A32_UINT" => vk::Format::R32G32B32A32_UINT,
+        "D16_UNORM" => vk::Format::D16_UNORM,
+        "D32_SFLOAT" => vk::Format::D32_SFLOAT,
+        "D24_UNORM_S8_UINT" => vk::Format::D24_UNORM_S8_UINT,
+        "D32_SFLOAT_S8_UINT" => vk::Format::D32_SFLOAT_S8_UINT,
+        _ => return None,
+    };
+    Some(format)
+}
+
+///
+/// Closure of a [`PassKind::Custom`] pass
+///
+/// Gets the resources the description binds to the pass in place of captured handles.
+///
+pub type CustomPass = Rc<dyn Fn(&mut RenderGraphResource, &RenderContext, u32, &PassResources) -> Result<(), Box<dyn Error>>>;
+
+///
+/// Resources of a described pass by the names used in the description
+///
+#[derive(Debug, Clone, Default)]
+pub struct PassResources {
+    pub reads: Vec<(String, GraphResource)>,
+    pub writes: Vec<(String, GraphResource)>,
+}
+
+impl PassResources {
+
+    pub fn get(&self, name: &str) -> Option<GraphResource> {
+        self.reads.iter().chain(&self.writes)
+            .find(|(resource, _)| *resource == name)
+            .map(|(_, resource)| *resource)
+    }
+
+    pub fn image(&self, name: &str) -> Option<ImageHandle> {
+        match self.get(name)? {
+            GraphResource::Image(handle) => Some(handle),
+            _ => None,
+        }
+    }
+}
+
+///
+/// Compiled shader of a [`PassKind::Compute`] pass, checked before the graph changes
+///
+struct ComputeShader {
+    path: PathBuf,
+    spv: Vec<u32>,
+    reflection: PipelineReflection,
+    local_size: [u32; 3],
+    push_constants: Vec<u8>,
+}
+
+impl ComputeShader {
+
+    fn compile(pass: &PassDescription, path: PathBuf, params: &[f32]) -> Result<Self, DescriptionError> {
+        let error = |error: String| DescriptionError::Shader { pass: pass.name.clone(), error };
+
+        let spv = ShaderCompiler::new().compile_file(&path).map_err(|err| error(err.to_string()))?.spv;
+        let shader = ShaderReflection::from_spv(&spv).map_err(|err| error(err.to_string()))?;
+        let reflection = PipelineReflection::merge(std::slice::from_ref(&shader)).map_err(|err| error(err.to_string()))?;
+
+        if shader.stage != vk::ShaderStageFlags::COMPUTE {
+            return Err(error(format!("{:?} is not a compute shader", path)));
+        }
+
+        check_bindings(&reflection, pass.reads.len(), pass.writes.len()).map_err(error)?;
+
+        let mut push_constants = params.iter().flat_map(|param| param.to_ne_bytes()).collect::<Vec<u8>>();
+        match reflection.push_constant_range {
+            Some(range) if (range.size as usize) < push_constants.len() => {
+                return Err(error(format!("{} params don't fit into {} bytes of push constants", params.len(), range.size)));
+            },
+            Some(range) => push_constants.resize(range.size as usize, 0),
+            None if !params.is_empty() => return Err(error("params are set, but the shader has no push constants".to_owned())),
+            None => {},
+        }
+
+        let local_size = shader.local_size.unwrap_or([1, 1, 1]);
+        Ok(Self { path, spv, reflection, local_size, push_constants })
+    }
+
+    /// Pipeline and descriptor sets for `frames` frames, nothing is left on error
+    fn create_objects(&self, device: &ash::Device, pass: &str, frames: usize) -> Result<ComputeObjects, DescriptionError> {
+
+        let set_layout = self.reflection.create_set_layouts(device).remove(0).raw;
+        let destroy_layout = || unsafe { device.destroy_descriptor_set_layout(set_layout, None) };
+
+        let pipeline = match build_compute_pipeline(device, self.spv.clone(), set_layout, self.reflection.push_constant_range) {
+            Ok(pipeline) => pipeline,
+            Err(err) => {
+                destroy_layout();
+                return Err(DescriptionError::Shader { pass: pass.to_owned(), error: err.to_string() });
+            }
+        };
+
+        let pool_sizes = self.reflection.descriptor_pool_sizes(0).into_iter()
+            .map(|size| vk::DescriptorPoolSize { descriptor_count: size.descriptor_count * frames as u32, ..size })
+            .collect::<Vec<_>>();
+
+        let pool = DescriptorPoolBuilder::new()
+            .with_device(device)
+            .with_pool_sizes(&pool_sizes)
+            .with_max_sets(frames as u32)
+            .try_build();
+
+        let pool = match pool {
+            Ok(pool) => pool,
+            Err(err) => {
+                pipeline.destroy(device);
+                destroy_layout();
+                return Err(err.into());
+            }
+        };
+
+        match pool.allocate(device, &vec![set_layout; frames]) {
+            Ok(sets) => Ok(ComputeObjects { pipeline, set_layout, pool, sets }),
+            Err(err) => {
+                ComputeObjects { pipeline, set_layout, pool, sets: vec![] }.destroy(device);
+                Err(err.into())
+            }
+        }
+    }
+}
+
+/// Sampled images for the reads and then storage images for the writes at bindings `0..` of set 0
+fn check_bindings(reflection: &PipelineReflection, reads: usize, writes: usize) -> Result<(), String> {
+
+    let expected = std::iter::repeat_n(vk::DescriptorType::SAMPLED_IMAGE, reads)
+        .chain(std::iter::repeat_n(vk::DescriptorType::STORAGE_IMAGE, writes))
+        .enumerate()
+        .map(|(binding, ty)| (0, binding as u32, ty, 1))
+        .collect::<Vec<_>>();
+
+    let declared = reflection.bindings.iter()
+        .map(|binding| (binding.set, binding.binding, binding.descriptor_type, binding.count))
+        .collect::<Vec<_>>();
+
+    if declared != expected {
+        return Err(format!(
+            "expected {} sampled and then {} storage images at bindings 0.. of set 0, the shader declares {:?}",
+            reads, writes, declared
+        ));
+    }
+    Ok(())
+}
+
+fn same_push_constants(a: Option<vk::PushConstantRange>, b: Option<vk::PushConstantRange>) -> bool {
+    let key = |range: Option<vk::PushConstantRange>| range.map(|range| (range.stage_flags, range.offset, range.size));
+    key(a) == key(b)
+}
+
+fn build_compute_pipeline(
+    device: &ash::Device,
+    spv: Vec<u32>,
+    set_layout: vk::DescriptorSetLayout,
+    push_constants: Option<vk::PushConstantRange>
+) -> Result<RenderPipeline, Box<dyn Error>> {
+
+    let program = ShaderProgramBuilder::new()
+        .with_device(device)
+        .with_compute_shader(spv)
+        .try_build()?;
+
+    let mut builder = ComputePipelineBuilder::new()
+        .with_device(device)
+        .with_shader_program(&program)
+        .add_set_layout(set_layout);
+
+    if let Some(range) = push_constants {
+        builder = builder.add_push_constant_range(range);
+    }
+
+    let pipeline = builder.build();
+    program.destroy(device);
+    Ok(pipeline)
+}
+
+///
+/// GPU objects of a [`PassKind::Compute`] pass
+///
+pub struct ComputePass {
+    pub pipeline: PipelineHandle,
+    pub set_layout: vk::DescriptorSetLayout,
+    pub pool: DescriptorPool,
+}
+
+///
+/// [`ComputePass`] before the pipeline is registered in the graph
+///
+struct ComputeObjects {
+    pipeline: RenderPipeline,
+    set_layout: vk::DescriptorSetLayout,
+    pool: DescriptorPool,
+    sets: Vec<DescriptorSet>,
+}
+
+impl ComputeObjects {
+
+    fn destroy(self, device: &ash::Device) {
+        self.pipeline.destroy(device);
+        self.pool.destroy(device);
+        unsafe { device.destroy_descriptor_set_layout(self.set_layout, None) };
+    }
+}
+
+///
+/// What the last applied description added to the graph
+///
+#[derive(Default)]
+pub struct LoadedDescription {
+    pub passes: Vec<Arc<str>>,
+    /// Transient images, temporal ones stay in the graph
+    pub images: Vec<ImageHandle>,
+    pub compute: Vec<ComputePass>,
+}
+
+///
+/// Keeps a graph in sync with a [`GraphDescription`] file
+///
+/// Call [`GraphDescriptionLoader::update`] before every execute. It reapplies the file when
+/// it changes and the image sizes when the swapchain does. A file that fails to load is
+/// logged and the graph keeps the passes of the last good one. Compute shaders are also
+/// reloaded with the other shaders, see [`RenderGraph::watch_pipeline`].
+///
+/// # Example:
+///
+/// ```ignore
+/// let mut loader = GraphDescriptionLoader::new("./shared/graphs/post.ron")
+///     .with_resource("scene_depth", depth.into())
+///     .with_custom_pass("compose", |res, ctx, image_index, resources| {
+///         let taa = res.image(resources.image("taa").unwrap())?;
+///         ...
+///     });
+///
+/// loader.load(&mut graph, &ctx)?;
+///
+/// // Каждый кадр
+/// loader.update(&mut graph, &ctx);
+/// graph.execute(&ctx)?;
+/// ```
+///
+#[derive(Default)]
+pub struct GraphDescriptionLoader {
+    pub path: PathBuf,
+    pub custom_passes: HashMap<String, CustomPass>,
+    /// Resources created in code that the description refers to by name
+    pub imports: HashMap<String, GraphResource>,
+    /// Last applied description
+    pub description: Option<GraphDescription>,
+    /// Modification time of the file when it was last read
+    pub modified: Option<SystemTime>,
+    /// Swapchain extent the image sizes were computed for
+    pub extent: vk::Extent2D,
+    pub loaded: LoadedDescription,
+}
+
+impl GraphDescriptionLoader {
+
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into(), ..Default::default() }
+    }
+
+    pub fn with_custom_pass<F>(mut self, name: impl Into<String>, pass: F) -> Self
+        where F: Fn(&mut RenderGraphResource, &RenderContext, u32, &PassResources) -> Result<(), Box<dyn Error>> + 'static
+    {
+        self.custom_passes.insert(name.into(), Rc::new(pass));
+        self
+    }
+
+    pub fn with_resource(mut self, name: impl Into<String>, resource: GraphResource) -> Self {
+        self.imports.insert(name.into(), resource);
+        self
+    }
+
+    /// Read the file and apply it, the graph is unchanged on error
+    pub fn load(&mut self, graph: &mut RenderGraph, ctx: &RenderContext) -> Result<(), DescriptionError> {
+        self.modified = self.file_modified();
+        let description = GraphDescription::load(&self.path)?;
+        self.apply(description, graph, ctx)
+    }
+
+    /// Reapply the file if it or the swapchain extent changed
+    pub fn update(&mut self, graph: &mut RenderGraph, ctx: &RenderContext) {
+
+        let modified = self.file_modified();
+        let reload = modified != self.modified;
+        let resized = ctx.window_manager.caps.current_extent != self.extent;
+
+        let description = if reload {
+            self.modified = modified;
+            match GraphDescription::load(&self.path) {
+                Ok(description) => description,
+                Err(err) => {
+                    log::error!("Render graph description {:?} is not reloaded, {}", self.path, err);
+                    return;
+                }
+            }
+        } else if let Some(description) = self.description.clone().filter(|_| resized) {
+            description
+        } else {
+            return;
+        };
+
+        match self.apply(description, graph, ctx) {
+            Ok(()) => log::info!("Render graph description {:?} applied", self.path),
+            Err(err) => log::error!("Render graph description {:?} is not applied, {}", self.path, err),
+        }
+    }
+
+    fn file_modified(&self) -> Option<SystemTime> {
+        fs::metadata(&self.path).and_then(|metadata| metadata.modified()).ok()
+    }
+
+    ///
+    /// Replace the passes of the previous description with those of `description`
+    ///
+    /// GPU objects are created and the new passes compiled before the previous ones are
+    /// destroyed, so a description that fails anywhere leaves the graph as it was.
+    ///
+    pub fn apply(&mut self, description: GraphDescription, graph: &mut RenderGraph, ctx: &RenderContext) -> Result<(), DescriptionError> {
+
+        let custom_passes = self.custom_passes.keys().map(String::as_str).collect::<Vec<_>>();
+        description.check(&self.imports, &custom_passes)?;
+
+        let dir = self.path.parent().unwrap_or(Path::new("."));
+        let shaders = description.passes.iter()
+            .map(|pass| match &pass.kind {
+                PassKind::Compute { shader, params } => ComputeShader::compile(pass, dir.join(shader), params).map(Some),
+                PassKind::Custom(_) => Ok(None),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let device = ctx.graphics_device.raw_device();
+        let extent = ctx.window_manager.caps.current_extent;
+        let frames = ctx.window_manager.frame_buffers.raw.len();
+
+        let mut objects = Vec::new();
+        for (pass, shader) in description.passes.iter().zip(&shaders) {
+            let Some(shader) = shader else {
+                continue;
+            };
+
+            match shader.create_objects(device, &pass.name, frames) {
+                Ok(created) => objects.push(created),
+                Err(err) => {
+                    objects.into_iter().for_each(|created| created.destroy(device));
+                    return Err(err);
+                }
+            }
+        }
+
+        // Пассы и ресурсы прошлого описания могут использоваться любым кадром в полёте
+        let fences = graph.sync.iter().map(|sync| sync.fence).collect::<Vec<_>>();
+        if !fences.is_empty()
+            && let Err(err) = unsafe { device.wait_for_fences(&fences, true, u64::MAX) }
+        {
+            objects.into_iter().for_each(|created| created.destroy(device));
+            return Err(err.into());
+        }
+
+        let temporals = graph.temporals.clone();
+        let previous = graph.take_passes(&self.loaded.passes);
+        let added = self.add_to_graph(&description, shaders, objects, graph, extent);
+
+        // Пробная компиляция, с ошибкой граф возвращается к прошлому описанию
+        if let Err(err) = graph.compile() {
+            graph.nodes.truncate(graph.nodes.len() - added.passes.len());
+            destroy_resources(graph, device, added);
+
+            graph.restore_temporals(temporals);
+            graph.restore_passes(previous);
+            return Err(DescriptionError::Graph(err));
+        }
+
+        drop(previous);
+        destroy_resources(graph, device, std::mem::replace(&mut self.loaded, added));
+
+        self.extent = extent;
+        self.description = Some(description);
+        Ok(())
+    }
+
+    /// Passes and images of `description`, appended after the passes already in the graph
+    fn add_to_graph(
+        &self,
+        description: &GraphDescription,
+        shaders: Vec<Option<ComputeShader>>,
+        objects: Vec<ComputeObjects>,
+        graph: &mut RenderGraph,
+        extent: vk::Extent2D
+    ) -> LoadedDescription {
+
+        let mut loaded = LoadedDescription::default();
+        let mut resources = self.imports.clone();
+        resources.insert(SWAPCHAIN.to_owned(), GraphResource::Swapchain);
+
+        for image in &description.images {
+            let format = parse_format(&image.format).expect("formats are checked");
+            let desc = ImageDesc::new_2d(format, image.size.extent(extent));
+            let name = image.name.as_str();
+
+            if image.temporal {
+                let temporal = graph.get_or_create_temporal_image(name, desc);
+                resources.insert(image.name.clone(), temporal.current.into());
+                resources.insert(format!("{}{}", image.name, HISTORY_SUFFIX), temporal.history.into());
+            } else {
+                let handle = graph.create_image(name, desc);
+                loaded.images.push(handle);
+                resources.insert(image.name.clone(), handle.into());
+            }
+        }
+
+        let mut objects = objects.into_iter();
+
+        for (pass, shader) in description.passes.iter().zip(shaders) {
+            let name: Arc<str> = pass.name.as_str().into();
+            let bound = PassResources {
+                reads: pass.reads.iter().map(|resource| (resource.clone(), resources[resource])).collect(),
+                writes: pass.writes.iter().map(|resource| (resource.clone(), resources[resource])).collect(),
+            };
+
+            match (&pass.kind, shader) {
+                (PassKind::Compute { .. }, Some(shader)) => {
+                    let ComputeObjects { pipeline, set_layout, pool, sets } = objects.next().expect("objects are created for every compute pass");
+                    let push_constants = shader.reflection.push_constant_range;
+
+                    let (reads, writes, local_size) = (pass.reads.len(), pass.writes.len(), shader.local_size);
+
+                    let pipeline = graph.register_pipeline(name.clone(), pipeline);
+                    graph.watch_pipeline(pipeline, &[&shader.path], move |ctx, spv| {
+                        // Набор дескрипторов, пуш-константы и размер группы пасса остаются прежними
+                        let reloaded = ShaderReflection::from_spv(&spv[0])?;
+                        let reflection = PipelineReflection::merge(std::slice::from_ref(&reloaded))?;
+
+                        if reloaded.stage != vk::ShaderStageFlags::COMPUTE {
+                            return Err("the shader is not a compute shader anymore".into());
+                        }
+                        check_bindings(&reflection, reads, writes)?;
+                        if !same_push_constants(reflection.push_constant_range, push_constants) {
+                            return Err(format!("push constants changed from {:?} to {:?}", push_constants, reflection.push_constant_range).into());
+                        }
+                        if reloaded.local_size.unwrap_or([1, 1, 1]) != local_size {
+                            return Err(format!("workgroup size changed from {:?} to {:?}", local_size, reloaded.local_size).into());
+                        }
+
+                        build_compute_pipeline(ctx.graphics_device.raw_device(), spv[0].clone(), set_layout, push_constants)
+                    });
+                    loaded.compute.push(ComputePass { pipeline, set_layout, pool });
+
+                    let mut builder = graph.add_pass(name.clone());
+                    for (_, resource) in &bound.reads {
+                        builder = builder.access(*resource, AccessType::ComputeShaderReadSampledImage);
+                    }
+                    for (_, resource) in &bound.writes {
+                        builder = builder.access(*resource, AccessType::ComputeShaderWrite);
+                    }
+                    if let Some(feature) = &pass.feature {
+                        builder = builder.with_feature(feature.as_str());
+                    }
+                    builder.execute(compute_pass(pipeline, sets, &bound, shader.local_size, shader.push_constants));
+                },
+                (PassKind::Custom(custom), _) => {
+                    let func = self.custom_passes[custom].clone();
+
+                    let mut builder = graph.add_pass(name.clone());
+                    for (_, resource) in &bound.reads {
+                        builder = builder.read(*resource);
+                    }
+                    for (_, resource) in &bound.writes {
+                        builder = builder.write(*resource);
+                    }
+                    if let Some(feature) = &pass.feature {
+                        builder = builder.with_feature(feature.as_str());
+                    }
+                    builder.execute(move |res, ctx, image_index| func(res, ctx, image_index, &bound));
+                },
+                (PassKind::Compute { .. }, None) => unreachable!("compute shaders are compiled for every compute pass"),
+            }
+
+            loaded.passes.push(name);
+        }
+
+        loaded
+    }
+
+    /// Remove what the last description added, none of its frames may be in flight
+    pub fn unload(&mut self, graph: &mut RenderGraph, device: &ash::Device) {
+
+        for pass in &self.loaded.passes {
+            graph.remove_pass(pass);
+        }
+
+        destroy_resources(graph, device, std::mem::take(&mut self.loaded));
+        self.description = None;
+    }
+}
+
+/// Images and compute objects of `loaded`, its passes are already out of the graph
+fn destroy_resources(graph: &mut RenderGraph, device: &ash::Device, loaded: LoadedDescription) {
+
+    for image in loaded.images {
+        if let Some(image) = graph.remove_image(image) {
+            image.destroy(device);
+        }
+    }
+
+    for compute in loaded.compute {
+        if let Some(pipeline) = graph.remove_pipeline(compute.pipeline) {
+            pipeline.destroy(device);
+        }
+        compute.pool.destroy(device);
+        unsafe { device.destroy_descriptor_set_layout(compute.set_layout, None) };
+    }
+}
+
+fn compute_pass(
+    pipeline: PipelineHandle,
+    sets: Vec<DescriptorSet>,
+    resources: &PassResources,
+    local_size: [u32; 3],
+    push_constants: Vec<u8>
+) -> impl Fn(&mut RenderGraphResource, &RenderContext, u32) -> Result<(), Box<dyn Error>> + 'static {
+
+    // Проверено в GraphDescription::check
+    let image = |(_, resource): &(String, GraphResource)| match resource {
+        GraphResource::Image(handle) => *handle,
+        _ => unreachable!("compute passes use only images"),
+    };
+    let reads = resources.reads.iter().map(image).collect::<Vec<_>>();
+    let writes = resources.writes.iter().map(image).collect::<Vec<_>>();
+
+    move |res, ctx, _| {
+        let device = ctx.graphics_device.raw_device();
+        let set = sets[res.current_frame];
+
+        let mut writer = DescriptorSetWriter::new();
+        for (binding, image) in reads.iter().enumerate() {
+            writer = writer.write_sampled_image(binding as u32, res.image(*image)?.view, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        }
+        for (binding, image) in writes.iter().enumerate() {
+            writer = writer.write_storage_image((reads.len() + binding) as u32, res.image(*image)?.view);
+        }
+        writer.update(device, &set);
+
+        let extent = res.image(writes[0])?.desc.extent;
+        let command_buffer = res.begin_pass_commands(device)?;
+        let pipeline = res.pipeline.try_get(pipeline)?;
+
+        unsafe {
+            pipeline.bind(device, command_buffer);
+            set.bind(device, command_buffer, pipeline.bind_point, pipeline.raw_layout, 0);
+            if !push_constants.is_empty() {
+                device.cmd_push_constants(command_buffer, pipeline.raw_layout, vk::ShaderStageFlags::COMPUTE, 0, &push_constants);
+            }
+            device.cmd_dispatch(command_buffer, extent.width.div_ceil(local_size[0]), extent.height.div_ceil(local_size[1]), 1);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compile_debug_view_shader, Handle};
+
+    const POST: &str = r#"
+        (
+            images: [
+                (name: "hdr", format: "R16G16B16A16_SFLOAT"),
+                (name: "bloom", format: "R16G16B16A16_SFLOAT", size: Scale(0.5)),
+                (name: "taa", format: "R16G16B16A16_SFLOAT", temporal: true),
+            ],
+            passes: [
+                (name: "scene", kind: Custom("scene"), writes: ["hdr"]),
+                (name: "bloom", kind: Compute(shader: "bloom.wgsl", params: [0.8]), reads: ["hdr"], writes: ["bloom"], feature: Some("bloom")),
+                (name: "taa", kind: Compute(shader: "taa.wgsl"), reads: ["hdr", "taa.history"], writes: ["taa"]),
+                (name: "compose", kind: Custom("compose"), reads: ["taa", "bloom", "ui"], writes: ["swapchain"]),
+            ],
+        )
+    "#;
+
+    fn imports() -> HashMap<String, GraphResource> {
+        HashMap::from([("ui".to_owned(), GraphResource::Image(Handle::from_raw(0, 0)))])
+    }
+
+    #[test]
+    fn parses_and_checks_descriptions() {
+        let description = GraphDescription::from_ron(POST).unwrap();
+        assert_eq!(description.images[1].size.extent(vk::Extent2D { width: 1280, height: 721 }), vk::Extent2D { width: 640, height: 360 });
+        assert_eq!(description.passes[1].kind, PassKind::Compute { shader: "bloom.wgsl".to_owned(), params: vec![0.8] });
+        assert_eq!(description.passes[1].feature.as_deref(), Some("bloom"));
+
+        description.check(&imports(), &["scene", "compose"]).unwrap();
+
+        let err = description.check(&HashMap::new(), &["scene", "compose"]).unwrap_err();
+        assert!(matches!(&err, DescriptionError::UnknownResource { pass, resource } if pass == "compose" && resource == "ui"), "{}", err);
+
+        let err = description.check(&imports(), &["scene"]).unwrap_err();
+        assert!(matches!(&err, DescriptionError::UnknownCustomPass { custom, .. } if custom == "compose"), "{}", err);
+
+        let mut swapchain = description.clone();
+        swapchain.passes[1].writes = vec![SWAPCHAIN.to_owned()];
+        let err = swapchain.check(&imports(), &["scene", "compose"]).unwrap_err();
+        assert!(matches!(&err, DescriptionError::InvalidPass { pass, .. } if pass == "bloom"), "{}", err);
+
+        assert!(GraphDescription::from_ron("(passes: [(name: \"a\")])").is_err());
+    }
+
+    #[test]
+    fn checks_compute_bindings() {
+        let spv = compile_debug_view_shader().unwrap().spv;
+        let reflection = PipelineReflection::from_spv(&[&spv]).unwrap();
+
+        check_bindings(&reflection, 1, 1).unwrap();
+        assert!(check_bindings(&reflection, 2, 1).is_err());
+        assert!(check_bindings(&reflection, 0, 2).is_err());
+    }
+
+    #[test]
+    fn restores_taken_passes_in_place() {
+        let mut graph = RenderGraph::new();
+        for name in ["scene", "bloom", "ui", "taa"] {
+            graph.add_pass(name).write(GraphResource::Swapchain).execute(|_, _, _| Ok(()));
+        }
+
+        let taken = graph.take_passes(&["bloom".into(), "taa".into()]);
+        assert_eq!(graph.nodes.iter().map(|node| &*node.desc.name).collect::<Vec<_>>(), ["scene", "ui"]);
+
+        graph.restore_passes(taken);
+        assert_eq!(graph.nodes.iter().map(|node| &*node.desc.name).collect::<Vec<_>>(), ["scene", "bloom", "ui", "taa"]);
+    }
+}
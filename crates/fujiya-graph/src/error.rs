@@ -0,0 +1,102 @@
+use std::{error::Error, fmt, sync::Arc};
+
+use ash::vk;
+
+use crate::GraphError;
+
+///
+/// Error of [`crate::RenderGraph::execute`]
+///
+/// Every error except [`ExecuteError::Fatal`] leaves the graph ready for the next frame.
+///
+/// # Example:
+///
+/// ```ignore
+/// match graph.execute(&ctx) {
+///     Ok(()) => {},
+///     Err(ExecuteError::OutOfDate) => recreate_swapchain(&mut ctx, &mut graph),
+///     Err(err) if err.is_fatal() => panic!("{}", err),
+///     Err(err) => log::warn!("Frame is skipped, {}", err),
+/// }
+/// ```
+///
+#[derive(Debug)]
+pub enum ExecuteError {
+    /// The passes or their resources are invalid, nothing was submitted
+    Graph(GraphError),
+    /// The swapchain doesn't match the surface anymore and has to be recreated
+    OutOfDate,
+    /// The GPU or the presentation engine didn't respond in time
+    Timeout,
+    /// A pass returned an error, the passes after it were skipped and the frame was not presented
+    PassFailed {
+        pass: Arc<str>,
+        error: Box<dyn Error>,
+    },
+    /// The device can't be used anymore, e.g. it was lost or is out of memory
+    Fatal(vk::Result),
+}
+
+impl ExecuteError {
+
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, Self::Fatal(_))
+    }
+
+    pub fn is_recoverable(&self) -> bool {
+        !self.is_fatal()
+    }
+}
+
+impl From<vk::Result> for ExecuteError {
+    fn from(result: vk::Result) -> Self {
+        match result {
+            vk::Result::ERROR_OUT_OF_DATE_KHR => Self::OutOfDate,
+            vk::Result::TIMEOUT | vk::Result::NOT_READY => Self::Timeout,
+            _ => Self::Fatal(result),
+        }
+    }
+}
+
+impl From<GraphError> for ExecuteError {
+    fn from(err: GraphError) -> Self {
+        Self::Graph(err)
+    }
+}
+
+impl fmt::Display for ExecuteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Graph(err) => write!(f, "render graph is invalid, {}", err),
+            Self::OutOfDate => write!(f, "swapchain is out of date"),
+            Self::Timeout => write!(f, "timed out waiting for the GPU"),
+            Self::PassFailed { pass, error } => write!(f, "pass {:?} failed, {}", pass, error),
+            Self::Fatal(result) => write!(f, "device error {:?}", result),
+        }
+    }
+}
+
+impl Error for ExecuteError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Graph(err) => Some(err),
+            Self::PassFailed { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_vulkan_errors() {
+        assert!(matches!(ExecuteError::from(vk::Result::ERROR_OUT_OF_DATE_KHR), ExecuteError::OutOfDate));
+        assert!(matches!(ExecuteError::from(vk::Result::TIMEOUT), ExecuteError::Timeout));
+
+        assert!(ExecuteError::from(vk::Result::ERROR_DEVICE_LOST).is_fatal());
+        assert!(ExecuteError::from(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY).is_fatal());
+        assert!(ExecuteError::PassFailed { pass: "Scene".into(), error: "stale handle".into() }.is_recoverable());
+    }
+}
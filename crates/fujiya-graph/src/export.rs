@@ -0,0 +1,388 @@
+use std::{collections::HashMap, error::Error, fmt::Write, path::Path, sync::Arc};
+
+use ash::vk;
+use fujiya_render::{escape_json, AccessType, BarrierBatch};
+
+use crate::{GraphError, GraphResource, RenderGraph};
+
+///
+/// Barrier the graph records before a pass for one of its resources
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BarrierSnapshot {
+    pub previous: Vec<AccessType>,
+    pub src_stage: vk::PipelineStageFlags,
+    pub dst_stage: vk::PipelineStageFlags,
+    /// `(old, new)` for images
+    pub layouts: Option<(vk::ImageLayout, vk::ImageLayout)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessSnapshot {
+    /// Index into [`GraphSnapshot::resources`]
+    pub resource: usize,
+    pub access: AccessType,
+    /// Only on the first access of the resource in the pass, the barrier covers all of them
+    pub barrier: Option<BarrierSnapshot>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PassSnapshot {
+    pub name: Arc<str>,
+    /// Position in the execution order, `None` if culled
+    pub position: Option<usize>,
+    pub side_effects: bool,
+    /// Turned off by name or feature
+    pub enabled: bool,
+    pub accesses: Vec<AccessSnapshot>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResourceSnapshot {
+    pub resource: GraphResource,
+    pub name: String,
+    pub output: bool,
+    /// First and last position in the execution order of a transient
+    pub lifetime: Option<(usize, usize)>,
+    pub transient: bool,
+}
+
+///
+/// Compiled structure of a [`RenderGraph`], for DOT and JSON export
+///
+/// Barriers are those of a frame that starts from the current resource states.
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GraphSnapshot {
+    /// In registration order
+    pub passes: Vec<PassSnapshot>,
+    pub resources: Vec<ResourceSnapshot>,
+    /// `(producer, consumer)` pass indices
+    pub edges: Vec<(usize, usize)>,
+}
+
+fn resource_id(resource: GraphResource) -> String {
+    match resource {
+        GraphResource::Buffer(handle) => format!("buffer_{}v{}", handle.index(), handle.generation()),
+        GraphResource::Image(handle) => format!("image_{}v{}", handle.index(), handle.generation()),
+        GraphResource::Swapchain => "swapchain".into(),
+    }
+}
+
+fn join_accesses(accesses: &[AccessType]) -> String {
+    accesses.iter().map(|access| format!("{:?}", access)).collect::<Vec<_>>().join(" | ")
+}
+
+impl GraphSnapshot {
+
+    ///
+    /// Graphviz document, passes are boxes and resources are ellipses
+    ///
+    /// Culled passes are gray, transients are dashed, outputs have a double border.
+    /// Edges are labeled with the access and the barrier before it.
+    ///
+    pub fn to_dot(&self) -> String {
+
+        let mut dot = String::from("digraph RenderGraph {\n    rankdir=LR;\n    node [fontname=\"monospace\"];\n    edge [fontname=\"monospace\", fontsize=10];\n\n");
+
+        for (index, pass) in self.passes.iter().enumerate() {
+            let (label, style) = match (pass.position, pass.enabled) {
+                (Some(position), _) => (format!("#{} {}", position, pass.name), ""),
+                (None, true) => (format!("{} (culled)", pass.name), ", style=dashed, color=gray, fontcolor=gray"),
+                (None, false) => (format!("{} (disabled)", pass.name), ", style=dotted, color=gray, fontcolor=gray"),
+            };
+            let _ = writeln!(dot, "    \"pass_{}\" [shape=box, label=\"{}\"{}];", index, escape_json(&label), style);
+        }
+
+        dot.push('\n');
+
+        for resource in &self.resources {
+            let mut label = escape_json(&resource.name);
+            if let Some((first, last)) = resource.lifetime {
+                label += &format!("\\ntransient #{}..#{}", first, last);
+            }
+
+            let mut style = String::new();
+            if resource.transient {
+                style += ", style=dashed";
+            }
+            if resource.output {
+                style += ", peripheries=2";
+            }
+
+            let _ = writeln!(dot, "    \"{}\" [shape=ellipse, label=\"{}\"{}];", resource_id(resource.resource), label, style);
+        }
+
+        dot.push('\n');
+
+        for (index, pass) in self.passes.iter().enumerate() {
+            for access in &pass.accesses {
+                let resource = resource_id(self.resources[access.resource].resource);
+
+                let mut label = format!("{:?}", access.access);
+                if let Some(barrier) = &access.barrier {
+                    label += &format!("\\n{:?} -> {:?}", barrier.src_stage, barrier.dst_stage);
+                    if let Some((old, new)) = barrier.layouts {
+                        label += &format!("\\n{:?} -> {:?}", old, new);
+                    }
+                }
+
+                let edge = match (access.access.is_read(), access.access.is_write()) {
+                    (true, true) => format!("\"pass_{}\" -> \"{}\" [dir=both, ", index, resource),
+                    (false, true) => format!("\"pass_{}\" -> \"{}\" [", index, resource),
+                    _ => format!("\"{}\" -> \"pass_{}\" [", resource, index),
+                };
+                let _ = writeln!(dot, "    {}label=\"{}\"];", edge, label);
+            }
+        }
+
+        dot.push('\n');
+
+        for (producer, consumer) in &self.edges {
+            let _ = writeln!(dot, "    \"pass_{}\" -> \"pass_{}\" [style=dotted, constraint=false];", producer, consumer);
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    pub fn to_json(&self) -> String {
+
+        let mut json = String::from("{\"passes\":[");
+
+        for (index, pass) in self.passes.iter().enumerate() {
+            if index > 0 {
+                json.push(',');
+            }
+
+            let position = pass.position.map(|position| position.to_string()).unwrap_or("null".into());
+            let _ = write!(
+                json,
+                "{{\"name\":\"{}\",\"position\":{},\"culled\":{},\"enabled\":{},\"side_effects\":{},\"accesses\":[",
+                escape_json(&pass.name), position, pass.position.is_none(), pass.enabled, pass.side_effects
+            );
+
+            for (index, access) in pass.accesses.iter().enumerate() {
+                if index > 0 {
+                    json.push(',');
+                }
+
+                let _ = write!(
+                    json,
+                    "{{\"resource\":\"{}\",\"access\":\"{:?}\",\"read\":{},\"write\":{},\"barrier\":",
+                    resource_id(self.resources[access.resource].resource), access.access, access.access.is_read(), access.access.is_write()
+                );
+
+                match &access.barrier {
+                    Some(barrier) => {
+                        let _ = write!(
+                            json,
+                            "{{\"previous\":\"{}\",\"src_stage\":\"{:?}\",\"dst_stage\":\"{:?}\"",
+                            join_accesses(&barrier.previous), barrier.src_stage, barrier.dst_stage
+                        );
+                        if let Some((old, new)) = barrier.layouts {
+                            let _ = write!(json, ",\"old_layout\":\"{:?}\",\"new_layout\":\"{:?}\"", old, new);
+                        }
+                        json.push('}');
+                    },
+                    None => json.push_str("null"),
+                }
+
+                json.push('}');
+            }
+
+            json.push_str("]}");
+        }
+
+        json.push_str("],\"resources\":[");
+
+        for (index, resource) in self.resources.iter().enumerate() {
+            if index > 0 {
+                json.push(',');
+            }
+
+            let lifetime = match resource.lifetime {
+                Some((first, last)) => format!("[{},{}]", first, last),
+                None => "null".into(),
+            };
+
+            let _ = write!(
+                json,
+                "{{\"id\":\"{}\",\"name\":\"{}\",\"output\":{},\"transient\":{},\"lifetime\":{}}}",
+                resource_id(resource.resource), escape_json(&resource.name), resource.output, resource.transient, lifetime
+            );
+        }
+
+        json.push_str("],\"edges\":[");
+
+        let edges = self.edges.iter()
+            .map(|(producer, consumer)| format!("[{},{}]", producer, consumer))
+            .collect::<Vec<_>>();
+        json.push_str(&edges.join(","));
+
+        json.push_str("]}");
+        json
+    }
+}
+
+impl RenderGraph {
+
+    ///
+    /// Passes, resources and barriers of the compiled graph
+    ///
+    /// Compiles the graph if the passes changed. Barriers come from the same derivation as in
+    /// [`RenderGraph::execute`], starting from the states left by the last frame. Transients get
+    /// their memory blocks on the first execute after compile, until then the snapshot shows
+    /// them without aliasing: no waits on the previous owner of the memory.
+    ///
+    pub fn snapshot(&mut self) -> Result<GraphSnapshot, GraphError> {
+
+        if self.schedule.is_none() {
+            self.compile()?;
+        }
+        let schedule = self.schedule.as_ref().unwrap();
+
+        let mut snapshot = GraphSnapshot { edges: schedule.edges.clone(), ..Default::default() };
+
+        let mut resources: Vec<GraphResource> = vec![];
+        for pass in &schedule.passes {
+            for (resource, _) in &pass.accesses {
+                if !resources.contains(resource) {
+                    resources.push(*resource);
+                }
+            }
+        }
+        for transient in &self.transients.resources {
+            if !resources.contains(&transient.resource) {
+                resources.push(transient.resource);
+            }
+        }
+
+        for resource in &resources {
+            let transient = self.transients.get(*resource);
+            snapshot.resources.push(ResourceSnapshot {
+                resource: *resource,
+                name: self.resource_name(*resource),
+                output: *resource == GraphResource::Swapchain
+                    || self.outputs.contains(resource)
+                    || self.temporals.currents().any(|current| current == *resource),
+                lifetime: transient.and_then(|transient| transient.lifetime),
+                transient: transient.is_some(),
+            });
+        }
+
+        for (index, node) in self.nodes.iter().enumerate() {
+            snapshot.passes.push(PassSnapshot {
+                name: node.desc.name.clone(),
+                position: None,
+                side_effects: node.desc.side_effects,
+                enabled: !schedule.disabled.contains(&index),
+                accesses: vec![],
+            });
+        }
+
+        // Прогоняем кадр так же, как execute, но без устройства
+        let mut states = self.resource_states.clone();
+        let mut transients = self.transients.clone();
+        states.remove(&GraphResource::Swapchain);
+
+        for (position, index) in schedule.order.iter().enumerate() {
+            let desc = &schedule.passes[*index];
+            snapshot.passes[*index].position = Some(position);
+
+            let mut barriers = HashMap::new();
+            for resource in desc.resources() {
+                let transition = Self::transition(&mut states, &mut transients, desc, resource, position);
+
+                let mut batch = BarrierBatch::new();
+                Self::add_barrier(&mut batch, &transition, vk::Image::null(), Default::default(), vk::Buffer::null());
+
+                let barrier = (!batch.src_stage.is_empty()).then(|| BarrierSnapshot {
+                    previous: transition.previous,
+                    src_stage: batch.src_stage,
+                    dst_stage: batch.dst_stage,
+                    layouts: batch.image_barriers.first().map(|barrier| (barrier.old_layout, barrier.new_layout)),
+                });
+                barriers.insert(resource, barrier);
+            }
+
+            // Барьер показываем у первого доступа к ресурсу
+            for (resource, access) in &desc.accesses {
+                let resource_index = resources.iter().position(|other| other == resource).unwrap();
+                let barrier = barriers.remove(resource).flatten();
+                snapshot.passes[*index].accesses.push(AccessSnapshot { resource: resource_index, access: *access, barrier });
+            }
+
+            for (resource, _) in &desc.readbacks {
+                Self::note_recorded_access(&mut states, &mut transients, *resource, AccessType::TransferRead);
+            }
+        }
+
+        Ok(snapshot)
+    }
+
+    /// Write [`GraphSnapshot::to_dot`], render it with `dot -Tsvg graph.dot -o graph.svg`
+    pub fn write_dot(&mut self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        std::fs::write(path, self.snapshot()?.to_dot())?;
+        Ok(())
+    }
+
+    pub fn write_json(&mut self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        std::fs::write(path, self.snapshot()?.to_json())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fujiya_render::BufferDesc;
+
+    use super::*;
+
+    #[test]
+    fn exports_passes_resources_and_barriers() {
+        let mut graph = RenderGraph::new();
+        let blur = graph.create_buffer("blur", BufferDesc::new(64));
+
+        graph.add_pass("Compose")
+            .read(blur)
+            .write(GraphResource::Swapchain)
+            .execute(|_, _, _| Ok(()));
+
+        graph.add_pass("Blur")
+            .access(blur, AccessType::ComputeShaderWrite)
+            .execute(|_, _, _| Ok(()));
+
+        graph.add_pass("Unused")
+            .access(blur, AccessType::TransferRead)
+            .access(GraphResource::Swapchain, AccessType::TransferRead)
+            .execute(|_, _, _| Ok(()));
+
+        let snapshot = graph.snapshot().unwrap();
+
+        assert_eq!(snapshot.passes[1].position, Some(0));
+        assert_eq!(snapshot.passes[0].position, Some(1));
+        assert_eq!(snapshot.passes[2].position, None);
+        assert_eq!(snapshot.resources[0].lifetime, Some((0, 1)));
+
+        let compose = &snapshot.passes[0].accesses;
+        let barrier = compose[0].barrier.as_ref().unwrap();
+        assert_eq!(barrier.previous, [AccessType::ComputeShaderWrite]);
+        assert_eq!(barrier.src_stage, vk::PipelineStageFlags::COMPUTE_SHADER);
+
+        let swapchain = compose[1].barrier.as_ref().unwrap();
+        assert_eq!(swapchain.layouts, Some((vk::ImageLayout::UNDEFINED, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)));
+
+        let dot = snapshot.to_dot();
+        assert!(dot.contains("\"pass_1\" -> \"buffer_0v0\" [label=\"ComputeShaderWrite"), "{}", dot);
+        assert!(dot.contains("Unused (culled)"), "{}", dot);
+
+        graph.set_pass_enabled("Unused", false);
+        let dot = graph.snapshot().unwrap().to_dot();
+        assert!(dot.contains("Unused (disabled)"), "{}", dot);
+
+        let json = snapshot.to_json();
+        assert!(json.contains("\"lifetime\":[0,1]"), "{}", json);
+        assert!(json.contains("\"old_layout\":\"UNDEFINED\",\"new_layout\":\"COLOR_ATTACHMENT_OPTIMAL\""), "{}", json);
+    }
+}
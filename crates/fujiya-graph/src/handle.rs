@@ -0,0 +1,356 @@
+use std::{
+    fmt,
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+    ops::{Index, IndexMut},
+    sync::Arc
+};
+
+use fujiya_render::{CommandPool, GPUBuffer, GPUImage, RenderPass, RenderPipeline};
+
+pub type BufferHandle = Handle<GPUBuffer>;
+pub type ImageHandle = Handle<GPUImage>;
+pub type PipelineHandle = Handle<RenderPipeline>;
+pub type CommandPoolHandle = Handle<CommandPool>;
+pub type RenderPassHandle = Handle<RenderPass>;
+
+///
+/// Typed generational index into a [`Pool`]
+///
+/// The generation changes every time a slot is reused, so a handle kept after
+/// [`Pool::remove`] never resolves to the resource that took its place.
+///
+pub struct Handle<T> {
+    index: u32,
+    generation: u32,
+    _marker: PhantomData<fn() -> T>
+}
+
+impl<T> Handle<T> {
+
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    #[cfg(test)]
+    pub(crate) fn from_raw(index: u32, generation: u32) -> Self {
+        Self { index, generation, _marker: PhantomData }
+    }
+}
+
+// derive добавил бы лишние ограничения `T: Clone` и т.д.
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index && self.generation == other.generation
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+        self.generation.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let type_name = std::any::type_name::<T>().rsplit("::").next().unwrap_or_default();
+        write!(f, "Handle<{}>({}v{})", type_name, self.index, self.generation)
+    }
+}
+
+///
+/// A handle whose resource was removed
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StaleHandle {
+    pub index: u32,
+    pub generation: u32
+}
+
+impl fmt::Display for StaleHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "handle {}v{} refers to a removed resource", self.index, self.generation)
+    }
+}
+
+impl std::error::Error for StaleHandle {}
+
+struct Slot<T> {
+    generation: u32,
+    name: Arc<str>,
+    value: Option<T>
+}
+
+///
+/// Resources of one type addressed by [`Handle`]
+///
+/// # Example:
+///
+/// ```ignore
+/// let mut buffers = Pool::new();
+/// let vertices = buffers.insert("vertices", buffer);
+///
+/// let buffer = buffers.try_get(vertices)?;
+/// buffers.remove(vertices);
+/// assert!(buffers.get(vertices).is_none());
+/// ```
+///
+pub struct Pool<T> {
+    slots: Vec<Slot<T>>,
+    free: Vec<u32>
+}
+
+impl<T> Default for Pool<T> {
+    fn default() -> Self {
+        Self { slots: vec![], free: vec![] }
+    }
+}
+
+impl<T> Pool<T> {
+
+    pub fn new() -> Self {
+        Self { ..Default::default() }
+    }
+
+    /// `name` is only used in logs and debug output
+    pub fn insert(&mut self, name: impl Into<Arc<str>>, value: T) -> Handle<T> {
+        self.insert_slot(name.into(), Some(value))
+    }
+
+    fn insert_slot(&mut self, name: Arc<str>, value: Option<T>) -> Handle<T> {
+
+        let index = match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.name = name;
+                slot.value = value;
+                index
+            },
+            None => {
+                self.slots.push(Slot { generation: 0, name, value });
+                self.slots.len() as u32 - 1
+            }
+        };
+
+        Handle { index, generation: self.slots[index as usize].generation, _marker: PhantomData }
+    }
+
+    ///
+    /// Handle to an empty slot, filled later with [`Pool::set`]
+    ///
+    /// Until then [`Pool::get`] returns `None`, e.g. for resources the graph creates on compile.
+    ///
+    pub fn reserve(&mut self, name: impl Into<Arc<str>>) -> Handle<T> {
+        self.insert_slot(name.into(), None)
+    }
+
+    /// Put `value` into the slot of a reserved or live handle
+    pub fn set(&mut self, handle: Handle<T>, value: T) -> Result<Option<T>, StaleHandle> {
+        let slot = self.live_slot_mut(handle)?;
+        Ok(slot.value.replace(value))
+    }
+
+    /// Take the value out, the handle stays reserved
+    pub fn take(&mut self, handle: Handle<T>) -> Option<T> {
+        self.live_slot_mut(handle).ok()?.value.take()
+    }
+
+    /// Exchange the values behind two live or reserved handles, the handles stay with their slots
+    pub fn swap(&mut self, a: Handle<T>, b: Handle<T>) -> Result<(), StaleHandle> {
+        self.live_slot_mut(a)?;
+        self.live_slot_mut(b)?;
+
+        let first = self.slots[a.index as usize].value.take();
+        self.slots[a.index as usize].value = std::mem::replace(&mut self.slots[b.index as usize].value, first);
+        Ok(())
+    }
+
+    /// Removes reserved slots too, returns `None` for them
+    pub fn remove(&mut self, handle: Handle<T>) -> Option<T> {
+
+        let slot = self.live_slot_mut(handle).ok()?;
+
+        let value = slot.value.take();
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(handle.index);
+
+        value
+    }
+
+    fn live_slot_mut(&mut self, handle: Handle<T>) -> Result<&mut Slot<T>, StaleHandle> {
+        // Поколение освобождённого слота уже увеличено, старые хэндлы на него не попадут
+        self.slots.get_mut(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)
+            .ok_or(StaleHandle { index: handle.index, generation: handle.generation })
+    }
+
+    /// Put `value` in place of the resource behind `handle`, the handle stays valid
+    pub fn replace(&mut self, handle: Handle<T>, value: T) -> Result<T, StaleHandle> {
+        Ok(std::mem::replace(self.try_get_mut(handle)?, value))
+    }
+
+    pub fn get(&self, handle: Handle<T>) -> Option<&T> {
+        self.slots.get(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)
+            .and_then(|slot| slot.value.as_ref())
+    }
+
+    pub fn get_mut(&mut self, handle: Handle<T>) -> Option<&mut T> {
+        self.slots.get_mut(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)
+            .and_then(|slot| slot.value.as_mut())
+    }
+
+    /// [`Pool::get`] for passes returning `Result<(), Box<dyn Error>>`
+    pub fn try_get(&self, handle: Handle<T>) -> Result<&T, StaleHandle> {
+        self.get(handle).ok_or(StaleHandle { index: handle.index, generation: handle.generation })
+    }
+
+    pub fn try_get_mut(&mut self, handle: Handle<T>) -> Result<&mut T, StaleHandle> {
+        self.get_mut(handle).ok_or(StaleHandle { index: handle.index, generation: handle.generation })
+    }
+
+    pub fn contains(&self, handle: Handle<T>) -> bool {
+        self.get(handle).is_some()
+    }
+
+    /// Also for reserved slots
+    pub fn name(&self, handle: Handle<T>) -> Option<&str> {
+        self.slots.get(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)
+            .map(|slot| &*slot.name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Handle<T>, &T)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            let handle = Handle { index: index as u32, generation: slot.generation, _marker: PhantomData };
+            slot.value.as_ref().map(|value| (handle, value))
+        })
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Handle<T>, &mut T)> {
+        self.slots.iter_mut().enumerate().filter_map(|(index, slot)| {
+            let handle = Handle { index: index as u32, generation: slot.generation, _marker: PhantomData };
+            slot.value.as_mut().map(|value| (handle, value))
+        })
+    }
+
+    /// Remove every resource, e.g. to destroy them
+    pub fn drain(&mut self) -> Vec<T> {
+        let mut values = vec![];
+
+        for (index, slot) in self.slots.iter_mut().enumerate() {
+            if let Some(value) = slot.value.take() {
+                slot.generation = slot.generation.wrapping_add(1);
+                self.free.push(index as u32);
+                values.push(value);
+            }
+        }
+
+        values
+    }
+}
+
+impl<T> Index<Handle<T>> for Pool<T> {
+    type Output = T;
+
+    fn index(&self, handle: Handle<T>) -> &T {
+        self.get(handle).unwrap_or_else(|| panic!("{:?} is stale", handle))
+    }
+}
+
+impl<T> IndexMut<Handle<T>> for Pool<T> {
+    fn index_mut(&mut self, handle: Handle<T>) -> &mut T {
+        self.get_mut(handle).unwrap_or_else(|| panic!("{:?} is stale", handle))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale_handles_are_detected() {
+        let mut pool = Pool::new();
+        let first = pool.insert("first", 1);
+        let second = pool.insert("second", 2);
+
+        assert_eq!(pool.remove(first), Some(1));
+        assert_eq!(pool.get(first), None);
+        assert_eq!(pool.remove(first), None);
+
+        // Слот переиспользуется, но старый хэндл на него не указывает
+        let third = pool.insert("third", 3);
+        assert_eq!(third.index(), first.index());
+        assert_ne!(third, first);
+        assert_eq!(pool.try_get(first), Err(StaleHandle { index: first.index(), generation: first.generation() }));
+        assert_eq!(pool[third], 3);
+        assert_eq!(pool.name(third), Some("third"));
+        assert_eq!(pool[second], 2);
+        assert_eq!(pool.len(), 2);
+    }
+
+    #[test]
+    fn replace_keeps_the_handle() {
+        let mut pool = Pool::new();
+        let handle = pool.insert("value", 1);
+
+        assert_eq!(pool.replace(handle, 2), Ok(1));
+        assert_eq!(pool[handle], 2);
+        assert_eq!(pool.iter().map(|(handle, value)| (handle, *value)).collect::<Vec<_>>(), [(handle, 2)]);
+    }
+
+    #[test]
+    fn swap_exchanges_values() {
+        let mut pool = Pool::new();
+        let current = pool.insert("current", 1);
+        let history = pool.reserve("history");
+
+        assert_eq!(pool.swap(current, history), Ok(()));
+        assert_eq!(pool.get(current), None);
+        assert_eq!(pool[history], 1);
+
+        pool.remove(current);
+        assert!(pool.swap(current, history).is_err());
+        assert_eq!(pool[history], 1);
+    }
+
+    #[test]
+    fn reserved_slots_are_filled_later() {
+        let mut pool = Pool::new();
+        let handle = pool.reserve("transient");
+
+        assert_eq!(pool.get(handle), None);
+        assert_eq!(pool.name(handle), Some("transient"));
+        assert_eq!(pool.set(handle, 1), Ok(None));
+        assert_eq!(pool.take(handle), Some(1));
+        assert_eq!(pool.name(handle), Some("transient"));
+
+        assert_eq!(pool.remove(handle), None);
+        assert_eq!(pool.name(handle), None);
+        assert!(pool.set(handle, 2).is_err());
+    }
+}
@@ -1,22 +1,186 @@
-use std::{collections::HashMap, error::Error};
-use ash::vk::{self, CommandBuffer, PipelineStageFlags, SubmitInfo};
-use fujiya_render::{CommandPool, FrameSync, GPUBuffer, RenderContext, RenderPass, RenderPipeline};
+pub(crate) mod debug_view;
+pub(crate) mod description;
+pub(crate) mod error;
+pub(crate) mod export;
+pub(crate) mod handle;
+pub(crate) mod pass;
+pub(crate) mod readback;
+pub(crate) mod schedule;
+pub(crate) mod sub_graph;
+pub(crate) mod temporal;
+pub(crate) mod transient;
+pub use debug_view::*;
+pub use description::*;
+pub use error::*;
+pub use export::*;
+pub use handle::*;
+pub use pass::*;
+pub use readback::*;
+pub use schedule::*;
+pub use sub_graph::*;
+pub use temporal::*;
+pub use transient::*;
+
+use std::{collections::{HashMap, HashSet}, error::Error, path::{Path, PathBuf}, sync::Arc};
+use ash::vk::{self, CommandBuffer};
+use fujiya_render::{AccessType, BarrierBatch, BufferDesc, CommandAllocator, CommandAllocatorBuilder, CommandAllocatorStats, CommandPool, FrameSync, GPUBuffer, GPUImage, GpuFrameTimings, GpuProfiler, GpuProfilerBuilder, GpuQueries, GpuQueriesBuilder, GpuQuery, ImageDesc, OcclusionResult, PipelineReflection, PipelineStatistics, RenderContext, RenderPass, RenderPipeline, ShaderCompiler, ShaderWatcher, ThreadCommandPools, ThreadCommandPoolsBuilder};
 
 #[derive(Default)]
 pub struct RenderGraphResource {
-    pub pipeline: HashMap<&'static str, RenderPipeline>,
-    pub buffers: HashMap<&'static str, GPUBuffer>,
+    pub pipeline: Pool<RenderPipeline>,
+    pub buffers: Pool<GPUBuffer>,
+    pub images: Pool<GPUImage>,
     pub command_buffers: Vec<CommandBuffer>,
-    pub command_pool: HashMap<&'static str, CommandPool>,
-    pub render_pass: HashMap<&'static str, RenderPass>
+    pub command_pool: Pool<CommandPool>,
+    pub command_allocators: Vec<CommandAllocator>,
+    pub thread_command_pools: Vec<ThreadCommandPools>,
+    pub render_pass: Pool<RenderPass>,
+    pub queries: Option<GpuQueries>,
+    /// Frames of history behind the history half of every temporal resource
+    pub history_frames: HashMap<GraphResource, u32>,
+    /// Resources read in place of those only disabled passes write, see [`RenderGraph::set_fallback`]
+    pub fallbacks: HashMap<GraphResource, GraphResource>,
+    pub current_frame: usize,
+    /// Name of the pass being recorded
+    pub current_pass: Option<Arc<str>>,
+    /// Opened by [`RenderGraphResource::begin_pass_commands`], closed by the graph after the pass
+    pub pass_commands: Vec<(CommandBuffer, GpuQuery)>,
+}
+
+impl RenderGraphResource {
+
+    /// Command allocator of the frame being recorded, reset once its fence is signaled
+    pub fn command_allocator(&mut self) -> &mut CommandAllocator {
+        &mut self.command_allocators[self.current_frame]
+    }
+
+    /// Per-thread command pools of the frame being recorded, for parallel secondary command buffers
+    pub fn thread_command_pools(&self) -> &ThreadCommandPools {
+        &self.thread_command_pools[self.current_frame]
+    }
+
+    /// Pipeline statistics and occlusion queries of the frame being recorded
+    pub fn queries(&mut self) -> &mut GpuQueries {
+        self.queries.as_mut().expect("Queries are created on the first execute")
+    }
+
+    ///
+    /// Number of previous frames in `history`, 0 when it holds nothing yet
+    ///
+    /// It is 0 on the first frame and after the temporal resource is recreated, e.g. to
+    /// reset accumulation or skip the history in TAA.
+    ///
+    pub fn history_frames(&self, history: impl Into<GraphResource>) -> u32 {
+        self.history_frames.get(&history.into()).copied().unwrap_or_default()
+    }
+
+    /// Image behind `handle`, or its fallback while the passes writing it are disabled
+    pub fn image(&self, handle: ImageHandle) -> Result<&GPUImage, StaleHandle> {
+        match self.fallbacks.get(&GraphResource::Image(handle)) {
+            Some(GraphResource::Image(fallback)) => self.images.try_get(*fallback),
+            _ => self.images.try_get(handle),
+        }
+    }
+
+    /// Buffer behind `handle`, or its fallback while the passes writing it are disabled
+    pub fn buffer(&self, handle: BufferHandle) -> Result<&GPUBuffer, StaleHandle> {
+        match self.fallbacks.get(&GraphResource::Buffer(handle)) {
+            Some(GraphResource::Buffer(fallback)) => self.buffers.try_get(*fallback),
+            _ => self.buffers.try_get(handle),
+        }
+    }
+
+    /// Allocate a primary command buffer for the current frame and queue it for submission
+    pub fn submit_command_buffer(&mut self, device: &ash::Device) -> CommandBuffer {
+        let command_buffer = self.command_allocator().primary(device);
+        self.command_buffers.push(command_buffer);
+        command_buffer
+    }
+
+    ///
+    /// Begun primary command buffer for the current pass, queued for submission
+    ///
+    /// A pipeline statistics query named after the pass is open in it, see
+    /// [`RenderGraph::pipeline_statistics`]. The graph ends the query and the command buffer
+    /// after the pass returns, so the pass must not end it.
+    ///
+    pub fn begin_pass_commands(&mut self, device: &ash::Device) -> Result<CommandBuffer, vk::Result> {
+        let command_buffer = RenderGraph::begin_graph_commands(self, device)?;
+
+        let name = self.current_pass.clone().unwrap_or_default();
+        let query = self.queries().begin_statistics(device, command_buffer, &name);
+        self.pass_commands.push((command_buffer, query));
+
+        Ok(command_buffer)
+    }
+
+    fn end_pass_commands(&mut self, device: &ash::Device) -> Result<(), vk::Result> {
+        for (command_buffer, query) in std::mem::take(&mut self.pass_commands) {
+            self.queries().end_statistics(device, command_buffer, query);
+            unsafe { device.end_command_buffer(command_buffer)? };
+        }
+        Ok(())
+    }
+}
+
+/// Rebuilds a pipeline from freshly compiled SPIR-V, one module per source in registration order
+pub type PipelineRebuild = Box<dyn Fn(&RenderContext, &[Vec<u32>]) -> Result<RenderPipeline, Box<dyn Error>>>;
+
+///
+/// Pipeline rebuilt by the graph when one of its shader sources changes
+///
+pub struct ReloadablePipeline {
+    pub sources: Vec<PathBuf>,
+    /// Sources and everything they include, refreshed after every successful reload
+    pub dependencies: Vec<PathBuf>,
+    pub rebuild: PipelineRebuild
+}
+
+/// State change of one resource before a pass, see [`RenderGraph::transition`]
+pub(crate) struct Transition {
+    pub resource: GraphResource,
+    pub previous: Vec<AccessType>,
+    pub next: Vec<AccessType>,
+    /// Previous contents are not needed, images start from `UNDEFINED`
+    pub discard: bool,
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
 }
 
 #[derive(Default)]
 pub struct RenderGraph {
     pub resources: RenderGraphResource,
-    pub nodes: HashMap<&'static str, Box<dyn Fn(&mut RenderGraphResource, &RenderContext, u32) -> Result<(), Box<dyn Error>>>>,
+    pub nodes: Vec<PassNode>,
+    /// Resources whose writers are never culled, the swapchain image always is one
+    pub outputs: Vec<GraphResource>,
+    /// Result of the last [`RenderGraph::compile`], `None` after the passes change
+    pub schedule: Option<Schedule>,
     pub sync: Vec<FrameSync>,
-    pub current_frame: usize
+    pub current_frame: usize,
+    pub profiler: Option<GpuProfiler>,
+    pub reloadable: HashMap<PipelineHandle, ReloadablePipeline>,
+    pub shader_watcher: Option<ShaderWatcher>,
+    /// Last accesses of every resource, barriers before a pass go from them to the pass accesses
+    pub resource_states: HashMap<GraphResource, Vec<AccessType>>,
+    pub swapchain_images: Vec<vk::Image>,
+    /// Image of a frame whose pass failed, the next frame renders into it instead of acquiring
+    pub unpresented: Option<u32>,
+    pub transients: Transients,
+    pub temporals: Temporals,
+    /// Set with [`RenderGraph::set_debug_view`]
+    pub debug_view: Option<DebugView>,
+    pub debug_renderer: Option<DebugViewRenderer>,
+    pub readbacks: Readbacks,
+    /// Turned off with [`RenderGraph::set_pass_enabled`]
+    pub disabled_passes: HashSet<Arc<str>>,
+    /// Turned on with [`RenderGraph::set_feature`]
+    pub features: HashSet<Arc<str>>,
+    /// Declared with [`RenderGraph::set_fallback`]
+    pub fallbacks: HashMap<GraphResource, GraphResource>,
+    /// Instances added with [`RenderGraph::add_sub_graph`]
+    pub sub_graphs: HashMap<Arc<str>, SubGraphInstance>,
 }
 
 impl RenderGraph {
@@ -25,101 +189,919 @@ impl RenderGraph {
         Self { ..Default::default() }
     }
 
-    pub fn register_render_pass(&mut self, name: &'static str, pass: RenderPass) {
-        self.resources.render_pass.insert(name, pass);
+    /// `name` is only used in logs, passes address resources by the returned handle
+    pub fn register_render_pass(&mut self, name: impl Into<Arc<str>>, pass: RenderPass) -> RenderPassHandle {
+        self.resources.render_pass.insert(name, pass)
+    }
+
+    pub fn register_command_pool(&mut self, name: impl Into<Arc<str>>, pool: CommandPool) -> CommandPoolHandle {
+        self.resources.command_pool.insert(name, pool)
+    }
+
+    pub fn register_buffer(&mut self, name: impl Into<Arc<str>>, buffer: GPUBuffer) -> BufferHandle {
+        self.resources.buffers.insert(name, buffer)
+    }
+
+    /// The image starts in `UNDEFINED` layout, the graph transitions it for every pass
+    pub fn register_image(&mut self, name: impl Into<Arc<str>>, image: GPUImage) -> ImageHandle {
+        self.resources.images.insert(name, image)
     }
 
-    pub fn register_command_pool(&mut self, name: &'static str, pool: CommandPool) {
-        self.resources.command_pool.insert(name, pool);
+    ///
+    /// Image the graph creates for the passes that use it
+    ///
+    /// Usage flags of the declared accesses are added to `desc`. The image exists only
+    /// between its first and last pass in the schedule and may share memory with other
+    /// transients, so its contents are not kept between frames.
+    ///
+    pub fn create_image(&mut self, name: impl Into<Arc<str>>, desc: ImageDesc) -> ImageHandle {
+        let handle = self.resources.images.reserve(name);
+        self.transients.add(GraphResource::Image(handle), TransientDesc::Image(desc));
+        self.schedule = None;
+        handle
     }
 
-    pub fn register_buffer(&mut self, name: &'static str, buffer: GPUBuffer) {
-        self.resources.buffers.insert(name, buffer);
+    /// Buffer the graph creates for the passes that use it, see [`RenderGraph::create_image`]
+    pub fn create_buffer(&mut self, name: impl Into<Arc<str>>, desc: BufferDesc) -> BufferHandle {
+        let handle = self.resources.buffers.reserve(name);
+        self.transients.add(GraphResource::Buffer(handle), TransientDesc::Buffer(desc));
+        self.schedule = None;
+        handle
     }
 
-    pub fn register_pipeline(&mut self, name: &'static str, pipeline: RenderPipeline) {
-        self.resources.pipeline.insert(name, pipeline);
+    ///
+    /// Image pair whose contents are kept between frames, see [`TemporalImage`]
+    ///
+    /// Created on the first frame a scheduled pass uses it and kept until the graph is
+    /// dropped. Requesting the same `name` again returns the same handles, a different
+    /// `desc`, e.g. after a resize, recreates both images and their contents are lost.
+    /// Usage flags of the declared accesses are added to `desc` as for transients.
+    ///
+    /// # Example:
+    ///
+    /// ```ignore
+    /// let taa = graph.get_or_create_temporal_image("TAA", desc);
+    ///
+    /// graph.add_pass("TAA")
+    ///     .read(color)
+    ///     .access(taa.history, AccessType::ComputeShaderReadSampledImage)
+    ///     .access(taa.current, AccessType::ComputeShaderWrite)
+    ///     .execute(move |res, ctx, _| {
+    ///         let reset = res.history_frames(taa.history) == 0;
+    ///         ...
+    ///     });
+    /// ```
+    ///
+    pub fn get_or_create_temporal_image(&mut self, name: impl Into<Arc<str>>, desc: ImageDesc) -> TemporalImage {
+        let name = name.into();
+
+        if let Some(temporal) = self.temporals.get(&name) {
+            let (GraphResource::Image(current), GraphResource::Image(history)) = (temporal.current, temporal.history) else {
+                panic!("Temporal resource {:?} is not an image", name);
+            };
+
+            if self.temporals.request(&name, TransientDesc::Image(desc)) {
+                self.schedule = None;
+            }
+            return TemporalImage { current, history };
+        }
+
+        let current = self.resources.images.reserve(name.clone());
+        let history = self.resources.images.reserve(name.clone());
+        self.temporals.add(name, GraphResource::Image(current), GraphResource::Image(history), TransientDesc::Image(desc));
+        self.schedule = None;
+
+        TemporalImage { current, history }
     }
 
-    pub fn add_raw_pass<F>(&mut self, name: &'static str, clojure: F)
+    /// Buffer pair whose contents are kept between frames, see [`RenderGraph::get_or_create_temporal_image`]
+    pub fn get_or_create_temporal_buffer(&mut self, name: impl Into<Arc<str>>, desc: BufferDesc) -> TemporalBuffer {
+        let name = name.into();
+
+        if let Some(temporal) = self.temporals.get(&name) {
+            let (GraphResource::Buffer(current), GraphResource::Buffer(history)) = (temporal.current, temporal.history) else {
+                panic!("Temporal resource {:?} is not a buffer", name);
+            };
+
+            if self.temporals.request(&name, TransientDesc::Buffer(desc)) {
+                self.schedule = None;
+            }
+            return TemporalBuffer { current, history };
+        }
+
+        let current = self.resources.buffers.reserve(name.clone());
+        let history = self.resources.buffers.reserve(name.clone());
+        self.temporals.add(name, GraphResource::Buffer(current), GraphResource::Buffer(history), TransientDesc::Buffer(desc));
+        self.schedule = None;
+
+        TemporalBuffer { current, history }
+    }
+
+    /// Call after the swapchain is recreated, e.g. on [`ExecuteError::OutOfDate`]
+    pub fn swapchain_recreated(&mut self) {
+        self.swapchain_images.clear();
+        self.unpresented = None;
+        self.resource_states.remove(&GraphResource::Swapchain);
+    }
+
+    pub fn register_pipeline(&mut self, name: impl Into<Arc<str>>, pipeline: RenderPipeline) -> PipelineHandle {
+        self.resources.pipeline.insert(name, pipeline)
+    }
+
+    /// The buffer must not be used by any frame in flight, handles to it become stale
+    pub fn remove_buffer(&mut self, handle: BufferHandle) -> Option<GPUBuffer> {
+        self.schedule = None;
+        self.transients.remove(GraphResource::Buffer(handle));
+        self.resources.buffers.remove(handle)
+    }
+
+    /// The image must not be used by any frame in flight, handles to it become stale
+    pub fn remove_image(&mut self, handle: ImageHandle) -> Option<GPUImage> {
+        self.schedule = None;
+        self.resource_states.remove(&GraphResource::Image(handle));
+        self.transients.remove(GraphResource::Image(handle));
+        self.resources.images.remove(handle)
+    }
+
+    /// The pipeline must not be used by any frame in flight, handles to it become stale
+    pub fn remove_pipeline(&mut self, handle: PipelineHandle) -> Option<RenderPipeline> {
+        self.reloadable.remove(&handle);
+        self.resources.pipeline.remove(handle)
+    }
+
+    ///
+    /// Rebuild the pipeline behind `handle` with `rebuild` whenever one of `sources` changes
+    ///
+    /// Takes effect after [`RenderGraph::enable_shader_hot_reload`]. If a source fails to
+    /// compile, the stages don't match or `rebuild` fails, the old pipeline is kept and the
+    /// error is logged.
+    /// The handle stays valid across reloads.
+    ///
+    pub fn watch_pipeline<F>(&mut self, handle: PipelineHandle, sources: &[impl AsRef<Path>], rebuild: F)
+        where F: Fn(&RenderContext, &[Vec<u32>]) -> Result<RenderPipeline, Box<dyn Error>> + 'static
+    {
+        let sources = sources.iter()
+            .map(|path| canonical(path.as_ref()))
+            .collect::<Vec<_>>();
+
+        let compiler = ShaderCompiler::new();
+        let dependencies = sources.iter()
+            .flat_map(|source| compiler.dependencies(source).unwrap_or_else(|_| vec![source.clone()]))
+            .map(|path| canonical(&path))
+            .collect();
+
+        self.reloadable.insert(handle, ReloadablePipeline { sources, dependencies, rebuild: Box::new(rebuild) });
+    }
+
+    /// Watch `dir` for changed shader sources and rebuild the pipelines that use them
+    pub fn enable_shader_hot_reload(&mut self, dir: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        self.shader_watcher = Some(ShaderWatcher::new(dir)?);
+        Ok(())
+    }
+
+    fn reload_shaders(&mut self, ctx: &RenderContext) -> Result<(), ExecuteError> {
+
+        let Some(watcher) = &self.shader_watcher else {
+            return Ok(());
+        };
+
+        let changed = watcher.poll();
+        if changed.is_empty() {
+            return Ok(());
+        }
+
+        let device = ctx.graphics_device.raw_device();
+        let mut gpu_idle = false;
+
+        let compiler = ShaderCompiler::new();
+
+        for (handle, reloadable) in &mut self.reloadable {
+
+            // Пайплайн удалили мимо remove_pipeline
+            let Some(name) = self.resources.pipeline.name(*handle).map(str::to_owned) else {
+                continue;
+            };
+
+            if !reloadable.dependencies.iter().any(|dependency| changed.contains(dependency)) {
+                continue;
+            }
+
+            let compiled = match reloadable.sources.iter().map(|source| compiler.compile_file(source)).collect::<Result<Vec<_>, _>>() {
+                Ok(compiled) => compiled,
+                Err(err) => {
+                    log::error!("Pipeline {:?} is not reloaded, {}", name, err);
+                    continue;
+                }
+            };
+
+            reloadable.dependencies = compiled.iter()
+                .flat_map(|shader| shader.dependencies.iter().map(|path| canonical(path)))
+                .collect();
+
+            let spv = compiled.into_iter().map(|shader| shader.spv).collect::<Vec<_>>();
+
+            let modules = spv.iter().map(|module| module.as_slice()).collect::<Vec<_>>();
+            if let Err(err) = PipelineReflection::from_spv(&modules) {
+                log::error!("Pipeline {:?} is not reloaded, {}", name, err);
+                continue;
+            }
+
+            let pipeline = match (reloadable.rebuild)(ctx, &spv) {
+                Ok(pipeline) => pipeline,
+                Err(err) => {
+                    log::error!("Pipeline {:?} is not reloaded, {}", name, err);
+                    continue;
+                }
+            };
+
+            // Старый пайплайн может использоваться любым кадром в полёте
+            if !gpu_idle {
+                let fences = self.sync.iter().map(|sync| sync.fence).collect::<Vec<_>>();
+                if let Err(err) = unsafe { device.wait_for_fences(&fences, true, u64::MAX) } {
+                    pipeline.destroy(device);
+                    return Err(err.into());
+                }
+                gpu_idle = true;
+            }
+
+            match self.resources.pipeline.get_mut(*handle) {
+                Some(slot) => std::mem::replace(slot, pipeline).destroy(device),
+                None => pipeline.destroy(device),
+            }
+
+            log::info!("Pipeline {:?} reloaded", name);
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Pass that doesn't declare its resources
+    ///
+    /// It is never culled and runs after the kept passes registered before it. Passes registered
+    /// after it are not ordered against it and may run earlier.
+    ///
+    pub fn add_raw_pass<F>(&mut self, name: impl Into<Arc<str>>, clojure: F)
         where F: Fn(&mut RenderGraphResource, &RenderContext, u32) -> Result<(), Box<dyn Error>> + 'static
     {
-        self.nodes.insert(name, Box::new(clojure));
+        let mut pass = self.add_pass(name).with_side_effects();
+        pass.desc.raw = true;
+        pass.execute(clojure);
+    }
+
+    /// Returns whether a pass named `name` was in the graph
+    pub fn remove_pass(&mut self, name: &str) -> bool {
+        let count = self.nodes.len();
+        self.nodes.retain(|pass| *pass.desc.name != *name);
+        self.schedule = None;
+        self.nodes.len() != count
+    }
+
+    /// Declare the resources of a new pass, see [`PassBuilder`]
+    pub fn add_pass(&mut self, name: impl Into<Arc<str>>) -> PassBuilder<'_> {
+        PassBuilder { graph: self, desc: PassDesc::new(name) }
+    }
+
+    pub(crate) fn insert_pass(&mut self, node: PassNode) {
+        // Пасс с тем же именем заменяется, как и раньше в HashMap
+        match self.nodes.iter_mut().find(|pass| pass.desc.name == node.desc.name) {
+            Some(pass) => *pass = node,
+            None => self.nodes.push(node),
+        }
+        self.schedule = None;
+    }
+
+    /// Take the passes named `names` out of the graph with their positions
+    pub(crate) fn take_passes(&mut self, names: &[Arc<str>]) -> Vec<(usize, PassNode)> {
+
+        let mut taken = Vec::new();
+        for (index, node) in std::mem::take(&mut self.nodes).into_iter().enumerate() {
+            match names.contains(&node.desc.name) {
+                true => taken.push((index, node)),
+                false => self.nodes.push(node),
+            }
+        }
+
+        self.schedule = None;
+        taken
+    }
+
+    /// Put passes from [`RenderGraph::take_passes`] back where they were
+    pub(crate) fn restore_passes(&mut self, taken: Vec<(usize, PassNode)>) {
+        for (index, node) in taken {
+            self.nodes.insert(index, node);
+        }
+        self.schedule = None;
+    }
+
+    /// Go back to a clone of [`RenderGraph::temporals`], slots of the temporals added since are removed
+    pub(crate) fn restore_temporals(&mut self, temporals: Temporals) {
+        for temporal in &self.temporals.resources {
+            if temporals.get(&temporal.name).is_some() {
+                continue;
+            }
+
+            // Слоты только зарезервированы, память temporals получают в execute
+            for resource in [temporal.current, temporal.history] {
+                match resource {
+                    GraphResource::Image(handle) => { self.resources.images.remove(handle); },
+                    GraphResource::Buffer(handle) => { self.resources.buffers.remove(handle); },
+                    GraphResource::Swapchain => {},
+                }
+            }
+        }
+
+        self.temporals = temporals;
+        self.schedule = None;
+    }
+
+    /// Keep the passes writing `resource` even if no pass reads it
+    pub fn mark_output(&mut self, resource: impl Into<GraphResource>) {
+        let resource = resource.into();
+        if !self.outputs.contains(&resource) {
+            self.outputs.push(resource);
+            self.schedule = None;
+        }
+    }
+
+    ///
+    /// Turn a pass off without removing it
+    ///
+    /// The graph is recompiled: passes that only fed the disabled one are culled and readers
+    /// of what it wrote get the fallback of [`RenderGraph::set_fallback`].
+    ///
+    pub fn set_pass_enabled(&mut self, name: &str, enabled: bool) {
+        let changed = match enabled {
+            true => self.disabled_passes.remove(name),
+            false => self.disabled_passes.insert(name.into()),
+        };
+
+        if changed {
+            self.schedule = None;
+        }
+    }
+
+    /// Turn on the passes declared with [`PassBuilder::with_feature`], off by default
+    pub fn set_feature(&mut self, feature: &str, enabled: bool) {
+        let changed = match enabled {
+            true => self.features.insert(feature.into()),
+            false => self.features.remove(feature),
+        };
+
+        if changed {
+            self.schedule = None;
+        }
+    }
+
+    pub fn is_feature_enabled(&self, feature: &str) -> bool {
+        self.features.contains(feature)
     }
 
-    pub fn compile(&mut self) {
+    /// Whether the pass runs, it still may be culled
+    pub fn is_pass_enabled(&self, name: &str) -> bool {
+        self.nodes.iter()
+            .find(|pass| *pass.desc.name == *name)
+            .is_some_and(|pass| self.is_active(&pass.desc))
+    }
 
+    fn is_active(&self, desc: &PassDesc) -> bool {
+        !self.disabled_passes.contains(&desc.name) && desc.feature.as_ref().is_none_or(|feature| self.features.contains(feature))
     }
 
-    pub fn execute(&mut self, ctx: &RenderContext) {
+    ///
+    /// Resource read instead of `resource` while every pass writing it is disabled
+    ///
+    /// E.g. a 1x1 white image for the SSAO output. Passes should get such resources with
+    /// [`RenderGraphResource::image`] and [`RenderGraphResource::buffer`], which follow the fallback.
+    ///
+    pub fn set_fallback(&mut self, resource: impl Into<GraphResource>, fallback: impl Into<GraphResource>) {
+        self.fallbacks.insert(resource.into(), fallback.into());
+        self.schedule = None;
+    }
 
-        for (name, func) in &self.nodes {
+    fn resource_name(&self, resource: GraphResource) -> String {
+        if self.temporals.is_history(resource) {
+            let name = self.temporals.find(resource).unwrap().name.clone();
+            return match resource {
+                GraphResource::Buffer(_) => format!("buffer {:?} history", name),
+                _ => format!("image {:?} history", name),
+            };
+        }
 
-            if self.sync.is_empty() {
-                let frame_count = ctx.window_manager.frame_buffers.raw.len();
-                for _ in 0..frame_count {
-                    self.sync.push(FrameSync::new(ctx.graphics_device.raw_device()));
+        match resource {
+            GraphResource::Buffer(handle) => format!("buffer {:?}", self.resources.buffers.name(handle).unwrap_or("<removed>")),
+            GraphResource::Image(handle) => format!("image {:?}", self.resources.images.name(handle).unwrap_or("<removed>")),
+            GraphResource::Swapchain => "swapchain image".into(),
+        }
+    }
+
+    ///
+    /// Order the passes by their resources and cull the ones that don't contribute to outputs
+    ///
+    /// Called by [`RenderGraph::execute`] after passes change, call it earlier to get the error.
+    ///
+    pub fn compile(&mut self) -> Result<&Schedule, GraphError> {
+
+        for pass in &self.nodes {
+            for resource in pass.desc.reads.iter().chain(&pass.desc.writes) {
+                let exists = match resource {
+                    // Транзиентные ресурсы создаются позже, их слоты только зарезервированы
+                    GraphResource::Buffer(handle) => self.resources.buffers.name(*handle).is_some(),
+                    GraphResource::Image(handle) => self.resources.images.name(*handle).is_some(),
+                    GraphResource::Swapchain => true,
+                };
+
+                if !exists {
+                    return Err(GraphError::StaleResource { pass: pass.desc.name.clone(), resource: self.resource_name(*resource) });
                 }
             }
 
-            let current_frame = self.current_frame;
-            let fence = self.sync[current_frame].fence;
-            let swapchain = &ctx.window_manager.swapchain;
-            let queue = ctx.graphics_device.universal_queue.raw_graphics();
+            for (resource, _) in &pass.desc.readbacks {
+                let reason = match resource {
+                    GraphResource::Swapchain => "the swapchain image is presented, not read back",
+                    _ if !pass.desc.accesses.iter().any(|(accessed, _)| accessed == resource) => "the pass doesn't access it",
+                    _ => continue,
+                };
+                return Err(GraphError::InvalidReadback { pass: pass.desc.name.clone(), resource: self.resource_name(*resource), reason });
+            }
+        }
+
+        let mut outputs = self.outputs.clone();
+        outputs.push(GraphResource::Swapchain);
+        // Следующий кадр читает их как историю
+        outputs.extend(self.temporals.currents());
+
+        let declared = self.nodes.iter().map(|pass| &pass.desc).collect::<Vec<_>>();
+        let active = declared.iter().map(|desc| self.is_active(desc)).collect::<Vec<_>>();
+        let (resolved, fallbacks) = resolve_inputs(&declared, &active, &self.fallbacks, &|resource| self.resource_name(resource))?;
+
+        let passes = resolved.iter().collect::<Vec<_>>();
+        let mut schedule = schedule(&passes, &outputs, &|resource| self.resource_name(resource))?;
+        schedule.disabled = (0..active.len()).filter(|index| !active[*index]).collect();
+
+        for index in &schedule.culled {
+            match active[*index] {
+                true => log::debug!("Pass {:?} is culled, nothing reads its outputs", self.nodes[*index].desc.name),
+                false => log::debug!("Pass {:?} is disabled", self.nodes[*index].desc.name),
+            }
+        }
+
+        self.transients.plan(&passes, &schedule.order);
+        self.temporals.plan(&passes, &schedule.order);
+        self.plan_debug_view();
+        self.plan_readbacks(&passes, &schedule.order);
+
+        schedule.passes = resolved;
+        self.resources.fallbacks = fallbacks;
+
+        Ok(self.schedule.insert(schedule))
+    }
+
+    /// Usage of the command allocator of every frame in flight
+    pub fn command_allocator_stats(&self) -> Vec<CommandAllocatorStats> {
+        self.resources.command_allocators.iter().map(|allocator| allocator.stats()).collect()
+    }
+
+    /// GPU time of every pass in the most recently resolved frame
+    pub fn gpu_timings(&self) -> Option<&GpuFrameTimings> {
+        self.profiler.as_ref()?.last_frame()
+    }
+
+    ///
+    /// Pipeline statistics of the most recently resolved frame
+    ///
+    /// One entry per command buffer from [`RenderGraphResource::begin_pass_commands`], named
+    /// after its pass, and the queries passes record themselves.
+    ///
+    pub fn pipeline_statistics(&self) -> &[PipelineStatistics] {
+        self.resources.queries.as_ref().map(|queries| queries.last_statistics()).unwrap_or_default()
+    }
+
+    /// Occlusion results recorded by passes in the most recently resolved frame
+    pub fn occlusion_results(&self) -> &[OcclusionResult] {
+        self.resources.queries.as_ref().map(|queries| queries.last_occlusion()).unwrap_or_default()
+    }
+
+    /// Write the recorded pass timings in the Chrome trace format
+    pub fn write_chrome_trace(&self, path: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+        match &self.profiler {
+            Some(profiler) => profiler.write_chrome_trace(path),
+            None => std::fs::write(path, "{\"traceEvents\":[]}")
+        }
+    }
+
+    /// Usage of the per-thread command pools of every frame in flight
+    pub fn thread_command_pools_stats(&self) -> Vec<CommandAllocatorStats> {
+        self.resources.thread_command_pools.iter().map(|pools| pools.stats()).collect()
+    }
+
+    fn begin_graph_commands(resources: &mut RenderGraphResource, device: &ash::Device) -> Result<CommandBuffer, vk::Result> {
+
+        let command_buffer = resources.submit_command_buffer(device);
+        let begin_info = vk::CommandBufferBeginInfo::default()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+
+        unsafe { device.begin_command_buffer(command_buffer, &begin_info)? };
+        Ok(command_buffer)
+    }
+
+    /// Usage flags for an access the graph itself makes outside of the passes, call on compile
+    pub(crate) fn note_extra_access(&mut self, resource: GraphResource, access: AccessType) {
+        self.transients.add_usage(resource, access);
+        self.temporals.add_usage(resource, access);
+    }
+
+    /// Make `access`, recorded after a pass, the last access of `resource`
+    pub(crate) fn note_recorded_access(
+        states: &mut HashMap<GraphResource, Vec<AccessType>>,
+        transients: &mut Transients,
+        resource: GraphResource,
+        access: AccessType
+    ) {
+        // Память транзиента может перейти к следующему владельцу, он должен дождаться этого доступа
+        if let Some(block) = transients.get(resource).and_then(|transient| transient.block) {
+            transients.blocks[block].last_accesses = vec![access];
+        }
+        states.insert(resource, vec![access]);
+    }
+
+    fn swapchain_range() -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange::default()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .level_count(1)
+            .layer_count(1)
+    }
+
+    ///
+    /// Accesses `resource` waits for before the pass at `position` and whether its contents are discarded
+    ///
+    /// Moves the state of the resource and of its memory block to the pass accesses.
+    /// Shared by [`RenderGraph::execute`] and [`RenderGraph::snapshot`].
+    ///
+    pub(crate) fn transition(
+        states: &mut HashMap<GraphResource, Vec<AccessType>>,
+        transients: &mut Transients,
+        desc: &PassDesc,
+        resource: GraphResource,
+        position: usize
+    ) -> Transition {
+        let next = desc.accesses_of(resource);
+
+        // Первое использование транзиента ждёт прошлого владельца его памяти, содержимое не нужно
+        let (previous, discard) = match transients.first_use(resource, position) {
+            Some(block) => (transients.blocks[block].last_accesses.clone(), true),
+            None => match (resource, states.get(&resource)) {
+                (GraphResource::Swapchain, None) => (vec![AccessType::Present], true),
+                (GraphResource::Swapchain, Some(previous)) => (previous.clone(), false),
+                (_, previous) => {
+                    let previous = previous.cloned().unwrap_or_default();
+                    let discard = previous.is_empty();
+                    (previous, discard)
+                },
+            },
+        };
+
+        if let Some(block) = transients.get(resource).and_then(|transient| transient.block) {
+            transients.blocks[block].last_accesses = next.clone();
+        }
+        states.insert(resource, next.clone());
+
+        Transition { resource, previous, next, discard }
+    }
+
+    /// Add the barrier of `transition` on `image` or `buffer`
+    pub(crate) fn add_barrier(
+        barriers: &mut BarrierBatch,
+        transition: &Transition,
+        image: vk::Image,
+        range: vk::ImageSubresourceRange,
+        buffer: vk::Buffer
+    ) {
+        let Transition { resource, previous, next, discard } = transition;
+        match resource {
+            GraphResource::Buffer(_) => barriers.add_buffer(buffer, previous, next),
+            GraphResource::Image(_) => barriers.add_image(image, range, previous, next, *discard),
+            GraphResource::Swapchain => {
+                barriers.add_image(image, range, previous, next, *discard);
+                if *discard {
+                    // Ожидание image_available стоит на COLOR_ATTACHMENT_OUTPUT,
+                    // барьер должен начинаться с той же стадии, чтобы встать в цепочку за ним
+                    barriers.src_stage |= vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT;
+                }
+            },
+        }
+    }
+
+    ///
+    /// Barriers from the last accesses of the pass resources to the pass accesses
+    ///
+    /// All resources of a pass go into one `vkCmdPipelineBarrier`.
+    ///
+    #[allow(clippy::too_many_arguments)]
+    fn record_barriers(
+        resources: &RenderGraphResource,
+        states: &mut HashMap<GraphResource, Vec<AccessType>>,
+        transients: &mut Transients,
+        desc: &PassDesc,
+        position: usize,
+        swapchain_image: vk::Image,
+        device: &ash::Device,
+        command_buffer: CommandBuffer
+    ) {
+        let mut barriers = BarrierBatch::new();
+
+        for resource in desc.resources() {
+            let transition = Self::transition(states, transients, desc, resource, position);
+
+            match resource {
+                GraphResource::Buffer(handle) => if let Some(buffer) = resources.buffers.get(handle) {
+                    Self::add_barrier(&mut barriers, &transition, vk::Image::null(), Default::default(), buffer.raw);
+                },
+                GraphResource::Image(handle) => if let Some(image) = resources.images.get(handle) {
+                    Self::add_barrier(&mut barriers, &transition, image.raw, image.subresource_range(), vk::Buffer::null());
+                },
+                GraphResource::Swapchain => {
+                    Self::add_barrier(&mut barriers, &transition, swapchain_image, Self::swapchain_range(), vk::Buffer::null());
+                },
+            }
+        }
+
+        barriers.record(device, command_buffer);
+    }
+
+    ///
+    /// Record and submit the scheduled passes and present the frame
+    ///
+    /// See [`ExecuteError`] for what can be recovered from.
+    ///
+    pub fn execute(&mut self, ctx: &RenderContext) -> Result<(), ExecuteError> {
+
+        if self.sync.is_empty() {
+            let frame_count = ctx.window_manager.frame_buffers.raw.len();
+
+            // Пулы запросов создаём первыми: пока sync пуст, неудачный вызов можно повторить
+            let queries = GpuQueriesBuilder::new()
+                .with_device(ctx.graphics_device.raw_device())
+                .with_features(&ctx.graphics_device.device.features)
+                .with_frames_in_flight(frame_count)
+                .build()
+                .map_err(ExecuteError::Fatal)?;
+            self.resources.queries = Some(queries);
+
+            for _ in 0..frame_count {
+                self.sync.push(FrameSync::new(ctx.graphics_device.raw_device()));
+                self.resources.command_allocators.push(
+                    CommandAllocatorBuilder::new()
+                        .device(ctx.graphics_device.raw_device())
+                        .family_index(ctx.graphics_device.universal_queue.graphics_index())
+                        .build()
+                );
+                self.resources.thread_command_pools.push(
+                    ThreadCommandPoolsBuilder::new()
+                        .device(ctx.graphics_device.raw_device())
+                        .family_index(ctx.graphics_device.universal_queue.graphics_index())
+                        .build()
+                );
+            }
+
+            self.profiler = Some(
+                GpuProfilerBuilder::new()
+                    .with_device(ctx.graphics_device.raw_device())
+                    .with_phys_info(&ctx.graphics_device.phys_dev.phys_info)
+                    .with_family_index(ctx.graphics_device.universal_queue.graphics_index())
+                    .with_frames_in_flight(frame_count)
+                    .build()
+            );
+        }
+
+        if self.swapchain_images.is_empty() {
+            self.swapchain_images = ctx.window_manager.swapchain.get_swapchain_images();
+        }
+
+        if self.schedule.is_none() {
+            self.compile()?;
+        }
+
+        if !self.transients.allocated {
             let device = ctx.graphics_device.raw_device();
-            let sync = &self.sync;
 
-            // 2. Дождаться завершения предыдущего кадра
-            unsafe {
-                device.wait_for_fences(&[fence], true, u64::MAX).unwrap();
-                device.reset_fences(&[fence]).unwrap();
+            // Старые транзиенты могут использоваться любым кадром в полёте
+            let fences = self.sync.iter().map(|sync| sync.fence).collect::<Vec<_>>();
+            unsafe { device.wait_for_fences(&fences, true, u64::MAX)? };
+
+            self.transients.release(device, &mut self.resources.images, &mut self.resources.buffers);
+            for transient in &self.transients.resources {
+                self.resource_states.remove(&transient.resource);
             }
 
-            // 3. Получить новое изображение из swapchain
-            let (image_index, _) = unsafe {
+            let memory_prop = &ctx.graphics_device.phys_dev.phys_info.memory_prop;
+            if let Err(err) = self.transients.allocate(device, memory_prop, &mut self.resources.images, &mut self.resources.buffers) {
+                self.transients.release(device, &mut self.resources.images, &mut self.resources.buffers);
+                return Err(err.into());
+            }
+        }
+
+        if !self.temporals.allocated {
+            let device = ctx.graphics_device.raw_device();
+
+            let fences = self.sync.iter().map(|sync| sync.fence).collect::<Vec<_>>();
+            unsafe { device.wait_for_fences(&fences, true, u64::MAX)? };
+
+            let memory_prop = &ctx.graphics_device.phys_dev.phys_info.memory_prop;
+            let recreated = self.temporals.allocate(device, memory_prop, &mut self.resources.images, &mut self.resources.buffers)?;
+            for resource in recreated {
+                self.resource_states.remove(&resource);
+            }
+            self.resources.history_frames = self.temporals.history_frames();
+        }
+
+        let debug_capture = self.prepare_debug_view(ctx)?;
+
+        self.reload_shaders(ctx)?;
+
+        let current_frame = self.current_frame;
+        let fence = self.sync[current_frame].fence;
+        let swapchain = &ctx.window_manager.swapchain;
+        let queue = ctx.graphics_device.universal_queue.raw_graphics();
+        let device = ctx.graphics_device.raw_device();
+        let sync = &self.sync;
+
+        // 1. Дождаться завершения кадра, который использовал эти ресурсы
+        unsafe { device.wait_for_fences(&[fence], true, u64::MAX)? };
+        self.readbacks.begin_frame(device, current_frame)?;
+
+        // 2. Получить новое изображение из swapchain, если прошлый кадр не оставил своё
+        let reused = self.unpresented.take();
+        let image_index = match reused {
+            Some(image_index) => image_index,
+            None => unsafe {
                 swapchain.swapchain_load.acquire_next_image(
                     swapchain.raw,
                     u64::MAX,
                     sync[current_frame].image_available,
                     vk::Fence::null(),
-                )
-            }.unwrap();
+                )?.0
+            },
+        };
+
+        // Забор сбрасывается только когда кадр точно будет отправлен, иначе следующее ожидание зависнет
+        unsafe { device.reset_fences(&[fence])? };
+
+        // 3. Командные буферы этого кадра больше не используются GPU
+        self.resources.current_frame = current_frame;
+        self.resources.command_buffers.clear();
+        self.resources.command_allocator().reset(device);
+        self.resources.thread_command_pools[current_frame].reset(device);
+
+        // 4. Выполнить рендер-пассы, между ними записываются метки времени
+        let profiler = self.profiler.as_mut().unwrap();
+        let mut command_buffer = Self::begin_graph_commands(&mut self.resources, device)?;
+        profiler.begin_frame(device, current_frame, command_buffer);
+        self.resources.queries().begin_frame(device, current_frame, command_buffer);
 
-            // 4. Выполнить рендер-пассы (теперь безопасно)
-            if let Err(err) = func(&mut self.resources, ctx, image_index) {
-                log::error!("Error in {:?} pass: {:?}", name, err);
+        let schedule = self.schedule.as_ref().unwrap();
+        let order = &schedule.order;
+        let swapchain_image = self.swapchain_images[image_index as usize];
+        let mut failed = None;
+
+        // Изображение swapchain каждый кадр приходит после present
+        self.resource_states.remove(&GraphResource::Swapchain);
+
+        for (position, index) in order.iter().enumerate() {
+            let node = &self.nodes[*index];
+
+            let name = node.desc.name.clone();
+            let scope = profiler.begin_scope(device, command_buffer, &name);
+            Self::record_barriers(
+                &self.resources,
+                &mut self.resource_states,
+                &mut self.transients,
+                &schedule.passes[*index],
+                position,
+                swapchain_image,
+                device,
+                command_buffer
+            );
+            unsafe { device.end_command_buffer(command_buffer)? };
+
+            let submitted = self.resources.command_buffers.len();
+            self.resources.current_pass = Some(name.clone());
+            let result = (node.func)(&mut self.resources, ctx, image_index);
+            self.resources.current_pass = None;
+
+            match result {
+                Ok(()) => self.resources.end_pass_commands(device)?,
+                Err(err) => {
+                    // Командные буферы упавшего пасса могут быть не закончены, их не отправляем
+                    self.resources.pass_commands.clear();
+                    self.resources.command_buffers.truncate(submitted);
+                    failed = Some(ExecuteError::PassFailed { pass: name, error: err });
+                },
             }
 
-            // 5. Отправить команды в очередь
-            let binding1 = [sync[current_frame].image_available];
-            let binding2 = [sync[current_frame].render_finished];
+            command_buffer = Self::begin_graph_commands(&mut self.resources, device)?;
+            profiler.end_scope(device, command_buffer, scope);
 
-            let submit_info = vk::SubmitInfo::default()
-                .wait_semaphores(&binding1)
-                .wait_dst_stage_mask(&[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT])
-                .command_buffers(&self.resources.command_buffers)
-                .signal_semaphores(&binding2);
+            // Копия снимается сразу, следующие пассы могут перезаписать выход или его память
+            if let Some((pass, handle)) = debug_capture
+                && *index == pass
+                && failed.is_none()
+                && let (Some(renderer), Some(view), Some(source)) = (&mut self.debug_renderer, &self.debug_view, self.resources.images.get(handle))
+            {
+                renderer.capture(
+                    device,
+                    command_buffer,
+                    current_frame,
+                    view,
+                    GraphResource::Image(handle),
+                    source,
+                    &mut self.resource_states,
+                    &mut self.transients
+                );
+            }
 
-            unsafe {
-                device.queue_submit(queue, &[submit_info], fence).unwrap();
+            if failed.is_some() {
+                break;
             }
 
-            // 6. Представить изображение
-            let binding1 = [sync[current_frame].render_finished];
-            let binding2 = [swapchain.raw];
-            let binding3 = [image_index];
+            let memory_prop = &ctx.graphics_device.phys_dev.phys_info.memory_prop;
+            for (resource, readback) in &schedule.passes[*index].readbacks {
+                self.readbacks.copy(
+                    device,
+                    memory_prop,
+                    command_buffer,
+                    *readback,
+                    *resource,
+                    &self.resources,
+                    &mut self.resource_states,
+                    &mut self.transients
+                );
+            }
+        }
 
-            let present_info = vk::PresentInfoKHR::default()
-                .wait_semaphores(&binding1)
-                .swapchains(&binding2)
-                .image_indices(&binding3);
+        // Сырые пассы не объявляют swapchain, но рисуют в него через render pass окна
+        let mut previous = self.resource_states.get(&GraphResource::Swapchain)
+            .cloned()
+            .unwrap_or(vec![AccessType::ColorAttachmentWrite]);
 
-            unsafe {
-                swapchain.swapchain_load.queue_present(queue, &present_info).unwrap();
+        if let (Some(renderer), Some(_), None) = (&mut self.debug_renderer, &self.debug_view, &failed) {
+            let extent = ctx.window_manager.caps.current_extent;
+            if let Some(access) = renderer.blit(device, command_buffer, swapchain_image, Self::swapchain_range(), extent, &previous) {
+                previous = vec![access];
             }
+        }
+
+        let mut barriers = BarrierBatch::new();
+        barriers.add_image(swapchain_image, Self::swapchain_range(), &previous, &[AccessType::Present], false);
+        barriers.record(device, command_buffer);
+
+        unsafe { device.end_command_buffer(command_buffer)? };
+
+        // 5. Отправить команды в очередь, забор должен сработать даже если кадр не будет показан
+        let wait_semaphores = match reused {
+            Some(_) => vec![],
+            None => vec![sync[current_frame].image_available],
+        };
+        let wait_stages = vec![vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT; wait_semaphores.len()];
+
+        // Семафор, который никто не ждёт, нельзя сигналить повторно
+        let signal_semaphores = match failed {
+            Some(_) => vec![],
+            None => vec![sync[current_frame].render_finished],
+        };
+
+        let submit_info = vk::SubmitInfo::default()
+            .wait_semaphores(&wait_semaphores)
+            .wait_dst_stage_mask(&wait_stages)
+            .command_buffers(&self.resources.command_buffers)
+            .signal_semaphores(&signal_semaphores);
+
+        unsafe { device.queue_submit(queue, &[submit_info], fence)? };
 
-            self.current_frame = (current_frame + 1) % self.sync.len();
+        self.current_frame = (current_frame + 1) % self.sync.len();
+        self.readbacks.end_frame();
+
+        if let Some(err) = failed {
+            // История не меняется, следующий кадр повторит этот
+            self.unpresented = Some(image_index);
+            return Err(err);
         }
+
+        self.temporals.swap(&mut self.resources.images, &mut self.resources.buffers, &mut self.resource_states);
+        self.resources.history_frames = self.temporals.history_frames();
+
+        // 6. Представить изображение
+        let binding1 = [sync[current_frame].render_finished];
+        let binding2 = [swapchain.raw];
+        let binding3 = [image_index];
+
+        let present_info = vk::PresentInfoKHR::default()
+            .wait_semaphores(&binding1)
+            .swapchains(&binding2)
+            .image_indices(&binding3);
+
+        unsafe { swapchain.swapchain_load.queue_present(queue, &present_info)? };
+
+        Ok(())
     }
 }
-
@@ -0,0 +1,211 @@
+use std::{error::Error, sync::Arc};
+
+use fujiya_render::{AccessType, RenderContext};
+
+use crate::{BufferHandle, ImageHandle, ReadbackHandle, RenderGraph, RenderGraphResource};
+
+/// Records the commands of one pass, `u32` is the index of the acquired swapchain image
+pub type PassFn = Box<dyn Fn(&mut RenderGraphResource, &RenderContext, u32) -> Result<(), Box<dyn Error>>>;
+
+///
+/// Resource a pass can read or write
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GraphResource {
+    Buffer(BufferHandle),
+    Image(ImageHandle),
+    /// Image acquired from the swapchain this frame
+    Swapchain,
+}
+
+impl GraphResource {
+
+    /// Access of [`PassBuilder::read`]
+    pub fn default_read(self) -> AccessType {
+        match self {
+            Self::Buffer(_) => AccessType::AnyShaderReadOther,
+            Self::Image(_) => AccessType::AnyShaderReadSampledImage,
+            Self::Swapchain => AccessType::ColorAttachmentRead,
+        }
+    }
+
+    /// Access of [`PassBuilder::write`]
+    pub fn default_write(self) -> AccessType {
+        match self {
+            Self::Buffer(_) => AccessType::AnyShaderWrite,
+            Self::Image(_) | Self::Swapchain => AccessType::ColorAttachmentWrite,
+        }
+    }
+}
+
+impl From<BufferHandle> for GraphResource {
+    fn from(handle: BufferHandle) -> Self {
+        Self::Buffer(handle)
+    }
+}
+
+impl From<ImageHandle> for GraphResource {
+    fn from(handle: ImageHandle) -> Self {
+        Self::Image(handle)
+    }
+}
+
+///
+/// What a pass reads and writes, used by [`RenderGraph::compile`] to order and cull passes
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PassDesc {
+    pub name: Arc<str>,
+    pub reads: Vec<GraphResource>,
+    pub writes: Vec<GraphResource>,
+    /// How the resources are used, barriers before the pass are derived from it
+    pub accesses: Vec<(GraphResource, AccessType)>,
+    /// Never culled, e.g. readbacks or passes that don't declare their resources
+    pub side_effects: bool,
+    /// Declares no resources, runs after the kept passes registered before it, see [`RenderGraph::add_raw_pass`]
+    pub raw: bool,
+    /// Runs only while the feature is on, see [`RenderGraph::set_feature`]
+    pub feature: Option<Arc<str>>,
+    /// Copied to host memory after the pass, see [`PassBuilder::readback`]
+    pub readbacks: Vec<(GraphResource, ReadbackHandle)>,
+}
+
+impl PassDesc {
+
+    pub fn new(name: impl Into<Arc<str>>) -> Self {
+        Self { name: name.into(), ..Default::default() }
+    }
+
+    pub fn reads(&self, resource: GraphResource) -> bool {
+        self.reads.contains(&resource)
+    }
+
+    pub fn writes(&self, resource: GraphResource) -> bool {
+        self.writes.contains(&resource)
+    }
+
+    /// Every access of `resource` in this pass
+    pub fn accesses_of(&self, resource: GraphResource) -> Vec<AccessType> {
+        self.accesses.iter()
+            .filter(|(accessed, _)| *accessed == resource)
+            .map(|(_, access)| *access)
+            .collect()
+    }
+
+    /// Accessed resources in the order of their first access
+    pub fn resources(&self) -> Vec<GraphResource> {
+        let mut resources = vec![];
+        for (resource, _) in &self.accesses {
+            if !resources.contains(resource) {
+                resources.push(*resource);
+            }
+        }
+        resources
+    }
+}
+
+pub struct PassNode {
+    pub desc: PassDesc,
+    pub func: PassFn,
+}
+
+///
+/// Declares the resources of a pass, returned by [`RenderGraph::add_pass`]
+///
+/// # Example:
+///
+/// ```ignore
+/// graph.add_pass("Scene")
+///     .access(vertex_buffer, AccessType::VertexBuffer)
+///     .access(index_buffer, AccessType::IndexBuffer)
+///     .read(shadow_map)
+///     .write(GraphResource::Swapchain)
+///     .execute(move |res, ctx, image_index| {
+///         let buffer = res.buffers.try_get(vertex_buffer)?;
+///         ...
+///         Ok(())
+///     });
+/// ```
+///
+pub struct PassBuilder<'g> {
+    pub(crate) graph: &'g mut RenderGraph,
+    pub(crate) desc: PassDesc,
+}
+
+impl PassBuilder<'_> {
+
+    ///
+    /// Use `resource` as `access`, the graph inserts barriers and layout transitions for it
+    ///
+    /// Accesses that write count as writes for scheduling, the rest as reads,
+    /// [`AccessType::ColorAttachmentReadWrite`] and [`AccessType::General`] as both.
+    ///
+    pub fn access(mut self, resource: impl Into<GraphResource>, access: AccessType) -> Self {
+        let resource = resource.into();
+
+        if access.is_read() && !self.desc.reads.contains(&resource) {
+            self.desc.reads.push(resource);
+        }
+
+        if access.is_write() && !self.desc.writes.contains(&resource) {
+            self.desc.writes.push(resource);
+        }
+
+        if !self.desc.accesses.contains(&(resource, access)) {
+            self.desc.accesses.push((resource, access));
+        }
+        self
+    }
+
+    /// Read from any shader, or as a color attachment for the swapchain image
+    pub fn read(self, resource: impl Into<GraphResource>) -> Self {
+        let resource = resource.into();
+        self.access(resource, resource.default_read())
+    }
+
+    /// Write from any shader, or as a color attachment for images
+    pub fn write(self, resource: impl Into<GraphResource>) -> Self {
+        let resource = resource.into();
+        self.access(resource, resource.default_write())
+    }
+
+    /// Read the previous contents and write over them, e.g. UI drawn on top of the scene
+    pub fn read_write(self, resource: impl Into<GraphResource>) -> Self {
+        let resource = resource.into();
+        match resource {
+            GraphResource::Buffer(_) => self.read(resource).write(resource),
+            GraphResource::Image(_) | GraphResource::Swapchain => self.access(resource, AccessType::ColorAttachmentReadWrite),
+        }
+    }
+
+    /// Keep the pass even if nothing reads its outputs
+    pub fn with_side_effects(mut self) -> Self {
+        self.desc.side_effects = true;
+        self
+    }
+
+    /// Run only while `feature` is on, e.g. `"ssao"` on high settings
+    pub fn with_feature(mut self, feature: impl Into<Arc<str>>) -> Self {
+        self.desc.feature = Some(feature.into());
+        self
+    }
+
+    ///
+    /// Copy `resource` to host memory after the pass, see [`RenderGraph::create_readback`]
+    ///
+    /// The pass has to access `resource` and it can't be the swapchain image, otherwise
+    /// [`RenderGraph::compile`] fails. The pass is never culled. Images and buffers the graph
+    /// creates get `TRANSFER_SRC` usage, registered ones must have it.
+    ///
+    pub fn readback(mut self, resource: impl Into<GraphResource>, readback: ReadbackHandle) -> Self {
+        self.desc.readbacks.push((resource.into(), readback));
+        self.desc.side_effects = true;
+        self
+    }
+
+    pub fn execute<F>(self, func: F)
+        where F: Fn(&mut RenderGraphResource, &RenderContext, u32) -> Result<(), Box<dyn Error>> + 'static
+    {
+        self.graph.insert_pass(PassNode { desc: self.desc, func: Box::new(func) });
+    }
+}
@@ -0,0 +1,374 @@
+use std::{collections::HashMap, sync::Arc};
+
+use ash::vk::{self, CommandBuffer, PhysicalDeviceMemoryProperties};
+use fujiya_render::{AccessType, BarrierBatch, GPUBuffer};
+
+use crate::{GraphResource, Handle, PassDesc, Pool, RenderGraph, RenderGraphResource, Transients};
+
+pub type ReadbackHandle = Handle<Readback>;
+
+/// Called with every delivered copy, see [`RenderGraph::on_readback`]
+pub type ReadbackCallback = Box<dyn FnMut(&ReadbackData)>;
+
+///
+/// Contents of a resource as a pass left it, delivered a few frames later
+///
+/// Images are mip 0 with tightly packed rows.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadbackData {
+    pub data: Vec<u8>,
+    /// Frame the copy was recorded in, counted by the readbacks from the first execute
+    pub frame: u64,
+    /// Format and size of an image, `None` for buffers
+    pub format: Option<vk::Format>,
+    pub extent: Option<vk::Extent2D>,
+}
+
+///
+/// Copy recorded into a staging buffer and not delivered yet
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PendingReadback {
+    pub frame: u64,
+    pub size: u64,
+    pub format: Option<vk::Format>,
+    pub extent: Option<vk::Extent2D>,
+}
+
+///
+/// Host-visible copies of a resource, one per frame in flight
+///
+/// The copy recorded in a frame is read after the fence of that frame, which the graph
+/// waits for anyway before reusing its command buffers, so reading back never stalls.
+///
+#[derive(Default)]
+pub struct Readback {
+    /// Created on the first copy into the slot and recreated when the source grows
+    pub staging: Vec<Option<GPUBuffer>>,
+    pub pending: Vec<Option<PendingReadback>>,
+    /// Last delivered copy, see [`RenderGraph::take_readback`]
+    pub latest: Option<ReadbackData>,
+    pub callback: Option<ReadbackCallback>,
+}
+
+impl Readback {
+
+    /// None of the frames that used it may be in flight
+    pub fn destroy(&mut self, device: &ash::Device) {
+        for mut staging in self.staging.drain(..).flatten() {
+            staging.destroy(device);
+        }
+        self.pending.clear();
+    }
+}
+
+///
+/// Readbacks of a graph
+///
+/// [`Readbacks::begin_frame`] runs after the fence of the frame is waited for,
+/// [`Readbacks::copy`] after every pass that declares a readback and
+/// [`Readbacks::end_frame`] once the frame is submitted.
+///
+#[derive(Default)]
+pub struct Readbacks {
+    pub pool: Pool<Readback>,
+    /// Frames submitted since the first execute, the number of the frame being recorded
+    pub frame: u64,
+}
+
+impl Readbacks {
+
+    pub fn new() -> Self {
+        Self { ..Default::default() }
+    }
+
+    ///
+    /// Deliver the copies recorded the last time `slot` was used
+    ///
+    /// The fence of `slot` must be signaled.
+    ///
+    pub fn begin_frame(&mut self, device: &ash::Device, slot: usize) -> Result<(), vk::Result> {
+
+        for (_, readback) in self.pool.iter_mut() {
+            let Some(Some(pending)) = readback.pending.get(slot).copied() else {
+                continue;
+            };
+            let Some(Some(staging)) = readback.staging.get(slot) else {
+                continue;
+            };
+
+            // Копия остаётся в очереди, если отобразить память не получилось
+            let data = unsafe {
+                let ptr = device.map_memory(staging.memory, 0, pending.size, vk::MemoryMapFlags::empty())?;
+                let data = std::slice::from_raw_parts(ptr as *const u8, pending.size as usize).to_vec();
+                device.unmap_memory(staging.memory);
+                data
+            };
+            readback.pending[slot] = None;
+
+            let data = ReadbackData { data, frame: pending.frame, format: pending.format, extent: pending.extent };
+            if let Some(callback) = &mut readback.callback {
+                callback(&data);
+            }
+            readback.latest = Some(data);
+        }
+
+        Ok(())
+    }
+
+    /// The frame was submitted, copies recorded from now on belong to the next one
+    pub fn end_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    ///
+    /// Copy `resource` into the staging buffer of the frame being recorded
+    ///
+    /// A readback that can't be copied is skipped with a message, the frame goes on.
+    ///
+    #[allow(clippy::too_many_arguments)]
+    pub fn copy(
+        &mut self,
+        device: &ash::Device,
+        memory_prop: &PhysicalDeviceMemoryProperties,
+        command_buffer: CommandBuffer,
+        handle: ReadbackHandle,
+        resource: GraphResource,
+        resources: &RenderGraphResource,
+        states: &mut HashMap<GraphResource, Vec<AccessType>>,
+        transients: &mut Transients
+    ) {
+        let slot = resources.current_frame;
+        let name = self.pool.name(handle).unwrap_or("<removed>").to_owned();
+        let Some(readback) = self.pool.get_mut(handle) else {
+            return;
+        };
+
+        let (raw, pending) = match resource {
+            GraphResource::Buffer(buffer) => {
+                let Some(buffer) = resources.buffers.get(buffer) else { return };
+                (None, PendingReadback { frame: self.frame, size: buffer.size, format: None, extent: None })
+            },
+            GraphResource::Image(image) => {
+                let Some(image) = resources.images.get(image) else { return };
+                let Some(texel) = texel_size(image.desc.format) else {
+                    log::warn!("Readback {:?} is skipped, {:?} images can't be copied to a buffer", name, image.desc.format);
+                    return;
+                };
+
+                let extent = image.desc.extent;
+                let size = extent.width as u64 * extent.height as u64 * texel;
+                (Some(image), PendingReadback { frame: self.frame, size, format: Some(image.desc.format), extent: Some(extent) })
+            },
+            GraphResource::Swapchain => return,
+        };
+
+        if readback.staging.len() <= slot {
+            readback.staging.resize_with(slot + 1, || None);
+            readback.pending.resize(slot + 1, None);
+        }
+
+        // Забор этого слота уже дождались, старый буфер GPU не использует
+        if readback.staging[slot].as_ref().is_none_or(|staging| staging.size < pending.size) {
+            if let Some(mut staging) = readback.staging[slot].take() {
+                staging.destroy(device);
+            }
+
+            let memory_flags = vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
+            match GPUBuffer::new(device, memory_prop, pending.size, vk::BufferUsageFlags::TRANSFER_DST, memory_flags) {
+                Ok(staging) => readback.staging[slot] = Some(staging),
+                Err(err) => {
+                    log::error!("Readback {:?} is skipped, {}", name, err);
+                    return;
+                }
+            }
+        }
+        let staging = readback.staging[slot].as_ref().unwrap();
+
+        let read = AccessType::TransferRead;
+        let previous = states.get(&resource).cloned().unwrap_or_default();
+
+        let mut barriers = BarrierBatch::new();
+        match (resource, raw) {
+            (GraphResource::Image(_), Some(image)) => barriers.add_image(image.raw, image.subresource_range(), &previous, &[read], previous.is_empty()),
+            (GraphResource::Buffer(buffer), _) => barriers.add_buffer(resources.buffers[buffer].raw, &previous, &[read]),
+            _ => {},
+        }
+        barriers.record(device, command_buffer);
+
+        unsafe {
+            match (resource, raw) {
+                (GraphResource::Image(_), Some(image)) => {
+                    let aspect = match image.desc.aspect() {
+                        aspect if aspect.contains(vk::ImageAspectFlags::DEPTH) => vk::ImageAspectFlags::DEPTH,
+                        aspect => aspect,
+                    };
+                    let extent = image.desc.extent;
+
+                    let region = vk::BufferImageCopy::default()
+                        .image_subresource(vk::ImageSubresourceLayers::default().aspect_mask(aspect).layer_count(1))
+                        .image_extent(vk::Extent3D { width: extent.width, height: extent.height, depth: 1 });
+
+                    device.cmd_copy_image_to_buffer(command_buffer, image.raw, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, staging.raw, &[region]);
+                },
+                (GraphResource::Buffer(buffer), _) => {
+                    let region = vk::BufferCopy::default().size(pending.size);
+                    device.cmd_copy_buffer(command_buffer, resources.buffers[buffer].raw, staging.raw, &[region]);
+                },
+                _ => {},
+            }
+        }
+
+        let mut barriers = BarrierBatch::new();
+        barriers.add_buffer(staging.raw, &[AccessType::TransferWrite], &[AccessType::HostRead]);
+        barriers.record(device, command_buffer);
+
+        RenderGraph::note_recorded_access(states, transients, resource, read);
+
+        readback.pending[slot] = Some(pending);
+    }
+}
+
+/// Bytes per texel of the formats a readback can copy, `None` for the rest
+pub fn texel_size(format: vk::Format) -> Option<u64> {
+    let size = match format {
+        vk::Format::R8_UNORM | vk::Format::R8_UINT | vk::Format::S8_UINT => 1,
+        vk::Format::R8G8_UNORM | vk::Format::R16_SFLOAT | vk::Format::R16_UINT | vk::Format::D16_UNORM => 2,
+        vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::B8G8R8A8_SRGB
+        | vk::Format::A2B10G10R10_UNORM_PACK32
+        | vk::Format::B10G11R11_UFLOAT_PACK32
+        | vk::Format::R16G16_SFLOAT
+        | vk::Format::R32_SFLOAT
+        | vk::Format::R32_UINT
+        | vk::Format::D32_SFLOAT
+        | vk::Format::X8_D24_UNORM_PACK32 => 4,
+        vk::Format::R16G16B16A16_SFLOAT | vk::Format::R32G32_SFLOAT | vk::Format::R32G32_UINT => 8,
+        vk::Format::R32G32B32A32_SFLOAT | vk::Format::R32G32B32A32_UINT => 16,
+        _ => return None,
+    };
+    Some(size)
+}
+
+impl RenderGraph {
+
+    ///
+    /// Destination of [`crate::PassBuilder::readback`]
+    ///
+    /// The copy recorded in a frame is delivered when the graph starts the frame that
+    /// reuses its slot, as many frames later as there are frames in flight.
+    ///
+    /// # Example:
+    ///
+    /// ```ignore
+    /// let picking = graph.create_readback("picking");
+    ///
+    /// graph.add_pass("Picking")
+    ///     .access(object_ids, AccessType::ComputeShaderWrite)
+    ///     .readback(object_ids, picking)
+    ///     .execute(...);
+    ///
+    /// // Позже, без ожидания GPU
+    /// if let Some(ids) = graph.take_readback(picking) {
+    ///     let id = u32::from_ne_bytes(ids.data[..4].try_into().unwrap());
+    /// }
+    /// ```
+    ///
+    pub fn create_readback(&mut self, name: impl Into<Arc<str>>) -> ReadbackHandle {
+        self.readbacks.pool.insert(name, Readback::default())
+    }
+
+    /// Call `callback` with every copy delivered to `readback`, replaces the previous one
+    pub fn on_readback<F>(&mut self, readback: ReadbackHandle, callback: F)
+        where F: FnMut(&ReadbackData) + 'static
+    {
+        if let Some(readback) = self.readbacks.pool.get_mut(readback) {
+            readback.callback = Some(Box::new(callback));
+        }
+    }
+
+    /// Last delivered copy, it stays until a newer one arrives
+    pub fn readback(&self, readback: ReadbackHandle) -> Option<&ReadbackData> {
+        self.readbacks.pool.get(readback)?.latest.as_ref()
+    }
+
+    /// Last delivered copy, `None` until a newer one arrives
+    pub fn take_readback(&mut self, readback: ReadbackHandle) -> Option<ReadbackData> {
+        self.readbacks.pool.get_mut(readback)?.latest.take()
+    }
+
+    /// The readback must not be used by any frame in flight, destroy it with [`Readback::destroy`]
+    pub fn remove_readback(&mut self, readback: ReadbackHandle) -> Option<Readback> {
+        self.readbacks.pool.remove(readback)
+    }
+
+    /// Called on compile, the copied resources are transfer sources
+    pub(crate) fn plan_readbacks(&mut self, passes: &[&PassDesc], order: &[usize]) {
+        for (resource, _) in order.iter().flat_map(|index| &passes[*index].readbacks) {
+            self.note_extra_access(*resource, AccessType::TransferRead);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fujiya_render::{BufferDesc, ImageDesc};
+
+    use super::*;
+    use crate::{GraphError, TransientDesc};
+
+    #[test]
+    fn readback_sources_are_transfer_sources() {
+        let mut graph = RenderGraph::new();
+        let extent = vk::Extent2D { width: 1, height: 1 };
+        let luminance = graph.create_image("luminance", ImageDesc::new_2d(vk::Format::R32_SFLOAT, extent));
+        let ids = graph.create_buffer("ids", BufferDesc::new(4));
+        let exposure = graph.create_readback("exposure");
+        let picking = graph.create_readback("picking");
+
+        // Никто не читает их выходы, но пассы не отсекаются
+        graph.add_pass("Luminance")
+            .access(luminance, AccessType::ComputeShaderWrite)
+            .readback(luminance, exposure)
+            .execute(|_, _, _| Ok(()));
+
+        graph.add_pass("Picking")
+            .access(ids, AccessType::ComputeShaderWrite)
+            .readback(ids, picking)
+            .execute(|_, _, _| Ok(()));
+
+        assert_eq!(graph.compile().unwrap().order, [0, 1]);
+
+        let TransientDesc::Image(image) = graph.transients.get(luminance.into()).unwrap().desc else { unreachable!() };
+        assert!(image.usage.contains(vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::STORAGE));
+
+        let TransientDesc::Buffer(buffer) = graph.transients.get(ids.into()).unwrap().desc else { unreachable!() };
+        assert!(buffer.usage.contains(vk::BufferUsageFlags::TRANSFER_SRC));
+
+        assert_eq!(graph.readback(exposure), None);
+        assert_eq!(texel_size(vk::Format::R32_SFLOAT), Some(4));
+        assert_eq!(texel_size(vk::Format::D24_UNORM_S8_UINT), None);
+    }
+
+    #[test]
+    fn rejects_readbacks_of_resources_the_pass_does_not_access() {
+        let mut graph = RenderGraph::new();
+        let ids = graph.create_buffer("ids", BufferDesc::new(4));
+        let picking = graph.create_readback("picking");
+
+        graph.add_pass("Picking")
+            .readback(ids, picking)
+            .write(GraphResource::Swapchain)
+            .execute(|_, _, _| Ok(()));
+
+        let err = graph.compile().unwrap_err();
+        assert_eq!(err, GraphError::InvalidReadback {
+            pass: "Picking".into(),
+            resource: "buffer \"ids\"".into(),
+            reason: "the pass doesn't access it",
+        });
+    }
+}
@@ -0,0 +1,405 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
+    fmt,
+    sync::Arc
+};
+
+use crate::{GraphResource, PassDesc};
+
+///
+/// Error of [`crate::RenderGraph::compile`] and [`crate::RenderGraph::add_sub_graph`]
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GraphError {
+    /// The passes depend on each other through their resources
+    Cycle {
+        passes: Vec<Arc<str>>,
+    },
+    /// Two passes overwrite the same resource without reading it, so neither order is right
+    WriteWriteHazard {
+        resource: String,
+        first: Arc<str>,
+        second: Arc<str>,
+    },
+    /// A pass declares a resource that was removed from the graph
+    StaleResource {
+        pass: Arc<str>,
+        resource: String,
+    },
+    /// A pass reads a resource only disabled passes write and it has no fallback
+    MissingInput {
+        pass: Arc<str>,
+        resource: String,
+        producer: Arc<str>,
+    },
+    /// A sub-graph instance was added without binding one of its inputs
+    UnboundInput {
+        instance: Arc<str>,
+        input: Arc<str>,
+    },
+    /// A sub-graph didn't set one of its declared outputs
+    MissingOutput {
+        instance: Arc<str>,
+        output: Arc<str>,
+    },
+    /// A pass reads back the swapchain image or a resource it doesn't access
+    InvalidReadback {
+        pass: Arc<str>,
+        resource: String,
+        reason: &'static str,
+    },
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Cycle { passes } => write!(f, "passes {} depend on each other", passes.join(" -> ")),
+            Self::WriteWriteHazard { resource, first, second } => write!(
+                f, "passes {:?} and {:?} both write {} without reading it, use read_write in the later one", first, second, resource
+            ),
+            Self::StaleResource { pass, resource } => write!(f, "pass {:?} uses removed resource {}", pass, resource),
+            Self::MissingInput { pass, resource, producer } => write!(
+                f, "pass {:?} reads {} but its writer {:?} is disabled, declare a fallback with set_fallback", pass, resource, producer
+            ),
+            Self::UnboundInput { instance, input } => write!(f, "input {:?} of sub-graph {:?} is not bound", input, instance),
+            Self::MissingOutput { instance, output } => write!(f, "sub-graph {:?} doesn't set its output {:?}", instance, output),
+            Self::InvalidReadback { pass, resource, reason } => write!(f, "pass {:?} can't read back {}, {}", pass, resource, reason),
+        }
+    }
+}
+
+impl std::error::Error for GraphError {}
+
+///
+/// Execution order of the passes that contribute to the outputs
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Schedule {
+    /// Pass indices in execution order
+    pub order: Vec<usize>,
+    /// Passes nothing depends on, in registration order
+    pub culled: Vec<usize>,
+    /// `(producer, consumer)` pairs, a consumer runs after its producers
+    pub edges: Vec<(usize, usize)>,
+    /// Passes turned off by name or feature, they are culled too
+    pub disabled: Vec<usize>,
+    /// Declarations the order was computed from, see [`resolve_inputs`]
+    pub passes: Vec<PassDesc>,
+}
+
+///
+/// Declarations of `passes` as they run this frame
+///
+/// Inactive passes are emptied, so nothing depends on them and they are culled. Reads of a
+/// resource that only inactive passes write go to its fallback instead. Returns the
+/// declarations and the replaced resources.
+///
+pub fn resolve_inputs(
+    passes: &[&PassDesc],
+    active: &[bool],
+    fallbacks: &HashMap<GraphResource, GraphResource>,
+    resource_name: &dyn Fn(GraphResource) -> String
+) -> Result<(Vec<PassDesc>, HashMap<GraphResource, GraphResource>), GraphError> {
+
+    let mut replaced = HashMap::new();
+
+    for (pass, _) in passes.iter().zip(active).filter(|(_, active)| !**active) {
+        for resource in &pass.writes {
+            let written = passes.iter().zip(active).any(|(other, active)| *active && other.writes(*resource));
+            if written || *resource == GraphResource::Swapchain || replaced.contains_key(resource) {
+                continue;
+            }
+
+            if let Some(fallback) = fallbacks.get(resource) {
+                replaced.insert(*resource, *fallback);
+                continue;
+            }
+
+            let reader = passes.iter().zip(active).find(|(other, active)| **active && other.reads(*resource));
+            if let Some((reader, _)) = reader {
+                return Err(GraphError::MissingInput { pass: reader.name.clone(), resource: resource_name(*resource), producer: pass.name.clone() });
+            }
+        }
+    }
+
+    let resolved = passes.iter().zip(active).map(|(pass, active)| {
+        if !active {
+            return PassDesc { name: pass.name.clone(), feature: pass.feature.clone(), ..Default::default() };
+        }
+
+        let resolve = |resource: &GraphResource| *replaced.get(resource).unwrap_or(resource);
+
+        let mut reads: Vec<GraphResource> = vec![];
+        for resource in pass.reads.iter().map(resolve) {
+            if !reads.contains(&resource) {
+                reads.push(resource);
+            }
+        }
+
+        PassDesc {
+            reads,
+            accesses: pass.accesses.iter().map(|(resource, access)| (resolve(resource), *access)).collect(),
+            ..(*pass).clone()
+        }
+    }).collect();
+
+    Ok((resolved, replaced))
+}
+
+///
+/// Order `passes` by their resources
+///
+/// A resource is written by at most one pass that doesn't read it. Passes that read and
+/// write it modify it in registration order, plain readers see the last modification.
+/// Passes are kept if they have side effects, write one of `outputs`, or feed a kept pass.
+/// Independent passes keep their registration order. Raw passes run after every kept pass
+/// registered before them.
+///
+pub fn schedule(
+    passes: &[&PassDesc],
+    outputs: &[GraphResource],
+    resource_name: &dyn Fn(GraphResource) -> String
+) -> Result<Schedule, GraphError> {
+
+    let mut resources: Vec<GraphResource> = vec![];
+    for pass in passes {
+        for resource in pass.reads.iter().chain(&pass.writes) {
+            if !resources.contains(resource) {
+                resources.push(*resource);
+            }
+        }
+    }
+
+    let mut edges = HashSet::new();
+
+    for &resource in &resources {
+
+        let mut producer: Option<usize> = None;
+        let mut modifiers = vec![];
+        let mut readers = vec![];
+
+        for (index, pass) in passes.iter().enumerate() {
+            match (pass.reads(resource), pass.writes(resource)) {
+                (false, true) => match producer {
+                    Some(first) => return Err(GraphError::WriteWriteHazard {
+                        resource: resource_name(resource),
+                        first: passes[first].name.clone(),
+                        second: pass.name.clone(),
+                    }),
+                    None => producer = Some(index),
+                },
+                (true, true) => modifiers.push(index),
+                (true, false) => readers.push(index),
+                (false, false) => {},
+            }
+        }
+
+        // Каждая следующая версия ресурса зависит от предыдущей
+        let versions = producer.into_iter().chain(modifiers).collect::<Vec<_>>();
+        for pair in versions.windows(2) {
+            edges.insert((pair[0], pair[1]));
+        }
+
+        if let Some(&last) = versions.last() {
+            for reader in readers {
+                edges.insert((last, reader));
+            }
+        }
+    }
+
+    let mut edges = edges.into_iter().collect::<Vec<_>>();
+    edges.sort();
+
+    // Отсечение: идём от выходов назад по зависимостям
+    let mut alive = vec![false; passes.len()];
+    let mut stack = passes.iter()
+        .enumerate()
+        .filter(|(_, pass)| pass.side_effects || pass.writes.iter().any(|resource| outputs.contains(resource)))
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+
+    while let Some(index) = stack.pop() {
+        if alive[index] {
+            continue;
+        }
+        alive[index] = true;
+        stack.extend(edges.iter().filter(|(_, consumer)| *consumer == index).map(|(producer, _)| *producer));
+    }
+
+    edges.retain(|(producer, consumer)| alive[*producer] && alive[*consumer]);
+
+    // Ресурсов у raw пасса нет, порядок задаём явно и только после отсечения, чтобы он не держал чужие пассы
+    for (raw, _) in passes.iter().enumerate().filter(|(index, pass)| pass.raw && alive[*index]) {
+        for earlier in (0..raw).filter(|index| alive[*index]) {
+            if !edges.contains(&(earlier, raw)) {
+                edges.push((earlier, raw));
+            }
+        }
+    }
+    edges.sort();
+
+    let mut in_degree = vec![0; passes.len()];
+    for (_, consumer) in &edges {
+        in_degree[*consumer] += 1;
+    }
+
+    let mut ready = (0..passes.len())
+        .filter(|index| alive[*index] && in_degree[*index] == 0)
+        .map(Reverse)
+        .collect::<BinaryHeap<_>>();
+
+    let mut order = vec![];
+
+    while let Some(Reverse(index)) = ready.pop() {
+        order.push(index);
+
+        for (_, consumer) in edges.iter().filter(|(producer, _)| *producer == index) {
+            in_degree[*consumer] -= 1;
+            if in_degree[*consumer] == 0 {
+                ready.push(Reverse(*consumer));
+            }
+        }
+    }
+
+    let alive_count = alive.iter().filter(|alive| **alive).count();
+    if order.len() < alive_count {
+        let mut remaining = (0..passes.len())
+            .filter(|index| alive[*index] && !order.contains(index))
+            .collect::<Vec<_>>();
+
+        // Убираем пассы, которые только ждут цикл, но сами в него не входят
+        loop {
+            let before = remaining.len();
+            let snapshot = remaining.clone();
+            remaining.retain(|index| edges.iter().any(|(producer, consumer)| producer == index && snapshot.contains(consumer)));
+
+            if remaining.len() == before {
+                break;
+            }
+        }
+
+        let passes = remaining.into_iter().map(|index| passes[index].name.clone()).collect();
+
+        return Err(GraphError::Cycle { passes });
+    }
+
+    let culled = (0..passes.len()).filter(|index| !alive[*index]).collect();
+
+    Ok(Schedule { order, culled, edges, ..Default::default() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Handle;
+
+    fn pass(name: &'static str, reads: &[GraphResource], writes: &[GraphResource]) -> PassDesc {
+        PassDesc { name: name.into(), reads: reads.to_vec(), writes: writes.to_vec(), ..Default::default() }
+    }
+
+    fn buffers(count: usize) -> Vec<GraphResource> {
+        (0..count as u32).map(|index| GraphResource::Buffer(Handle::from_raw(index, 0))).collect()
+    }
+
+    fn run(passes: &[PassDesc]) -> Result<Schedule, GraphError> {
+        let passes = passes.iter().collect::<Vec<_>>();
+        schedule(&passes, &[GraphResource::Swapchain], &|resource| format!("{:?}", resource))
+    }
+
+    #[test]
+    fn orders_by_dependencies_not_registration() {
+        let [gbuffer, lighting] = buffers(2)[..] else { unreachable!() };
+
+        let schedule = run(&[
+            pass("post", &[lighting], &[GraphResource::Swapchain]),
+            pass("lighting", &[gbuffer], &[lighting]),
+            pass("gbuffer", &[], &[gbuffer]),
+            pass("ui", &[GraphResource::Swapchain], &[GraphResource::Swapchain]),
+        ]).unwrap();
+
+        assert_eq!(schedule.order, [2, 1, 0, 3]);
+        assert!(schedule.culled.is_empty());
+    }
+
+    #[test]
+    fn culls_unused_passes() {
+        let [shadow, debug] = buffers(2)[..] else { unreachable!() };
+
+        let mut readback = pass("readback", &[debug], &[]);
+        readback.side_effects = true;
+
+        let schedule = run(&[
+            pass("shadow", &[], &[shadow]),
+            pass("debug", &[], &[debug]),
+            pass("scene", &[], &[GraphResource::Swapchain]),
+            readback,
+        ]).unwrap();
+
+        assert_eq!(schedule.order, [1, 2, 3]);
+        assert_eq!(schedule.culled, [0]);
+    }
+
+    #[test]
+    fn disabled_writers_fall_back() {
+        let [ssao, white, hdr] = buffers(3)[..] else { unreachable!() };
+
+        let passes = [
+            pass("ssao", &[], &[ssao]),
+            pass("lighting", &[ssao], &[hdr]),
+        ];
+        let passes = passes.iter().collect::<Vec<_>>();
+        let name = |resource| format!("{:?}", resource);
+
+        let (resolved, replaced) = resolve_inputs(&passes, &[true, true], &HashMap::new(), &name).unwrap();
+        assert_eq!(resolved[1].reads, [ssao]);
+        assert!(replaced.is_empty());
+
+        let err = resolve_inputs(&passes, &[false, true], &HashMap::new(), &name).unwrap_err();
+        assert!(matches!(&err, GraphError::MissingInput { pass, producer, .. } if **pass == *"lighting" && **producer == *"ssao"), "{}", err);
+
+        let fallbacks = HashMap::from([(ssao, white)]);
+        let (resolved, replaced) = resolve_inputs(&passes, &[false, true], &fallbacks, &name).unwrap();
+        assert!(resolved[0].writes.is_empty());
+        assert_eq!(resolved[1].reads, [white]);
+        assert_eq!(replaced, fallbacks);
+    }
+
+    #[test]
+    fn raw_passes_run_after_earlier_passes() {
+        let [lighting, shadow] = buffers(2)[..] else { unreachable!() };
+
+        let mut capture = pass("capture", &[], &[]);
+        capture.side_effects = true;
+        capture.raw = true;
+
+        let schedule = run(&[
+            pass("post", &[lighting], &[GraphResource::Swapchain]),
+            pass("shadow", &[], &[shadow]),
+            capture,
+            pass("lighting", &[], &[lighting]),
+        ]).unwrap();
+
+        assert_eq!(schedule.order, [3, 0, 2]);
+        assert_eq!(schedule.culled, [1]);
+        assert!(schedule.edges.contains(&(0, 2)));
+    }
+
+    #[test]
+    fn reports_cycles_and_hazards() {
+        let [a, b] = buffers(2)[..] else { unreachable!() };
+
+        let err = run(&[
+            pass("first", &[a], &[b]),
+            pass("second", &[b], &[a]),
+            pass("present", &[b], &[GraphResource::Swapchain]),
+        ]).unwrap_err();
+        assert_eq!(err, GraphError::Cycle { passes: vec!["first".into(), "second".into()] });
+
+        let err = run(&[
+            pass("scene", &[], &[GraphResource::Swapchain]),
+            pass("ui", &[], &[GraphResource::Swapchain]),
+        ]).unwrap_err();
+        assert!(matches!(&err, GraphError::WriteWriteHazard { first, second, .. } if **first == *"scene" && **second == *"ui"), "{}", err);
+    }
+}
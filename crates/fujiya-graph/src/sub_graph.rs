@@ -0,0 +1,444 @@
+use std::{collections::HashMap, sync::Arc};
+
+use fujiya_render::{BufferDesc, ImageDesc};
+
+use crate::{BufferHandle, GraphError, GraphResource, ImageHandle, PassBuilder, RenderGraph, TemporalBuffer, TemporalImage};
+
+///
+/// Group of passes with named inputs and outputs, instantiated with [`RenderGraph::add_sub_graph`]
+///
+/// # Example:
+///
+/// ```ignore
+/// struct Bloom {
+///     extent: vk::Extent2D,
+/// }
+///
+/// impl SubGraph for Bloom {
+///     fn inputs(&self) -> &[&'static str] {
+///         &["color"]
+///     }
+///
+///     fn outputs(&self) -> &[&'static str] {
+///         &["bloom"]
+///     }
+///
+///     fn build(&self, sub: &mut SubGraphBuilder) {
+///         let color = sub.input_image("color");
+///         let half = sub.create_image("half", ImageDesc::new_2d(vk::Format::R16G16B16A16_SFLOAT, self.extent));
+///
+///         sub.add_pass("downsample")
+///             .read(color)
+///             .access(half, AccessType::ComputeShaderWrite)
+///             .execute(move |res, ctx, _| { ... });
+///
+///         sub.set_output("bloom", half);
+///     }
+/// }
+/// ```
+///
+pub trait SubGraph {
+    /// Resources the caller binds on instantiation
+    fn inputs(&self) -> &[&'static str];
+
+    /// Resources [`SubGraph::build`] has to set with [`SubGraphBuilder::set_output`]
+    fn outputs(&self) -> &[&'static str];
+
+    fn build(&self, sub: &mut SubGraphBuilder<'_>);
+}
+
+///
+/// Adds the passes and resources of one sub-graph instance
+///
+/// Names are prefixed with the instance name, e.g. pass `downsample` of instance `bloom`
+/// becomes `bloom/downsample`.
+///
+pub struct SubGraphBuilder<'g> {
+    graph: &'g mut RenderGraph,
+    instance: Arc<str>,
+    inputs: HashMap<&'static str, GraphResource>,
+    outputs: HashMap<&'static str, GraphResource>,
+    passes: Vec<Arc<str>>,
+    /// Removed again if an output is missing
+    created: Vec<GraphResource>,
+}
+
+impl SubGraphBuilder<'_> {
+
+    pub fn instance(&self) -> &str {
+        &self.instance
+    }
+
+    /// `name` prefixed with the instance name
+    pub fn name(&self, name: &str) -> Arc<str> {
+        format!("{}/{}", self.instance, name).into()
+    }
+
+    ///
+    /// Resource bound to a declared input
+    ///
+    /// # Panics
+    /// If `name` is not one of [`SubGraph::inputs`]
+    ///
+    pub fn input(&self, name: &str) -> GraphResource {
+        *self.inputs.get(name).unwrap_or_else(|| panic!("{:?} is not an input of sub-graph {:?}", name, self.instance))
+    }
+
+    /// See [`SubGraphBuilder::input`], panics if the input is not an image
+    pub fn input_image(&self, name: &str) -> ImageHandle {
+        match self.input(name) {
+            GraphResource::Image(handle) => handle,
+            resource => panic!("Input {:?} of sub-graph {:?} is {:?}, not an image", name, self.instance, resource),
+        }
+    }
+
+    /// See [`SubGraphBuilder::input`], panics if the input is not a buffer
+    pub fn input_buffer(&self, name: &str) -> BufferHandle {
+        match self.input(name) {
+            GraphResource::Buffer(handle) => handle,
+            resource => panic!("Input {:?} of sub-graph {:?} is {:?}, not a buffer", name, self.instance, resource),
+        }
+    }
+
+    /// Make `resource` the output `name` of the instance
+    pub fn set_output(&mut self, name: &'static str, resource: impl Into<GraphResource>) {
+        self.outputs.insert(name, resource.into());
+    }
+
+    /// See [`RenderGraph::create_image`]
+    pub fn create_image(&mut self, name: &str, desc: ImageDesc) -> ImageHandle {
+        let handle = self.graph.create_image(self.name(name), desc);
+        self.created.push(handle.into());
+        handle
+    }
+
+    /// See [`RenderGraph::create_buffer`]
+    pub fn create_buffer(&mut self, name: &str, desc: BufferDesc) -> BufferHandle {
+        let handle = self.graph.create_buffer(self.name(name), desc);
+        self.created.push(handle.into());
+        handle
+    }
+
+    /// See [`RenderGraph::get_or_create_temporal_image`]
+    pub fn get_or_create_temporal_image(&mut self, name: &str, desc: ImageDesc) -> TemporalImage {
+        self.graph.get_or_create_temporal_image(self.name(name), desc)
+    }
+
+    /// See [`RenderGraph::get_or_create_temporal_buffer`]
+    pub fn get_or_create_temporal_buffer(&mut self, name: &str, desc: BufferDesc) -> TemporalBuffer {
+        self.graph.get_or_create_temporal_buffer(self.name(name), desc)
+    }
+
+    /// See [`RenderGraph::add_pass`]
+    pub fn add_pass(&mut self, name: &str) -> PassBuilder<'_> {
+        let name = self.name(name);
+        self.passes.push(name.clone());
+        self.graph.add_pass(name)
+    }
+
+    /// The whole graph, e.g. to register pipelines, names are not prefixed and nothing is rolled back
+    pub fn graph(&mut self) -> &mut RenderGraph {
+        self.graph
+    }
+}
+
+///
+/// Passes and outputs of an instantiated [`SubGraph`]
+///
+#[derive(Debug, Clone, Default)]
+pub struct SubGraphInstance {
+    pub name: Arc<str>,
+    /// Prefixed names in the order they were added
+    pub passes: Vec<Arc<str>>,
+    pub outputs: HashMap<&'static str, GraphResource>,
+    /// Transients created by [`SubGraph::build`], retired when the instance is rebuilt
+    pub created: Vec<GraphResource>,
+}
+
+impl SubGraphInstance {
+
+    /// # Panics
+    /// If `name` is not one of [`SubGraph::outputs`]
+    pub fn output(&self, name: &str) -> GraphResource {
+        *self.outputs.get(name).unwrap_or_else(|| panic!("{:?} is not an output of sub-graph {:?}", name, self.name))
+    }
+
+    /// See [`SubGraphInstance::output`], panics if the output is not an image
+    pub fn image(&self, name: &str) -> ImageHandle {
+        match self.output(name) {
+            GraphResource::Image(handle) => handle,
+            resource => panic!("Output {:?} of sub-graph {:?} is {:?}, not an image", name, self.name, resource),
+        }
+    }
+
+    /// See [`SubGraphInstance::output`], panics if the output is not a buffer
+    pub fn buffer(&self, name: &str) -> BufferHandle {
+        match self.output(name) {
+            GraphResource::Buffer(handle) => handle,
+            resource => panic!("Output {:?} of sub-graph {:?} is {:?}, not a buffer", name, self.name, resource),
+        }
+    }
+}
+
+impl RenderGraph {
+
+    ///
+    /// Add the passes of `sub_graph` with `inputs` bound to graph resources
+    ///
+    /// Adding an instance with the same name again replaces it: its passes are removed, the
+    /// new ones take the place of the first of them, and its transients are retired. If an
+    /// output is missing, the passes, resources and temporals added by `build` are removed
+    /// and the previous instance is kept.
+    ///
+    /// # Example:
+    ///
+    /// ```ignore
+    /// let bloom = graph.add_sub_graph("bloom", &Bloom { extent }, &[("color", hdr.into())])?;
+    ///
+    /// graph.add_pass("Compose")
+    ///     .read(bloom.image("bloom"))
+    ///     ...
+    /// ```
+    ///
+    pub fn add_sub_graph(
+        &mut self,
+        instance: &str,
+        sub_graph: &dyn SubGraph,
+        inputs: &[(&str, GraphResource)]
+    ) -> Result<SubGraphInstance, GraphError> {
+
+        let instance: Arc<str> = instance.into();
+        let mut bound = HashMap::new();
+
+        for input in sub_graph.inputs() {
+            match inputs.iter().find(|(name, _)| name == input) {
+                Some((_, resource)) => bound.insert(*input, *resource),
+                None => return Err(GraphError::UnboundInput { instance, input: (*input).into() }),
+            };
+        }
+
+        for (name, _) in inputs {
+            if !sub_graph.inputs().contains(name) {
+                log::warn!("Sub-graph {:?} has no input {:?}, the binding is ignored", instance, name);
+            }
+        }
+
+        // Пассы прошлого экземпляра вынимаем, чтобы build их не заменил до проверки выходов
+        let names = self.sub_graphs.get(&instance).map(|previous| previous.passes.clone()).unwrap_or_default();
+        let previous = self.take_passes(&names);
+        let count = self.nodes.len();
+        let temporals = self.temporals.clone();
+
+        let mut builder = SubGraphBuilder {
+            graph: self,
+            instance: instance.clone(),
+            inputs: bound,
+            outputs: HashMap::new(),
+            passes: vec![],
+            created: vec![],
+        };
+        sub_graph.build(&mut builder);
+
+        let SubGraphBuilder { outputs, passes, created, .. } = builder;
+
+        let added = self.nodes.split_off(count);
+
+        if let Some(output) = sub_graph.outputs().iter().find(|output| !outputs.contains_key(*output)) {
+            self.restore_passes(previous);
+
+            for resource in created {
+                match resource {
+                    GraphResource::Image(handle) => { self.remove_image(handle); },
+                    GraphResource::Buffer(handle) => { self.remove_buffer(handle); },
+                    GraphResource::Swapchain => {},
+                }
+            }
+            self.restore_temporals(temporals);
+
+            return Err(GraphError::MissingOutput { instance, output: (*output).into() });
+        }
+
+        // Первый пасс прошлого экземпляра стоял после всех оставшихся перед ним
+        let position = previous.first().map(|(index, _)| *index).unwrap_or(self.nodes.len());
+        self.nodes.splice(position..position, added);
+
+        let sub_graph = SubGraphInstance { name: instance.clone(), passes, outputs, created };
+        if let Some(previous) = self.sub_graphs.insert(instance, sub_graph.clone()) {
+            for resource in previous.created {
+                self.transients.retire(resource);
+                self.resource_states.remove(&resource);
+            }
+        }
+        self.schedule = None;
+
+        Ok(sub_graph)
+    }
+
+    /// Turn every pass of an instance on or off, see [`RenderGraph::set_pass_enabled`]
+    pub fn set_sub_graph_enabled(&mut self, instance: &SubGraphInstance, enabled: bool) {
+        for pass in &instance.passes {
+            self.set_pass_enabled(pass, enabled);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk;
+    use fujiya_render::AccessType;
+
+    use super::*;
+
+    struct Blur;
+
+    impl SubGraph for Blur {
+        fn inputs(&self) -> &[&'static str] {
+            &["color"]
+        }
+
+        fn outputs(&self) -> &[&'static str] {
+            &["blurred"]
+        }
+
+        fn build(&self, sub: &mut SubGraphBuilder) {
+            let color = sub.input_image("color");
+            let desc = ImageDesc::new_2d(vk::Format::R16G16B16A16_SFLOAT, vk::Extent2D { width: 64, height: 64 });
+            let horizontal = sub.create_image("horizontal", desc);
+            let vertical = sub.create_image("vertical", desc);
+
+            sub.add_pass("horizontal")
+                .read(color)
+                .access(horizontal, AccessType::ComputeShaderWrite)
+                .execute(|_, _, _| Ok(()));
+
+            sub.add_pass("vertical")
+                .read(horizontal)
+                .access(vertical, AccessType::ComputeShaderWrite)
+                .execute(|_, _, _| Ok(()));
+
+            sub.set_output("blurred", vertical);
+        }
+    }
+
+    #[test]
+    fn instantiates_sub_graphs_with_prefixed_names() {
+        let mut graph = RenderGraph::new();
+        let desc = ImageDesc::new_2d(vk::Format::R8G8B8A8_UNORM, vk::Extent2D { width: 64, height: 64 });
+        let first = graph.create_image("first", desc);
+        let second = graph.create_image("second", desc);
+
+        let a = graph.add_sub_graph("blur_a", &Blur, &[("color", first.into())]).unwrap();
+        let b = graph.add_sub_graph("blur_b", &Blur, &[("color", second.into())]).unwrap();
+
+        assert_eq!(a.passes, ["blur_a/horizontal".into(), "blur_a/vertical".into()]);
+        assert_ne!(a.image("blurred"), b.image("blurred"));
+        assert_eq!(graph.resources.images.name(b.image("blurred")), Some("blur_b/vertical"));
+
+        graph.add_pass("Compose")
+            .read(a.image("blurred"))
+            .read(b.image("blurred"))
+            .write(GraphResource::Swapchain)
+            .execute(|_, _, _| Ok(()));
+
+        assert_eq!(graph.compile().unwrap().order, [0, 1, 2, 3, 4]);
+
+        graph.set_sub_graph_enabled(&b, false);
+        assert!(!graph.is_pass_enabled("blur_b/vertical"));
+        assert!(graph.is_pass_enabled("blur_a/vertical"));
+    }
+
+    #[test]
+    fn checks_bindings() {
+        let mut graph = RenderGraph::new();
+
+        let err = graph.add_sub_graph("blur", &Blur, &[]).unwrap_err();
+        assert_eq!(err, GraphError::UnboundInput { instance: "blur".into(), input: "color".into() });
+        assert!(graph.nodes.is_empty());
+    }
+
+    struct Single;
+
+    impl SubGraph for Single {
+        fn inputs(&self) -> &[&'static str] {
+            &["color"]
+        }
+
+        fn outputs(&self) -> &[&'static str] {
+            &["blurred"]
+        }
+
+        fn build(&self, sub: &mut SubGraphBuilder) {
+            let color = sub.input_image("color");
+            let blurred = sub.create_image("blurred", ImageDesc::new_2d(vk::Format::R16G16B16A16_SFLOAT, vk::Extent2D { width: 64, height: 64 }));
+
+            sub.add_pass("blur")
+                .read(color)
+                .access(blurred, AccessType::ComputeShaderWrite)
+                .execute(|_, _, _| Ok(()));
+
+            sub.set_output("blurred", blurred);
+        }
+    }
+
+    #[test]
+    fn rebuilding_replaces_the_instance() {
+        let mut graph = RenderGraph::new();
+        let desc = ImageDesc::new_2d(vk::Format::R8G8B8A8_UNORM, vk::Extent2D { width: 64, height: 64 });
+        let color = graph.create_image("color", desc);
+
+        graph.add_pass("Scene").write(color).execute(|_, _, _| Ok(()));
+        let blur = graph.add_sub_graph("blur", &Blur, &[("color", color.into())]).unwrap();
+        graph.add_pass("Compose").read(blur.image("blurred")).write(GraphResource::Swapchain).execute(|_, _, _| Ok(()));
+
+        let blur = graph.add_sub_graph("blur", &Single, &[("color", color.into())]).unwrap();
+
+        let names = graph.nodes.iter().map(|node| &*node.desc.name).collect::<Vec<_>>();
+        assert_eq!(names, ["Scene", "blur/blur", "Compose"]);
+        assert_eq!(graph.transients.resources.len(), 2);
+        assert_eq!(graph.transients.retired.len(), 2);
+        assert_eq!(graph.sub_graphs["blur"].created, [GraphResource::Image(blur.image("blurred"))]);
+    }
+
+    struct Unfinished;
+
+    impl SubGraph for Unfinished {
+        fn inputs(&self) -> &[&'static str] {
+            &[]
+        }
+
+        fn outputs(&self) -> &[&'static str] {
+            &["blurred"]
+        }
+
+        fn build(&self, sub: &mut SubGraphBuilder) {
+            let desc = ImageDesc::new_2d(vk::Format::R16G16B16A16_SFLOAT, vk::Extent2D { width: 64, height: 64 });
+            let horizontal = sub.create_image("horizontal", desc);
+            let history = sub.get_or_create_temporal_image("history", desc);
+
+            sub.add_pass("horizontal")
+                .access(horizontal, AccessType::ComputeShaderWrite)
+                .access(history.current, AccessType::ComputeShaderWrite)
+                .execute(|_, _, _| Ok(()));
+        }
+    }
+
+    #[test]
+    fn missing_outputs_roll_back() {
+        let mut graph = RenderGraph::new();
+        let desc = ImageDesc::new_2d(vk::Format::R8G8B8A8_UNORM, vk::Extent2D { width: 64, height: 64 });
+        let color = graph.create_image("color", desc);
+
+        graph.add_sub_graph("blur", &Blur, &[("color", color.into())]).unwrap();
+        let images = graph.resources.images.len();
+
+        let err = graph.add_sub_graph("blur", &Unfinished, &[]).unwrap_err();
+        assert_eq!(err, GraphError::MissingOutput { instance: "blur".into(), output: "blurred".into() });
+
+        // Пассы прошлого экземпляра остались, добавленное build удалено
+        let names = graph.nodes.iter().map(|node| &*node.desc.name).collect::<Vec<_>>();
+        assert_eq!(names, ["blur/horizontal", "blur/vertical"]);
+        assert_eq!(graph.resources.images.len(), images);
+        assert!(graph.temporals.resources.is_empty());
+        assert_eq!(graph.transients.resources.len(), 3);
+    }
+}
@@ -0,0 +1,305 @@
+use std::{collections::HashMap, sync::Arc};
+
+use ash::vk::{self, PhysicalDeviceMemoryProperties};
+use fujiya_render::{AccessType, GPUBuffer, GPUImage};
+
+use crate::{BufferHandle, GraphResource, ImageHandle, PassDesc, Pool, TransientDesc};
+
+///
+/// Image written this frame and the one written the frame before
+///
+/// Passes write `current` and read `history`. After every presented frame the graph swaps
+/// their contents, the handles stay the same.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TemporalImage {
+    pub current: ImageHandle,
+    pub history: ImageHandle,
+}
+
+/// Buffer version of [`TemporalImage`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TemporalBuffer {
+    pub current: BufferHandle,
+    pub history: BufferHandle,
+}
+
+///
+/// Named pair of graph-owned resources whose contents are kept between frames
+///
+#[derive(Debug, Clone)]
+pub struct TemporalResource {
+    pub name: Arc<str>,
+    pub current: GraphResource,
+    pub history: GraphResource,
+    /// Description given on the last request
+    pub requested: TransientDesc,
+    /// `requested` with the usage flags of the scheduled accesses of both halves
+    pub desc: TransientDesc,
+    /// Description of the live resources, `None` until a scheduled pass uses them
+    pub created: Option<TransientDesc>,
+    /// Frames `history` went through, 0 right after the resources are (re)created
+    pub frames: u32,
+}
+
+impl TemporalResource {
+
+    /// Created on first use and recreated when the description or the usage changes
+    pub fn is_stale(&self, used: bool) -> bool {
+        match self.created {
+            Some(created) => created != self.desc,
+            None => used,
+        }
+    }
+}
+
+///
+/// Temporal resources of a graph
+///
+/// [`Temporals::plan`] runs on compile, [`Temporals::allocate`] before the next execute
+/// and [`Temporals::swap`] after every presented frame.
+///
+#[derive(Default, Clone)]
+pub struct Temporals {
+    pub resources: Vec<TemporalResource>,
+    /// Whether a scheduled pass uses the resource at the same index
+    pub used: Vec<bool>,
+    pub allocated: bool,
+}
+
+impl Temporals {
+
+    pub fn new() -> Self {
+        Self { ..Default::default() }
+    }
+
+    pub fn add(&mut self, name: Arc<str>, current: GraphResource, history: GraphResource, desc: TransientDesc) {
+        self.resources.push(TemporalResource { name, current, history, requested: desc, desc, created: None, frames: 0 });
+        self.used.push(false);
+        self.allocated = false;
+    }
+
+    pub fn get(&self, name: &str) -> Option<&TemporalResource> {
+        self.resources.iter().find(|temporal| *temporal.name == *name)
+    }
+
+    /// Temporal resource `resource` is a half of
+    pub fn find(&self, resource: GraphResource) -> Option<&TemporalResource> {
+        self.resources.iter().find(|temporal| temporal.current == resource || temporal.history == resource)
+    }
+
+    pub fn is_history(&self, resource: GraphResource) -> bool {
+        self.resources.iter().any(|temporal| temporal.history == resource)
+    }
+
+    /// Halves written this frame, their writers are never culled
+    pub fn currents(&self) -> impl Iterator<Item = GraphResource> + '_ {
+        self.resources.iter().map(|temporal| temporal.current)
+    }
+
+    ///
+    /// Change the description of `name`, returns whether it differs from the previous one
+    ///
+    pub fn request(&mut self, name: &str, desc: TransientDesc) -> bool {
+        let Some(temporal) = self.resources.iter_mut().find(|temporal| *temporal.name == *name) else {
+            return false;
+        };
+
+        if temporal.requested == desc {
+            return false;
+        }
+
+        temporal.requested = desc;
+        temporal.desc = desc;
+        self.allocated = false;
+        true
+    }
+
+    ///
+    /// Usage flags from the passes in `order`
+    ///
+    /// Both halves get the same flags, they swap every frame.
+    ///
+    pub fn plan(&mut self, passes: &[&PassDesc], order: &[usize]) {
+
+        for (temporal, used) in self.resources.iter_mut().zip(&mut self.used) {
+            temporal.desc = temporal.requested;
+            *used = false;
+
+            for pass in order.iter().map(|index| passes[*index]) {
+                let accesses = pass.accesses_of(temporal.current).into_iter()
+                    .chain(pass.accesses_of(temporal.history))
+                    .collect::<Vec<AccessType>>();
+
+                for access in accesses {
+                    *used = true;
+                    temporal.desc.add_usage(access);
+                }
+            }
+        }
+
+        self.allocated = self.resources.iter().zip(&self.used).all(|(temporal, used)| !temporal.is_stale(*used));
+    }
+
+    /// Usage of an access outside of the passes, call after [`Temporals::plan`]
+    pub fn add_usage(&mut self, resource: GraphResource, access: AccessType) {
+        let Some(index) = self.resources.iter().position(|temporal| temporal.current == resource || temporal.history == resource) else {
+            return;
+        };
+
+        let temporal = &mut self.resources[index];
+        temporal.desc.add_usage(access);
+        self.allocated &= !temporal.is_stale(self.used[index]);
+    }
+
+    ///
+    /// Create the used temporals that don't exist yet and recreate the stale ones
+    ///
+    /// Returns the halves whose contents are lost. The old resources must no longer be
+    /// used by the GPU.
+    ///
+    pub fn allocate(
+        &mut self,
+        device: &ash::Device,
+        memory_prop: &PhysicalDeviceMemoryProperties,
+        images: &mut Pool<GPUImage>,
+        buffers: &mut Pool<GPUBuffer>
+    ) -> Result<Vec<GraphResource>, vk::Result> {
+
+        let mut recreated = vec![];
+
+        for (temporal, used) in self.resources.iter_mut().zip(&self.used) {
+            if !temporal.is_stale(*used) {
+                continue;
+            }
+
+            for half in [temporal.current, temporal.history] {
+                destroy(device, half, images, buffers);
+            }
+            temporal.created = None;
+            temporal.frames = 0;
+
+            for half in [temporal.current, temporal.history] {
+                // Слоты зарезервированы в add, set не может не найти их
+                match (half, temporal.desc) {
+                    (GraphResource::Image(handle), TransientDesc::Image(desc)) => {
+                        let _ = images.set(handle, GPUImage::new(device, memory_prop, desc)?);
+                    },
+                    (GraphResource::Buffer(handle), TransientDesc::Buffer(desc)) => {
+                        let buffer = GPUBuffer::new(device, memory_prop, desc.size, desc.usage, vk::MemoryPropertyFlags::DEVICE_LOCAL)?;
+                        let _ = buffers.set(handle, buffer);
+                    },
+                    _ => unreachable!("temporal desc doesn't match its resource"),
+                }
+                recreated.push(half);
+            }
+
+            temporal.created = Some(temporal.desc);
+            log::debug!("Temporal resource {:?} is created", temporal.name);
+        }
+
+        self.allocated = true;
+        Ok(recreated)
+    }
+
+    ///
+    /// Make what was written this frame the history of the next one
+    ///
+    /// The contents and the last accesses of the halves are exchanged.
+    ///
+    pub fn swap(
+        &mut self,
+        images: &mut Pool<GPUImage>,
+        buffers: &mut Pool<GPUBuffer>,
+        states: &mut HashMap<GraphResource, Vec<AccessType>>
+    ) {
+        for temporal in &mut self.resources {
+            if temporal.created.is_none() {
+                continue;
+            }
+
+            let _ = match (temporal.current, temporal.history) {
+                (GraphResource::Image(current), GraphResource::Image(history)) => images.swap(current, history),
+                (GraphResource::Buffer(current), GraphResource::Buffer(history)) => buffers.swap(current, history),
+                _ => unreachable!("halves of a temporal resource have different types"),
+            };
+
+            let current = states.remove(&temporal.current);
+            let history = states.remove(&temporal.history);
+            if let Some(accesses) = current {
+                states.insert(temporal.history, accesses);
+            }
+            if let Some(accesses) = history {
+                states.insert(temporal.current, accesses);
+            }
+
+            temporal.frames = temporal.frames.saturating_add(1);
+        }
+    }
+
+    /// [`TemporalResource::frames`] by the history half
+    pub fn history_frames(&self) -> HashMap<GraphResource, u32> {
+        self.resources.iter().map(|temporal| (temporal.history, temporal.frames)).collect()
+    }
+}
+
+fn destroy(device: &ash::Device, resource: GraphResource, images: &mut Pool<GPUImage>, buffers: &mut Pool<GPUBuffer>) {
+    match resource {
+        GraphResource::Image(handle) => if let Some(image) = images.take(handle) {
+            image.destroy(device);
+        },
+        GraphResource::Buffer(handle) => if let Some(mut buffer) = buffers.take(handle) {
+            buffer.destroy(device);
+        },
+        GraphResource::Swapchain => {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk;
+    use fujiya_render::ImageDesc;
+
+    use super::*;
+    use crate::Handle;
+
+    fn image(index: u32) -> GraphResource {
+        GraphResource::Image(Handle::from_raw(index, 0))
+    }
+
+    #[test]
+    fn recreated_on_first_use_and_on_changes() {
+        let desc = ImageDesc::new_2d(vk::Format::R16G16B16A16_SFLOAT, vk::Extent2D { width: 640, height: 480 });
+
+        let mut temporals = Temporals::new();
+        temporals.add("taa".into(), image(0), image(1), TransientDesc::Image(desc));
+
+        let mut resolve = PassDesc::new("TAA");
+        resolve.accesses = vec![
+            (image(1), AccessType::ComputeShaderReadSampledImage),
+            (image(0), AccessType::ComputeShaderWrite),
+        ];
+
+        // Пока ни один пасс его не использует, ресурс не создаётся
+        temporals.plan(&[&resolve], &[]);
+        assert!(temporals.allocated);
+
+        temporals.plan(&[&resolve], &[0]);
+        assert!(!temporals.allocated);
+
+        let TransientDesc::Image(planned) = temporals.resources[0].desc else { unreachable!() };
+        assert_eq!(planned.usage, desc.usage | vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::STORAGE);
+
+        temporals.resources[0].created = Some(temporals.resources[0].desc);
+        temporals.plan(&[&resolve], &[0]);
+        assert!(temporals.allocated);
+
+        // Новый размер окна
+        let resized = ImageDesc::new_2d(vk::Format::R16G16B16A16_SFLOAT, vk::Extent2D { width: 800, height: 600 });
+        assert!(!temporals.request("taa", TransientDesc::Image(desc)));
+        assert!(temporals.request("taa", TransientDesc::Image(resized)));
+        temporals.plan(&[&resolve], &[0]);
+        assert!(!temporals.allocated);
+        assert!(temporals.resources[0].is_stale(true));
+    }
+}
@@ -0,0 +1,417 @@
+use ash::vk::{self, PhysicalDeviceMemoryProperties};
+use fujiya_render::{find_memorytype_index, AccessType, BufferDesc, GPUBuffer, GPUImage, ImageDesc};
+
+use crate::{GraphResource, PassDesc, Pool};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransientDesc {
+    Image(ImageDesc),
+    Buffer(BufferDesc),
+}
+
+impl TransientDesc {
+
+    /// Add the usage flags `access` needs
+    pub fn add_usage(&mut self, access: AccessType) {
+        match self {
+            Self::Image(desc) => desc.usage |= access.image_usage(),
+            Self::Buffer(desc) => desc.usage |= access.buffer_usage(),
+        }
+    }
+}
+
+///
+/// Resource created by the graph for the passes between its first and last use
+///
+#[derive(Debug, Clone)]
+pub struct TransientResource {
+    pub resource: GraphResource,
+    /// Description given on creation
+    pub requested: TransientDesc,
+    /// `requested` with the usage flags of the scheduled accesses
+    pub desc: TransientDesc,
+    /// First and last position in the schedule order, `None` if no scheduled pass uses it
+    pub lifetime: Option<(usize, usize)>,
+    /// Memory block it shares with other transients
+    pub block: Option<usize>,
+}
+
+///
+/// Device memory shared by transients whose lifetimes don't overlap
+///
+#[derive(Debug, Clone, Default)]
+pub struct MemoryBlock {
+    pub memory: vk::DeviceMemory,
+    pub size: u64,
+    /// Last accesses of whichever transient used the block last
+    pub last_accesses: Vec<AccessType>,
+}
+
+///
+/// Memory requirements of one transient for [`assign_blocks`]
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AliasRequest {
+    pub lifetime: (usize, usize),
+    pub size: u64,
+    pub memory_type_bits: u32,
+    /// Buffers and images never share a block, so `bufferImageGranularity` doesn't matter
+    pub is_image: bool,
+}
+
+enum RawResource {
+    Image(vk::Image),
+    Buffer(vk::Buffer),
+}
+
+impl RawResource {
+
+    /// `view` is null for buffers and images whose view wasn't created yet
+    fn destroy(self, device: &ash::Device, view: vk::ImageView) {
+        unsafe {
+            match self {
+                Self::Image(image) => {
+                    device.destroy_image_view(view, None);
+                    device.destroy_image(image, None);
+                },
+                Self::Buffer(buffer) => device.destroy_buffer(buffer, None),
+            }
+        }
+    }
+}
+
+fn overlaps(a: (usize, usize), b: (usize, usize)) -> bool {
+    a.0 <= b.1 && b.0 <= a.1
+}
+
+///
+/// Put every request into a block shared only with requests that are dead while it lives
+///
+/// Largest requests are placed first, each into the first block it fits. Returns the block
+/// of every request and the number of blocks.
+///
+pub fn assign_blocks(requests: &[AliasRequest]) -> (Vec<usize>, usize) {
+
+    let mut sorted = (0..requests.len()).collect::<Vec<_>>();
+    sorted.sort_by_key(|index| std::cmp::Reverse(requests[*index].size));
+
+    // Для каждого блока: его запросы и общие типы памяти
+    let mut blocks: Vec<(Vec<usize>, u32)> = vec![];
+    let mut assigned = vec![0; requests.len()];
+
+    for index in sorted {
+        let request = &requests[index];
+
+        let found = blocks.iter().position(|(members, type_bits)| {
+            type_bits & request.memory_type_bits != 0
+                && members.iter().all(|member| {
+                    requests[*member].is_image == request.is_image && !overlaps(requests[*member].lifetime, request.lifetime)
+                })
+        });
+
+        let block = match found {
+            Some(block) => block,
+            None => {
+                blocks.push((vec![], u32::MAX));
+                blocks.len() - 1
+            }
+        };
+
+        blocks[block].0.push(index);
+        blocks[block].1 &= request.memory_type_bits;
+        assigned[index] = block;
+    }
+
+    (assigned, blocks.len())
+}
+
+///
+/// Graph-owned images and buffers that live only within a frame
+///
+/// [`Transients::plan`] runs on compile, [`Transients::allocate`] before the next execute.
+///
+#[derive(Clone, Default)]
+pub struct Transients {
+    pub resources: Vec<TransientResource>,
+    pub blocks: Vec<MemoryBlock>,
+    pub allocated: bool,
+    /// Removed with [`Transients::retire`], their slots are freed by the next [`Transients::release`]
+    pub retired: Vec<GraphResource>,
+}
+
+impl Transients {
+
+    pub fn new() -> Self {
+        Self { ..Default::default() }
+    }
+
+    pub fn add(&mut self, resource: GraphResource, desc: TransientDesc) {
+        self.resources.push(TransientResource { resource, requested: desc, desc, lifetime: None, block: None });
+        self.allocated = false;
+    }
+
+    pub fn remove(&mut self, resource: GraphResource) {
+        self.resources.retain(|transient| transient.resource != resource);
+        self.allocated = false;
+    }
+
+    /// Remove `resource` whose image or buffer may still be used by a frame in flight
+    pub fn retire(&mut self, resource: GraphResource) {
+        if self.is_transient(resource) {
+            self.remove(resource);
+            self.retired.push(resource);
+        }
+    }
+
+    pub fn get(&self, resource: GraphResource) -> Option<&TransientResource> {
+        self.resources.iter().find(|transient| transient.resource == resource)
+    }
+
+    pub fn is_transient(&self, resource: GraphResource) -> bool {
+        self.get(resource).is_some()
+    }
+
+    /// Usage of an access outside of the passes, call after [`Transients::plan`]
+    pub fn add_usage(&mut self, resource: GraphResource, access: AccessType) {
+        if let Some(transient) = self.resources.iter_mut().find(|transient| transient.resource == resource) {
+            transient.desc.add_usage(access);
+        }
+    }
+
+    /// Block whose previous user has to finish before `resource` is first used at `position`
+    pub fn first_use(&self, resource: GraphResource, position: usize) -> Option<usize> {
+        let transient = self.get(resource)?;
+        match transient.lifetime {
+            Some((first, _)) if first == position => transient.block,
+            _ => None,
+        }
+    }
+
+    ///
+    /// Lifetimes and usage flags from the passes in `order`
+    ///
+    pub fn plan(&mut self, passes: &[&PassDesc], order: &[usize]) {
+
+        for transient in &mut self.resources {
+            transient.lifetime = None;
+            transient.desc = transient.requested;
+
+            for (position, pass) in order.iter().map(|index| passes[*index]).enumerate() {
+                let accesses = pass.accesses_of(transient.resource);
+                if accesses.is_empty() {
+                    continue;
+                }
+
+                transient.lifetime = Some(match transient.lifetime {
+                    Some((first, _)) => (first, position),
+                    None => (position, position),
+                });
+
+                for access in accesses {
+                    transient.desc.add_usage(access);
+                }
+            }
+        }
+
+        self.allocated = false;
+    }
+
+    ///
+    /// Create the transients with a lifetime and bind them to aliased memory
+    ///
+    /// Previously allocated transients must be released and no longer used by the GPU.
+    ///
+    pub fn allocate(
+        &mut self,
+        device: &ash::Device,
+        memory_prop: &PhysicalDeviceMemoryProperties,
+        images: &mut Pool<GPUImage>,
+        buffers: &mut Pool<GPUBuffer>
+    ) -> Result<(), vk::Result> {
+
+        let mut created = vec![];
+
+        if let Err(err) = self.create(device, memory_prop, &mut created) {
+            // В пулы ещё ничего не попало, всё созданное удаляется здесь
+            for (_, raw, view) in created {
+                raw.destroy(device, view);
+            }
+            for block in self.blocks.drain(..) {
+                unsafe { device.free_memory(block.memory, None) };
+            }
+            for transient in &mut self.resources {
+                transient.block = None;
+            }
+            return Err(err);
+        }
+
+        for (index, raw, view) in created {
+            let transient = &self.resources[index];
+
+            match (transient.resource, transient.desc, raw) {
+                (GraphResource::Image(handle), TransientDesc::Image(desc), RawResource::Image(image)) => {
+                    let _ = images.set(handle, GPUImage { raw: image, view, memory: vk::DeviceMemory::null(), desc });
+                },
+                (GraphResource::Buffer(handle), TransientDesc::Buffer(desc), RawResource::Buffer(buffer)) => {
+                    let _ = buffers.set(handle, GPUBuffer { raw: buffer, memory: vk::DeviceMemory::null(), size: desc.size });
+                },
+                _ => unreachable!("transient desc doesn't match its resource"),
+            }
+        }
+
+        self.allocated = true;
+        Ok(())
+    }
+
+    ///
+    /// Resources, their memory and image views of [`Transients::allocate`]
+    ///
+    /// Everything created so far is in `created` and `blocks`, also when an error is returned.
+    ///
+    fn create(
+        &mut self,
+        device: &ash::Device,
+        memory_prop: &PhysicalDeviceMemoryProperties,
+        created: &mut Vec<(usize, RawResource, vk::ImageView)>
+    ) -> Result<(), vk::Result> {
+
+        let mut requests = vec![];
+
+        for (index, transient) in self.resources.iter().enumerate() {
+            let Some(lifetime) = transient.lifetime else {
+                continue;
+            };
+
+            let (raw, req) = match transient.desc {
+                TransientDesc::Image(desc) => unsafe {
+                    let image = device.create_image(&desc.create_info(), None)?;
+                    (RawResource::Image(image), device.get_image_memory_requirements(image))
+                },
+                TransientDesc::Buffer(desc) => unsafe {
+                    let buffer = device.create_buffer(&desc.create_info(), None)?;
+                    (RawResource::Buffer(buffer), device.get_buffer_memory_requirements(buffer))
+                },
+            };
+
+            created.push((index, raw, vk::ImageView::null()));
+            requests.push(AliasRequest {
+                lifetime,
+                size: req.size,
+                memory_type_bits: req.memory_type_bits,
+                is_image: matches!(transient.desc, TransientDesc::Image(_)),
+            });
+        }
+
+        let (assigned, block_count) = assign_blocks(&requests);
+
+        self.blocks = vec![MemoryBlock::default(); block_count];
+        let mut type_bits = vec![u32::MAX; block_count];
+
+        for (request, block) in requests.iter().zip(&assigned) {
+            self.blocks[*block].size = self.blocks[*block].size.max(request.size);
+            type_bits[*block] &= request.memory_type_bits;
+        }
+
+        for (block, type_bits) in self.blocks.iter_mut().zip(type_bits) {
+            let req = vk::MemoryRequirements { size: block.size, alignment: 0, memory_type_bits: type_bits };
+            let memory_type_index = find_memorytype_index(&req, memory_prop, vk::MemoryPropertyFlags::DEVICE_LOCAL)
+                .ok_or(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY)?;
+
+            let alloc_info = vk::MemoryAllocateInfo::default()
+                .allocation_size(block.size)
+                .memory_type_index(memory_type_index);
+
+            block.memory = unsafe { device.allocate_memory(&alloc_info, None)? };
+        }
+
+        for ((index, raw, view), block) in created.iter_mut().zip(assigned) {
+            let transient = &mut self.resources[*index];
+            let memory = self.blocks[block].memory;
+            transient.block = Some(block);
+
+            // Все ресурсы блока начинаются с нулевого смещения и перекрывают друг друга
+            match (transient.desc, raw) {
+                (TransientDesc::Image(desc), RawResource::Image(image)) => {
+                    unsafe { device.bind_image_memory(*image, memory, 0)? };
+                    *view = GPUImage::create_view(device, *image, &desc)?;
+                },
+                (TransientDesc::Buffer(_), RawResource::Buffer(buffer)) => {
+                    unsafe { device.bind_buffer_memory(*buffer, memory, 0)? };
+                },
+                _ => unreachable!("transient desc doesn't match its resource"),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Destroy the transients and their memory, handles stay reserved and retired ones are freed
+    pub fn release(&mut self, device: &ash::Device, images: &mut Pool<GPUImage>, buffers: &mut Pool<GPUBuffer>) {
+
+        for transient in &mut self.resources {
+            transient.block = None;
+
+            match transient.resource {
+                GraphResource::Image(handle) => if let Some(image) = images.take(handle) {
+                    image.destroy(device);
+                },
+                GraphResource::Buffer(handle) => if let Some(mut buffer) = buffers.take(handle) {
+                    buffer.destroy(device);
+                },
+                GraphResource::Swapchain => {},
+            }
+        }
+
+        for resource in self.retired.drain(..) {
+            match resource {
+                GraphResource::Image(handle) => if let Some(image) = images.remove(handle) {
+                    image.destroy(device);
+                },
+                GraphResource::Buffer(handle) => if let Some(mut buffer) = buffers.remove(handle) {
+                    buffer.destroy(device);
+                },
+                GraphResource::Swapchain => {},
+            }
+        }
+
+        for block in self.blocks.drain(..) {
+            unsafe { device.free_memory(block.memory, None) };
+        }
+
+        self.allocated = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(lifetime: (usize, usize), size: u64) -> AliasRequest {
+        AliasRequest { lifetime, size, memory_type_bits: 0b11, is_image: true }
+    }
+
+    #[test]
+    fn aliases_resources_that_do_not_overlap() {
+        let (blocks, count) = assign_blocks(&[
+            image((0, 1), 100),
+            image((1, 2), 50),
+            image((2, 3), 80),
+            image((3, 3), 10),
+        ]);
+
+        // 0 и 2 живут в разное время, 1 пересекается с обоими, 3 помещается к 1
+        assert_eq!(blocks, [0, 1, 0, 1]);
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn keeps_incompatible_resources_apart() {
+        let buffer = AliasRequest { is_image: false, ..image((2, 3), 10) };
+        let other_memory = AliasRequest { memory_type_bits: 0b100, ..image((4, 5), 10) };
+
+        let (blocks, count) = assign_blocks(&[image((0, 1), 100), buffer, other_memory]);
+
+        assert_eq!(blocks, [0, 1, 2]);
+        assert_eq!(count, 3);
+    }
+}
@@ -124,4 +124,67 @@ pub fn derive_vertex(input: TokenStream) -> TokenStream {
     };
 
     expanded.into()
-}
\ No newline at end of file
+}
+
+#[proc_macro_derive(SpecializationConstants, attributes(constant_id))]
+pub fn derive_specialization_constants(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let name = &input.ident;
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => panic!("SpecializationConstants can only be derived for structs with named fields"),
+        },
+        _ => panic!("SpecializationConstants can only be derived for structs"),
+    };
+
+    let mut entries = Vec::new();
+    let mut constant_ids = Vec::new();
+
+    for (index, field) in fields.iter().enumerate() {
+        let field_name = field.ident.as_ref().unwrap();
+        let field_ty = &field.ty;
+
+        // bool в Rust занимает 1 байт, а в SPIR-V — 4
+        if let Type::Path(type_path) = field_ty && type_path.path.is_ident("bool") {
+            panic!("Field {} is bool, use ash::vk::Bool32 for boolean specialization constants", field_name);
+        }
+
+        let constant_id: u32 = field.attrs.iter()
+            .find(|attr| attr.path().is_ident("constant_id"))
+            .map(|attr| {
+                attr.parse_args_with(|input: syn::parse::ParseStream| {
+                    input.parse::<syn::LitInt>()?
+                        .base10_parse::<u32>()
+                })
+                .unwrap_or_else(|_| {
+                    panic!("Failed to parse constant_id attribute for field {}", field_name);
+                })
+            })
+            .unwrap_or(index as u32);
+
+        if constant_ids.contains(&constant_id) {
+            panic!("Field {} has constant_id {}, which another field already uses", field_name, constant_id);
+        }
+        constant_ids.push(constant_id);
+
+        entries.push(quote! {
+            ash::vk::SpecializationMapEntry {
+                constant_id: #constant_id,
+                offset: std::mem::offset_of!(#name, #field_name) as u32,
+                size: std::mem::size_of::<#field_ty>(),
+            }
+        });
+    }
+
+    let expanded = quote! {
+        impl ::fujiya_render::SpecializationConstants for #name {
+            fn map_entries() -> Vec<ash::vk::SpecializationMapEntry> {
+                vec![#(#entries),*]
+            }
+        }
+    };
+
+    expanded.into()
+}
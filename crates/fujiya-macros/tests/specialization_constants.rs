@@ -0,0 +1,48 @@
+use ash::vk;
+use bytemuck::{Pod, Zeroable};
+use fujiya_macros::SpecializationConstants;
+use fujiya_render::{SpecializationConstants, SpecializationData};
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable, SpecializationConstants)]
+struct Quality {
+    #[constant_id(3)]
+    samples: u32,
+    soft_shadows: vk::Bool32,
+    radius: f32,
+    #[constant_id(10)]
+    lights: u32,
+    seed: u64,
+}
+
+fn entry(constant_id: u32, offset: u32, size: usize) -> (u32, u32, usize) {
+    (constant_id, offset, size)
+}
+
+#[test]
+fn map_entries_follow_fields_and_constant_ids() {
+    let entries = Quality::map_entries().iter()
+        .map(|entry| (entry.constant_id, entry.offset, entry.size))
+        .collect::<Vec<_>>();
+
+    // Без атрибута constant_id равен номеру поля
+    assert_eq!(entries, [
+        entry(3, 0, 4),
+        entry(1, 4, 4),
+        entry(2, 8, 4),
+        entry(10, 12, 4),
+        entry(4, 16, 8),
+    ]);
+}
+
+#[test]
+fn data_is_the_bytes_of_the_struct() {
+    let quality = Quality { samples: 16, soft_shadows: vk::TRUE, radius: 0.5, lights: 4, seed: u64::MAX };
+    let data = SpecializationData::new(&quality);
+
+    assert_eq!(data.data.len(), std::mem::size_of::<Quality>());
+    assert_eq!(data.data[0..4], 16u32.to_ne_bytes());
+    assert_eq!(data.data[8..12], 0.5f32.to_ne_bytes());
+    assert_eq!(data.data[16..24], u64::MAX.to_ne_bytes());
+    assert_eq!(data.info().map_entry_count, 5);
+}
@@ -0,0 +1,411 @@
+use ash::vk::{self, AccessFlags, ImageLayout, PipelineStageFlags};
+
+///
+/// How a pass uses a resource, modeled after `vk_sync::AccessType`
+///
+/// Every access maps to the pipeline stages, memory access and image layout it needs,
+/// so barriers between two uses can be derived instead of written by hand.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AccessType {
+    /// Not used, the contents are undefined
+    Nothing,
+    IndirectBuffer,
+    IndexBuffer,
+    VertexBuffer,
+    VertexShaderReadUniformBuffer,
+    VertexShaderReadSampledImage,
+    VertexShaderReadOther,
+    FragmentShaderReadUniformBuffer,
+    FragmentShaderReadSampledImage,
+    FragmentShaderReadColorInputAttachment,
+    FragmentShaderReadOther,
+    ColorAttachmentRead,
+    DepthStencilAttachmentRead,
+    ComputeShaderReadUniformBuffer,
+    ComputeShaderReadSampledImage,
+    ComputeShaderReadOther,
+    AnyShaderReadSampledImage,
+    AnyShaderReadOther,
+    TransferRead,
+    HostRead,
+    /// Swapchain image handed to the presentation engine
+    Present,
+    VertexShaderWrite,
+    FragmentShaderWrite,
+    ColorAttachmentWrite,
+    DepthStencilAttachmentWrite,
+    ComputeShaderWrite,
+    AnyShaderWrite,
+    TransferWrite,
+    HostWrite,
+    ColorAttachmentReadWrite,
+    /// Any access in `GENERAL` layout, slow but always valid
+    General,
+}
+
+///
+/// Synchronization scope of an [`AccessType`]
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessInfo {
+    pub stage_mask: PipelineStageFlags,
+    pub access_mask: AccessFlags,
+    pub image_layout: ImageLayout,
+}
+
+impl AccessType {
+
+    pub fn info(self) -> AccessInfo {
+
+        let (stage_mask, access_mask, image_layout) = match self {
+            Self::Nothing => (PipelineStageFlags::empty(), AccessFlags::empty(), ImageLayout::UNDEFINED),
+            Self::IndirectBuffer => (PipelineStageFlags::DRAW_INDIRECT, AccessFlags::INDIRECT_COMMAND_READ, ImageLayout::UNDEFINED),
+            Self::IndexBuffer => (PipelineStageFlags::VERTEX_INPUT, AccessFlags::INDEX_READ, ImageLayout::UNDEFINED),
+            Self::VertexBuffer => (PipelineStageFlags::VERTEX_INPUT, AccessFlags::VERTEX_ATTRIBUTE_READ, ImageLayout::UNDEFINED),
+            Self::VertexShaderReadUniformBuffer => (PipelineStageFlags::VERTEX_SHADER, AccessFlags::UNIFORM_READ, ImageLayout::UNDEFINED),
+            Self::VertexShaderReadSampledImage => (PipelineStageFlags::VERTEX_SHADER, AccessFlags::SHADER_READ, ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+            Self::VertexShaderReadOther => (PipelineStageFlags::VERTEX_SHADER, AccessFlags::SHADER_READ, ImageLayout::GENERAL),
+            Self::FragmentShaderReadUniformBuffer => (PipelineStageFlags::FRAGMENT_SHADER, AccessFlags::UNIFORM_READ, ImageLayout::UNDEFINED),
+            Self::FragmentShaderReadSampledImage => (PipelineStageFlags::FRAGMENT_SHADER, AccessFlags::SHADER_READ, ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+            Self::FragmentShaderReadColorInputAttachment => (PipelineStageFlags::FRAGMENT_SHADER, AccessFlags::INPUT_ATTACHMENT_READ, ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+            Self::FragmentShaderReadOther => (PipelineStageFlags::FRAGMENT_SHADER, AccessFlags::SHADER_READ, ImageLayout::GENERAL),
+            Self::ColorAttachmentRead => (PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT, AccessFlags::COLOR_ATTACHMENT_READ, ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+            Self::DepthStencilAttachmentRead => (
+                PipelineStageFlags::EARLY_FRAGMENT_TESTS | PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ,
+                ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL
+            ),
+            Self::ComputeShaderReadUniformBuffer => (PipelineStageFlags::COMPUTE_SHADER, AccessFlags::UNIFORM_READ, ImageLayout::UNDEFINED),
+            Self::ComputeShaderReadSampledImage => (PipelineStageFlags::COMPUTE_SHADER, AccessFlags::SHADER_READ, ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+            Self::ComputeShaderReadOther => (PipelineStageFlags::COMPUTE_SHADER, AccessFlags::SHADER_READ, ImageLayout::GENERAL),
+            Self::AnyShaderReadSampledImage => (PipelineStageFlags::ALL_COMMANDS, AccessFlags::SHADER_READ, ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+            Self::AnyShaderReadOther => (PipelineStageFlags::ALL_COMMANDS, AccessFlags::SHADER_READ, ImageLayout::GENERAL),
+            Self::TransferRead => (PipelineStageFlags::TRANSFER, AccessFlags::TRANSFER_READ, ImageLayout::TRANSFER_SRC_OPTIMAL),
+            Self::HostRead => (PipelineStageFlags::HOST, AccessFlags::HOST_READ, ImageLayout::GENERAL),
+            Self::Present => (PipelineStageFlags::empty(), AccessFlags::empty(), ImageLayout::PRESENT_SRC_KHR),
+            Self::VertexShaderWrite => (PipelineStageFlags::VERTEX_SHADER, AccessFlags::SHADER_WRITE, ImageLayout::GENERAL),
+            Self::FragmentShaderWrite => (PipelineStageFlags::FRAGMENT_SHADER, AccessFlags::SHADER_WRITE, ImageLayout::GENERAL),
+            Self::ColorAttachmentWrite => (PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT, AccessFlags::COLOR_ATTACHMENT_WRITE, ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+            Self::DepthStencilAttachmentWrite => (
+                PipelineStageFlags::EARLY_FRAGMENT_TESTS | PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+            ),
+            Self::ComputeShaderWrite => (PipelineStageFlags::COMPUTE_SHADER, AccessFlags::SHADER_WRITE, ImageLayout::GENERAL),
+            Self::AnyShaderWrite => (PipelineStageFlags::ALL_COMMANDS, AccessFlags::SHADER_WRITE, ImageLayout::GENERAL),
+            Self::TransferWrite => (PipelineStageFlags::TRANSFER, AccessFlags::TRANSFER_WRITE, ImageLayout::TRANSFER_DST_OPTIMAL),
+            Self::HostWrite => (PipelineStageFlags::HOST, AccessFlags::HOST_WRITE, ImageLayout::GENERAL),
+            Self::ColorAttachmentReadWrite => (
+                PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                AccessFlags::COLOR_ATTACHMENT_READ | AccessFlags::COLOR_ATTACHMENT_WRITE,
+                ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+            ),
+            Self::General => (PipelineStageFlags::ALL_COMMANDS, AccessFlags::MEMORY_READ | AccessFlags::MEMORY_WRITE, ImageLayout::GENERAL),
+        };
+
+        AccessInfo { stage_mask, access_mask, image_layout }
+    }
+
+    pub fn is_write(self) -> bool {
+        matches!(
+            self,
+            Self::VertexShaderWrite
+                | Self::FragmentShaderWrite
+                | Self::ColorAttachmentWrite
+                | Self::DepthStencilAttachmentWrite
+                | Self::ComputeShaderWrite
+                | Self::AnyShaderWrite
+                | Self::TransferWrite
+                | Self::HostWrite
+                | Self::ColorAttachmentReadWrite
+                | Self::General
+        )
+    }
+
+    pub fn is_read(self) -> bool {
+        !matches!(self, Self::Nothing | Self::Present) && (!self.is_write() || matches!(self, Self::ColorAttachmentReadWrite | Self::General))
+    }
+
+    /// Usage flags an image needs for this access
+    pub fn image_usage(self) -> vk::ImageUsageFlags {
+        match self {
+            Self::VertexShaderReadSampledImage
+            | Self::FragmentShaderReadSampledImage
+            | Self::ComputeShaderReadSampledImage
+            | Self::AnyShaderReadSampledImage => vk::ImageUsageFlags::SAMPLED,
+            Self::FragmentShaderReadColorInputAttachment => vk::ImageUsageFlags::INPUT_ATTACHMENT,
+            Self::ColorAttachmentRead
+            | Self::ColorAttachmentWrite
+            | Self::ColorAttachmentReadWrite => vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            Self::DepthStencilAttachmentRead
+            | Self::DepthStencilAttachmentWrite => vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            Self::VertexShaderReadOther
+            | Self::FragmentShaderReadOther
+            | Self::ComputeShaderReadOther
+            | Self::AnyShaderReadOther
+            | Self::VertexShaderWrite
+            | Self::FragmentShaderWrite
+            | Self::ComputeShaderWrite
+            | Self::AnyShaderWrite => vk::ImageUsageFlags::STORAGE,
+            Self::TransferRead => vk::ImageUsageFlags::TRANSFER_SRC,
+            Self::TransferWrite => vk::ImageUsageFlags::TRANSFER_DST,
+            _ => vk::ImageUsageFlags::empty(),
+        }
+    }
+
+    /// Usage flags a buffer needs for this access
+    pub fn buffer_usage(self) -> vk::BufferUsageFlags {
+        match self {
+            Self::IndirectBuffer => vk::BufferUsageFlags::INDIRECT_BUFFER,
+            Self::IndexBuffer => vk::BufferUsageFlags::INDEX_BUFFER,
+            Self::VertexBuffer => vk::BufferUsageFlags::VERTEX_BUFFER,
+            Self::VertexShaderReadUniformBuffer
+            | Self::FragmentShaderReadUniformBuffer
+            | Self::ComputeShaderReadUniformBuffer => vk::BufferUsageFlags::UNIFORM_BUFFER,
+            Self::VertexShaderReadOther
+            | Self::FragmentShaderReadOther
+            | Self::ComputeShaderReadOther
+            | Self::AnyShaderReadOther
+            | Self::VertexShaderWrite
+            | Self::FragmentShaderWrite
+            | Self::ComputeShaderWrite
+            | Self::AnyShaderWrite
+            | Self::General => vk::BufferUsageFlags::STORAGE_BUFFER,
+            Self::TransferRead => vk::BufferUsageFlags::TRANSFER_SRC,
+            Self::TransferWrite => vk::BufferUsageFlags::TRANSFER_DST,
+            _ => vk::BufferUsageFlags::empty(),
+        }
+    }
+}
+
+/// Stages, memory accesses and the layout of a set of accesses of one resource
+fn combine(accesses: &[AccessType]) -> (PipelineStageFlags, AccessFlags, AccessFlags, ImageLayout) {
+
+    let mut stages = PipelineStageFlags::empty();
+    let mut all = AccessFlags::empty();
+    let mut writes = AccessFlags::empty();
+    let mut layout = None;
+
+    for access in accesses {
+        let info = access.info();
+        stages |= info.stage_mask;
+        all |= info.access_mask;
+
+        if access.is_write() {
+            writes |= info.access_mask;
+        }
+
+        // Разные раскладки в одном пассе не совместимы, остаётся только GENERAL
+        layout = match layout {
+            None => Some(info.image_layout),
+            Some(layout) if layout == info.image_layout => Some(layout),
+            Some(_) => Some(ImageLayout::GENERAL),
+        };
+    }
+
+    (stages, all, writes, layout.unwrap_or(ImageLayout::UNDEFINED))
+}
+
+///
+/// Barriers recorded together with one `vkCmdPipelineBarrier`
+///
+/// Add every resource whose access changes before a pass, then [`BarrierBatch::record`]
+/// once, stages of all barriers are merged.
+///
+/// # Example:
+///
+/// ```ignore
+/// let mut barriers = BarrierBatch::new();
+/// barriers.add_image(gbuffer.raw, gbuffer.subresource_range(), &[AccessType::ColorAttachmentWrite], &[AccessType::FragmentShaderReadSampledImage], false);
+/// barriers.add_buffer(lights.raw, &[AccessType::ComputeShaderWrite], &[AccessType::FragmentShaderReadOther]);
+/// barriers.record(device, command_buffer);
+/// ```
+///
+#[derive(Debug, Default)]
+pub struct BarrierBatch {
+    pub src_stage: PipelineStageFlags,
+    pub dst_stage: PipelineStageFlags,
+    pub memory_barriers: Vec<vk::MemoryBarrier<'static>>,
+    pub buffer_barriers: Vec<vk::BufferMemoryBarrier<'static>>,
+    pub image_barriers: Vec<vk::ImageMemoryBarrier<'static>>,
+}
+
+impl BarrierBatch {
+
+    pub fn new() -> Self {
+        Self { ..Default::default() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.memory_barriers.is_empty() && self.buffer_barriers.is_empty() && self.image_barriers.is_empty()
+    }
+
+    /// Merge the stages of a dependency, returns `false` if none is needed
+    fn add_dependency(&mut self, previous: &[AccessType], next: &[AccessType], layout_changes: bool) -> Option<(AccessFlags, AccessFlags)> {
+
+        let (src_stage, _, src_writes, _) = combine(previous);
+        let (dst_stage, dst_access, _, _) = combine(next);
+
+        let previous_writes = previous.iter().any(|access| access.is_write());
+        let next_writes = next.iter().any(|access| access.is_write());
+
+        // Чтение после чтения без смены раскладки синхронизировать не нужно
+        if !previous_writes && !next_writes && !layout_changes {
+            return None;
+        }
+
+        self.src_stage |= if src_stage.is_empty() { PipelineStageFlags::TOP_OF_PIPE } else { src_stage };
+        self.dst_stage |= if dst_stage.is_empty() { PipelineStageFlags::BOTTOM_OF_PIPE } else { dst_stage };
+
+        // Запись после чтения требует только зависимости по исполнению
+        let dst_access = if previous_writes || layout_changes { dst_access } else { AccessFlags::empty() };
+        Some((src_writes, dst_access))
+    }
+
+    pub fn add_buffer(&mut self, buffer: vk::Buffer, previous: &[AccessType], next: &[AccessType]) {
+
+        let Some((src_access, dst_access)) = self.add_dependency(previous, next, false) else {
+            return;
+        };
+
+        if src_access.is_empty() && dst_access.is_empty() {
+            return;
+        }
+
+        self.buffer_barriers.push(
+            vk::BufferMemoryBarrier::default()
+                .src_access_mask(src_access)
+                .dst_access_mask(dst_access)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .buffer(buffer)
+                .offset(0)
+                .size(vk::WHOLE_SIZE)
+        );
+    }
+
+    ///
+    /// Transition `image` between the layouts of `previous` and `next` accesses
+    ///
+    /// With `discard_contents` the old layout is `UNDEFINED`, e.g. for the swapchain image
+    /// or a target that is fully overwritten.
+    ///
+    pub fn add_image(
+        &mut self,
+        image: vk::Image,
+        range: vk::ImageSubresourceRange,
+        previous: &[AccessType],
+        next: &[AccessType],
+        discard_contents: bool
+    ) {
+        let (_, _, _, old_layout) = combine(previous);
+        let (_, _, _, new_layout) = combine(next);
+
+        let old_layout = if discard_contents { ImageLayout::UNDEFINED } else { old_layout };
+        let layout_changes = old_layout != new_layout;
+
+        let Some((src_access, dst_access)) = self.add_dependency(previous, next, layout_changes) else {
+            return;
+        };
+
+        if !layout_changes && src_access.is_empty() && dst_access.is_empty() {
+            return;
+        }
+
+        self.image_barriers.push(
+            vk::ImageMemoryBarrier::default()
+                .src_access_mask(src_access)
+                .dst_access_mask(dst_access)
+                .old_layout(old_layout)
+                .new_layout(new_layout)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(image)
+                .subresource_range(range)
+        );
+    }
+
+    pub fn record(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+
+        if self.is_empty() && self.src_stage.is_empty() {
+            return;
+        }
+
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                self.src_stage,
+                self.dst_stage,
+                vk::DependencyFlags::empty(),
+                &self.memory_barriers,
+                &self.buffer_barriers,
+                &self.image_barriers
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn color_range() -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange::default()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .level_count(1)
+            .layer_count(1)
+    }
+
+    #[test]
+    fn read_after_read_needs_no_barrier() {
+        let mut barriers = BarrierBatch::new();
+        barriers.add_buffer(vk::Buffer::null(), &[AccessType::VertexBuffer], &[AccessType::IndexBuffer]);
+        barriers.add_image(
+            vk::Image::null(), color_range(),
+            &[AccessType::FragmentShaderReadSampledImage], &[AccessType::ComputeShaderReadSampledImage], false
+        );
+
+        assert!(barriers.is_empty());
+        assert!(barriers.src_stage.is_empty());
+    }
+
+    #[test]
+    fn write_then_sample_transitions_layout() {
+        let mut barriers = BarrierBatch::new();
+        barriers.add_image(
+            vk::Image::null(), color_range(),
+            &[AccessType::ColorAttachmentWrite], &[AccessType::FragmentShaderReadSampledImage], false
+        );
+
+        let barrier = barriers.image_barriers[0];
+        assert_eq!(barrier.old_layout, ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+        assert_eq!(barrier.new_layout, ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        assert_eq!(barrier.src_access_mask, AccessFlags::COLOR_ATTACHMENT_WRITE);
+        assert_eq!(barrier.dst_access_mask, AccessFlags::SHADER_READ);
+        assert_eq!(barriers.src_stage, PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT);
+        assert_eq!(barriers.dst_stage, PipelineStageFlags::FRAGMENT_SHADER);
+    }
+
+    #[test]
+    fn write_after_read_is_execution_only() {
+        let mut barriers = BarrierBatch::new();
+        barriers.add_buffer(vk::Buffer::null(), &[AccessType::ComputeShaderReadOther], &[AccessType::ComputeShaderWrite]);
+
+        assert!(barriers.buffer_barriers.is_empty());
+        assert_eq!(barriers.src_stage, PipelineStageFlags::COMPUTE_SHADER);
+        assert_eq!(barriers.dst_stage, PipelineStageFlags::COMPUTE_SHADER);
+    }
+
+    #[test]
+    fn merges_stages_of_a_batch() {
+        let mut barriers = BarrierBatch::new();
+        barriers.add_buffer(vk::Buffer::null(), &[AccessType::TransferWrite], &[AccessType::VertexBuffer]);
+        barriers.add_image(vk::Image::null(), color_range(), &[AccessType::Nothing], &[AccessType::ColorAttachmentWrite], true);
+
+        assert_eq!(barriers.buffer_barriers.len(), 1);
+        assert_eq!(barriers.image_barriers.len(), 1);
+        assert_eq!(barriers.src_stage, PipelineStageFlags::TRANSFER | PipelineStageFlags::TOP_OF_PIPE);
+        assert_eq!(barriers.dst_stage, PipelineStageFlags::VERTEX_INPUT | PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT);
+    }
+}
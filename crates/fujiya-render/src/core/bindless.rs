@@ -0,0 +1,360 @@
+use ash::vk::{self, DescriptorBindingFlags, DescriptorType, TRUE};
+use log::warn;
+
+use crate::{
+    DescriptorPool,
+    DescriptorPoolBuilder,
+    DescriptorSet,
+    DescriptorSetLayout,
+    DescriptorSetLayoutBuilder,
+    DescriptorSetWriter,
+    Device,
+    GPUBuffer,
+    PhysicalDeviceInfo
+};
+
+/// Binding of the sampled image array in the bindless set
+pub const BINDLESS_IMAGES_BINDING: u32 = 0;
+/// Binding of the storage buffer array in the bindless set
+pub const BINDLESS_BUFFERS_BINDING: u32 = 1;
+/// Binding of the sampler array in the bindless set
+pub const BINDLESS_SAMPLERS_BINDING: u32 = 2;
+
+///
+/// Stable index of a resource in a [`BindlessTable`], passed to shaders through push constants
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BindlessIndex(pub u32);
+
+impl BindlessIndex {
+    pub fn index(&self) -> u32 {
+        self.0
+    }
+}
+
+#[derive(Default)]
+struct IndexAllocator {
+    capacity: u32,
+    next: u32,
+    free: Vec<u32>,
+    retired: Vec<(u32, u64)>,
+}
+
+impl IndexAllocator {
+
+    fn allocate(&mut self) -> Option<u32> {
+        if let Some(index) = self.free.pop() {
+            return Some(index);
+        }
+
+        if self.next == self.capacity {
+            return None;
+        }
+
+        self.next += 1;
+        Some(self.next - 1)
+    }
+
+    fn retire(&mut self, index: u32, frame: u64) {
+        self.retired.push((index, frame));
+    }
+
+    fn collect(&mut self, completed_frame: u64) {
+        let free = &mut self.free;
+        self.retired.retain(|&(index, frame)| {
+            if frame <= completed_frame {
+                free.push(index);
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    fn used(&self) -> u32 {
+        self.next - self.free.len() as u32 - self.retired.len() as u32
+    }
+}
+
+///
+/// Global bindless descriptor table
+///
+/// One descriptor set with large partially bound, update-after-bind arrays of sampled images,
+/// storage buffers and samplers. Resources register once and keep their index until released.
+///
+/// ```glsl
+/// layout(set = 0, binding = 0) uniform texture2D textures[];
+/// layout(set = 0, binding = 1) buffer Buffers { uint data[]; } buffers[];
+/// layout(set = 0, binding = 2) uniform sampler samplers[];
+///
+/// vec4 color = texture(sampler2D(textures[nonuniformEXT(pc.albedo)], samplers[pc.sampler]), uv);
+/// ```
+///
+/// Released indices are reused only after [`BindlessTable::end_frame`] has been called for
+/// every frame in flight, so the GPU never reads a slot that was rewritten while pending.
+///
+pub struct BindlessTable {
+    pub layout: DescriptorSetLayout,
+    pub pool: DescriptorPool,
+    pub set: DescriptorSet,
+    images: IndexAllocator,
+    buffers: IndexAllocator,
+    samplers: IndexAllocator,
+    frame: u64,
+    frames_in_flight: u64,
+}
+
+impl BindlessTable {
+
+    ///
+    /// Check that the device was created with the descriptor indexing features the table needs
+    ///
+    pub fn is_supported(device: &Device) -> bool {
+        let indexing = &device.descriptor_indexing;
+        indexing.descriptor_binding_partially_bound == TRUE
+            && indexing.descriptor_binding_sampled_image_update_after_bind == TRUE
+            && indexing.descriptor_binding_storage_buffer_update_after_bind == TRUE
+            && indexing.shader_sampled_image_array_non_uniform_indexing == TRUE
+            && indexing.runtime_descriptor_array == TRUE
+    }
+
+    pub fn register_image(&mut self, device: &ash::Device, view: vk::ImageView, layout: vk::ImageLayout) -> Option<BindlessIndex> {
+
+        let Some(index) = self.images.allocate() else {
+            warn!("Bindless table is out of image slots ({})", self.images.capacity);
+            return None;
+        };
+
+        self.write_image(device, BindlessIndex(index), view, layout);
+        Some(BindlessIndex(index))
+    }
+
+    pub fn register_buffer(&mut self, device: &ash::Device, buffer: &GPUBuffer) -> Option<BindlessIndex> {
+
+        let Some(index) = self.buffers.allocate() else {
+            warn!("Bindless table is out of buffer slots ({})", self.buffers.capacity);
+            return None;
+        };
+
+        self.write_buffer(device, BindlessIndex(index), buffer.raw, 0, buffer.size);
+        Some(BindlessIndex(index))
+    }
+
+    pub fn register_sampler(&mut self, device: &ash::Device, sampler: vk::Sampler) -> Option<BindlessIndex> {
+
+        let Some(index) = self.samplers.allocate() else {
+            warn!("Bindless table is out of sampler slots ({})", self.samplers.capacity);
+            return None;
+        };
+
+        DescriptorSetWriter::new()
+            .write_image_element(BINDLESS_SAMPLERS_BINDING, index, DescriptorType::SAMPLER, vk::ImageView::null(), sampler, vk::ImageLayout::UNDEFINED)
+            .update(device, &self.set);
+
+        Some(BindlessIndex(index))
+    }
+
+    /// Point an already registered image index at another view, e.g. after a streaming update
+    pub fn write_image(&self, device: &ash::Device, index: BindlessIndex, view: vk::ImageView, layout: vk::ImageLayout) {
+        DescriptorSetWriter::new()
+            .write_image_element(BINDLESS_IMAGES_BINDING, index.0, DescriptorType::SAMPLED_IMAGE, view, vk::Sampler::null(), layout)
+            .update(device, &self.set);
+    }
+
+    /// Point an already registered buffer index at another buffer range
+    pub fn write_buffer(&self, device: &ash::Device, index: BindlessIndex, buffer: vk::Buffer, offset: u64, range: u64) {
+        DescriptorSetWriter::new()
+            .write_buffer_element(BINDLESS_BUFFERS_BINDING, index.0, DescriptorType::STORAGE_BUFFER, buffer, offset, range)
+            .update(device, &self.set);
+    }
+
+    pub fn release_image(&mut self, index: BindlessIndex) {
+        self.images.retire(index.0, self.frame);
+    }
+
+    pub fn release_buffer(&mut self, index: BindlessIndex) {
+        self.buffers.retire(index.0, self.frame);
+    }
+
+    pub fn release_sampler(&mut self, index: BindlessIndex) {
+        self.samplers.retire(index.0, self.frame);
+    }
+
+    /// Advance the frame counter and recycle indices released `frames_in_flight` frames ago
+    pub fn end_frame(&mut self) {
+
+        self.frame += 1;
+
+        if let Some(completed) = self.frame.checked_sub(self.frames_in_flight) {
+            self.images.collect(completed);
+            self.buffers.collect(completed);
+            self.samplers.collect(completed);
+        }
+    }
+
+    /// Number of registered images, buffers and samplers
+    pub fn usage(&self) -> (u32, u32, u32) {
+        (self.images.used(), self.buffers.used(), self.samplers.used())
+    }
+
+    /// Bind the table as set `set_index` of `pipeline_layout`
+    pub fn bind(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        bind_point: vk::PipelineBindPoint,
+        pipeline_layout: vk::PipelineLayout,
+        set_index: u32
+    ) {
+        self.set.bind(device, command_buffer, bind_point, pipeline_layout, set_index);
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        self.pool.destroy(device);
+        unsafe { device.destroy_descriptor_set_layout(self.layout.raw, None) };
+    }
+}
+
+///
+/// Default values:
+///     - max_images = 16384
+///     - max_buffers = 4096
+///     - max_samplers = 64
+///     - frames_in_flight = 2
+///
+/// Capacities are clamped to the update-after-bind limits of the device.
+///
+#[derive(Default)]
+pub struct BindlessTableBuilder<'n> {
+    device: Option<&'n Device>,
+    phys_info: Option<&'n PhysicalDeviceInfo>,
+    max_images: Option<u32>,
+    max_buffers: Option<u32>,
+    max_samplers: Option<u32>,
+    frames_in_flight: Option<u32>
+}
+
+impl<'n> BindlessTableBuilder<'n> {
+
+    pub fn new() -> Self {
+        Self { ..Default::default() }
+    }
+
+    pub fn with_device(mut self, device: &'n Device) -> Self {
+        self.device = Some(device);
+        self
+    }
+
+    pub fn with_phys_info(mut self, phys_info: &'n PhysicalDeviceInfo) -> Self {
+        self.phys_info = Some(phys_info);
+        self
+    }
+
+    pub fn with_max_images(mut self, count: u32) -> Self {
+        self.max_images = Some(count);
+        self
+    }
+
+    pub fn with_max_buffers(mut self, count: u32) -> Self {
+        self.max_buffers = Some(count);
+        self
+    }
+
+    pub fn with_max_samplers(mut self, count: u32) -> Self {
+        self.max_samplers = Some(count);
+        self
+    }
+
+    pub fn with_frames_in_flight(mut self, frames: u32) -> Self {
+        self.frames_in_flight = Some(frames);
+        self
+    }
+
+    /// Returns `None` if the device was created without descriptor indexing
+    pub fn build(self) -> Option<BindlessTable> {
+
+        let device = self.device.expect("Device is missing");
+        let phys_info = self.phys_info.expect("Physical device info is missing");
+
+        if !BindlessTable::is_supported(device) {
+            warn!("Descriptor indexing is not enabled, bindless table is unavailable");
+            return None;
+        }
+
+        let limits = &phys_info.descriptor_indexing_prop;
+        let per_stage = limits.max_per_stage_update_after_bind_resources;
+
+        let max_images = self.max_images.unwrap_or(16384)
+            .min(limits.max_descriptor_set_update_after_bind_sampled_images)
+            .min(limits.max_per_stage_descriptor_update_after_bind_sampled_images);
+        let max_buffers = self.max_buffers.unwrap_or(4096)
+            .min(limits.max_descriptor_set_update_after_bind_storage_buffers)
+            .min(limits.max_per_stage_descriptor_update_after_bind_storage_buffers);
+        let max_samplers = self.max_samplers.unwrap_or(64)
+            .min(limits.max_descriptor_set_update_after_bind_samplers)
+            .min(limits.max_per_stage_descriptor_update_after_bind_samplers);
+
+        if max_images + max_buffers + max_samplers > per_stage {
+            warn!("Bindless table exceeds maxPerStageUpdateAfterBindResources ({})", per_stage);
+        }
+
+        let bindings = [
+            vk::DescriptorSetLayoutBinding::default()
+                .binding(BINDLESS_IMAGES_BINDING)
+                .descriptor_type(DescriptorType::SAMPLED_IMAGE)
+                .descriptor_count(max_images)
+                .stage_flags(vk::ShaderStageFlags::ALL),
+            vk::DescriptorSetLayoutBinding::default()
+                .binding(BINDLESS_BUFFERS_BINDING)
+                .descriptor_type(DescriptorType::STORAGE_BUFFER)
+                .descriptor_count(max_buffers)
+                .stage_flags(vk::ShaderStageFlags::ALL),
+            vk::DescriptorSetLayoutBinding::default()
+                .binding(BINDLESS_SAMPLERS_BINDING)
+                .descriptor_type(DescriptorType::SAMPLER)
+                .descriptor_count(max_samplers)
+                .stage_flags(vk::ShaderStageFlags::ALL),
+        ];
+
+        let mut flags = DescriptorBindingFlags::PARTIALLY_BOUND | DescriptorBindingFlags::UPDATE_AFTER_BIND;
+
+        if device.descriptor_indexing.descriptor_binding_update_unused_while_pending == TRUE {
+            flags |= DescriptorBindingFlags::UPDATE_UNUSED_WHILE_PENDING;
+        }
+
+        let binding_flags = [flags; 3];
+
+        let layout = DescriptorSetLayoutBuilder::new()
+            .with_device(&device.raw)
+            .with_bindings(&bindings)
+            .with_binding_flags(&binding_flags)
+            .with_flags(vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL)
+            .build();
+
+        let pool_sizes = [
+            vk::DescriptorPoolSize::default().ty(DescriptorType::SAMPLED_IMAGE).descriptor_count(max_images),
+            vk::DescriptorPoolSize::default().ty(DescriptorType::STORAGE_BUFFER).descriptor_count(max_buffers),
+            vk::DescriptorPoolSize::default().ty(DescriptorType::SAMPLER).descriptor_count(max_samplers),
+        ];
+
+        let pool = DescriptorPoolBuilder::new()
+            .with_device(&device.raw)
+            .with_flags(vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND)
+            .with_pool_sizes(&pool_sizes)
+            .with_max_sets(1)
+            .build();
+
+        let set = pool.allocate(&device.raw, &[layout.raw]).expect("Error allocate bindless set").remove(0);
+
+        Some(BindlessTable {
+            layout,
+            pool,
+            set,
+            images: IndexAllocator { capacity: max_images, ..Default::default() },
+            buffers: IndexAllocator { capacity: max_buffers, ..Default::default() },
+            samplers: IndexAllocator { capacity: max_samplers, ..Default::default() },
+            frame: 0,
+            frames_in_flight: self.frames_in_flight.unwrap_or(2) as u64,
+        })
+    }
+}
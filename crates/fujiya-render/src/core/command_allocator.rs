@@ -0,0 +1,147 @@
+use ash::vk::{CommandBuffer, CommandBufferLevel, CommandPoolResetFlags};
+
+use crate::{CommandPool, CommandPoolBuilder};
+
+///
+/// Usage counters of a [`CommandAllocator`] for the last recorded frame
+///
+#[derive(Default, Debug, Clone, Copy)]
+pub struct CommandAllocatorStats {
+    pub primary_allocated: usize,
+    pub primary_used: usize,
+    pub secondary_allocated: usize,
+    pub secondary_used: usize,
+    pub resets: u64,
+}
+
+///
+/// Command buffer allocator for one frame in flight
+///
+/// Command buffers are allocated on demand and reused after [`CommandAllocator::reset`],
+/// which must only be called once the fence of the frame that used them is signaled.
+///
+/// # Example:
+///
+/// ```ignore
+/// let mut allocator = CommandAllocatorBuilder::new()
+///     .device(&device.raw)
+///     .family_index(family_index)
+///     .build();
+///
+/// device.wait_for_fences(&[fence], true, u64::MAX).unwrap();
+/// allocator.reset(&device.raw);
+///
+/// let command_buffer = allocator.primary(&device.raw);
+/// ```
+///
+pub struct CommandAllocator {
+    pub pool: CommandPool,
+    primary: Vec<CommandBuffer>,
+    secondary: Vec<CommandBuffer>,
+    primary_used: usize,
+    secondary_used: usize,
+    resets: u64,
+}
+
+impl CommandAllocator {
+
+    /// Reset the pool and make every allocated command buffer available again
+    pub fn reset(&mut self, device: &ash::Device) {
+
+        if self.primary_used != 0 || self.secondary_used != 0 {
+            self.pool.reset(device, CommandPoolResetFlags::empty());
+        }
+
+        self.primary_used = 0;
+        self.secondary_used = 0;
+        self.resets += 1;
+    }
+
+    /// Get a primary command buffer in the initial state
+    pub fn primary(&mut self, device: &ash::Device) -> CommandBuffer {
+        Self::next(&self.pool, device, CommandBufferLevel::PRIMARY, &mut self.primary, &mut self.primary_used)
+    }
+
+    /// Get a secondary command buffer in the initial state
+    pub fn secondary(&mut self, device: &ash::Device) -> CommandBuffer {
+        Self::next(&self.pool, device, CommandBufferLevel::SECONDARY, &mut self.secondary, &mut self.secondary_used)
+    }
+
+    pub fn stats(&self) -> CommandAllocatorStats {
+        CommandAllocatorStats {
+            primary_allocated: self.primary.len(),
+            primary_used: self.primary_used,
+            secondary_allocated: self.secondary.len(),
+            secondary_used: self.secondary_used,
+            resets: self.resets,
+        }
+    }
+
+    fn next(
+        pool: &CommandPool,
+        device: &ash::Device,
+        level: CommandBufferLevel,
+        buffers: &mut Vec<CommandBuffer>,
+        used: &mut usize
+    ) -> CommandBuffer {
+
+        if *used == buffers.len() {
+            buffers.extend(pool.create_command_buffers(device, 1, level));
+        }
+
+        let buffer = buffers[*used];
+        *used += 1;
+        buffer
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device) {
+        unsafe { device.destroy_command_pool(self.pool.raw, None) };
+        self.primary.clear();
+        self.secondary.clear();
+        self.primary_used = 0;
+        self.secondary_used = 0;
+    }
+}
+
+#[derive(Default)]
+pub struct CommandAllocatorBuilder<'n> {
+    device: Option<&'n ash::Device>,
+    family_index: Option<u32>
+}
+
+impl<'n> CommandAllocatorBuilder<'n> {
+
+    pub fn new() -> Self {
+        Self { ..Default::default() }
+    }
+
+    pub fn device(mut self, device: &'n ash::Device) -> Self {
+        self.device = Some(device);
+        self
+    }
+
+    pub fn family_index(mut self, family_index: u32) -> Self {
+        self.family_index = Some(family_index);
+        self
+    }
+
+    pub fn build(self) -> CommandAllocator {
+
+        let device = self.device.expect("Device is missing");
+        let family_index = self.family_index.expect("Family index is missing");
+
+        let pool = CommandPoolBuilder::new()
+            .device(device)
+            .family_index(family_index)
+            .build();
+
+        CommandAllocator {
+            pool,
+            primary: vec![],
+            secondary: vec![],
+            primary_used: 0,
+            secondary_used: 0,
+            resets: 0
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use ash::vk::{CommandBuffer, CommandBufferAllocateInfo, CommandBufferLevel, CommandPoolCreateFlags, CommandPoolCreateInfo};
+use ash::vk::{CommandBuffer, CommandBufferAllocateInfo, CommandBufferLevel, CommandPoolCreateFlags, CommandPoolCreateInfo, CommandPoolResetFlags};
 
 pub struct CommandPool {
     pub raw: ash::vk::CommandPool
@@ -15,6 +15,11 @@ impl CommandPool {
         let buffers = unsafe { device.allocate_command_buffers(&allocate_info).unwrap() };
         buffers
     }
+
+    /// Return all command buffers allocated from this pool to the initial state
+    pub fn reset(&self, device: &ash::Device, flags: CommandPoolResetFlags) {
+        unsafe { device.reset_command_pool(self.raw, flags).unwrap() };
+    }
 }
 
 #[derive(Default)]
@@ -1,14 +1,41 @@
 
-use ash::vk::{self, DescriptorPoolSize};
+use ash::vk::{self, DescriptorPoolCreateFlags, DescriptorPoolResetFlags, DescriptorPoolSize};
+use log::debug;
+
+use crate::{DescriptorSet, DescriptorSetLayout};
 
 pub struct DescriptorPool {
     pub raw: vk::DescriptorPool
 }
 
+impl DescriptorPool {
+
+    /// Allocate one descriptor set per layout
+    pub fn allocate(&self, device: &ash::Device, layouts: &[vk::DescriptorSetLayout]) -> Result<Vec<DescriptorSet>, vk::Result> {
+
+        let allocate_info = vk::DescriptorSetAllocateInfo::default()
+            .descriptor_pool(self.raw)
+            .set_layouts(layouts);
+
+        let sets = unsafe { device.allocate_descriptor_sets(&allocate_info)? };
+        Ok(sets.into_iter().map(|raw| DescriptorSet { raw }).collect())
+    }
+
+    /// Return every set allocated from this pool
+    pub fn reset(&self, device: &ash::Device) {
+        unsafe { device.reset_descriptor_pool(self.raw, DescriptorPoolResetFlags::empty()).unwrap() };
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe { device.destroy_descriptor_pool(self.raw, None) };
+    }
+}
+
 #[derive(Default)]
 pub struct DescriptorPoolBuilder<'n> {
     pub pool_sizes: Option<&'n [DescriptorPoolSize]>,
     pub max_sets: Option<u32>,
+    pub flags: Option<DescriptorPoolCreateFlags>,
     pub device: Option<&'n ash::Device>,
 }
 
@@ -28,27 +55,199 @@ impl<'n> DescriptorPoolBuilder<'n> {
         self
     }
 
+    pub fn with_flags(mut self, flags: DescriptorPoolCreateFlags) -> Self {
+        self.flags = Some(flags);
+        self
+    }
+
     pub fn with_device(mut self, dev: &'n ash::Device) -> Self {
         self.device = Some(dev);
         self
     }
 
     pub fn build(self) -> DescriptorPool {
+        self.try_build().expect("Error create Description Pool")
+    }
+
+    pub fn try_build(self) -> Result<DescriptorPool, vk::Result> {
 
         let device = self.device.expect("Device is missing");
         let pool_sizes = self.pool_sizes.expect("Pool sizes is missing");
         let max_sets = self.max_sets.unwrap_or(1);
+        let flags = self.flags.unwrap_or_default();
 
         let pool_info = vk::DescriptorPoolCreateInfo::default()
-            .pool_sizes(&pool_sizes)
+            .flags(flags)
+            .pool_sizes(pool_sizes)
             .max_sets(max_sets);
 
-        let descriptor_pool = unsafe {
-            device
-                .create_descriptor_pool(&pool_info, None)
-                .expect("Error create Description Pool")
-        };
+        let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None)? };
+
+        Ok(DescriptorPool { raw: descriptor_pool })
+    }
+}
+
+///
+/// Growable descriptor set allocator
+///
+/// Pool sizes are given per set and scaled by the number of sets of the pool.
+/// When a pool runs out of memory a new, larger pool is created, [`DescriptorAllocator::reset`]
+/// returns every set of every pool at once.
+///
+/// # Example:
+///
+/// ```ignore
+/// let mut allocator = DescriptorAllocatorBuilder::new()
+///     .with_pool_sizes(&[
+///         vk::DescriptorPoolSize::default()
+///             .ty(vk::DescriptorType::UNIFORM_BUFFER)
+///             .descriptor_count(1)
+///     ])
+///     .build();
+///
+/// let set = allocator.allocate(&device.raw, &layout)?;
+///
+/// DescriptorSetWriter::new()
+///     .write_uniform_buffer(0, &uniform_buffer)
+///     .update(&device.raw, &set);
+/// ```
+///
+pub struct DescriptorAllocator {
+    pool_sizes: Vec<DescriptorPoolSize>,
+    flags: DescriptorPoolCreateFlags,
+    sets_per_pool: u32,
+    max_sets_per_pool: u32,
+    current: Option<DescriptorPool>,
+    full: Vec<DescriptorPool>,
+    free: Vec<DescriptorPool>,
+}
+
+impl DescriptorAllocator {
+
+    pub fn allocate(&mut self, device: &ash::Device, layout: &DescriptorSetLayout) -> Result<DescriptorSet, vk::Result> {
+
+        if self.current.is_none() {
+            self.current = Some(self.next_pool(device)?);
+        }
+
+        let layouts = [layout.raw];
+
+        match self.current.as_ref().unwrap().allocate(device, &layouts) {
+            Ok(mut sets) => return Ok(sets.remove(0)),
+            Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY) | Err(vk::Result::ERROR_FRAGMENTED_POOL) => {},
+            Err(err) => return Err(err)
+        }
+
+        // Пул заполнен, берём следующий
+        let full = self.current.take().unwrap();
+        self.full.push(full);
+
+        // Пул сохраняем до выделения: набор может не влезть и в пустой пул, тогда его уничтожит destroy
+        let pool = self.next_pool(device)?;
+        let pool = self.current.insert(pool);
+        let mut sets = pool.allocate(device, &layouts)?;
+
+        Ok(sets.remove(0))
+    }
+
+    /// Return every allocated set, the sets must no longer be in use by the GPU
+    pub fn reset(&mut self, device: &ash::Device) {
+
+        for pool in self.full.drain(..).chain(self.current.take()) {
+            pool.reset(device);
+            self.free.push(pool);
+        }
+    }
+
+    /// Number of pools created so far
+    pub fn pool_count(&self) -> usize {
+        self.full.len() + self.free.len() + self.current.iter().len()
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device) {
+        for pool in self.full.drain(..).chain(self.free.drain(..)).chain(self.current.take()) {
+            pool.destroy(device);
+        }
+    }
+
+    fn next_pool(&mut self, device: &ash::Device) -> Result<DescriptorPool, vk::Result> {
+
+        if let Some(pool) = self.free.pop() {
+            return Ok(pool);
+        }
+
+        let sets = self.sets_per_pool;
+        self.sets_per_pool = (self.sets_per_pool * 2).min(self.max_sets_per_pool);
+
+        let pool_sizes = self.pool_sizes.iter()
+            .map(|size| size.descriptor_count(size.descriptor_count * sets))
+            .collect::<Vec<_>>();
+
+        debug!("New descriptor pool for {} sets", sets);
+
+        DescriptorPoolBuilder::new()
+            .with_device(device)
+            .with_flags(self.flags)
+            .with_pool_sizes(&pool_sizes)
+            .with_max_sets(sets)
+            .try_build()
+    }
+}
+
+///
+/// Default values:
+///     - sets_per_pool = 64, doubled for every new pool
+///     - max_sets_per_pool = 4096
+///
+#[derive(Default)]
+pub struct DescriptorAllocatorBuilder<'n> {
+    pool_sizes: Option<&'n [DescriptorPoolSize]>,
+    flags: Option<DescriptorPoolCreateFlags>,
+    sets_per_pool: Option<u32>,
+    max_sets_per_pool: Option<u32>
+}
+
+impl<'n> DescriptorAllocatorBuilder<'n> {
+
+    pub fn new() -> Self {
+        Self { ..Default::default() }
+    }
+
+    /// Descriptors needed by one set
+    pub fn with_pool_sizes(mut self, pool_sizes: &'n [DescriptorPoolSize]) -> Self {
+        self.pool_sizes = Some(pool_sizes);
+        self
+    }
+
+    pub fn with_flags(mut self, flags: DescriptorPoolCreateFlags) -> Self {
+        self.flags = Some(flags);
+        self
+    }
+
+    pub fn with_sets_per_pool(mut self, sets: u32) -> Self {
+        self.sets_per_pool = Some(sets);
+        self
+    }
+
+    pub fn with_max_sets_per_pool(mut self, sets: u32) -> Self {
+        self.max_sets_per_pool = Some(sets);
+        self
+    }
+
+    pub fn build(self) -> DescriptorAllocator {
+
+        let pool_sizes = self.pool_sizes.expect("Pool sizes is missing");
+        let sets_per_pool = self.sets_per_pool.unwrap_or(64).max(1);
+        let max_sets_per_pool = self.max_sets_per_pool.unwrap_or(4096).max(sets_per_pool);
 
-        DescriptorPool { raw: descriptor_pool }
+        DescriptorAllocator {
+            pool_sizes: pool_sizes.to_vec(),
+            flags: self.flags.unwrap_or_default(),
+            sets_per_pool,
+            max_sets_per_pool,
+            current: None,
+            full: vec![],
+            free: vec![],
+        }
     }
 }
\ No newline at end of file
@@ -1,2 +1,36 @@
+use ash::vk;
 
+///
+/// Wraper around [`ash::vk::DescriptorSet`]
+///
+/// Allocated from a [`crate::DescriptorPool`] or [`crate::DescriptorAllocator`],
+/// filled with [`crate::DescriptorSetWriter`].
+///
+#[derive(Clone, Copy, Default, Debug)]
+pub struct DescriptorSet {
+    pub raw: vk::DescriptorSet
+}
 
+impl DescriptorSet {
+
+    /// Bind the set at index `first_set` of `layout`
+    pub fn bind(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        bind_point: vk::PipelineBindPoint,
+        layout: vk::PipelineLayout,
+        first_set: u32
+    ) {
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                bind_point,
+                layout,
+                first_set,
+                &[self.raw],
+                &[],
+            );
+        }
+    }
+}
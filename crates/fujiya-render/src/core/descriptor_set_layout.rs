@@ -3,6 +3,8 @@ use ash::vk;
 #[derive(Default)]
 pub struct DescriptorSetLayoutBuilder<'n> {
     pub bindings: Option<&'n [vk::DescriptorSetLayoutBinding<'n>]>,
+    pub binding_flags: Option<&'n [vk::DescriptorBindingFlags]>,
+    pub flags: Option<vk::DescriptorSetLayoutCreateFlags>,
     pub device: Option<&'n ash::Device>,
     pub allocation: ()
 }
@@ -22,11 +24,30 @@ impl<'n> DescriptorSetLayoutBuilder<'n> {
         self
     }
 
+    /// Flags for every binding in the same order, requires descriptor indexing
+    pub fn with_binding_flags(mut self, flags: &'n [vk::DescriptorBindingFlags]) -> Self {
+        self.binding_flags = Some(flags);
+        self
+    }
+
+    pub fn with_flags(mut self, flags: vk::DescriptorSetLayoutCreateFlags) -> Self {
+        self.flags = Some(flags);
+        self
+    }
+
     pub fn build(self) -> DescriptorSetLayout {
         let device = self.device.expect("Device is missing");
         let bindings = self.bindings.expect("Bingings is missing");
-        let layout_info = vk::DescriptorSetLayoutCreateInfo::default()
-            .bindings(&bindings);
+        let mut binding_flags_info = vk::DescriptorSetLayoutBindingFlagsCreateInfo::default();
+        let mut layout_info = vk::DescriptorSetLayoutCreateInfo::default()
+            .flags(self.flags.unwrap_or_default())
+            .bindings(bindings);
+
+        if let Some(binding_flags) = self.binding_flags {
+            assert_eq!(binding_flags.len(), bindings.len(), "Binding flags count must match bindings count");
+            binding_flags_info = binding_flags_info.binding_flags(binding_flags);
+            layout_info = layout_info.push_next(&mut binding_flags_info);
+        }
 
         let layout = unsafe { device.create_descriptor_set_layout(&layout_info, None).unwrap() };
         DescriptorSetLayout { raw: layout }
@@ -0,0 +1,167 @@
+use ash::vk::{self, DescriptorType};
+
+use crate::{DescriptorSet, GPUBuffer};
+
+enum DescriptorInfos {
+    Buffers(Vec<vk::DescriptorBufferInfo>),
+    Images(Vec<vk::DescriptorImageInfo>),
+}
+
+struct BindingWrite {
+    binding: u32,
+    first_element: u32,
+    ty: DescriptorType,
+    infos: DescriptorInfos,
+}
+
+///
+/// Collects descriptor writes and applies them with a single `vkUpdateDescriptorSets`
+///
+/// Writes to consecutive array elements of the same binding are merged into one
+/// [`vk::WriteDescriptorSet`].
+///
+/// # Example:
+///
+/// ```ignore
+/// DescriptorSetWriter::new()
+///     .write_uniform_buffer(0, &uniform_buffer)
+///     .write_combined_image_sampler(1, view, sampler, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+///     .update(&device.raw, &set);
+/// ```
+///
+#[derive(Default)]
+pub struct DescriptorSetWriter {
+    writes: Vec<BindingWrite>,
+}
+
+impl DescriptorSetWriter {
+
+    pub fn new() -> Self {
+        Self { ..Default::default() }
+    }
+
+    /// Write a range of a buffer to array element `element` of `binding`
+    pub fn write_buffer_element(
+        mut self,
+        binding: u32,
+        element: u32,
+        ty: DescriptorType,
+        buffer: vk::Buffer,
+        offset: u64,
+        range: u64
+    ) -> Self {
+
+        let info = vk::DescriptorBufferInfo::default()
+            .buffer(buffer)
+            .offset(offset)
+            .range(range);
+
+        match self.merge_target(binding, element, ty) {
+            Some(BindingWrite { infos: DescriptorInfos::Buffers(infos), .. }) => infos.push(info),
+            _ => self.writes.push(BindingWrite {
+                binding,
+                first_element: element,
+                ty,
+                infos: DescriptorInfos::Buffers(vec![info]),
+            })
+        }
+
+        self
+    }
+
+    /// Write an image and/or sampler to array element `element` of `binding`
+    pub fn write_image_element(
+        mut self,
+        binding: u32,
+        element: u32,
+        ty: DescriptorType,
+        view: vk::ImageView,
+        sampler: vk::Sampler,
+        layout: vk::ImageLayout
+    ) -> Self {
+
+        let info = vk::DescriptorImageInfo::default()
+            .image_view(view)
+            .sampler(sampler)
+            .image_layout(layout);
+
+        match self.merge_target(binding, element, ty) {
+            Some(BindingWrite { infos: DescriptorInfos::Images(infos), .. }) => infos.push(info),
+            _ => self.writes.push(BindingWrite {
+                binding,
+                first_element: element,
+                ty,
+                infos: DescriptorInfos::Images(vec![info]),
+            })
+        }
+
+        self
+    }
+
+    pub fn write_buffer(self, binding: u32, ty: DescriptorType, buffer: vk::Buffer, offset: u64, range: u64) -> Self {
+        self.write_buffer_element(binding, 0, ty, buffer, offset, range)
+    }
+
+    pub fn write_uniform_buffer(self, binding: u32, buffer: &GPUBuffer) -> Self {
+        self.write_buffer(binding, DescriptorType::UNIFORM_BUFFER, buffer.raw, 0, buffer.size)
+    }
+
+    pub fn write_storage_buffer(self, binding: u32, buffer: &GPUBuffer) -> Self {
+        self.write_buffer(binding, DescriptorType::STORAGE_BUFFER, buffer.raw, 0, buffer.size)
+    }
+
+    pub fn write_sampled_image(self, binding: u32, view: vk::ImageView, layout: vk::ImageLayout) -> Self {
+        self.write_image_element(binding, 0, DescriptorType::SAMPLED_IMAGE, view, vk::Sampler::null(), layout)
+    }
+
+    pub fn write_storage_image(self, binding: u32, view: vk::ImageView) -> Self {
+        self.write_image_element(binding, 0, DescriptorType::STORAGE_IMAGE, view, vk::Sampler::null(), vk::ImageLayout::GENERAL)
+    }
+
+    pub fn write_sampler(self, binding: u32, sampler: vk::Sampler) -> Self {
+        self.write_image_element(binding, 0, DescriptorType::SAMPLER, vk::ImageView::null(), sampler, vk::ImageLayout::UNDEFINED)
+    }
+
+    pub fn write_combined_image_sampler(self, binding: u32, view: vk::ImageView, sampler: vk::Sampler, layout: vk::ImageLayout) -> Self {
+        self.write_image_element(binding, 0, DescriptorType::COMBINED_IMAGE_SAMPLER, view, sampler, layout)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    /// Apply all writes to `set`
+    pub fn update(&self, device: &ash::Device, set: &DescriptorSet) {
+
+        let writes = self.writes.iter()
+            .map(|write| {
+                let descriptor = vk::WriteDescriptorSet::default()
+                    .dst_set(set.raw)
+                    .dst_binding(write.binding)
+                    .dst_array_element(write.first_element)
+                    .descriptor_type(write.ty);
+
+                match &write.infos {
+                    DescriptorInfos::Buffers(infos) => descriptor.buffer_info(infos),
+                    DescriptorInfos::Images(infos) => descriptor.image_info(infos),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        unsafe { device.update_descriptor_sets(&writes, &[]) };
+    }
+
+    /// Last write of `binding` if `element` directly follows it
+    fn merge_target(&mut self, binding: u32, element: u32, ty: DescriptorType) -> Option<&mut BindingWrite> {
+        self.writes.iter_mut()
+            .rev()
+            .find(|write| write.binding == binding)
+            .filter(|write| {
+                let count = match &write.infos {
+                    DescriptorInfos::Buffers(infos) => infos.len(),
+                    DescriptorInfos::Images(infos) => infos.len(),
+                };
+                write.ty == ty && write.first_element + count as u32 == element
+            })
+    }
+}
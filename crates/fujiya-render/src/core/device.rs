@@ -4,13 +4,18 @@ use ash::vk::*;
 use crate::core::*;
 
 pub struct Device {
-    pub raw: ash::Device
+    pub raw: ash::Device,
+    /// Features enabled at creation
+    pub features: PhysicalDeviceFeatures,
+    /// Descriptor indexing features enabled at creation, all false if not requested
+    pub descriptor_indexing: PhysicalDeviceDescriptorIndexingFeatures<'static>
 }
 
 #[derive(Default)]
 pub struct DeviceBuilder<'n> {
     extensions: Vec<*const i8>,
     features: Option<PhysicalDeviceFeatures>,
+    descriptor_indexing: Option<PhysicalDeviceDescriptorIndexingFeatures<'static>>,
     family: Option<&'n Vec<QueueFamily>>,
     insatnce: Option<&'n ash::Instance>,
     phys_dev: Option<&'n ash::vk::PhysicalDevice>,
@@ -28,6 +33,12 @@ impl<'n> DeviceBuilder<'n> {
         self
     }
 
+    /// Requires Vulkan 1.2
+    pub fn with_descriptor_indexing(mut self, features: PhysicalDeviceDescriptorIndexingFeatures<'static>) -> Self {
+        self.descriptor_indexing = Some(features);
+        self
+    }
+
     pub fn with_extensions(mut self, names: Vec<&'static CStr>) -> Self {
         self.extensions.extend(names.iter().map(|name| name.as_ptr()).collect::<Vec<_>>());
         self
@@ -72,12 +83,21 @@ impl<'n> DeviceBuilder<'n> {
             queue_infos.push(queue_info);
         }
 
-        let create_info = DeviceCreateInfo::default()
+        let mut create_info = DeviceCreateInfo::default()
             .queue_create_infos(&queue_infos)
             .enabled_extension_names(&extensions)
             .enabled_features(&features);
 
+        let mut descriptor_indexing = self.descriptor_indexing.unwrap_or_default();
+        descriptor_indexing.p_next = std::ptr::null_mut();
+
+        if self.descriptor_indexing.is_some() {
+            create_info = create_info.push_next(&mut descriptor_indexing);
+        }
+
         let device = unsafe { instance.create_device(*phys_dev, &create_info, None).unwrap() };
-        Device { raw: device }
+        descriptor_indexing.p_next = std::ptr::null_mut();
+
+        Device { raw: device, features, descriptor_indexing }
     }
 }
\ No newline at end of file
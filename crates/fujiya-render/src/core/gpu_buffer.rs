@@ -2,6 +2,35 @@ use ash::vk::{self, PhysicalDeviceMemoryProperties};
 use log::warn;
 use crate::{find_memorytype_index};
 
+///
+/// Size and usage of a device local buffer
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BufferDesc {
+    pub size: u64,
+    pub usage: vk::BufferUsageFlags,
+}
+
+impl BufferDesc {
+
+    pub fn new(size: u64) -> Self {
+        Self { size, usage: vk::BufferUsageFlags::empty() }
+    }
+
+    /// Added to the usage flags already set
+    pub fn with_usage(mut self, usage: vk::BufferUsageFlags) -> Self {
+        self.usage |= usage;
+        self
+    }
+
+    pub fn create_info(&self) -> vk::BufferCreateInfo<'static> {
+        vk::BufferCreateInfo::default()
+            .size(self.size)
+            .usage(self.usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+    }
+}
+
 ///
 /// Wraper around [`ash::vk::Buffer`] for simple use
 /// # Panic
@@ -25,6 +54,7 @@ use crate::{find_memorytype_index};
 ///
 pub struct GPUBuffer {
     pub raw: vk::Buffer,
+    /// Null when the memory is owned by someone else, e.g. an aliasing allocator
     pub memory: vk::DeviceMemory,
     pub size: u64,
 }
@@ -97,6 +127,17 @@ impl GPUBuffer {
         unsafe { device.unmap_memory(self.memory) };
     }
 
+    /// The buffer must not be used by any pending command buffer
+    pub fn destroy(&mut self, device: &ash::Device) {
+        unsafe {
+            device.destroy_buffer(self.raw, None);
+
+            if self.memory != vk::DeviceMemory::null() {
+                device.free_memory(self.memory, None);
+            }
+        }
+        self.size = 0;
+    }
 }
 
 
@@ -0,0 +1,156 @@
+use ash::vk::{self, PhysicalDeviceMemoryProperties};
+
+use crate::find_memorytype_index;
+
+///
+/// Size, format and usage of a 2D image
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ImageDesc {
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+    pub mip_levels: u32,
+    pub usage: vk::ImageUsageFlags,
+}
+
+impl ImageDesc {
+
+    pub fn new_2d(format: vk::Format, extent: vk::Extent2D) -> Self {
+        Self { format, extent, mip_levels: 1, usage: vk::ImageUsageFlags::empty() }
+    }
+
+    /// Added to the usage flags already set
+    pub fn with_usage(mut self, usage: vk::ImageUsageFlags) -> Self {
+        self.usage |= usage;
+        self
+    }
+
+    pub fn with_mip_levels(mut self, mip_levels: u32) -> Self {
+        self.mip_levels = mip_levels;
+        self
+    }
+
+    pub fn aspect(&self) -> vk::ImageAspectFlags {
+        match self.format {
+            vk::Format::D16_UNORM | vk::Format::D32_SFLOAT | vk::Format::X8_D24_UNORM_PACK32 => vk::ImageAspectFlags::DEPTH,
+            vk::Format::D16_UNORM_S8_UINT | vk::Format::D24_UNORM_S8_UINT | vk::Format::D32_SFLOAT_S8_UINT => {
+                vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+            },
+            vk::Format::S8_UINT => vk::ImageAspectFlags::STENCIL,
+            _ => vk::ImageAspectFlags::COLOR,
+        }
+    }
+
+    /// Every mip level of the image
+    pub fn subresource_range(&self) -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange::default()
+            .aspect_mask(self.aspect())
+            .base_mip_level(0)
+            .level_count(self.mip_levels)
+            .base_array_layer(0)
+            .layer_count(1)
+    }
+
+    pub fn create_info(&self) -> vk::ImageCreateInfo<'static> {
+        vk::ImageCreateInfo::default()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(self.format)
+            .extent(vk::Extent3D { width: self.extent.width, height: self.extent.height, depth: 1 })
+            .mip_levels(self.mip_levels)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(self.usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+    }
+}
+
+///
+/// Device local 2D image with a view of all its mips
+///
+/// # Example:
+///
+/// ```ignore
+/// let desc = ImageDesc::new_2d(vk::Format::R16G16B16A16_SFLOAT, extent)
+///     .with_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED);
+///
+/// let hdr = GPUImage::new(device, &memory_prop, desc)?;
+/// ```
+///
+pub struct GPUImage {
+    pub raw: vk::Image,
+    pub view: vk::ImageView,
+    /// Null when the memory is owned by someone else, e.g. an aliasing allocator
+    pub memory: vk::DeviceMemory,
+    pub desc: ImageDesc,
+}
+
+impl GPUImage {
+
+    pub fn new(
+        device: &ash::Device,
+        memory_prop: &PhysicalDeviceMemoryProperties,
+        desc: ImageDesc
+    ) -> Result<Self, vk::Result> {
+
+        let mut image = Self { raw: vk::Image::null(), view: vk::ImageView::null(), memory: vk::DeviceMemory::null(), desc };
+
+        // Уничтожение нулевых хэндлов ничего не делает, чистим всё, что успели создать
+        if let Err(err) = Self::create(device, memory_prop, &mut image) {
+            image.destroy(device);
+            return Err(err);
+        }
+
+        Ok(image)
+    }
+
+    fn create(device: &ash::Device, memory_prop: &PhysicalDeviceMemoryProperties, image: &mut Self) -> Result<(), vk::Result> {
+
+        image.raw = unsafe { device.create_image(&image.desc.create_info(), None)? };
+        let req = unsafe { device.get_image_memory_requirements(image.raw) };
+
+        let memory_type_index = find_memorytype_index(
+            &req,
+            memory_prop,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        ).ok_or(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY)?;
+
+        let alloc_info = vk::MemoryAllocateInfo::default()
+            .allocation_size(req.size)
+            .memory_type_index(memory_type_index);
+
+        image.memory = unsafe { device.allocate_memory(&alloc_info, None)? };
+        unsafe { device.bind_image_memory(image.raw, image.memory, 0)? };
+
+        image.view = Self::create_view(device, image.raw, &image.desc)?;
+        Ok(())
+    }
+
+    pub fn create_view(device: &ash::Device, image: vk::Image, desc: &ImageDesc) -> Result<vk::ImageView, vk::Result> {
+
+        let view_info = vk::ImageViewCreateInfo::default()
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(desc.format)
+            .subresource_range(desc.subresource_range())
+            .image(image);
+
+        unsafe { device.create_image_view(&view_info, None) }
+    }
+
+    pub fn subresource_range(&self) -> vk::ImageSubresourceRange {
+        self.desc.subresource_range()
+    }
+
+    /// The image must not be used by any pending command buffer
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            device.destroy_image_view(self.view, None);
+            device.destroy_image(self.raw, None);
+
+            if self.memory != vk::DeviceMemory::null() {
+                device.free_memory(self.memory, None);
+            }
+        }
+    }
+}
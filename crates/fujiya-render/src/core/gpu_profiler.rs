@@ -0,0 +1,469 @@
+use std::{collections::VecDeque, fmt::Write as _, time::Instant};
+
+use ash::vk::{self, PipelineStageFlags, QueryType};
+use log::warn;
+
+use crate::{PhysicalDeviceInfo, QueryPool, QueryPoolBuilder};
+
+///
+/// GPU time of one profiled scope
+///
+/// `start_ms` is relative to the first timestamp written in the frame.
+///
+#[derive(Debug, Clone)]
+pub struct GpuScopeTiming {
+    pub name: String,
+    pub start_ms: f64,
+    pub duration_ms: f64,
+}
+
+///
+/// GPU timings of one resolved frame
+///
+#[derive(Debug, Clone, Default)]
+pub struct GpuFrameTimings {
+    pub frame: u64,
+    pub scopes: Vec<GpuScopeTiming>,
+    /// CPU time of [`GpuProfiler::begin_frame`], used to place the frame in traces
+    pub cpu_start: Option<Instant>,
+}
+
+impl GpuFrameTimings {
+
+    /// Time from the first scope begin to the last scope end
+    pub fn total_ms(&self) -> f64 {
+        self.scopes.iter()
+            .map(|scope| scope.start_ms + scope.duration_ms)
+            .fold(0.0, f64::max)
+    }
+
+    pub fn scope(&self, name: &str) -> Option<&GpuScopeTiming> {
+        self.scopes.iter().find(|scope| scope.name == name)
+    }
+}
+
+/// Handle of an open scope returned by [`GpuProfiler::begin_scope`]
+#[derive(Debug, Clone, Copy)]
+pub struct GpuScope(Option<u32>);
+
+struct ProfilerFrame {
+    pool: QueryPool,
+    scopes: Vec<(String, u32)>,
+    next_query: u32,
+    frame: u64,
+    cpu_start: Option<Instant>,
+    pending: bool,
+}
+
+///
+/// Timestamp profiler with one query pool per frame in flight
+///
+/// Results of a frame are read back in [`GpuProfiler::begin_frame`] of the next frame
+/// that uses the same slot, after its fence has been waited on, so reading never stalls.
+///
+/// # Example:
+///
+/// ```ignore
+/// profiler.begin_frame(&device.raw, current_frame, command_buffer);
+///
+/// let scope = profiler.begin_scope(&device.raw, command_buffer, "Shadows");
+/// // ...
+/// profiler.end_scope(&device.raw, command_buffer, scope);
+///
+/// if let Some(timings) = profiler.last_frame() {
+///     println!("{:.3} ms", timings.total_ms());
+/// }
+/// ```
+///
+pub struct GpuProfiler {
+    frames: Vec<ProfilerFrame>,
+    current: usize,
+    frame_counter: u64,
+    timestamp_period: f64,
+    timestamp_mask: u64,
+    enabled: bool,
+    history: VecDeque<GpuFrameTimings>,
+    history_len: usize,
+    overflow_reported: bool,
+    #[cfg(feature = "puffin")]
+    puffin_scopes: std::collections::HashMap<String, puffin::ScopeId>,
+}
+
+impl GpuProfiler {
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    ///
+    /// Start recording frame `frame_index` (the frame in flight slot)
+    ///
+    /// The fence of the slot must be signaled. Resolves the previous results of the slot
+    /// and records a query reset into `command_buffer`, which must be outside of a render pass.
+    ///
+    pub fn begin_frame(&mut self, device: &ash::Device, frame_index: usize, command_buffer: vk::CommandBuffer) {
+
+        if !self.enabled {
+            return;
+        }
+
+        self.current = frame_index;
+        self.resolve(device, frame_index);
+
+        let frame = &mut self.frames[frame_index];
+        frame.pool.cmd_reset(device, command_buffer, 0, frame.pool.count);
+        frame.scopes.clear();
+        frame.next_query = 0;
+        frame.frame = self.frame_counter;
+        frame.cpu_start = Some(Instant::now());
+        frame.pending = true;
+
+        self.frame_counter += 1;
+    }
+
+    /// Write the begin timestamp of a scope
+    pub fn begin_scope(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer, name: &str) -> GpuScope {
+
+        if !self.enabled {
+            return GpuScope(None);
+        }
+
+        let frame = &mut self.frames[self.current];
+
+        if frame.next_query + 2 > frame.pool.count {
+            if !self.overflow_reported {
+                warn!("GPU profiler is out of queries ({}), scope {:?} is skipped", frame.pool.count, name);
+                self.overflow_reported = true;
+            }
+            return GpuScope(None);
+        }
+
+        let query = frame.next_query;
+        frame.next_query += 2;
+        frame.scopes.push((name.to_string(), query));
+
+        unsafe {
+            device.cmd_write_timestamp(command_buffer, PipelineStageFlags::TOP_OF_PIPE, frame.pool.raw, query);
+        }
+
+        GpuScope(Some(query))
+    }
+
+    /// Write the end timestamp of a scope
+    pub fn end_scope(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer, scope: GpuScope) {
+
+        let Some(query) = scope.0 else {
+            return;
+        };
+
+        let frame = &self.frames[self.current];
+
+        unsafe {
+            device.cmd_write_timestamp(command_buffer, PipelineStageFlags::BOTTOM_OF_PIPE, frame.pool.raw, query + 1);
+        }
+    }
+
+    /// Timings of the most recently resolved frame
+    pub fn last_frame(&self) -> Option<&GpuFrameTimings> {
+        self.history.back()
+    }
+
+    /// Resolved frames, oldest first
+    pub fn history(&self) -> impl Iterator<Item = &GpuFrameTimings> {
+        self.history.iter()
+    }
+
+    fn resolve(&mut self, device: &ash::Device, frame_index: usize) {
+
+        let frame = &mut self.frames[frame_index];
+
+        if !frame.pending {
+            return;
+        }
+
+        frame.pending = false;
+
+        let results = match frame.pool.get_results(device, 0, frame.next_query) {
+            Ok(results) => results,
+            Err(err) => {
+                warn!("Failed to read GPU timestamps: {:?}", err);
+                return;
+            }
+        };
+
+        let ticks = |query: u32| -> Option<u64> {
+            results.get(query as usize)?.as_ref().map(|values| values[0] & self.timestamp_mask)
+        };
+
+        let base = frame.scopes.iter()
+            .filter_map(|(_, query)| ticks(*query))
+            .min();
+
+        let Some(base) = base else {
+            return;
+        };
+
+        let to_ms = |ticks: u64| ticks as f64 * self.timestamp_period / 1_000_000.0;
+
+        let scopes = frame.scopes.iter()
+            .filter_map(|(name, query)| {
+                let begin = ticks(*query)?;
+                let end = ticks(*query + 1)?;
+                Some(GpuScopeTiming {
+                    name: name.clone(),
+                    start_ms: to_ms(begin - base),
+                    duration_ms: to_ms(end.saturating_sub(begin)),
+                })
+            })
+            .collect();
+
+        let timings = GpuFrameTimings {
+            frame: frame.frame,
+            scopes,
+            cpu_start: frame.cpu_start,
+        };
+
+        #[cfg(feature = "puffin")]
+        self.report_puffin(&timings);
+
+        if self.history.len() == self.history_len {
+            self.history.pop_front();
+        }
+        self.history.push_back(timings);
+    }
+
+    #[cfg(feature = "puffin")]
+    fn report_puffin(&mut self, timings: &GpuFrameTimings) {
+
+        if !puffin::are_scopes_on() {
+            return;
+        }
+
+        let Some(cpu_start) = timings.cpu_start else {
+            return;
+        };
+
+        let mut profiler = puffin::GlobalProfiler::lock();
+        let start_ns = puffin::now_ns() - cpu_start.elapsed().as_nanos() as puffin::NanoSecond;
+        let mut stream = puffin::StreamInfo::default();
+
+        for scope in &timings.scopes {
+
+            let scope_id = *self.puffin_scopes.entry(scope.name.clone()).or_insert_with(|| {
+                profiler.register_user_scopes(&[puffin::ScopeDetails::from_scope_name(scope.name.clone())])[0]
+            });
+
+            let begin_ns = start_ns + (scope.start_ms * 1_000_000.0) as puffin::NanoSecond;
+            let end_ns = begin_ns + (scope.duration_ms * 1_000_000.0) as puffin::NanoSecond;
+
+            let (offset, _) = stream.stream.begin_scope(|| begin_ns, scope_id, "");
+            stream.stream.end_scope(offset, end_ns);
+            stream.num_scopes += 1;
+            stream.depth = 1;
+            stream.range_ns.0 = stream.range_ns.0.min(begin_ns);
+            stream.range_ns.1 = stream.range_ns.1.max(end_ns);
+        }
+
+        let info = puffin::ThreadInfo {
+            start_time_ns: None,
+            name: "GPU".to_string(),
+        };
+
+        profiler.report_user_scopes(info, &stream.as_stream_into_ref());
+    }
+
+    ///
+    /// Resolved history in the Chrome trace event format
+    ///
+    /// Open the result in `chrome://tracing` or <https://ui.perfetto.dev>.
+    ///
+    pub fn chrome_trace_json(&self) -> String {
+
+        let origin = self.history.iter().find_map(|frame| frame.cpu_start);
+        let mut json = String::from("{\"traceEvents\":[");
+        let mut first = true;
+
+        for frame in &self.history {
+
+            let frame_start_us = match (origin, frame.cpu_start) {
+                (Some(origin), Some(start)) => start.duration_since(origin).as_secs_f64() * 1_000_000.0,
+                _ => 0.0
+            };
+
+            for scope in &frame.scopes {
+
+                if !first {
+                    json.push(',');
+                }
+                first = false;
+
+                let _ = write!(
+                    json,
+                    "{{\"name\":\"{}\",\"cat\":\"gpu\",\"ph\":\"X\",\"pid\":0,\"tid\":0,\"ts\":{:.3},\"dur\":{:.3},\"args\":{{\"frame\":{}}}}}",
+                    escape_json(&scope.name),
+                    frame_start_us + scope.start_ms * 1000.0,
+                    scope.duration_ms * 1000.0,
+                    frame.frame
+                );
+            }
+        }
+
+        json.push_str("]}");
+        json
+    }
+
+    pub fn write_chrome_trace(&self, path: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+        std::fs::write(path, self.chrome_trace_json())
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device) {
+        for frame in &self.frames {
+            frame.pool.destroy(device);
+        }
+        self.frames.clear();
+        self.enabled = false;
+    }
+}
+
+/// Escape `text` for a JSON string literal
+pub fn escape_json(text: &str) -> String {
+
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => { let _ = write!(escaped, "\\u{:04x}", c as u32); },
+            c => escaped.push(c)
+        }
+    }
+
+    escaped
+}
+
+///
+/// Default values:
+///     - frames_in_flight = 2
+///     - max_scopes = 128 per frame
+///     - history = 120 frames
+///
+#[derive(Default)]
+pub struct GpuProfilerBuilder<'n> {
+    device: Option<&'n ash::Device>,
+    phys_info: Option<&'n PhysicalDeviceInfo>,
+    family_index: Option<u32>,
+    frames_in_flight: Option<usize>,
+    max_scopes: Option<u32>,
+    history: Option<usize>
+}
+
+impl<'n> GpuProfilerBuilder<'n> {
+
+    pub fn new() -> Self {
+        Self { ..Default::default() }
+    }
+
+    pub fn with_device(mut self, device: &'n ash::Device) -> Self {
+        self.device = Some(device);
+        self
+    }
+
+    /// Source of `timestampPeriod` and the timestamp valid bits of the queue family
+    pub fn with_phys_info(mut self, phys_info: &'n PhysicalDeviceInfo) -> Self {
+        self.phys_info = Some(phys_info);
+        self
+    }
+
+    /// Queue family the profiled command buffers are submitted to
+    pub fn with_family_index(mut self, family_index: u32) -> Self {
+        self.family_index = Some(family_index);
+        self
+    }
+
+    pub fn with_frames_in_flight(mut self, frames: usize) -> Self {
+        self.frames_in_flight = Some(frames);
+        self
+    }
+
+    pub fn with_max_scopes(mut self, scopes: u32) -> Self {
+        self.max_scopes = Some(scopes);
+        self
+    }
+
+    /// Number of resolved frames kept for [`GpuProfiler::chrome_trace_json`]
+    pub fn with_history(mut self, frames: usize) -> Self {
+        self.history = Some(frames);
+        self
+    }
+
+    pub fn build(self) -> GpuProfiler {
+
+        let device = self.device.expect("Device is missing");
+        let phys_info = self.phys_info.expect("Physical device info is missing");
+        let family_index = self.family_index.expect("Family index is missing");
+        let frames_in_flight = self.frames_in_flight.unwrap_or(2);
+        let max_scopes = self.max_scopes.unwrap_or(128);
+
+        let valid_bits = phys_info.queue_family_prop
+            .get(family_index as usize)
+            .map(|family| family.timestamp_valid_bits)
+            .unwrap_or(0);
+
+        let timestamp_period = phys_info.phys_prop.limits.timestamp_period as f64;
+        let mut enabled = valid_bits != 0 && timestamp_period > 0.0;
+
+        if !enabled {
+            warn!("Queue family {} does not support timestamps, GPU profiler is disabled", family_index);
+        }
+
+        let mut frames = vec![];
+
+        if enabled {
+            for _ in 0..frames_in_flight {
+
+                let pool = QueryPoolBuilder::new()
+                    .with_device(device)
+                    .with_query_type(QueryType::TIMESTAMP)
+                    .with_count(max_scopes * 2)
+                    .build();
+
+                match pool {
+                    Ok(pool) => frames.push(ProfilerFrame {
+                        pool,
+                        scopes: vec![],
+                        next_query: 0,
+                        frame: 0,
+                        cpu_start: None,
+                        pending: false,
+                    }),
+                    Err(err) => {
+                        warn!("Failed to create timestamp query pool: {:?}, GPU profiler is disabled", err);
+                        frames.drain(..).for_each(|frame: ProfilerFrame| frame.pool.destroy(device));
+                        enabled = false;
+                        break;
+                    }
+                }
+            }
+        }
+
+        let timestamp_mask = if valid_bits >= 64 { u64::MAX } else { (1u64 << valid_bits) - 1 };
+
+        GpuProfiler {
+            frames,
+            current: 0,
+            frame_counter: 0,
+            timestamp_period,
+            timestamp_mask,
+            enabled,
+            history: VecDeque::new(),
+            history_len: self.history.unwrap_or(120).max(1),
+            overflow_reported: false,
+            #[cfg(feature = "puffin")]
+            puffin_scopes: Default::default(),
+        }
+    }
+}
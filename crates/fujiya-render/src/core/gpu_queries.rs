@@ -0,0 +1,377 @@
+use ash::vk::{self, PhysicalDeviceFeatures, QueryControlFlags, QueryPipelineStatisticFlags, QueryType, TRUE};
+use log::warn;
+
+use crate::{QueryPool, QueryPoolBuilder};
+
+/// Counters collected by pipeline statistics queries, in the order Vulkan writes them
+const STATISTICS: QueryPipelineStatisticFlags = QueryPipelineStatisticFlags::from_raw(
+    QueryPipelineStatisticFlags::INPUT_ASSEMBLY_VERTICES.as_raw()
+        | QueryPipelineStatisticFlags::INPUT_ASSEMBLY_PRIMITIVES.as_raw()
+        | QueryPipelineStatisticFlags::VERTEX_SHADER_INVOCATIONS.as_raw()
+        | QueryPipelineStatisticFlags::CLIPPING_INVOCATIONS.as_raw()
+        | QueryPipelineStatisticFlags::CLIPPING_PRIMITIVES.as_raw()
+        | QueryPipelineStatisticFlags::FRAGMENT_SHADER_INVOCATIONS.as_raw()
+        | QueryPipelineStatisticFlags::COMPUTE_SHADER_INVOCATIONS.as_raw()
+);
+
+///
+/// Result of one pipeline statistics query
+///
+#[derive(Debug, Clone, Default)]
+pub struct PipelineStatistics {
+    pub name: String,
+    pub input_assembly_vertices: u64,
+    pub input_assembly_primitives: u64,
+    pub vertex_shader_invocations: u64,
+    pub clipping_invocations: u64,
+    pub clipping_primitives: u64,
+    pub fragment_shader_invocations: u64,
+    pub compute_shader_invocations: u64,
+}
+
+///
+/// Result of one occlusion query
+///
+/// Without `occlusionQueryPrecise` the value is only guaranteed to be non-zero
+/// when any sample passed.
+///
+#[derive(Debug, Clone, Default)]
+pub struct OcclusionResult {
+    pub name: String,
+    pub samples_passed: u64,
+    pub precise: bool,
+}
+
+/// Handle of an open query, see [`GpuQueries::begin_statistics`] and [`GpuQueries::begin_occlusion`]
+#[derive(Debug, Clone, Copy)]
+pub struct GpuQuery(Option<u32>);
+
+struct QueriesFrame {
+    statistics_pool: Option<QueryPool>,
+    occlusion_pool: QueryPool,
+    statistics: Vec<String>,
+    occlusion: Vec<(String, bool)>,
+    pending: bool,
+}
+
+///
+/// Pipeline statistics and occlusion queries with one pool of each per frame in flight
+///
+/// A query must begin and end in the same command buffer. Pipeline statistics are only
+/// collected if `pipelineStatisticsQuery` was enabled on the device, precise occlusion
+/// needs `occlusionQueryPrecise`.
+///
+/// # Example:
+///
+/// ```ignore
+/// let query = queries.begin_statistics(&device.raw, command_buffer, "GBuffer");
+/// device.cmd_draw(command_buffer, 3, 1, 0, 0);
+/// queries.end_statistics(&device.raw, command_buffer, query);
+///
+/// for stats in queries.last_statistics() {
+///     println!("{}: {} fragments", stats.name, stats.fragment_shader_invocations);
+/// }
+/// ```
+///
+pub struct GpuQueries {
+    frames: Vec<QueriesFrame>,
+    current: usize,
+    max_queries: u32,
+    statistics_enabled: bool,
+    precise_occlusion: bool,
+    last_statistics: Vec<PipelineStatistics>,
+    last_occlusion: Vec<OcclusionResult>,
+    overflow_reported: bool,
+}
+
+impl GpuQueries {
+
+    pub fn statistics_enabled(&self) -> bool {
+        self.statistics_enabled
+    }
+
+    pub fn precise_occlusion(&self) -> bool {
+        self.precise_occlusion
+    }
+
+    ///
+    /// Start recording frame `frame_index` (the frame in flight slot)
+    ///
+    /// The fence of the slot must be signaled. Resolves the previous results of the slot
+    /// and records a reset into `command_buffer`, which must be outside of a render pass.
+    ///
+    pub fn begin_frame(&mut self, device: &ash::Device, frame_index: usize, command_buffer: vk::CommandBuffer) {
+
+        self.current = frame_index;
+        self.resolve(device, frame_index);
+
+        let frame = &mut self.frames[frame_index];
+
+        if let Some(pool) = &frame.statistics_pool {
+            pool.cmd_reset(device, command_buffer, 0, pool.count);
+        }
+
+        frame.occlusion_pool.cmd_reset(device, command_buffer, 0, frame.occlusion_pool.count);
+        frame.statistics.clear();
+        frame.occlusion.clear();
+        frame.pending = true;
+    }
+
+    /// Begin a pipeline statistics query, a no-op if the feature is not enabled
+    pub fn begin_statistics(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer, name: &str) -> GpuQuery {
+
+        let frame = &mut self.frames[self.current];
+
+        let Some(pool) = &frame.statistics_pool else {
+            return GpuQuery(None);
+        };
+
+        if frame.statistics.len() as u32 == self.max_queries {
+            Self::report_overflow(&mut self.overflow_reported, name);
+            return GpuQuery(None);
+        }
+
+        let query = frame.statistics.len() as u32;
+        frame.statistics.push(name.to_string());
+
+        unsafe { device.cmd_begin_query(command_buffer, pool.raw, query, QueryControlFlags::empty()) };
+        GpuQuery(Some(query))
+    }
+
+    pub fn end_statistics(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer, query: GpuQuery) {
+
+        let (Some(query), Some(pool)) = (query.0, &self.frames[self.current].statistics_pool) else {
+            return;
+        };
+
+        unsafe { device.cmd_end_query(command_buffer, pool.raw, query) };
+    }
+
+    ///
+    /// Begin an occlusion query
+    ///
+    /// `precise` asks for the exact sample count, it is ignored when `occlusionQueryPrecise`
+    /// is not enabled.
+    ///
+    pub fn begin_occlusion(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer, name: &str, precise: bool) -> GpuQuery {
+
+        let frame = &mut self.frames[self.current];
+
+        if frame.occlusion.len() as u32 == self.max_queries {
+            Self::report_overflow(&mut self.overflow_reported, name);
+            return GpuQuery(None);
+        }
+
+        let precise = precise && self.precise_occlusion;
+        let flags = if precise { QueryControlFlags::PRECISE } else { QueryControlFlags::empty() };
+        let query = frame.occlusion.len() as u32;
+        frame.occlusion.push((name.to_string(), precise));
+
+        unsafe { device.cmd_begin_query(command_buffer, frame.occlusion_pool.raw, query, flags) };
+        GpuQuery(Some(query))
+    }
+
+    pub fn end_occlusion(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer, query: GpuQuery) {
+
+        let Some(query) = query.0 else {
+            return;
+        };
+
+        unsafe { device.cmd_end_query(command_buffer, self.frames[self.current].occlusion_pool.raw, query) };
+    }
+
+    /// Pipeline statistics of the most recently resolved frame
+    pub fn last_statistics(&self) -> &[PipelineStatistics] {
+        &self.last_statistics
+    }
+
+    /// Occlusion results of the most recently resolved frame
+    pub fn last_occlusion(&self) -> &[OcclusionResult] {
+        &self.last_occlusion
+    }
+
+    fn report_overflow(reported: &mut bool, name: &str) {
+        if !*reported {
+            warn!("GPU queries are out of slots, query {:?} is skipped", name);
+            *reported = true;
+        }
+    }
+
+    fn resolve(&mut self, device: &ash::Device, frame_index: usize) {
+
+        let frame = &mut self.frames[frame_index];
+
+        if !frame.pending {
+            return;
+        }
+
+        frame.pending = false;
+
+        if let Some(pool) = &frame.statistics_pool {
+            match pool.get_results(device, 0, frame.statistics.len() as u32) {
+                Ok(results) => {
+                    self.last_statistics = frame.statistics.iter()
+                        .zip(results)
+                        .filter_map(|(name, values)| {
+                            let v = values?;
+                            Some(PipelineStatistics {
+                                name: name.clone(),
+                                input_assembly_vertices: v[0],
+                                input_assembly_primitives: v[1],
+                                vertex_shader_invocations: v[2],
+                                clipping_invocations: v[3],
+                                clipping_primitives: v[4],
+                                fragment_shader_invocations: v[5],
+                                compute_shader_invocations: v[6],
+                            })
+                        })
+                        .collect();
+                },
+                Err(err) => warn!("Failed to read pipeline statistics: {:?}", err)
+            }
+        }
+
+        match frame.occlusion_pool.get_results(device, 0, frame.occlusion.len() as u32) {
+            Ok(results) => {
+                self.last_occlusion = frame.occlusion.iter()
+                    .zip(results)
+                    .filter_map(|((name, precise), values)| {
+                        Some(OcclusionResult {
+                            name: name.clone(),
+                            samples_passed: values?[0],
+                            precise: *precise,
+                        })
+                    })
+                    .collect();
+            },
+            Err(err) => warn!("Failed to read occlusion queries: {:?}", err)
+        }
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device) {
+        for frame in &self.frames {
+            if let Some(pool) = &frame.statistics_pool {
+                pool.destroy(device);
+            }
+            frame.occlusion_pool.destroy(device);
+        }
+        self.frames.clear();
+    }
+}
+
+///
+/// Default values:
+///     - frames_in_flight = 2
+///     - max_queries = 64 of each type per frame
+///
+#[derive(Default)]
+pub struct GpuQueriesBuilder<'n> {
+    device: Option<&'n ash::Device>,
+    features: Option<&'n PhysicalDeviceFeatures>,
+    frames_in_flight: Option<usize>,
+    max_queries: Option<u32>
+}
+
+impl<'n> GpuQueriesBuilder<'n> {
+
+    pub fn new() -> Self {
+        Self { ..Default::default() }
+    }
+
+    pub fn with_device(mut self, device: &'n ash::Device) -> Self {
+        self.device = Some(device);
+        self
+    }
+
+    /// Features enabled on the device, see [`crate::Device::features`]
+    pub fn with_features(mut self, features: &'n PhysicalDeviceFeatures) -> Self {
+        self.features = Some(features);
+        self
+    }
+
+    pub fn with_frames_in_flight(mut self, frames: usize) -> Self {
+        self.frames_in_flight = Some(frames);
+        self
+    }
+
+    pub fn with_max_queries(mut self, queries: u32) -> Self {
+        self.max_queries = Some(queries);
+        self
+    }
+
+    pub fn build(self) -> Result<GpuQueries, vk::Result> {
+
+        let device = self.device.expect("Device is missing");
+        let features = self.features.expect("Device features are missing");
+        let frames_in_flight = self.frames_in_flight.unwrap_or(2);
+        let max_queries = self.max_queries.unwrap_or(64);
+
+        let statistics_enabled = features.pipeline_statistics_query == TRUE;
+        let precise_occlusion = features.occlusion_query_precise == TRUE;
+
+        if !statistics_enabled {
+            warn!("pipelineStatisticsQuery is not enabled, pipeline statistics are disabled");
+        }
+
+        let mut queries = GpuQueries {
+            frames: vec![],
+            current: 0,
+            max_queries,
+            statistics_enabled,
+            precise_occlusion,
+            last_statistics: vec![],
+            last_occlusion: vec![],
+            overflow_reported: false,
+        };
+
+        for _ in 0..frames_in_flight {
+
+            let statistics_pool = if statistics_enabled {
+                let pool = QueryPoolBuilder::new()
+                    .with_device(device)
+                    .with_query_type(QueryType::PIPELINE_STATISTICS)
+                    .with_pipeline_statistics(STATISTICS)
+                    .with_count(max_queries)
+                    .build();
+
+                match pool {
+                    Ok(pool) => Some(pool),
+                    Err(err) => {
+                        queries.destroy(device);
+                        return Err(err);
+                    }
+                }
+            } else {
+                None
+            };
+
+            let occlusion_pool = QueryPoolBuilder::new()
+                .with_device(device)
+                .with_query_type(QueryType::OCCLUSION)
+                .with_count(max_queries)
+                .build();
+
+            let occlusion_pool = match occlusion_pool {
+                Ok(pool) => pool,
+                Err(err) => {
+                    // Пулы уже созданных кадров и пул статистики этого кадра
+                    if let Some(pool) = &statistics_pool {
+                        pool.destroy(device);
+                    }
+                    queries.destroy(device);
+                    return Err(err);
+                }
+            };
+
+            queries.frames.push(QueriesFrame {
+                statistics_pool,
+                occlusion_pool,
+                statistics: vec![],
+                occlusion: vec![],
+                pending: false,
+            });
+        }
+
+        Ok(queries)
+    }
+}
@@ -31,6 +31,8 @@ pub struct InstanceBuilder<'n> {
 pub struct Instance {
     pub raw: ash::Instance,
     pub raw_entry: Entry,
+    /// Api version requested in [`ApplicationInfo`]
+    pub api_version: u32,
 }
 
 impl<'n> InstanceBuilder<'n> {
@@ -106,7 +108,8 @@ impl<'n> InstanceBuilder<'n> {
         let instance = unsafe { entry.create_instance(&create_info, None).expect("Error create Instance") };
         Instance {
             raw: instance,
-            raw_entry: entry
+            raw_entry: entry,
+            api_version: app_info.api_version
         }
     }
 
@@ -11,12 +11,24 @@ pub(crate) mod image_views;
 pub(crate) mod render_pass;
 pub(crate) mod swapchain;
 pub(crate) mod command_pool;
+pub(crate) mod command_allocator;
+pub(crate) mod thread_command_pools;
 pub(crate) mod pipeline;
 pub(crate) mod sync;
 pub(crate) mod frame_buffers;
 pub(crate) mod gpu_buffer;
+pub(crate) mod gpu_image;
+pub(crate) mod access;
 pub(crate) mod descriptor_pool;
 pub(crate) mod descriptor_set_layout;
+pub(crate) mod descriptor_set;
+pub(crate) mod descriptor_writer;
+pub(crate) mod bindless;
+pub(crate) mod shader_reflection;
+pub(crate) mod shader_watcher;
+pub(crate) mod query_pool;
+pub(crate) mod gpu_profiler;
+pub(crate) mod gpu_queries;
 
 pub use app::*;
 pub use instance::*;
@@ -30,9 +42,21 @@ pub use image_views::*;
 pub use render_pass::*;
 pub use swapchain::*;
 pub use command_pool::*;
+pub use command_allocator::*;
+pub use thread_command_pools::*;
 pub use pipeline::*;
 pub use sync::*;
 pub use frame_buffers::*;
 pub use gpu_buffer::*;
+pub use gpu_image::*;
+pub use access::*;
 pub use descriptor_pool::*;
-pub use descriptor_set_layout::*;
\ No newline at end of file
+pub use descriptor_set_layout::*;
+pub use descriptor_set::*;
+pub use descriptor_writer::*;
+pub use bindless::*;
+pub use shader_reflection::*;
+pub use shader_watcher::*;
+pub use query_pool::*;
+pub use gpu_profiler::*;
+pub use gpu_queries::*;
\ No newline at end of file
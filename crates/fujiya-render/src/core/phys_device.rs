@@ -16,7 +16,10 @@ pub struct PhysicalDeviceInfo {
     pub features: PhysicalDeviceFeatures,
     pub extensions: Vec<ExtensionProperties>,
     pub layers: Vec<LayerProperties>,
-    pub support_surface: bool
+    pub support_surface: bool,
+    /// Filled only when both the instance and the device support Vulkan 1.2
+    pub descriptor_indexing: PhysicalDeviceDescriptorIndexingFeatures<'static>,
+    pub descriptor_indexing_prop: PhysicalDeviceDescriptorIndexingProperties<'static>
 }
 
 #[derive(Default)]
@@ -24,6 +27,7 @@ pub struct PhysicalDeviceBuilder<'n >{
     pub instance: Option<&'n ash::Instance>,
     pub surface_load: Option<&'n ash::khr::surface::Instance>,
     pub surface: Option<&'n ash::vk::SurfaceKHR>,
+    pub api_version: Option<u32>,
     pub fn_select_phys_dev: Option<Box<dyn FnOnce(&Vec<PhysicalDeviceInfo>) -> usize>>
 }
 
@@ -43,6 +47,12 @@ impl<'n> PhysicalDeviceBuilder<'n> {
         self
     }
 
+    /// Api version of the instance, enables Vulkan 1.2 feature queries
+    pub fn with_api_version(mut self, api_version: u32) -> Self {
+        self.api_version = Some(api_version);
+        self
+    }
+
     fn phys_device_info(&self, phys_dev: &ash::vk::PhysicalDevice, instance: &ash::Instance) -> PhysicalDeviceInfo {
 
         let surface_load = self.surface_load.unwrap();
@@ -65,6 +75,24 @@ impl<'n> PhysicalDeviceBuilder<'n> {
                 }
             }
 
+            let mut descriptor_indexing = PhysicalDeviceDescriptorIndexingFeatures::default();
+            let mut descriptor_indexing_prop = PhysicalDeviceDescriptorIndexingProperties::default();
+            let api_version = self.api_version.unwrap_or(API_VERSION_1_0).min(phys_prop.api_version);
+
+            if api_version >= API_VERSION_1_2 {
+
+                let mut features2 = PhysicalDeviceFeatures2::default()
+                    .push_next(&mut descriptor_indexing);
+                instance.get_physical_device_features2(phys_dev, &mut features2);
+
+                let mut prop2 = PhysicalDeviceProperties2::default()
+                    .push_next(&mut descriptor_indexing_prop);
+                instance.get_physical_device_properties2(phys_dev, &mut prop2);
+
+                descriptor_indexing.p_next = std::ptr::null_mut();
+                descriptor_indexing_prop.p_next = std::ptr::null_mut();
+            }
+
             PhysicalDeviceInfo{
                 phys_prop,
                 memory_prop,
@@ -72,7 +100,9 @@ impl<'n> PhysicalDeviceBuilder<'n> {
                 features,
                 extensions,
                 layers,
-                support_surface: support
+                support_surface: support,
+                descriptor_indexing,
+                descriptor_indexing_prop
             }
         }
     }
@@ -1,8 +1,141 @@
+use std::ffi::CStr;
+
 use ash::vk::*;
+use bytemuck::Pod;
+
+use crate::{ShaderProgram, SpecializationData};
 
 pub struct RenderPipeline {
     pub raw: Pipeline,
-    pub raw_layout: PipelineLayout
+    pub raw_layout: PipelineLayout,
+    pub bind_point: PipelineBindPoint,
+    pub push_constant_ranges: Vec<PushConstantRange>
+}
+
+///
+/// View a value as the bytes passed to `cmd_push_constants`
+///
+/// [`bytemuck::Pod`] guarantees the struct has no padding, so every byte is initialized.
+///
+pub fn as_push_constant_bytes<T: Pod>(value: &T) -> &[u8] {
+    bytemuck::bytes_of(value)
+}
+
+///
+/// `cmd_push_constants` calls that write bytes `offset..end` into `ranges`
+///
+/// The bytes are split at the range bounds, so every part lies inside each range it overlaps
+/// and gets the stages of exactly those ranges. `None` if some byte is outside of every range.
+///
+pub fn push_constant_writes(ranges: &[PushConstantRange], offset: u32, end: u32) -> Option<Vec<(ShaderStageFlags, u32, u32)>> {
+
+    let mut bounds = vec![offset, end];
+    for range in ranges {
+        for bound in [range.offset, range.offset + range.size] {
+            if offset < bound && bound < end && !bounds.contains(&bound) {
+                bounds.push(bound);
+            }
+        }
+    }
+    bounds.sort();
+
+    bounds.windows(2).map(|part| {
+        let stages = ranges.iter()
+            .filter(|range| range.offset <= part[0] && part[1] <= range.offset + range.size)
+            .fold(ShaderStageFlags::empty(), |stages, range| stages | range.stage_flags);
+
+        (!stages.is_empty()).then_some((stages, part[0], part[1]))
+    }).collect()
+}
+
+impl RenderPipeline {
+
+    ///
+    /// Push a `#[repr(C)]` struct without padding at `offset`
+    ///
+    /// The bytes are split at the push constant ranges of the layout, see
+    /// [`push_constant_writes`]. Bytes outside of every range are a bug in the caller: it is
+    /// logged and nothing is pushed.
+    ///
+    /// # Example:
+    ///
+    /// ```ignore
+    /// #[repr(C)]
+    /// #[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+    /// struct DrawData {
+    ///     model: [[f32; 4]; 4],
+    ///     albedo: u32,
+    /// }
+    ///
+    /// pipeline.push_constants(device, command_buffer, 0, &DrawData { model, albedo });
+    /// ```
+    ///
+    pub fn push_constants<T: Pod>(&self, device: &ash::Device, command_buffer: CommandBuffer, offset: u32, value: &T) {
+
+        let bytes = as_push_constant_bytes(value);
+        let end = offset + bytes.len() as u32;
+
+        let Some(writes) = push_constant_writes(&self.push_constant_ranges, offset, end) else {
+            log::error!("Push constants {}..{} are outside of the ranges {:?}", offset, end, self.push_constant_ranges);
+            debug_assert!(false, "No push constant range covers bytes {}..{}", offset, end);
+            return;
+        };
+
+        for (stages, start, stop) in writes {
+            let part = &bytes[(start - offset) as usize..(stop - offset) as usize];
+            unsafe { device.cmd_push_constants(command_buffer, self.raw_layout, stages, start, part) };
+        }
+    }
+
+    pub fn bind(&self, device: &ash::Device, command_buffer: CommandBuffer) {
+        unsafe { device.cmd_bind_pipeline(command_buffer, self.bind_point, self.raw) };
+    }
+
+    /// The pipeline must not be used by any pending command buffer
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            device.destroy_pipeline(self.raw, None);
+            device.destroy_pipeline_layout(self.raw_layout, None);
+        }
+    }
+}
+
+/// Shader stage of a pipeline being built
+#[derive(Clone, Copy)]
+struct PipelineStage<'n> {
+    stage: ShaderStageFlags,
+    module: ShaderModule,
+    entry_point: &'n CStr,
+    specialization: Option<&'n SpecializationData>
+}
+
+impl<'n> PipelineStage<'n> {
+
+    fn from_program(program: &'n ShaderProgram) -> impl Iterator<Item = Self> + 'n {
+        program.stages.iter().map(|stage| Self {
+            stage: stage.stage,
+            module: stage.module,
+            entry_point: &stage.entry_point,
+            specialization: stage.specialization.as_ref()
+        })
+    }
+}
+
+/// Stage create infos pointing into `specialization_infos`, which must outlive them
+fn stage_create_infos<'a>(stages: &'a [PipelineStage<'a>], specialization_infos: &'a [Option<SpecializationInfo<'a>>]) -> Vec<PipelineShaderStageCreateInfo<'a>> {
+    stages.iter().zip(specialization_infos)
+        .map(|(stage, specialization)| {
+            let info = PipelineShaderStageCreateInfo::default()
+                .module(stage.module)
+                .name(stage.entry_point)
+                .stage(stage.stage);
+
+            match specialization {
+                Some(specialization) => info.specialization_info(specialization),
+                None => info,
+            }
+        })
+        .collect()
 }
 
 #[derive(Default)]
@@ -13,8 +146,8 @@ pub struct RenderPipelineBuilder<'n> {
     input_assembly_info: Option<PipelineInputAssemblyStateCreateInfo<'n>>,
     #[allow(dead_code)]
     multisampling_info: Option<PipelineMultisampleStateCreateInfo<'n>>,
-    vertex_shader: Option<ShaderModule>,
-    fragment_shader: Option<ShaderModule>,
+    stages: Vec<PipelineStage<'n>>,
+    patch_control_points: Option<u32>,
     #[allow(dead_code)]
     viewports: Option<bool>,
     #[allow(dead_code)]
@@ -26,7 +159,9 @@ pub struct RenderPipelineBuilder<'n> {
     vertex_input_info: Option<PipelineVertexInputStateCreateInfo<'n>>,
     resolution: Option<Extent2D>,
     format: Option<Format>,
-    render_pass: Option<&'n RenderPass>
+    render_pass: Option<&'n RenderPass>,
+    set_layouts: Vec<DescriptorSetLayout>,
+    push_constant_ranges: Vec<PushConstantRange>
 }
 
 impl<'n> RenderPipelineBuilder<'n> {
@@ -39,13 +174,33 @@ impl<'n> RenderPipelineBuilder<'n> {
         self
     }
 
-    pub fn with_vertex_shader(mut self, shader: ShaderModule) -> Self {
-        self.vertex_shader = Some(shader);
+    pub fn with_vertex_shader(self, shader: ShaderModule) -> Self {
+        self.add_stage(ShaderStageFlags::VERTEX, shader, c"main")
+    }
+
+    pub fn with_fragment_shader(self, shader: ShaderModule) -> Self {
+        self.add_stage(ShaderStageFlags::FRAGMENT, shader, c"main")
+    }
+
+    /// Add or replace the module of `stage`
+    pub fn add_stage(mut self, stage: ShaderStageFlags, shader: ShaderModule, entry_point: &'n CStr) -> Self {
+        self.stages.retain(|pipeline_stage| pipeline_stage.stage != stage);
+        self.stages.push(PipelineStage { stage, module: shader, entry_point, specialization: None });
         self
     }
 
-    pub fn with_fragment_shader(mut self, shader: ShaderModule) -> Self {
-        self.fragment_shader = Some(shader);
+    /// Every stage of the program with its entry point and specialization constants
+    pub fn with_shader_program(mut self, program: &'n ShaderProgram) -> Self {
+        for stage in PipelineStage::from_program(program) {
+            self.stages.retain(|pipeline_stage| pipeline_stage.stage != stage.stage);
+            self.stages.push(stage);
+        }
+        self
+    }
+
+    /// Vertices per patch for tessellation, 3 by default
+    pub fn with_tessellation_patch_control_points(mut self, count: u32) -> Self {
+        self.patch_control_points = Some(count);
         self
     }
 
@@ -82,19 +237,56 @@ impl<'n> RenderPipelineBuilder<'n> {
         self
     }
 
-    pub fn build(self, desc: DescriptorSetLayout) -> RenderPipeline {
+    /// Layout of the next descriptor set, sets are numbered in the order they are added
+    pub fn add_set_layout(mut self, layout: DescriptorSetLayout) -> Self {
+        self.set_layouts.push(layout);
+        self
+    }
 
-        let shader_states_infos = [
-            PipelineShaderStageCreateInfo::default()
-                .module(self.vertex_shader.unwrap())
-                .name(c"main")
-                .stage(ShaderStageFlags::VERTEX),
+    pub fn with_set_layouts(mut self, layouts: &[DescriptorSetLayout]) -> Self {
+        self.set_layouts.extend_from_slice(layouts);
+        self
+    }
 
-            PipelineShaderStageCreateInfo::default()
-                .module(self.fragment_shader.unwrap())
-                .name(c"main")
-                .stage(ShaderStageFlags::FRAGMENT),
-        ];
+    pub fn add_push_constant_range(mut self, range: PushConstantRange) -> Self {
+        self.push_constant_ranges.push(range);
+        self
+    }
+
+    pub fn with_push_constant_ranges(mut self, ranges: &[PushConstantRange]) -> Self {
+        self.push_constant_ranges.extend_from_slice(ranges);
+        self
+    }
+
+    /// Push constant range of `size_of::<T>()` bytes at `offset` visible to `stages`
+    pub fn add_push_constants<T: Pod>(self, stages: ShaderStageFlags, offset: u32) -> Self {
+        self.add_push_constant_range(
+            PushConstantRange::default()
+                .stage_flags(stages)
+                .offset(offset)
+                .size(std::mem::size_of::<T>() as u32)
+        )
+    }
+
+    pub fn build(self) -> RenderPipeline {
+
+        assert!(
+            self.stages.iter().any(|stage| stage.stage == ShaderStageFlags::VERTEX),
+            "Vertex shader is missing"
+        );
+
+        let specialization_infos = self.stages.iter()
+            .map(|stage| stage.specialization.map(|specialization| specialization.info()))
+            .collect::<Vec<_>>();
+
+        let shader_states_infos = stage_create_infos(&self.stages, &specialization_infos);
+
+        let has_tessellation = self.stages.iter().any(|stage| stage.stage.intersects(
+            ShaderStageFlags::TESSELLATION_CONTROL | ShaderStageFlags::TESSELLATION_EVALUATION
+        ));
+
+        let tessellation_info = PipelineTessellationStateCreateInfo::default()
+            .patch_control_points(self.patch_control_points.unwrap_or(3));
 
         let vertex_input_info = self.vertex_input_info.unwrap_or(PipelineVertexInputStateCreateInfo::default());
         let input_assembly_info = self.input_assembly_info.unwrap();
@@ -159,13 +351,13 @@ impl<'n> RenderPipelineBuilder<'n> {
         let mut rendering_info = PipelineRenderingCreateInfo::default()
             .color_attachment_formats(&color_attachment_formats);
 
-        let binding = [desc];
         let layout_info = PipelineLayoutCreateInfo::default()
-            .set_layouts(&binding);
+            .set_layouts(&self.set_layouts)
+            .push_constant_ranges(&self.push_constant_ranges);
 
         let pipeline_layout = unsafe { self.device.unwrap().create_pipeline_layout(&layout_info, None).unwrap() };
 
-        let pipeline_info = GraphicsPipelineCreateInfo::default()
+        let mut pipeline_info = GraphicsPipelineCreateInfo::default()
             .stages(&shader_states_infos)
             .vertex_input_state(&vertex_input_info)
             .input_assembly_state(&input_assembly_info)
@@ -177,6 +369,10 @@ impl<'n> RenderPipelineBuilder<'n> {
             .render_pass(*self.render_pass.unwrap())
             .push_next(&mut rendering_info);
 
+        if has_tessellation {
+            pipeline_info = pipeline_info.tessellation_state(&tessellation_info);
+        }
+
         let pipeline = unsafe {
             self.device.unwrap()
                 .create_graphics_pipelines(
@@ -187,7 +383,159 @@ impl<'n> RenderPipelineBuilder<'n> {
                 .map_err(|e| e.1)
         };
 
-        RenderPipeline { raw: pipeline.unwrap()[0], raw_layout: pipeline_layout }
+        RenderPipeline {
+            raw: pipeline.unwrap()[0],
+            raw_layout: pipeline_layout,
+            bind_point: PipelineBindPoint::GRAPHICS,
+            push_constant_ranges: self.push_constant_ranges
+        }
+    }
+}
+
+///
+/// Builds a compute pipeline from the compute stage of a [`ShaderProgram`]
+///
+/// # Example:
+///
+/// ```ignore
+/// let program = ShaderProgramBuilder::new()
+///     .with_device(device)
+///     .with_compute_shader(shaders::CULL_COMP.to_vec())
+///     .with_specialization(vk::ShaderStageFlags::COMPUTE, &CullConstants { group_size: 64 })
+///     .build();
+///
+/// let pipeline = ComputePipelineBuilder::new()
+///     .with_device(device)
+///     .with_shader_program(&program)
+///     .with_set_layouts(&set_layouts)
+///     .build();
+///
+/// pipeline.bind(device, command_buffer);
+/// device.cmd_dispatch(command_buffer, count.div_ceil(64), 1, 1);
+/// ```
+///
+#[derive(Default)]
+pub struct ComputePipelineBuilder<'n> {
+    device: Option<&'n ash::Device>,
+    stage: Option<PipelineStage<'n>>,
+    set_layouts: Vec<DescriptorSetLayout>,
+    push_constant_ranges: Vec<PushConstantRange>
+}
+
+impl<'n> ComputePipelineBuilder<'n> {
+
+    pub fn new() -> Self {
+        Self { ..Default::default() }
+    }
+
+    pub fn with_device(mut self, dev: &'n ash::Device) -> Self {
+        self.device = Some(dev);
+        self
+    }
+
+    pub fn with_compute_shader(mut self, shader: ShaderModule, entry_point: &'n CStr) -> Self {
+        self.stage = Some(PipelineStage { stage: ShaderStageFlags::COMPUTE, module: shader, entry_point, specialization: None });
+        self
+    }
+
+    pub fn with_shader_program(mut self, program: &'n ShaderProgram) -> Self {
+        self.stage = PipelineStage::from_program(program).find(|stage| stage.stage == ShaderStageFlags::COMPUTE);
+        self
+    }
+
+    pub fn add_set_layout(mut self, layout: DescriptorSetLayout) -> Self {
+        self.set_layouts.push(layout);
+        self
+    }
+
+    pub fn with_set_layouts(mut self, layouts: &[DescriptorSetLayout]) -> Self {
+        self.set_layouts.extend_from_slice(layouts);
+        self
+    }
+
+    pub fn add_push_constant_range(mut self, range: PushConstantRange) -> Self {
+        self.push_constant_ranges.push(range);
+        self
+    }
+
+    pub fn add_push_constants<T: Pod>(self, offset: u32) -> Self {
+        self.add_push_constant_range(
+            PushConstantRange::default()
+                .stage_flags(ShaderStageFlags::COMPUTE)
+                .offset(offset)
+                .size(std::mem::size_of::<T>() as u32)
+        )
+    }
+
+    pub fn build(self) -> RenderPipeline {
+
+        let device = self.device.expect("Device is missing");
+        let stages = [self.stage.expect("Compute shader is missing")];
+
+        let specialization_infos = [stages[0].specialization.map(|specialization| specialization.info())];
+        let shader_states_infos = stage_create_infos(&stages, &specialization_infos);
+
+        let layout_info = PipelineLayoutCreateInfo::default()
+            .set_layouts(&self.set_layouts)
+            .push_constant_ranges(&self.push_constant_ranges);
+
+        let pipeline_layout = unsafe { device.create_pipeline_layout(&layout_info, None).unwrap() };
+
+        let pipeline_info = ComputePipelineCreateInfo::default()
+            .stage(shader_states_infos[0])
+            .layout(pipeline_layout);
+
+        let pipeline = unsafe {
+            device
+                .create_compute_pipelines(
+                    PipelineCache::null(),
+                    std::slice::from_ref(&pipeline_info),
+                    None,
+                )
+                .map_err(|e| e.1)
+        };
+
+        RenderPipeline {
+            raw: pipeline.unwrap()[0],
+            raw_layout: pipeline_layout,
+            bind_point: PipelineBindPoint::COMPUTE,
+            push_constant_ranges: self.push_constant_ranges
+        }
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(stage_flags: ShaderStageFlags, offset: u32, size: u32) -> PushConstantRange {
+        PushConstantRange { stage_flags, offset, size }
+    }
+
+    #[test]
+    fn splits_writes_at_range_bounds() {
+        let ranges = [range(ShaderStageFlags::VERTEX, 0, 16), range(ShaderStageFlags::FRAGMENT, 16, 16)];
+
+        assert_eq!(push_constant_writes(&ranges, 0, 32), Some(vec![
+            (ShaderStageFlags::VERTEX, 0, 16),
+            (ShaderStageFlags::FRAGMENT, 16, 32),
+        ]));
+
+        // Общие байты получают стадии обоих диапазонов
+        let ranges = [range(ShaderStageFlags::VERTEX, 0, 32), range(ShaderStageFlags::FRAGMENT, 16, 16)];
+        assert_eq!(push_constant_writes(&ranges, 8, 32), Some(vec![
+            (ShaderStageFlags::VERTEX, 8, 16),
+            (ShaderStageFlags::VERTEX | ShaderStageFlags::FRAGMENT, 16, 32),
+        ]));
+    }
+
+    #[test]
+    fn rejects_bytes_outside_of_the_ranges() {
+        let ranges = [range(ShaderStageFlags::COMPUTE, 0, 16)];
+
+        assert_eq!(push_constant_writes(&ranges, 0, 16), Some(vec![(ShaderStageFlags::COMPUTE, 0, 16)]));
+        assert_eq!(push_constant_writes(&ranges, 0, 20), None);
+        assert_eq!(push_constant_writes(&ranges, 16, 20), None);
+    }
+}
@@ -0,0 +1,131 @@
+use ash::vk::{self, QueryPipelineStatisticFlags, QueryResultFlags, QueryType};
+
+///
+/// Wraper around [`ash::vk::QueryPool`]
+///
+pub struct QueryPool {
+    pub raw: vk::QueryPool,
+    pub query_type: QueryType,
+    pub count: u32,
+    pub pipeline_statistics: QueryPipelineStatisticFlags,
+}
+
+impl QueryPool {
+
+    /// Record a reset of `count` queries starting from `first`, must be outside of a render pass
+    pub fn cmd_reset(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, first: u32, count: u32) {
+        unsafe { device.cmd_reset_query_pool(command_buffer, self.raw, first, count) };
+    }
+
+    /// Number of values written per query
+    pub fn values_per_query(&self) -> usize {
+        match self.query_type {
+            QueryType::PIPELINE_STATISTICS => self.pipeline_statistics.as_raw().count_ones() as usize,
+            _ => 1,
+        }
+    }
+
+    ///
+    /// Read `count` queries starting from `first` without waiting
+    ///
+    /// Returns `None` for every query whose result is not available yet.
+    ///
+    pub fn get_results(&self, device: &ash::Device, first: u32, count: u32) -> Result<Vec<Option<Vec<u64>>>, vk::Result> {
+
+        if count == 0 {
+            return Ok(vec![]);
+        }
+
+        // Каждый запрос: значения + флаг доступности
+        let stride = self.values_per_query() + 1;
+        let mut data = vec![0u64; stride * count as usize];
+
+        let result = unsafe {
+            (device.fp_v1_0().get_query_pool_results)(
+                device.handle(),
+                self.raw,
+                first,
+                count,
+                std::mem::size_of_val(data.as_slice()),
+                data.as_mut_ptr().cast(),
+                (stride * std::mem::size_of::<u64>()) as u64,
+                QueryResultFlags::TYPE_64 | QueryResultFlags::WITH_AVAILABILITY,
+            )
+        };
+
+        match result {
+            vk::Result::SUCCESS | vk::Result::NOT_READY => {},
+            err => return Err(err)
+        }
+
+        Ok(data
+            .chunks_exact(stride)
+            .map(|query| {
+                let (values, available) = query.split_at(stride - 1);
+                (available[0] != 0).then(|| values.to_vec())
+            })
+            .collect())
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe { device.destroy_query_pool(self.raw, None) };
+    }
+}
+
+#[derive(Default)]
+pub struct QueryPoolBuilder<'n> {
+    device: Option<&'n ash::Device>,
+    query_type: Option<QueryType>,
+    count: Option<u32>,
+    pipeline_statistics: Option<QueryPipelineStatisticFlags>
+}
+
+impl<'n> QueryPoolBuilder<'n> {
+
+    pub fn new() -> Self {
+        Self { ..Default::default() }
+    }
+
+    pub fn with_device(mut self, device: &'n ash::Device) -> Self {
+        self.device = Some(device);
+        self
+    }
+
+    pub fn with_query_type(mut self, query_type: QueryType) -> Self {
+        self.query_type = Some(query_type);
+        self
+    }
+
+    pub fn with_count(mut self, count: u32) -> Self {
+        self.count = Some(count);
+        self
+    }
+
+    /// Counters written by [`QueryType::PIPELINE_STATISTICS`] queries
+    pub fn with_pipeline_statistics(mut self, flags: QueryPipelineStatisticFlags) -> Self {
+        self.pipeline_statistics = Some(flags);
+        self
+    }
+
+    pub fn build(self) -> Result<QueryPool, vk::Result> {
+
+        let device = self.device.expect("Device is missing");
+        let query_type = self.query_type.expect("Query type is missing");
+        let count = self.count.expect("Query count is missing");
+        let pipeline_statistics = self.pipeline_statistics.unwrap_or_default();
+
+        let create_info = vk::QueryPoolCreateInfo::default()
+            .query_type(query_type)
+            .query_count(count)
+            .pipeline_statistics(pipeline_statistics);
+
+        let pool = unsafe { device.create_query_pool(&create_info, None)? };
+
+        Ok(QueryPool {
+            raw: pool,
+            query_type,
+            count,
+            pipeline_statistics
+        })
+    }
+}
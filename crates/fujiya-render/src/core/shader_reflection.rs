@@ -0,0 +1,1034 @@
+use std::{collections::{BTreeMap, HashMap}, fmt};
+
+use ash::vk::{self, DescriptorType, Format, ShaderStageFlags};
+use log::warn;
+
+use crate::{DescriptorSetLayout, DescriptorSetLayoutBuilder};
+
+const SPIRV_MAGIC: u32 = 0x07230203;
+
+// Опкоды SPIR-V, которые нужны для рефлексии
+const OP_NAME: u32 = 5;
+const OP_ENTRY_POINT: u32 = 15;
+const OP_EXECUTION_MODE: u32 = 16;
+const OP_TYPE_VOID: u32 = 19;
+const OP_TYPE_BOOL: u32 = 20;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_SPEC_CONSTANT: u32 = 50;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+const OP_TYPE_ACCELERATION_STRUCTURE: u32 = 5341;
+
+const EXECUTION_MODE_LOCAL_SIZE: u32 = 17;
+
+const DECORATION_BLOCK: u32 = 2;
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_MATRIX_STRIDE: u32 = 7;
+const DECORATION_BUILTIN: u32 = 11;
+const DECORATION_LOCATION: u32 = 30;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+const STORAGE_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_INPUT: u32 = 1;
+const STORAGE_UNIFORM: u32 = 2;
+const STORAGE_OUTPUT: u32 = 3;
+const STORAGE_PUSH_CONSTANT: u32 = 9;
+const STORAGE_STORAGE_BUFFER: u32 = 12;
+
+const DIM_BUFFER: u32 = 5;
+const DIM_SUBPASS_DATA: u32 = 6;
+
+///
+/// Error returned by shader reflection
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReflectionError {
+    /// The module is not valid SPIR-V
+    InvalidSpirv(String),
+    /// No entry point with this name, or no entry point at all
+    MissingEntryPoint(String),
+    /// Two modules of the pipeline have the same stage
+    DuplicateStage(ShaderStageFlags),
+    /// The same set and binding is declared differently in two stages
+    BindingMismatch {
+        set: u32,
+        binding: u32,
+        first: String,
+        second: String,
+    },
+    /// The input of a stage doesn't match the output of the previous stage
+    InterfaceMismatch {
+        location: u32,
+        output: String,
+        input: String,
+    },
+    /// The input of a stage is not written by the previous stage
+    MissingOutput {
+        location: u32,
+        stage: ShaderStageFlags,
+        name: String,
+    },
+}
+
+impl fmt::Display for ReflectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidSpirv(reason) => write!(f, "invalid SPIR-V: {}", reason),
+            Self::MissingEntryPoint(name) => write!(f, "entry point {:?} not found", name),
+            Self::DuplicateStage(stage) => write!(f, "stage {:?} is present more than once", stage),
+            Self::BindingMismatch { set, binding, first, second } => write!(
+                f, "set {} binding {} is declared as {} and as {}", set, binding, first, second
+            ),
+            Self::InterfaceMismatch { location, output, input } => write!(
+                f, "location {}: output {} doesn't match input {}", location, output, input
+            ),
+            Self::MissingOutput { location, stage, name } => write!(
+                f, "input {:?} at location {} of stage {:?} is not written by the previous stage", name, location, stage
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ReflectionError {}
+
+///
+/// Descriptor binding declared by a shader
+///
+/// `count` is 0 for runtime arrays (`uniform texture2D textures[]`), their size is chosen
+/// when the layout is created, see [`crate::BindlessTable`].
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReflectedBinding {
+    pub name: String,
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: DescriptorType,
+    pub count: u32,
+    pub stages: ShaderStageFlags,
+    /// Size of the block for uniform and storage buffers, 0 for other types
+    pub size: u32,
+}
+
+impl ReflectedBinding {
+    fn describe(&self) -> String {
+        format!("{:?} {:?}[{}] in {:?}", self.name, self.descriptor_type, self.count, self.stages)
+    }
+}
+
+///
+/// Push constant block declared by a shader
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReflectedPushConstants {
+    pub name: String,
+    pub offset: u32,
+    pub size: u32,
+}
+
+///
+/// Stage input or output variable with a location
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReflectedInterface {
+    pub name: String,
+    pub location: u32,
+    /// Number of consecutive locations, more than one for matrices and arrays
+    pub locations: u32,
+    /// Format of a single location
+    pub format: Format,
+    /// GLSL-like type name used in error messages, e.g. `vec3` or `mat4`
+    pub type_name: String,
+}
+
+///
+/// Reflection of one SPIR-V module
+///
+#[derive(Debug, Clone)]
+pub struct ShaderReflection {
+    pub stage: ShaderStageFlags,
+    pub entry_point: String,
+    pub bindings: Vec<ReflectedBinding>,
+    pub push_constants: Option<ReflectedPushConstants>,
+    pub inputs: Vec<ReflectedInterface>,
+    pub outputs: Vec<ReflectedInterface>,
+    /// Workgroup size of a compute entry point
+    pub local_size: Option<[u32; 3]>,
+}
+
+impl ShaderReflection {
+
+    /// Reflect the first entry point of `spv`, e.g. the words returned by [`crate::load_spv`]
+    pub fn from_spv(spv: &[u32]) -> Result<Self, ReflectionError> {
+        Module::parse(spv)?.reflect(None)
+    }
+
+    /// Reflect the entry point `name` of `spv`
+    pub fn from_spv_entry_point(spv: &[u32], name: &str) -> Result<Self, ReflectionError> {
+        Module::parse(spv)?.reflect(Some(name))
+    }
+}
+
+///
+/// Reflection of all stages of a pipeline
+///
+/// Descriptor bindings of all stages are merged, the interface between consecutive
+/// stages is checked.
+///
+/// # Example:
+///
+/// ```ignore
+/// let reflection = PipelineReflection::from_spv(&[&vertex_spv, &fragment_spv])?;
+/// let set_layouts = reflection.create_set_layouts(&device.raw);
+/// let (binding, attributes) = reflection.vertex_input(0);
+/// ```
+///
+#[derive(Debug, Clone, Default)]
+pub struct PipelineReflection {
+    pub stages: ShaderStageFlags,
+    /// Sorted by set and binding
+    pub bindings: Vec<ReflectedBinding>,
+    /// One range covering the push constants of every stage
+    pub push_constant_range: Option<vk::PushConstantRange>,
+    pub vertex_inputs: Vec<ReflectedInterface>,
+    pub fragment_outputs: Vec<ReflectedInterface>,
+}
+
+impl PipelineReflection {
+
+    /// Reflect and merge the first entry point of every module
+    pub fn from_spv(modules: &[&[u32]]) -> Result<Self, ReflectionError> {
+        let shaders = modules.iter()
+            .map(|spv| ShaderReflection::from_spv(spv))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Self::merge(&shaders)
+    }
+
+    pub fn merge(shaders: &[ShaderReflection]) -> Result<Self, ReflectionError> {
+
+        let mut shaders = shaders.iter().collect::<Vec<_>>();
+        shaders.sort_by_key(|shader| stage_order(shader.stage));
+
+        let mut reflection = Self::default();
+        let mut bindings: BTreeMap<(u32, u32), ReflectedBinding> = BTreeMap::new();
+        let mut push_constants: Option<(u32, u32)> = None;
+
+        for shader in &shaders {
+
+            if reflection.stages.intersects(shader.stage) {
+                return Err(ReflectionError::DuplicateStage(shader.stage));
+            }
+
+            reflection.stages |= shader.stage;
+
+            for binding in &shader.bindings {
+                match bindings.get_mut(&(binding.set, binding.binding)) {
+                    Some(existing) => {
+                        if existing.descriptor_type != binding.descriptor_type || existing.count != binding.count {
+                            return Err(ReflectionError::BindingMismatch {
+                                set: binding.set,
+                                binding: binding.binding,
+                                first: existing.describe(),
+                                second: binding.describe(),
+                            });
+                        }
+                        existing.stages |= binding.stages;
+                        existing.size = existing.size.max(binding.size);
+                    },
+                    None => {
+                        bindings.insert((binding.set, binding.binding), binding.clone());
+                    }
+                }
+            }
+
+            if let Some(block) = &shader.push_constants {
+                let (start, end) = push_constants.unwrap_or((block.offset, block.offset + block.size));
+                push_constants = Some((start.min(block.offset), end.max(block.offset + block.size)));
+            }
+        }
+
+        for pair in shaders.windows(2) {
+            check_interface(pair[0], pair[1])?;
+        }
+
+        reflection.bindings = bindings.into_values().collect();
+
+        reflection.push_constant_range = push_constants.map(|(start, end)| {
+            let stages = shaders.iter()
+                .filter(|shader| shader.push_constants.is_some())
+                .fold(ShaderStageFlags::empty(), |stages, shader| stages | shader.stage);
+
+            vk::PushConstantRange::default()
+                .stage_flags(stages)
+                .offset(start)
+                .size(end - start)
+        });
+
+        if let Some(vertex) = shaders.iter().find(|shader| shader.stage == ShaderStageFlags::VERTEX) {
+            reflection.vertex_inputs = vertex.inputs.clone();
+        }
+
+        if let Some(fragment) = shaders.iter().find(|shader| shader.stage == ShaderStageFlags::FRAGMENT) {
+            reflection.fragment_outputs = fragment.outputs.clone();
+        }
+
+        Ok(reflection)
+    }
+
+    /// Number of descriptor set layouts the pipeline layout needs, including empty sets in between
+    pub fn set_count(&self) -> u32 {
+        self.bindings.iter().map(|binding| binding.set + 1).max().unwrap_or(0)
+    }
+
+    pub fn set_layout_bindings(&self, set: u32) -> Vec<vk::DescriptorSetLayoutBinding<'static>> {
+        self.bindings.iter()
+            .filter(|binding| binding.set == set)
+            .map(|binding| {
+                vk::DescriptorSetLayoutBinding::default()
+                    .binding(binding.binding)
+                    .descriptor_type(binding.descriptor_type)
+                    .descriptor_count(binding.count)
+                    .stage_flags(binding.stages)
+            })
+            .collect()
+    }
+
+    /// Pool sizes for allocating one descriptor set of `set`
+    pub fn descriptor_pool_sizes(&self, set: u32) -> Vec<vk::DescriptorPoolSize> {
+        let mut sizes: Vec<vk::DescriptorPoolSize> = vec![];
+
+        for binding in self.bindings.iter().filter(|binding| binding.set == set) {
+            match sizes.iter_mut().find(|size| size.ty == binding.descriptor_type) {
+                Some(size) => size.descriptor_count += binding.count,
+                None => sizes.push(
+                    vk::DescriptorPoolSize::default()
+                        .ty(binding.descriptor_type)
+                        .descriptor_count(binding.count)
+                ),
+            }
+        }
+
+        sizes
+    }
+
+    ///
+    /// Create one layout per set from 0 to [`Self::set_count`]
+    ///
+    /// Sets with runtime arrays get a binding with zero descriptors, replace them with a
+    /// layout created by hand, e.g. [`crate::BindlessTable::layout`].
+    ///
+    pub fn create_set_layouts(&self, device: &ash::Device) -> Vec<DescriptorSetLayout> {
+        (0..self.set_count())
+            .map(|set| {
+                let bindings = self.set_layout_bindings(set);
+
+                if bindings.iter().any(|binding| binding.descriptor_count == 0) {
+                    warn!("Set {} has a runtime array, its reflected layout has no descriptors for it", set);
+                }
+
+                DescriptorSetLayoutBuilder::new()
+                    .with_device(device)
+                    .with_bindings(&bindings)
+                    .build()
+            })
+            .collect()
+    }
+
+    pub fn create_pipeline_layout(&self, device: &ash::Device, set_layouts: &[DescriptorSetLayout]) -> Result<vk::PipelineLayout, vk::Result> {
+
+        let set_layouts = set_layouts.iter().map(|layout| layout.raw).collect::<Vec<_>>();
+        let push_constant_ranges = self.push_constant_range.as_slice();
+
+        let layout_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(push_constant_ranges);
+
+        unsafe { device.create_pipeline_layout(&layout_info, None) }
+    }
+
+    ///
+    /// Vertex input for one interleaved vertex buffer bound at `binding`
+    ///
+    /// Attributes are tightly packed in location order, which matches a `#[repr(C)]`
+    /// vertex struct whose fields follow the shader inputs.
+    ///
+    pub fn vertex_input(&self, binding: u32) -> (vk::VertexInputBindingDescription, Vec<vk::VertexInputAttributeDescription>) {
+
+        let mut inputs = self.vertex_inputs.iter().collect::<Vec<_>>();
+        inputs.sort_by_key(|input| input.location);
+
+        let mut attributes = vec![];
+        let mut offset = 0;
+
+        for input in inputs {
+            for i in 0..input.locations {
+                attributes.push(
+                    vk::VertexInputAttributeDescription::default()
+                        .location(input.location + i)
+                        .binding(binding)
+                        .format(input.format)
+                        .offset(offset)
+                );
+                offset += format_size(input.format);
+            }
+        }
+
+        let binding = vk::VertexInputBindingDescription::default()
+            .binding(binding)
+            .stride(offset)
+            .input_rate(vk::VertexInputRate::VERTEX);
+
+        (binding, attributes)
+    }
+}
+
+fn stage_order(stage: ShaderStageFlags) -> u32 {
+    match stage {
+        ShaderStageFlags::VERTEX => 0,
+        ShaderStageFlags::TESSELLATION_CONTROL => 1,
+        ShaderStageFlags::TESSELLATION_EVALUATION => 2,
+        ShaderStageFlags::GEOMETRY => 3,
+        ShaderStageFlags::FRAGMENT => 4,
+        _ => 5,
+    }
+}
+
+/// Check that every input of `next` is written by `previous` with the same type
+fn check_interface(previous: &ShaderReflection, next: &ShaderReflection) -> Result<(), ReflectionError> {
+
+    // Входы и выходы тесселяции и геометрии — массивы по вершинам, их не сравниваем
+    let arrayed = ShaderStageFlags::TESSELLATION_CONTROL | ShaderStageFlags::TESSELLATION_EVALUATION | ShaderStageFlags::GEOMETRY;
+    let graphics = ShaderStageFlags::ALL_GRAPHICS;
+
+    if previous.stage.intersects(arrayed) || next.stage.intersects(arrayed) || !graphics.contains(previous.stage | next.stage) {
+        return Ok(());
+    }
+
+    for input in &next.inputs {
+
+        let Some(output) = previous.outputs.iter().find(|output| output.location == input.location) else {
+            return Err(ReflectionError::MissingOutput {
+                location: input.location,
+                stage: next.stage,
+                name: input.name.clone(),
+            });
+        };
+
+        if output.type_name != input.type_name {
+            return Err(ReflectionError::InterfaceMismatch {
+                location: input.location,
+                output: format!("{} {:?} of {:?}", output.type_name, output.name, previous.stage),
+                input: format!("{} {:?} of {:?}", input.type_name, input.name, next.stage),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+fn format_size(format: Format) -> u32 {
+    match format {
+        Format::R32_SFLOAT | Format::R32_SINT | Format::R32_UINT => 4,
+        Format::R32G32_SFLOAT | Format::R32G32_SINT | Format::R32G32_UINT => 8,
+        Format::R32G32B32_SFLOAT | Format::R32G32B32_SINT | Format::R32G32B32_UINT => 12,
+        Format::R32G32B32A32_SFLOAT | Format::R32G32B32A32_SINT | Format::R32G32B32A32_UINT => 16,
+        Format::R16_SFLOAT => 2,
+        Format::R16G16_SFLOAT => 4,
+        Format::R16G16B16_SFLOAT => 6,
+        Format::R16G16B16A16_SFLOAT => 8,
+        Format::R64_SFLOAT => 8,
+        Format::R64G64_SFLOAT => 16,
+        Format::R64G64B64_SFLOAT => 24,
+        Format::R64G64B64A64_SFLOAT => 32,
+        _ => 0,
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Type {
+    Void,
+    Bool,
+    Int { width: u32, signed: bool },
+    Float { width: u32 },
+    Vector { component: u32, count: u32 },
+    Matrix { column: u32, columns: u32 },
+    Image { dim: u32, sampled: u32 },
+    Sampler,
+    SampledImage,
+    Array { element: u32, length: u32 },
+    RuntimeArray { element: u32 },
+    Struct { members: Vec<u32> },
+    Pointer { pointee: u32 },
+    AccelerationStructure,
+}
+
+#[derive(Default)]
+struct Decorations {
+    location: Option<u32>,
+    binding: Option<u32>,
+    set: Option<u32>,
+    block: bool,
+    buffer_block: bool,
+    builtin: bool,
+    array_stride: Option<u32>,
+}
+
+#[derive(Default)]
+struct MemberDecorations {
+    offset: Option<u32>,
+    matrix_stride: Option<u32>,
+    builtin: bool,
+}
+
+struct EntryPoint {
+    execution_model: u32,
+    function: u32,
+    name: String,
+    interface: Vec<u32>,
+}
+
+struct Variable {
+    id: u32,
+    pointee: u32,
+    storage: u32,
+}
+
+#[derive(Default)]
+struct Module {
+    entry_points: Vec<EntryPoint>,
+    names: HashMap<u32, String>,
+    types: HashMap<u32, Type>,
+    constants: HashMap<u32, u32>,
+    decorations: HashMap<u32, Decorations>,
+    member_decorations: HashMap<(u32, u32), MemberDecorations>,
+    variables: Vec<Variable>,
+    local_sizes: HashMap<u32, [u32; 3]>,
+}
+
+fn parse_string(words: &[u32]) -> (String, usize) {
+    let mut bytes = vec![];
+
+    for (i, word) in words.iter().enumerate() {
+        for byte in word.to_le_bytes() {
+            if byte == 0 {
+                return (String::from_utf8_lossy(&bytes).into_owned(), i + 1);
+            }
+            bytes.push(byte);
+        }
+    }
+
+    (String::from_utf8_lossy(&bytes).into_owned(), words.len())
+}
+
+impl Module {
+
+    fn parse(spv: &[u32]) -> Result<Self, ReflectionError> {
+
+        if spv.len() < 5 {
+            return Err(ReflectionError::InvalidSpirv("module is shorter than the header".into()));
+        }
+
+        if spv[0] != SPIRV_MAGIC {
+            return Err(ReflectionError::InvalidSpirv(format!("wrong magic number {:#x}", spv[0])));
+        }
+
+        let mut module = Module::default();
+        let mut offset = 5;
+
+        while offset < spv.len() {
+
+            let word_count = (spv[offset] >> 16) as usize;
+            let opcode = spv[offset] & 0xffff;
+
+            if word_count == 0 || offset + word_count > spv.len() {
+                return Err(ReflectionError::InvalidSpirv(format!("truncated instruction at word {}", offset)));
+            }
+
+            let ops = &spv[offset + 1..offset + word_count];
+            offset += word_count;
+
+            let operand = |index: usize| -> Result<u32, ReflectionError> {
+                ops.get(index).copied().ok_or_else(|| {
+                    ReflectionError::InvalidSpirv(format!("opcode {} is missing operand {}", opcode, index))
+                })
+            };
+
+            // Строка занимает хотя бы одно слово, даже пустая
+            let string = |index: usize| -> Result<(String, usize), ReflectionError> {
+                operand(index)?;
+                Ok(parse_string(&ops[index..]))
+            };
+
+            match opcode {
+                OP_NAME => {
+                    let (name, _) = string(1)?;
+                    module.names.insert(operand(0)?, name);
+                },
+                OP_ENTRY_POINT => {
+                    let (name, words) = string(2)?;
+                    module.entry_points.push(EntryPoint {
+                        execution_model: operand(0)?,
+                        function: operand(1)?,
+                        name,
+                        interface: ops[2 + words..].to_vec(),
+                    });
+                },
+                OP_EXECUTION_MODE if operand(1)? == EXECUTION_MODE_LOCAL_SIZE => {
+                    module.local_sizes.insert(operand(0)?, [operand(2)?, operand(3)?, operand(4)?]);
+                },
+                OP_DECORATE => {
+                    let decorations = module.decorations.entry(operand(0)?).or_default();
+                    match operand(1)? {
+                        DECORATION_BLOCK => decorations.block = true,
+                        DECORATION_BUFFER_BLOCK => decorations.buffer_block = true,
+                        DECORATION_BUILTIN => decorations.builtin = true,
+                        DECORATION_ARRAY_STRIDE => decorations.array_stride = Some(operand(2)?),
+                        DECORATION_LOCATION => decorations.location = Some(operand(2)?),
+                        DECORATION_BINDING => decorations.binding = Some(operand(2)?),
+                        DECORATION_DESCRIPTOR_SET => decorations.set = Some(operand(2)?),
+                        _ => {}
+                    }
+                },
+                OP_MEMBER_DECORATE => {
+                    let decorations = module.member_decorations.entry((operand(0)?, operand(1)?)).or_default();
+                    match operand(2)? {
+                        DECORATION_OFFSET => decorations.offset = Some(operand(3)?),
+                        DECORATION_MATRIX_STRIDE => decorations.matrix_stride = Some(operand(3)?),
+                        DECORATION_BUILTIN => decorations.builtin = true,
+                        _ => {}
+                    }
+                },
+                OP_TYPE_VOID => { module.types.insert(operand(0)?, Type::Void); },
+                OP_TYPE_BOOL => { module.types.insert(operand(0)?, Type::Bool); },
+                OP_TYPE_INT => {
+                    module.types.insert(operand(0)?, Type::Int { width: operand(1)?, signed: operand(2)? != 0 });
+                },
+                OP_TYPE_FLOAT => {
+                    module.types.insert(operand(0)?, Type::Float { width: operand(1)? });
+                },
+                OP_TYPE_VECTOR => {
+                    module.types.insert(operand(0)?, Type::Vector { component: operand(1)?, count: operand(2)? });
+                },
+                OP_TYPE_MATRIX => {
+                    module.types.insert(operand(0)?, Type::Matrix { column: operand(1)?, columns: operand(2)? });
+                },
+                OP_TYPE_IMAGE => {
+                    module.types.insert(operand(0)?, Type::Image { dim: operand(2)?, sampled: operand(6)? });
+                },
+                OP_TYPE_SAMPLER => { module.types.insert(operand(0)?, Type::Sampler); },
+                OP_TYPE_SAMPLED_IMAGE => { module.types.insert(operand(0)?, Type::SampledImage); },
+                OP_TYPE_ARRAY => {
+                    let length = module.constants.get(&operand(2)?).copied().ok_or_else(|| {
+                        ReflectionError::InvalidSpirv(format!("array length %{} is not a constant", ops[2]))
+                    })?;
+                    module.types.insert(operand(0)?, Type::Array { element: operand(1)?, length });
+                },
+                OP_TYPE_RUNTIME_ARRAY => {
+                    module.types.insert(operand(0)?, Type::RuntimeArray { element: operand(1)? });
+                },
+                OP_TYPE_STRUCT => {
+                    module.types.insert(operand(0)?, Type::Struct { members: ops[1..].to_vec() });
+                },
+                OP_TYPE_POINTER => {
+                    module.types.insert(operand(0)?, Type::Pointer { pointee: operand(2)? });
+                },
+                OP_TYPE_ACCELERATION_STRUCTURE => {
+                    module.types.insert(operand(0)?, Type::AccelerationStructure);
+                },
+                OP_CONSTANT | OP_SPEC_CONSTANT => {
+                    // Для длин массивов достаточно младшего слова
+                    module.constants.insert(operand(1)?, operand(2)?);
+                },
+                OP_VARIABLE => {
+                    let (pointer, id) = (operand(0)?, operand(1)?);
+                    let Some(Type::Pointer { pointee, .. }) = module.types.get(&pointer) else {
+                        return Err(ReflectionError::InvalidSpirv(format!("variable %{} is not a pointer", id)));
+                    };
+                    module.variables.push(Variable { id, pointee: *pointee, storage: operand(2)? });
+                },
+                _ => {}
+            }
+        }
+
+        Ok(module)
+    }
+
+    fn reflect(&self, entry_point: Option<&str>) -> Result<ShaderReflection, ReflectionError> {
+
+        let entry = match entry_point {
+            Some(name) => self.entry_points.iter().find(|entry| entry.name == name),
+            None => self.entry_points.first(),
+        }
+        .ok_or_else(|| ReflectionError::MissingEntryPoint(entry_point.unwrap_or_default().to_string()))?;
+
+        let stage = match entry.execution_model {
+            0 => ShaderStageFlags::VERTEX,
+            1 => ShaderStageFlags::TESSELLATION_CONTROL,
+            2 => ShaderStageFlags::TESSELLATION_EVALUATION,
+            3 => ShaderStageFlags::GEOMETRY,
+            4 => ShaderStageFlags::FRAGMENT,
+            5 => ShaderStageFlags::COMPUTE,
+            5267 => ShaderStageFlags::TASK_NV,
+            5268 => ShaderStageFlags::MESH_NV,
+            5313 => ShaderStageFlags::RAYGEN_KHR,
+            5314 => ShaderStageFlags::INTERSECTION_KHR,
+            5315 => ShaderStageFlags::ANY_HIT_KHR,
+            5316 => ShaderStageFlags::CLOSEST_HIT_KHR,
+            5317 => ShaderStageFlags::MISS_KHR,
+            5318 => ShaderStageFlags::CALLABLE_KHR,
+            5364 => ShaderStageFlags::TASK_EXT,
+            5365 => ShaderStageFlags::MESH_EXT,
+            model => return Err(ReflectionError::InvalidSpirv(format!("unknown execution model {}", model))),
+        };
+
+        let mut reflection = ShaderReflection {
+            stage,
+            entry_point: entry.name.clone(),
+            bindings: vec![],
+            push_constants: None,
+            inputs: vec![],
+            outputs: vec![],
+            local_size: self.local_sizes.get(&entry.function).copied(),
+        };
+
+        for variable in &self.variables {
+
+            let decorations = self.decorations.get(&variable.id);
+            let name = self.names.get(&variable.id).cloned().unwrap_or_default();
+
+            match variable.storage {
+                STORAGE_UNIFORM_CONSTANT | STORAGE_UNIFORM | STORAGE_STORAGE_BUFFER => {
+                    let (Some(set), Some(binding)) = (
+                        decorations.and_then(|decorations| decorations.set),
+                        decorations.and_then(|decorations| decorations.binding),
+                    ) else {
+                        continue;
+                    };
+
+                    let (element, count) = match self.types.get(&variable.pointee) {
+                        Some(Type::Array { element, length }) => (*element, *length),
+                        Some(Type::RuntimeArray { element }) => (*element, 0),
+                        _ => (variable.pointee, 1),
+                    };
+
+                    let descriptor_type = self.descriptor_type(element, variable.storage)?;
+                    let size = match descriptor_type {
+                        DescriptorType::UNIFORM_BUFFER | DescriptorType::STORAGE_BUFFER => self.size_of(element, None),
+                        _ => 0,
+                    };
+
+                    reflection.bindings.push(ReflectedBinding {
+                        name: self.block_name(variable.id, element),
+                        set,
+                        binding,
+                        descriptor_type,
+                        count,
+                        stages: stage,
+                        size,
+                    });
+                },
+                STORAGE_PUSH_CONSTANT => {
+                    let offset = self.member_offsets(variable.pointee).min().unwrap_or(0);
+                    reflection.push_constants = Some(ReflectedPushConstants {
+                        name: self.block_name(variable.id, variable.pointee),
+                        offset,
+                        size: self.size_of(variable.pointee, None) - offset,
+                    });
+                },
+                STORAGE_INPUT | STORAGE_OUTPUT => {
+                    // В SPIR-V 1.0-1.3 интерфейс точки входа содержит только Input/Output переменные
+                    if !entry.interface.contains(&variable.id) || self.is_builtin(variable) {
+                        continue;
+                    }
+
+                    let Some(location) = decorations.and_then(|decorations| decorations.location) else {
+                        continue;
+                    };
+
+                    let (format, locations, type_name) = self.interface_type(variable.pointee)?;
+                    let interface = ReflectedInterface { name, location, locations, format, type_name };
+
+                    if variable.storage == STORAGE_INPUT {
+                        reflection.inputs.push(interface);
+                    } else {
+                        reflection.outputs.push(interface);
+                    }
+                },
+                _ => {}
+            }
+        }
+
+        reflection.bindings.sort_by_key(|binding| (binding.set, binding.binding));
+        reflection.inputs.sort_by_key(|input| input.location);
+        reflection.outputs.sort_by_key(|output| output.location);
+
+        Ok(reflection)
+    }
+
+    fn ty(&self, id: u32) -> Result<&Type, ReflectionError> {
+        self.types.get(&id).ok_or_else(|| ReflectionError::InvalidSpirv(format!("unknown type %{}", id)))
+    }
+
+    fn block_name(&self, variable: u32, ty: u32) -> String {
+        match self.names.get(&variable) {
+            Some(name) if !name.is_empty() => name.clone(),
+            _ => self.names.get(&ty).cloned().unwrap_or_default(),
+        }
+    }
+
+    fn is_builtin(&self, variable: &Variable) -> bool {
+
+        if self.decorations.get(&variable.id).is_some_and(|decorations| decorations.builtin) {
+            return true;
+        }
+
+        // gl_PerVertex — структура, члены которой помечены BuiltIn
+        let mut ty = variable.pointee;
+        while let Some(Type::Array { element, .. } | Type::RuntimeArray { element }) = self.types.get(&ty) {
+            ty = *element;
+        }
+
+        match self.types.get(&ty) {
+            Some(Type::Struct { members }) => (0..members.len() as u32).any(|member| {
+                self.member_decorations.get(&(ty, member)).is_some_and(|decorations| decorations.builtin)
+            }),
+            _ => false,
+        }
+    }
+
+    fn descriptor_type(&self, ty: u32, storage: u32) -> Result<DescriptorType, ReflectionError> {
+
+        let decorations = self.decorations.get(&ty);
+
+        Ok(match (self.ty(ty)?, storage) {
+            (Type::Struct { .. }, STORAGE_STORAGE_BUFFER) => DescriptorType::STORAGE_BUFFER,
+            (Type::Struct { .. }, STORAGE_UNIFORM) if decorations.is_some_and(|decorations| decorations.buffer_block) => {
+                DescriptorType::STORAGE_BUFFER
+            },
+            (Type::Struct { .. }, STORAGE_UNIFORM) => DescriptorType::UNIFORM_BUFFER,
+            (Type::SampledImage, _) => DescriptorType::COMBINED_IMAGE_SAMPLER,
+            (Type::Sampler, _) => DescriptorType::SAMPLER,
+            (Type::AccelerationStructure, _) => DescriptorType::ACCELERATION_STRUCTURE_KHR,
+            (Type::Image { dim: DIM_SUBPASS_DATA, .. }, _) => DescriptorType::INPUT_ATTACHMENT,
+            (Type::Image { dim: DIM_BUFFER, sampled: 2 }, _) => DescriptorType::STORAGE_TEXEL_BUFFER,
+            (Type::Image { dim: DIM_BUFFER, .. }, _) => DescriptorType::UNIFORM_TEXEL_BUFFER,
+            (Type::Image { sampled: 2, .. }, _) => DescriptorType::STORAGE_IMAGE,
+            (Type::Image { .. }, _) => DescriptorType::SAMPLED_IMAGE,
+            (other, _) => return Err(ReflectionError::InvalidSpirv(format!("type {:?} can't be a descriptor", other))),
+        })
+    }
+
+    fn member_offsets(&self, ty: u32) -> impl Iterator<Item = u32> + '_ {
+        let members = match self.types.get(&ty) {
+            Some(Type::Struct { members }) => members.len() as u32,
+            _ => 0,
+        };
+
+        (0..members).filter_map(move |member| self.member_decorations.get(&(ty, member))?.offset)
+    }
+
+    /// Size in bytes of a type laid out with the offsets and strides from the module
+    fn size_of(&self, ty: u32, matrix_stride: Option<u32>) -> u32 {
+        match self.types.get(&ty) {
+            Some(Type::Bool) => 4,
+            Some(Type::Int { width, .. } | Type::Float { width }) => width / 8,
+            Some(Type::Vector { component, count }) => count * self.size_of(*component, None),
+            Some(Type::Matrix { column, columns }) => {
+                columns * matrix_stride.unwrap_or_else(|| self.size_of(*column, None))
+            },
+            Some(Type::Array { element, length }) => {
+                let stride = self.decorations.get(&ty)
+                    .and_then(|decorations| decorations.array_stride)
+                    .unwrap_or_else(|| self.size_of(*element, matrix_stride));
+                length * stride
+            },
+            Some(Type::Struct { members }) => {
+                let mut size = 0;
+                for (index, member) in members.iter().enumerate() {
+                    let decorations = self.member_decorations.get(&(ty, index as u32));
+                    let offset = decorations.and_then(|decorations| decorations.offset).unwrap_or(size);
+                    let member_size = self.size_of(*member, decorations.and_then(|decorations| decorations.matrix_stride));
+                    size = size.max(offset + member_size);
+                }
+                size
+            },
+            _ => 0,
+        }
+    }
+
+    /// Format of one location, number of locations and type name of an interface variable
+    fn interface_type(&self, ty: u32) -> Result<(Format, u32, String), ReflectionError> {
+        match self.ty(ty)? {
+            Type::Array { element, length } => {
+                let (format, locations, name) = self.interface_type(*element)?;
+                Ok((format, locations * length, format!("{}[{}]", name, length)))
+            },
+            Type::Matrix { column, columns } => {
+                let (format, _, name) = self.interface_type(*column)?;
+                let rows = name.chars().last().unwrap_or('4');
+                let prefix = if name.starts_with('d') { "dmat" } else { "mat" };
+                let name = if rows.to_digit(10) == Some(*columns) {
+                    format!("{}{}", prefix, columns)
+                } else {
+                    format!("{}{}x{}", prefix, columns, rows)
+                };
+                Ok((format, *columns, name))
+            },
+            Type::Vector { component, count } => {
+                let (prefix, format) = self.scalar_format(*component, *count)?;
+                Ok((format, 1, format!("{}vec{}", prefix, count)))
+            },
+            scalar => {
+                let (_, format) = self.scalar_format(ty, 1)?;
+                let name = match scalar {
+                    Type::Float { width: 64 } => "double",
+                    Type::Float { .. } => "float",
+                    Type::Int { signed: true, .. } => "int",
+                    Type::Int { .. } => "uint",
+                    Type::Bool => "bool",
+                    _ => "unknown",
+                };
+                Ok((format, 1, name.to_string()))
+            },
+        }
+    }
+
+    fn scalar_format(&self, ty: u32, count: u32) -> Result<(&'static str, Format), ReflectionError> {
+
+        const FLOAT32: [Format; 4] = [Format::R32_SFLOAT, Format::R32G32_SFLOAT, Format::R32G32B32_SFLOAT, Format::R32G32B32A32_SFLOAT];
+        const FLOAT16: [Format; 4] = [Format::R16_SFLOAT, Format::R16G16_SFLOAT, Format::R16G16B16_SFLOAT, Format::R16G16B16A16_SFLOAT];
+        const FLOAT64: [Format; 4] = [Format::R64_SFLOAT, Format::R64G64_SFLOAT, Format::R64G64B64_SFLOAT, Format::R64G64B64A64_SFLOAT];
+        const SINT32: [Format; 4] = [Format::R32_SINT, Format::R32G32_SINT, Format::R32G32B32_SINT, Format::R32G32B32A32_SINT];
+        const UINT32: [Format; 4] = [Format::R32_UINT, Format::R32G32_UINT, Format::R32G32B32_UINT, Format::R32G32B32A32_UINT];
+
+        let index = (count.clamp(1, 4) - 1) as usize;
+
+        Ok(match self.ty(ty)? {
+            Type::Float { width: 16 } => ("f16", FLOAT16[index]),
+            Type::Float { width: 64 } => ("d", FLOAT64[index]),
+            Type::Float { .. } => ("", FLOAT32[index]),
+            Type::Int { signed: true, .. } => ("i", SINT32[index]),
+            Type::Int { .. } | Type::Bool => ("u", UINT32[index]),
+            other => return Err(ReflectionError::InvalidSpirv(format!("type {:?} can't be a stage interface", other))),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read_shader_from_bytes;
+
+    fn triangle() -> (Vec<u32>, Vec<u32>) {
+        (
+            read_shader_from_bytes(include_bytes!("../../../../shared/shaders/spv/triangle-vert.spv")).unwrap(),
+            read_shader_from_bytes(include_bytes!("../../../../shared/shaders/spv/triangle-frag.spv")).unwrap(),
+        )
+    }
+
+    #[test]
+    fn reflects_triangle_vertex_shader() {
+        let (vertex, _) = triangle();
+        let reflection = ShaderReflection::from_spv(&vertex).unwrap();
+
+        assert_eq!(reflection.stage, ShaderStageFlags::VERTEX);
+        assert_eq!(reflection.entry_point, "main");
+        assert!(reflection.local_size.is_none());
+
+        assert_eq!(reflection.bindings.len(), 1);
+        let ubo = &reflection.bindings[0];
+        assert_eq!((ubo.set, ubo.binding, ubo.count), (0, 0, 1));
+        assert_eq!(ubo.descriptor_type, DescriptorType::UNIFORM_BUFFER);
+        assert_eq!(ubo.size, 3 * 64);
+
+        let inputs = reflection.inputs.iter().map(|input| (input.location, input.format)).collect::<Vec<_>>();
+        assert_eq!(inputs, [(0, Format::R32G32B32_SFLOAT), (1, Format::R32G32B32_SFLOAT)]);
+
+        // gl_PerVertex не попадает в выходы
+        assert_eq!(reflection.outputs.len(), 1);
+        assert_eq!(reflection.outputs[0].type_name, "vec3");
+    }
+
+    #[test]
+    fn rejects_truncated_instructions() {
+        let (vertex, _) = triangle();
+
+        // OpName только с id цели и OpEntryPoint без имени
+        for truncated in [vec![(2 << 16) | OP_NAME, 1], vec![(3 << 16) | OP_ENTRY_POINT, 0, 4]] {
+            let mut spv = vertex[..5].to_vec();
+            spv.extend(truncated);
+
+            let err = ShaderReflection::from_spv(&spv).unwrap_err();
+            assert!(matches!(err, ReflectionError::InvalidSpirv(_)), "{}", err);
+        }
+    }
+
+    #[test]
+    fn merges_triangle_pipeline() {
+        let (vertex, fragment) = triangle();
+        let reflection = PipelineReflection::from_spv(&[&fragment, &vertex]).unwrap();
+
+        assert_eq!(reflection.stages, ShaderStageFlags::VERTEX | ShaderStageFlags::FRAGMENT);
+        assert_eq!(reflection.set_count(), 1);
+        assert!(reflection.push_constant_range.is_none());
+        assert_eq!(reflection.fragment_outputs[0].format, Format::R32G32B32A32_SFLOAT);
+
+        let (binding, attributes) = reflection.vertex_input(0);
+        assert_eq!(binding.stride, 24);
+        assert_eq!(attributes.iter().map(|attribute| attribute.offset).collect::<Vec<_>>(), [0, 12]);
+
+        let sizes = reflection.descriptor_pool_sizes(0);
+        assert_eq!(sizes.len(), 1);
+        assert_eq!(sizes[0].ty, DescriptorType::UNIFORM_BUFFER);
+    }
+
+    #[test]
+    fn rejects_stage_interface_mismatch() {
+        let (vertex, fragment) = triangle();
+        let vertex = ShaderReflection::from_spv(&vertex).unwrap();
+        let fragment = ShaderReflection::from_spv(&fragment).unwrap();
+
+        let mut wrong_type = vertex.clone();
+        wrong_type.outputs[0].type_name = "vec4".into();
+        let err = PipelineReflection::merge(&[wrong_type, fragment.clone()]).unwrap_err();
+        assert!(matches!(err, ReflectionError::InterfaceMismatch { location: 0, .. }), "{}", err);
+
+        let mut missing = vertex;
+        missing.outputs.clear();
+        let err = PipelineReflection::merge(&[missing, fragment]).unwrap_err();
+        assert!(matches!(err, ReflectionError::MissingOutput { location: 0, .. }), "{}", err);
+    }
+
+    #[test]
+    fn reflects_compute_workgroup_size() {
+        let source = "#version 450\nlayout(local_size_x = 8, local_size_y = 4) in;\nvoid main() {}\n";
+        let shader = fujiya_shaders::ShaderCompiler::new()
+            .compile_source(std::path::Path::new("clear.comp"), source, fujiya_shaders::ShaderLanguage::Glsl(fujiya_shaders::ShaderStage::Compute))
+            .unwrap();
+
+        let reflection = ShaderReflection::from_spv(&shader.spv).unwrap();
+
+        assert_eq!(reflection.stage, ShaderStageFlags::COMPUTE);
+        assert_eq!(reflection.local_size, Some([8, 4, 1]));
+    }
+
+    #[test]
+    fn rejects_invalid_module() {
+        assert!(matches!(ShaderReflection::from_spv(&[0xdeadbeef, 0, 0, 0, 0]), Err(ReflectionError::InvalidSpirv(_))));
+    }
+}
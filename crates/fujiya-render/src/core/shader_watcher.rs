@@ -0,0 +1,101 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver}
+};
+
+use log::warn;
+use notify::{EventKind, RecursiveMode, Watcher};
+
+pub use fujiya_shaders::{same_file, CompiledShader, ShaderCompiler, ShaderDefines, ShaderError, ShaderLanguage, ShaderVariants};
+
+/// Extensions of shader sources that trigger a reload
+const SHADER_EXTENSIONS: [&str; 9] = ["vert", "frag", "comp", "geom", "tesc", "tese", "glsl", "wgsl", "hlsl"];
+
+///
+/// Compile a shader source file to SPIR-V in-process, see [`ShaderCompiler`]
+///
+pub fn compile_shader_file(path: impl AsRef<Path>) -> Result<Vec<u32>, ShaderError> {
+    Ok(ShaderCompiler::new().compile_file(path)?.spv)
+}
+
+///
+/// Watches a directory of shader sources
+///
+/// Events are collected in the background and returned by [`ShaderWatcher::poll`],
+/// so the render loop decides when to recompile.
+///
+/// # Example:
+///
+/// ```ignore
+/// let watcher = ShaderWatcher::new("./shared/shaders")?;
+///
+/// for path in watcher.poll() {
+///     match compile_shader_file(&path) {
+///         Ok(spv) => { /* rebuild pipelines */ },
+///         Err(err) => log::error!("{}", err),
+///     }
+/// }
+/// ```
+///
+pub struct ShaderWatcher {
+    pub dir: PathBuf,
+    events: Receiver<notify::Result<notify::Event>>,
+    _watcher: notify::RecommendedWatcher,
+}
+
+impl ShaderWatcher {
+
+    pub fn new(dir: impl AsRef<Path>) -> notify::Result<Self> {
+
+        let dir = dir.as_ref().canonicalize()?;
+        let (sender, events) = channel();
+
+        let mut watcher = notify::recommended_watcher(move |event| {
+            let _ = sender.send(event);
+        })?;
+
+        watcher.watch(&dir, RecursiveMode::Recursive)?;
+
+        Ok(Self { dir, events, _watcher: watcher })
+    }
+
+    ///
+    /// Shader sources changed since the last call, without duplicates
+    ///
+    /// Paths are canonical, compare them with [`Path::canonicalize`] of the registered sources.
+    ///
+    pub fn poll(&self) -> Vec<PathBuf> {
+
+        let mut changed: Vec<PathBuf> = vec![];
+
+        for event in self.events.try_iter() {
+
+            let event = match event {
+                Ok(event) => event,
+                Err(err) => {
+                    warn!("Shader watcher error: {}", err);
+                    continue;
+                }
+            };
+
+            if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                continue;
+            }
+
+            for path in event.paths {
+
+                let is_shader = path.extension()
+                    .and_then(|ext| ext.to_str())
+                    .is_some_and(|ext| SHADER_EXTENSIONS.contains(&ext));
+
+                let path = path.canonicalize().unwrap_or(path);
+
+                if is_shader && !changed.contains(&path) {
+                    changed.push(path);
+                }
+            }
+        }
+
+        changed
+    }
+}
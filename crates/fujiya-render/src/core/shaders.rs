@@ -1,23 +1,134 @@
 #![allow(warnings)]
 
 use std::{
-    fs::File, io::Read
+    error::Error, ffi::CString, fs::File, io::Read
 };
 use ash::vk::{
+    self,
     ShaderModule,
-    ShaderModuleCreateInfo
+    ShaderModuleCreateInfo,
+    ShaderStageFlags
 };
 
+use crate::{PipelineReflection, ReflectionError, ShaderReflection};
+
+///
+/// Struct whose fields are specialization constants, see `#[derive(SpecializationConstants)]`
+///
+/// The struct must be `#[repr(C)]` and contain only 4 or 8 byte scalars, booleans are
+/// [`vk::Bool32`]. It is [`bytemuck::Pod`], so it has no padding and every byte passed to
+/// Vulkan is initialized.
+///
+/// # Example:
+///
+/// ```ignore
+/// #[repr(C)]
+/// #[derive(Clone, Copy, Pod, Zeroable, SpecializationConstants)]
+/// struct Quality {
+///     #[constant_id(0)]
+///     samples: u32,
+///     #[constant_id(1)]
+///     soft_shadows: vk::Bool32,
+/// }
+/// ```
+///
+pub trait SpecializationConstants: bytemuck::Pod {
+    fn map_entries() -> Vec<vk::SpecializationMapEntry>;
+}
+
+///
+/// Specialization constant values of one stage
+///
+#[derive(Debug, Clone, Default)]
+pub struct SpecializationData {
+    pub entries: Vec<vk::SpecializationMapEntry>,
+    pub data: Vec<u8>
+}
+
+impl SpecializationData {
+
+    pub fn new<T: SpecializationConstants>(constants: &T) -> Self {
+        Self { entries: T::map_entries(), data: bytemuck::bytes_of(constants).to_vec() }
+    }
+
+    pub fn info(&self) -> vk::SpecializationInfo<'_> {
+        vk::SpecializationInfo::default()
+            .map_entries(&self.entries)
+            .data(&self.data)
+    }
+}
+
+///
+/// One stage of a [`ShaderProgram`]
+///
+pub struct ShaderProgramStage {
+    pub stage: ShaderStageFlags,
+    pub module: ShaderModule,
+    pub entry_point: CString,
+    pub specialization: Option<SpecializationData>,
+    pub reflection: ShaderReflection
+}
+
 pub struct ShaderProgram {
-    pub vertex_shader: ShaderModule,
-    pub fragment_shader: ShaderModule
+    pub stages: Vec<ShaderProgramStage>
 }
 
+impl ShaderProgram {
+
+    pub fn stage(&self, stage: ShaderStageFlags) -> Option<&ShaderProgramStage> {
+        self.stages.iter().find(|program_stage| program_stage.stage == stage)
+    }
+
+    /// Descriptor bindings, push constants and vertex input of all stages together
+    pub fn reflect(&self) -> Result<PipelineReflection, ReflectionError> {
+        let stages = self.stages.iter().map(|stage| stage.reflection.clone()).collect::<Vec<_>>();
+        PipelineReflection::merge(&stages)
+    }
+
+    pub fn has_tessellation(&self) -> bool {
+        self.stages.iter().any(|stage| stage.stage.intersects(
+            ShaderStageFlags::TESSELLATION_CONTROL | ShaderStageFlags::TESSELLATION_EVALUATION
+        ))
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        for stage in &self.stages {
+            unsafe { device.destroy_shader_module(stage.module, None) };
+        }
+    }
+}
+
+struct StageSource {
+    stage: ShaderStageFlags,
+    spv: Vec<u32>,
+    entry_point: String,
+    specialization: Option<SpecializationData>
+}
+
+///
+/// Builds shader modules for any set of stages
+///
+/// Every stage uses the entry point `main` unless [`ShaderProgramBuilder::with_entry_point`]
+/// says otherwise, so one SPIR-V module with several entry points can be added for
+/// several stages.
+///
+/// # Example:
+///
+/// ```ignore
+/// let program = ShaderProgramBuilder::new()
+///     .with_device(&device.raw)
+///     .add_stage(vk::ShaderStageFlags::VERTEX, shaders::MESH_WGSL.to_vec())
+///     .with_entry_point(vk::ShaderStageFlags::VERTEX, "vs_main")
+///     .add_stage(vk::ShaderStageFlags::FRAGMENT, shaders::MESH_WGSL.to_vec())
+///     .with_entry_point(vk::ShaderStageFlags::FRAGMENT, "fs_main")
+///     .with_specialization(vk::ShaderStageFlags::FRAGMENT, &Quality { samples: 16, soft_shadows: vk::TRUE })
+///     .build();
+/// ```
+///
 #[derive(Default)]
 pub struct ShaderProgramBuilder<'n> {
     pub device: Option<&'n ash::Device>,
-    pub vertex_shader_source: Option<Vec<u32>>,
-    pub fragment_shader_source: Option<Vec<u32>>,
+    stages: Vec<StageSource>,
     pub allocation: ()
 }
 
@@ -32,32 +143,91 @@ impl<'n> ShaderProgramBuilder<'n> {
         self
     }
 
-    pub fn with_vertex_shader(mut self, bytes: Vec<u32>) -> Self {
-        self.vertex_shader_source = Some(bytes);
+    pub fn with_vertex_shader(self, bytes: Vec<u32>) -> Self {
+        self.add_stage(ShaderStageFlags::VERTEX, bytes)
+    }
+
+    pub fn with_fragment_shader(self, bytes: Vec<u32>) -> Self {
+        self.add_stage(ShaderStageFlags::FRAGMENT, bytes)
+    }
+
+    pub fn with_compute_shader(self, bytes: Vec<u32>) -> Self {
+        self.add_stage(ShaderStageFlags::COMPUTE, bytes)
+    }
+
+    /// Add or replace the module of `stage`
+    pub fn add_stage(mut self, stage: ShaderStageFlags, bytes: Vec<u32>) -> Self {
+        self.stages.retain(|source| source.stage != stage);
+        self.stages.push(StageSource { stage, spv: bytes, entry_point: "main".into(), specialization: None });
         self
     }
 
-    pub fn with_fragment_shader(mut self, bytes: Vec<u32>) -> Self {
-        self.fragment_shader_source = Some(bytes);
+    pub fn with_entry_point(mut self, stage: ShaderStageFlags, name: &str) -> Self {
+        self.stage_mut(stage).entry_point = name.to_string();
         self
     }
 
-    pub fn build(self) -> ShaderProgram {
+    pub fn with_specialization<T: SpecializationConstants>(mut self, stage: ShaderStageFlags, constants: &T) -> Self {
+        self.stage_mut(stage).specialization = Some(SpecializationData::new(constants));
+        self
+    }
+
+    fn stage_mut(&mut self, stage: ShaderStageFlags) -> &mut StageSource {
+        self.stages.iter_mut()
+            .find(|source| source.stage == stage)
+            .unwrap_or_else(|| panic!("Stage {:?} is not added", stage))
+    }
+
+    ///
+    /// Create the modules, checking that every entry point exists and has the right stage
+    ///
+    pub fn try_build(self) -> Result<ShaderProgram, Box<dyn Error>> {
+
+        let device = self.device.expect("Device is missing");
+        let mut program = ShaderProgram { stages: vec![] };
 
-        let binding = self.fragment_shader_source.unwrap();
-        let create_info = ShaderModuleCreateInfo::default()
-            .code(&binding);
+        for source in self.stages {
 
-        let fs = unsafe { self.device.unwrap().create_shader_module(&create_info, None) };
+            let reflection = ShaderReflection::from_spv_entry_point(&source.spv, &source.entry_point);
 
-        //---------------------------------------------------
+            let reflection = match reflection {
+                Ok(reflection) if reflection.stage == source.stage => reflection,
+                Ok(reflection) => {
+                    program.destroy(device);
+                    return Err(format!(
+                        "entry point {:?} is a {:?} shader, expected {:?}", source.entry_point, reflection.stage, source.stage
+                    ).into());
+                },
+                Err(err) => {
+                    program.destroy(device);
+                    return Err(err.into());
+                }
+            };
 
-        let binding = self.vertex_shader_source.unwrap();
-        let create_info = ShaderModuleCreateInfo::default()
-            .code(&binding);
+            let create_info = ShaderModuleCreateInfo::default()
+                .code(&source.spv);
 
-        let vs = unsafe { self.device.unwrap().create_shader_module(&create_info, None) };
+            let module = match unsafe { device.create_shader_module(&create_info, None) } {
+                Ok(module) => module,
+                Err(err) => {
+                    program.destroy(device);
+                    return Err(err.into());
+                }
+            };
 
-        ShaderProgram { vertex_shader: vs.unwrap(), fragment_shader: fs.unwrap() }
+            program.stages.push(ShaderProgramStage {
+                stage: source.stage,
+                module,
+                entry_point: CString::new(source.entry_point)?,
+                specialization: source.specialization,
+                reflection
+            });
+        }
+
+        Ok(program)
     }
-}
\ No newline at end of file
+
+    pub fn build(self) -> ShaderProgram {
+        self.try_build().expect("Failed to build shader program")
+    }
+}
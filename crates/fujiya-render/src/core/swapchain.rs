@@ -3,6 +3,7 @@ use ash::vk::{
     Extent2D,
     Format,
     Image,
+    ImageUsageFlags,
     PresentModeKHR,
     SurfaceTransformFlagsKHR,
     SwapchainKHR
@@ -38,6 +39,7 @@ pub struct SwapchainBuilder<'n> {
     resolution: Option<Extent2D>,
     transform: Option<SurfaceTransformFlagsKHR>,
     present_mode: Option<PresentModeKHR>,
+    image_usage: Option<ImageUsageFlags>,
     instance: Option<&'n ash::Instance>,
     device: Option<&'n ash::Device>,
     surface: Option<&'n  ash::vk::SurfaceKHR>
@@ -74,6 +76,12 @@ impl<'n> SwapchainBuilder<'n> {
         self
     }
 
+    /// `COLOR_ATTACHMENT` if not set
+    pub fn with_image_usage(mut self, usage: ImageUsageFlags) -> Self {
+        self.image_usage = Some(usage);
+        self
+    }
+
     pub fn with_instance(mut self, instance: &'n ash::Instance) -> Self {
         self.instance = Some(instance);
         self
@@ -101,6 +109,7 @@ impl<'n> SwapchainBuilder<'n> {
         let resolution = self.resolution.expect("Missing resolution");
         let transform = self.transform.expect("Missing surface transform");
         let present_mode = self.present_mode.expect("Missing present mode");
+        let image_usage = self.image_usage.unwrap_or(ImageUsageFlags::COLOR_ATTACHMENT);
 
         log::info!("{:?}", resolution);
 
@@ -110,7 +119,7 @@ impl<'n> SwapchainBuilder<'n> {
             .image_color_space(image_color_space)
             .image_format(format)
             .image_extent(resolution)
-            .image_usage(ash::vk::ImageUsageFlags::COLOR_ATTACHMENT)
+            .image_usage(image_usage)
             .image_sharing_mode(ash::vk::SharingMode::EXCLUSIVE)
             .pre_transform(transform)
             .composite_alpha(ash::vk::CompositeAlphaFlagsKHR::OPAQUE)
@@ -0,0 +1,170 @@
+use std::sync::Mutex;
+
+use ash::vk::{self, CommandBuffer};
+use rayon::prelude::*;
+
+use crate::{CommandAllocator, CommandAllocatorBuilder, CommandAllocatorStats};
+
+///
+/// Render pass state inherited by secondary command buffers
+///
+/// Plain handles only, so it can be shared between recording threads.
+///
+#[derive(Clone, Copy, Default)]
+pub struct SecondaryInheritance {
+    pub render_pass: vk::RenderPass,
+    pub subpass: u32,
+    pub framebuffer: vk::Framebuffer,
+}
+
+///
+/// One [`CommandAllocator`] per rayon worker thread for one frame in flight
+///
+/// Vulkan requires a command pool to be externally synchronized while command buffers
+/// allocated from it are recorded, so every worker records only into its own pool.
+///
+/// The primary command buffer must begin the render pass with
+/// [`vk::SubpassContents::SECONDARY_COMMAND_BUFFERS`] and run the returned buffers
+/// with `cmd_execute_commands`.
+///
+pub struct ThreadCommandPools {
+    pools: Vec<Mutex<CommandAllocator>>,
+}
+
+impl ThreadCommandPools {
+
+    /// Reset the pool of every worker, the frame's fence must be signaled
+    pub fn reset(&mut self, device: &ash::Device) {
+        for pool in &mut self.pools {
+            pool.get_mut().unwrap().reset(device);
+        }
+    }
+
+    pub fn thread_count(&self) -> usize {
+        self.pools.len()
+    }
+
+    ///
+    /// Split `items` into batches of `batch_size` and record every batch into its own
+    /// secondary command buffer in parallel
+    ///
+    /// Returned command buffers keep the order of the batches.
+    ///
+    pub fn record_secondary<T, F>(
+        &self,
+        device: &ash::Device,
+        inheritance: SecondaryInheritance,
+        items: &[T],
+        batch_size: usize,
+        record: F
+    ) -> Vec<CommandBuffer>
+        where T: Sync, F: Fn(&ash::Device, CommandBuffer, &[T]) + Sync
+    {
+        assert!(
+            rayon::current_num_threads() <= self.pools.len(),
+            "Thread pool has {} threads but only {} command pools were created",
+            rayon::current_num_threads(),
+            self.pools.len()
+        );
+
+        items.par_chunks(batch_size.max(1))
+            .map(|batch| {
+
+                let thread = rayon::current_thread_index().unwrap_or(0);
+                let command_buffer = self.pools[thread].lock().unwrap().secondary(device);
+
+                let inheritance_info = vk::CommandBufferInheritanceInfo::default()
+                    .render_pass(inheritance.render_pass)
+                    .subpass(inheritance.subpass)
+                    .framebuffer(inheritance.framebuffer);
+
+                let begin_info = vk::CommandBufferBeginInfo::default()
+                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT | vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE)
+                    .inheritance_info(&inheritance_info);
+
+                unsafe {
+                    device.begin_command_buffer(command_buffer, &begin_info)
+                        .expect("Failed to begin secondary command buffer");
+                }
+
+                record(device, command_buffer, batch);
+
+                unsafe {
+                    device.end_command_buffer(command_buffer)
+                        .expect("Failed to end secondary command buffer");
+                }
+
+                command_buffer
+            })
+            .collect()
+    }
+
+    /// Usage summed over all worker pools
+    pub fn stats(&self) -> CommandAllocatorStats {
+        self.pools.iter().fold(CommandAllocatorStats::default(), |mut total, pool| {
+            let stats = pool.lock().unwrap().stats();
+            total.primary_allocated += stats.primary_allocated;
+            total.primary_used += stats.primary_used;
+            total.secondary_allocated += stats.secondary_allocated;
+            total.secondary_used += stats.secondary_used;
+            total.resets = total.resets.max(stats.resets);
+            total
+        })
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device) {
+        for pool in &mut self.pools {
+            pool.get_mut().unwrap().destroy(device);
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ThreadCommandPoolsBuilder<'n> {
+    device: Option<&'n ash::Device>,
+    family_index: Option<u32>,
+    thread_count: Option<usize>
+}
+
+impl<'n> ThreadCommandPoolsBuilder<'n> {
+
+    pub fn new() -> Self {
+        Self { ..Default::default() }
+    }
+
+    pub fn device(mut self, device: &'n ash::Device) -> Self {
+        self.device = Some(device);
+        self
+    }
+
+    pub fn family_index(mut self, family_index: u32) -> Self {
+        self.family_index = Some(family_index);
+        self
+    }
+
+    /// Number of recording threads, defaults to the size of the current rayon thread pool
+    pub fn thread_count(mut self, thread_count: usize) -> Self {
+        self.thread_count = Some(thread_count);
+        self
+    }
+
+    pub fn build(self) -> ThreadCommandPools {
+
+        let device = self.device.expect("Device is missing");
+        let family_index = self.family_index.expect("Family index is missing");
+        let thread_count = self.thread_count.unwrap_or_else(rayon::current_num_threads);
+
+        let pools = (0..thread_count)
+            .map(|_| {
+                Mutex::new(
+                    CommandAllocatorBuilder::new()
+                        .device(device)
+                        .family_index(family_index)
+                        .build()
+                )
+            })
+            .collect();
+
+        ThreadCommandPools { pools }
+    }
+}
@@ -32,7 +32,7 @@ impl<'n> GraphicsDeviceBuilder<()> {
                 .with_app_name(c"App")
                 .with_engine_name(c"Fujiya")
                 .with_engine_version(24_06_2025)
-                .with_api_version(ash::vk::API_VERSION_1_0)
+                .with_api_version(ash::vk::API_VERSION_1_2)
                 .build()
         })
 
@@ -1,5 +1,7 @@
 
 
+use ash::vk::{PhysicalDeviceDescriptorIndexingFeatures, PhysicalDeviceFeatures, TRUE};
+
 use crate::{core::{
     Instance,
 }, DeviceBuilder, QueueFamily};
@@ -24,10 +26,36 @@ impl GraphicsDeviceBuilder<WithQueueFamily> {
 
     pub fn build(self) -> GraphicsDevice {
         self.build_with_device(|instance, phys_dev, queue_family| {
-            DeviceBuilder::new()
+
+            // Запросы включаются только если устройство их поддерживает
+            let supported = phys_dev.phys_info.features;
+            let features = PhysicalDeviceFeatures::default()
+                .pipeline_statistics_query(supported.pipeline_statistics_query == TRUE)
+                .occlusion_query_precise(supported.occlusion_query_precise == TRUE);
+
+            // Bindless: частично заполненные массивы, обновляемые после привязки
+            let indexing = phys_dev.phys_info.descriptor_indexing;
+            let descriptor_indexing = PhysicalDeviceDescriptorIndexingFeatures::default()
+                .shader_sampled_image_array_non_uniform_indexing(indexing.shader_sampled_image_array_non_uniform_indexing == TRUE)
+                .shader_storage_buffer_array_non_uniform_indexing(indexing.shader_storage_buffer_array_non_uniform_indexing == TRUE)
+                .descriptor_binding_sampled_image_update_after_bind(indexing.descriptor_binding_sampled_image_update_after_bind == TRUE)
+                .descriptor_binding_storage_buffer_update_after_bind(indexing.descriptor_binding_storage_buffer_update_after_bind == TRUE)
+                .descriptor_binding_update_unused_while_pending(indexing.descriptor_binding_update_unused_while_pending == TRUE)
+                .descriptor_binding_partially_bound(indexing.descriptor_binding_partially_bound == TRUE)
+                .descriptor_binding_variable_descriptor_count(indexing.descriptor_binding_variable_descriptor_count == TRUE)
+                .runtime_descriptor_array(indexing.runtime_descriptor_array == TRUE);
+
+            let mut builder = DeviceBuilder::new();
+
+            if instance.api_version >= ash::vk::API_VERSION_1_2 && phys_dev.phys_info.phys_prop.api_version >= ash::vk::API_VERSION_1_2 {
+                builder = builder.with_descriptor_indexing(descriptor_indexing);
+            }
+
+            builder
                 .with_extensions(vec![
                     c"VK_KHR_swapchain"
                 ])
+                .with_features(features)
                 .queue_family(&queue_family)
                 .with_instance(&instance.raw)
                 .with_phys_dev(&phys_dev.raw)
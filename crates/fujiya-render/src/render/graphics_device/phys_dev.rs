@@ -41,6 +41,7 @@ impl<'n> GraphicsDeviceBuilder<WithInstance<'n>> {
             PhysicalDeviceBuilder::new()
                 .with_surface(&surface.raw)
                 .with_surface_load(&surface.raw_load)
+                .with_api_version(instance.api_version)
                 .select_physical_device(|phys_infos: &Vec<PhysicalDeviceInfo>| {
 
                         for &priority_type in PRIORITY_GPU {
@@ -3,8 +3,10 @@ pub(crate) mod graphics_device;
 pub(crate) mod window_manager;
 pub(crate) mod render_context;
 pub(crate) mod standart_pipeline;
+pub(crate) mod shader_variants;
 
 pub use window_manager::*;
 pub use graphics_device::*;
 pub use render_context::*;
-pub use standart_pipeline::*;
\ No newline at end of file
+pub use standart_pipeline::*;
+pub use shader_variants::*;
\ No newline at end of file
@@ -0,0 +1,239 @@
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    path::{Path, PathBuf}
+};
+
+use ash::vk::{self, ShaderStageFlags};
+
+use crate::{
+    RenderContext,
+    RenderPipeline,
+    ShaderCompiler,
+    ShaderDefines,
+    ShaderProgram,
+    ShaderProgramBuilder,
+    ShaderVariants,
+    same_file
+};
+
+/// Creates the pipeline of a variant from its shader program
+pub type VariantPipelineBuilder = Box<dyn Fn(&RenderContext, &ShaderProgram) -> RenderPipeline>;
+
+/// `get_variant` of the module generated by `embed_shader_variants`
+pub type PrecompiledShaders = fn(&str, &str) -> Option<&'static [u32]>;
+
+pub struct ShaderVariant {
+    pub program: ShaderProgram,
+    pub pipeline: RenderPipeline,
+    /// Stage sources and their includes
+    pub dependencies: Vec<PathBuf>
+}
+
+struct VariantStage {
+    stage: ShaderStageFlags,
+    path: PathBuf,
+    entry_point: String
+}
+
+///
+/// Shader program and pipeline for every combination of defines a material asks for
+///
+/// Variants are built on first use. SPIR-V is looked up in the precompiled shaders
+/// first and compiled from the sources otherwise, [`ShaderVariantCache::precompile`]
+/// builds a declared set up front so the first frame doesn't stall.
+///
+/// # Example:
+///
+/// ```ignore
+/// let mut mesh = ShaderVariantCache::new(move |ctx, program| {
+///     StandartPipelineBuilder::new()
+///         .with_graphics_device(ctx)
+///         .with_shader_program(program)
+///         .with_set_layouts(&set_layouts)
+///         .build()
+/// })
+/// .add_stage(vk::ShaderStageFlags::VERTEX, "./shared/shaders/mesh.vert")
+/// .add_stage(vk::ShaderStageFlags::FRAGMENT, "./shared/shaders/mesh.frag")
+/// .with_precompiled("./shared/shaders", shaders::get_variant);
+///
+/// mesh.precompile(&ctx, &ShaderDefines::permutations(&["NORMAL_MAP", "ALPHA_TEST"]))?;
+///
+/// let variant = mesh.get(&ctx, &ShaderDefines::new().with("ALPHA_TEST"))?;
+/// variant.pipeline.bind(device, command_buffer);
+/// ```
+///
+pub struct ShaderVariantCache {
+    pub sources: ShaderVariants,
+    stages: Vec<VariantStage>,
+    precompiled: Option<(PathBuf, PrecompiledShaders)>,
+    // После изменения исходников встроенный SPIR-V уже устарел
+    outdated: HashSet<PathBuf>,
+    build_pipeline: VariantPipelineBuilder,
+    variants: HashMap<ShaderDefines, ShaderVariant>
+}
+
+impl ShaderVariantCache {
+
+    pub fn new<F>(build_pipeline: F) -> Self
+    where
+        F: Fn(&RenderContext, &ShaderProgram) -> RenderPipeline + 'static
+    {
+        Self {
+            sources: ShaderVariants::default(),
+            stages: vec![],
+            precompiled: None,
+            outdated: HashSet::new(),
+            build_pipeline: Box::new(build_pipeline),
+            variants: HashMap::new()
+        }
+    }
+
+    /// Compiler for variants that are not precompiled, its defines are shared by all variants
+    pub fn with_compiler(mut self, compiler: ShaderCompiler) -> Self {
+        self.sources = ShaderVariants::new(compiler);
+        self
+    }
+
+    pub fn add_stage(mut self, stage: ShaderStageFlags, path: impl Into<PathBuf>) -> Self {
+        self.stages.push(VariantStage { stage, path: path.into(), entry_point: "main".into() });
+        self
+    }
+
+    pub fn with_entry_point(mut self, stage: ShaderStageFlags, name: &str) -> Self {
+        self.stages.iter_mut()
+            .find(|variant_stage| variant_stage.stage == stage)
+            .unwrap_or_else(|| panic!("Stage {:?} is not added", stage))
+            .entry_point = name.to_string();
+        self
+    }
+
+    /// Shaders embedded at build time, stage paths are looked up relative to `dir`
+    pub fn with_precompiled(mut self, dir: impl Into<PathBuf>, lookup: PrecompiledShaders) -> Self {
+        self.precompiled = Some((dir.into(), lookup));
+        self
+    }
+
+    pub fn contains(&self, defines: &ShaderDefines) -> bool {
+        self.variants.contains_key(defines)
+    }
+
+    pub fn len(&self) -> usize {
+        self.variants.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.variants.is_empty()
+    }
+
+    pub fn get(&mut self, ctx: &RenderContext, defines: &ShaderDefines) -> Result<&ShaderVariant, Box<dyn Error>> {
+
+        if !self.variants.contains_key(defines) {
+            let variant = self.build_variant(ctx, defines)?;
+            self.variants.insert(defines.clone(), variant);
+        }
+
+        Ok(&self.variants[defines])
+    }
+
+    /// Build every variant of `permutations` now instead of on first use
+    pub fn precompile(&mut self, ctx: &RenderContext, permutations: &[ShaderDefines]) -> Result<(), Box<dyn Error>> {
+        for defines in permutations {
+            self.get(ctx, defines)?;
+        }
+        Ok(())
+    }
+
+    ///
+    /// Drop the variants built from `changed` or one of its includes
+    ///
+    /// They are rebuilt from the sources on next use, even if they were precompiled.
+    /// Waits for the device to be idle before destroying their pipelines.
+    ///
+    pub fn invalidate(&mut self, ctx: &RenderContext, changed: impl AsRef<Path>) -> Result<usize, vk::Result> {
+
+        let changed = changed.as_ref();
+        self.sources.invalidate(changed);
+
+        let stale = self.variants.iter()
+            .filter(|(_, variant)| variant.dependencies.iter().any(|dependency| same_file(dependency, changed)))
+            .map(|(defines, _)| defines.clone())
+            .collect::<Vec<_>>();
+
+        if stale.is_empty() {
+            return Ok(0);
+        }
+
+        let outdated = self.stages.iter()
+            .filter(|stage| {
+                let dependencies = self.sources.compiler.dependencies(&stage.path)
+                    .unwrap_or_else(|_| vec![stage.path.clone()]);
+                dependencies.iter().any(|dependency| same_file(dependency, changed))
+            })
+            .map(|stage| stage.path.clone())
+            .collect::<Vec<_>>();
+        self.outdated.extend(outdated);
+
+        let device = ctx.graphics_device.raw_device();
+        unsafe { device.device_wait_idle()? };
+
+        for defines in &stale {
+            if let Some(variant) = self.variants.remove(defines) {
+                variant.pipeline.destroy(device);
+                variant.program.destroy(device);
+            }
+        }
+
+        Ok(stale.len())
+    }
+
+    /// The variants must not be used by any pending command buffer
+    pub fn destroy(&mut self, device: &ash::Device) {
+        for (_, variant) in self.variants.drain() {
+            variant.pipeline.destroy(device);
+            variant.program.destroy(device);
+        }
+    }
+
+    fn build_variant(&mut self, ctx: &RenderContext, defines: &ShaderDefines) -> Result<ShaderVariant, Box<dyn Error>> {
+
+        let mut builder = ShaderProgramBuilder::new()
+            .with_device(ctx.graphics_device.raw_device());
+
+        let mut dependencies = vec![];
+
+        for stage in &self.stages {
+
+            let precompiled = self.precompiled.as_ref()
+                .filter(|_| !self.outdated.contains(&stage.path))
+                .and_then(|(dir, lookup)| {
+                    let name = stage.path.strip_prefix(dir).ok()?.to_string_lossy().replace('\\', "/");
+                    lookup(&name, &defines.to_string())
+                });
+
+            let spv = match precompiled {
+                Some(spv) => {
+                    // Исходников может и не быть рядом с бинарником
+                    let includes = self.sources.compiler.dependencies(&stage.path)
+                        .unwrap_or_else(|_| vec![stage.path.clone()]);
+                    dependencies.extend(includes);
+                    spv.to_vec()
+                },
+                None => {
+                    let compiled = self.sources.compile(&stage.path, defines)?;
+                    dependencies.extend(compiled.dependencies.iter().cloned());
+                    compiled.spv.clone()
+                },
+            };
+
+            builder = builder
+                .add_stage(stage.stage, spv)
+                .with_entry_point(stage.stage, &stage.entry_point);
+        }
+
+        let program = builder.try_build()?;
+        let pipeline = (self.build_pipeline)(ctx, &program);
+
+        Ok(ShaderVariant { program, pipeline, dependencies })
+    }
+}
@@ -1,5 +1,3 @@
-use std::mem::offset_of;
-
 use ash::vk::{self,
     AttachmentReference,
     DescriptorSetLayout,
@@ -11,6 +9,7 @@ use crate::{
     RenderPassBuilder,
     RenderPipeline,
     RenderPipelineBuilder,
+    ShaderProgram,
     ShaderProgramBuilder,
     SubpassBuilder
 };
@@ -19,46 +18,12 @@ use crate::{
 pub struct StandartPipelineBuilder<'n> {
     pub ctx: Option<&'n RenderContext>,
     pub vertex_shader: Option<Vec<u32>>,
-    pub fragment_shader: Option<Vec<u32>>
-}
-
-#[repr(C)]
-#[derive(Copy, Clone)]
-struct Vertex {
-    pos: [f32; 3],
-    color: [f32; 3],
-}
-
-impl Vertex {
-
-    fn get_binding_descriptions() -> [vk::VertexInputBindingDescription; 1] {
-        [vk::VertexInputBindingDescription {
-            binding: 0,
-            stride: std::mem::size_of::<Self>() as u32,
-            input_rate: vk::VertexInputRate::VERTEX,
-        }]
-    }
-
-    fn get_attribute_descriptions() -> [vk::VertexInputAttributeDescription; 2] {
-        [
-            vk::VertexInputAttributeDescription {
-                location: 0,
-                binding: 0,
-                format: vk::Format::R32G32B32_SFLOAT,
-                offset: offset_of!(Self, pos) as u32,
-            },
-            vk::VertexInputAttributeDescription {
-                binding: 0,
-                location: 1,
-                format: vk::Format::R32G32B32_SFLOAT,
-                offset: offset_of!(Self, color) as u32,
-            },
-        ]
-    }
+    pub fragment_shader: Option<Vec<u32>>,
+    pub program: Option<&'n ShaderProgram>,
+    pub set_layouts: Vec<DescriptorSetLayout>,
+    pub push_constant_ranges: Vec<vk::PushConstantRange>
 }
 
-
-
 impl<'n> StandartPipelineBuilder<'n> {
 
     pub fn new() -> Self {
@@ -80,15 +45,48 @@ impl<'n> StandartPipelineBuilder<'n> {
         self
     }
 
-    pub fn build(self, desc: DescriptorSetLayout) -> RenderPipeline {
+    /// Use every stage of an already built program instead of the vertex and fragment shaders
+    pub fn with_shader_program(mut self, program: &'n ShaderProgram) -> Self {
+        self.program = Some(program);
+        self
+    }
+
+    pub fn add_set_layout(mut self, layout: DescriptorSetLayout) -> Self {
+        self.set_layouts.push(layout);
+        self
+    }
+
+    pub fn with_set_layouts(mut self, layouts: &[DescriptorSetLayout]) -> Self {
+        self.set_layouts.extend_from_slice(layouts);
+        self
+    }
+
+    /// Without explicit ranges the push constant range is taken from the shaders
+    pub fn add_push_constant_range(mut self, range: vk::PushConstantRange) -> Self {
+        self.push_constant_ranges.push(range);
+        self
+    }
+
+    pub fn build(self) -> RenderPipeline {
 
         let ctx = self.ctx.unwrap();
 
-        let shader = ShaderProgramBuilder::new()
-            .with_device(&ctx.graphics_device.device.raw)
-            .with_fragment_shader(self.fragment_shader.unwrap())
-            .with_vertex_shader(self.vertex_shader.unwrap())
-            .build();
+        let owned_program = match self.program {
+            Some(_) => None,
+            None => Some(
+                ShaderProgramBuilder::new()
+                    .with_device(&ctx.graphics_device.device.raw)
+                    .with_fragment_shader(self.fragment_shader.expect("Fragment shader is missing"))
+                    .with_vertex_shader(self.vertex_shader.expect("Vertex shader is missing"))
+                    .build()
+            ),
+        };
+
+        let program = self.program.or(owned_program.as_ref()).unwrap();
+
+        // Вершинный вход берём из шейдера, а не из захардкоженной структуры
+        let reflection = program.reflect()
+            .unwrap_or_else(|err| panic!("Shader reflection failed: {}", err));
 
         let subpass = SubpassBuilder::new()
             .add_color_attachment_ref(
@@ -115,32 +113,50 @@ impl<'n> StandartPipelineBuilder<'n> {
                     samples: vk::SampleCountFlags::TYPE_1,
                     load_op: vk::AttachmentLoadOp::CLEAR,
                     store_op: vk::AttachmentStoreOp::STORE,
-                    final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+                    final_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
                     ..Default::default()
                 })
             .build();
 
-        let binding_description = Vertex::get_binding_descriptions();
-        let attribute_description = Vertex::get_attribute_descriptions();
+        let push_constant_ranges = if self.push_constant_ranges.is_empty() {
+            reflection.push_constant_range.into_iter().collect()
+        } else {
+            self.push_constant_ranges
+        };
+
+        let (binding_description, attribute_description) = reflection.vertex_input(0);
+        let binding_description = [binding_description];
 
         let vertex_input_state_info = vk::PipelineVertexInputStateCreateInfo::default()
             .vertex_attribute_descriptions(&attribute_description)
             .vertex_binding_descriptions(&binding_description);
 
+        let topology = if program.has_tessellation() {
+            PrimitiveTopology::PATCH_LIST
+        } else {
+            PrimitiveTopology::TRIANGLE_LIST
+        };
+
         let pipeline = RenderPipelineBuilder::new()
-            .with_vertex_shader(shader.vertex_shader)
-            .with_fragment_shader(shader.fragment_shader)
+            .with_shader_program(program)
             .with_resolution(ctx.window_manager.caps.current_extent)
             .with_format(ctx.window_manager.format.format)
             .with_vertex_input_info(vertex_input_state_info)
             .with_input_assembly_info(
                 vk::PipelineInputAssemblyStateCreateInfo::default()
-                            .topology(PrimitiveTopology::TRIANGLE_LIST)
+                            .topology(topology)
                             .primitive_restart_enable(false)
             )
             .with_render_pass(&render_pass.raw)
             .with_device(&ctx.graphics_device.device.raw)
-            .build(desc);
+            .with_set_layouts(&self.set_layouts)
+            .with_push_constant_ranges(&push_constant_ranges)
+            .build();
+
+        // Модули нужны только при создании пайплайна
+        if let Some(program) = &owned_program {
+            program.destroy(&ctx.graphics_device.device.raw);
+        }
 
         pipeline
     }
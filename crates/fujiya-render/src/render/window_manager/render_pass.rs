@@ -68,7 +68,8 @@ impl WindowManagerBuilder<WithSwapchain> {
                         samples: vk::SampleCountFlags::TYPE_1,
                         load_op: vk::AttachmentLoadOp::CLEAR,
                         store_op: vk::AttachmentStoreOp::STORE,
-                        final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+                        // В PRESENT_SRC_KHR изображение переводит граф после последнего пасса
+                        final_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
                         ..Default::default()
                     })
                 .build()
@@ -1,4 +1,4 @@
-use ash::vk::{PresentModeKHR, SurfaceCapabilitiesKHR, SurfaceFormatKHR};
+use ash::vk::{ImageUsageFlags, PresentModeKHR, SurfaceCapabilitiesKHR, SurfaceFormatKHR};
 use winit::window::Window;
 
 use crate::{ Device, Instance, Surface, Swapchain, SwapchainBuilder, WindowManagerBuilder, WithMode };
@@ -39,6 +39,8 @@ impl WindowManagerBuilder<WithMode> {
 
             let extent = caps.current_extent;
             let transform = caps.current_transform;
+            // Копирование в swapchain нужно отладочному виду графа, если поверхность это позволяет
+            let usage = ImageUsageFlags::COLOR_ATTACHMENT | (caps.supported_usage_flags & ImageUsageFlags::TRANSFER_DST);
 
             log::info!("{:?}", extent);
 
@@ -48,6 +50,7 @@ impl WindowManagerBuilder<WithMode> {
                 .with_resolution(extent)
                 .with_transform(transform)
                 .with_present_mode(*mode)
+                .with_image_usage(usage)
                 .with_instance(&instance.raw)
                 .with_device(&device.raw)
                 .with_surface(&surface.raw)
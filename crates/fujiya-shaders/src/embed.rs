@@ -0,0 +1,306 @@
+use std::{
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf}
+};
+
+use crate::{ShaderCompiler, ShaderDefines, ShaderError, ShaderLanguage};
+
+///
+/// Shader compiled by [`embed_shaders`]
+///
+#[derive(Debug, Clone)]
+pub struct EmbeddedShader {
+    /// Path relative to the source directory, e.g. `triangle.vert`
+    pub name: String,
+    /// Name of the generated constant, e.g. `TRIANGLE_VERT` or `MESH_FRAG__ALPHA_TEST`
+    pub const_name: String,
+    /// Empty for the variant compiled without extra defines
+    pub defines: ShaderDefines,
+    pub spv: Vec<u32>,
+    pub dependencies: Vec<PathBuf>,
+}
+
+///
+/// Compile every shader in `src_dir` and write a Rust module embedding the SPIR-V
+///
+/// Meant for build scripts: prints `cargo:rerun-if-changed` for the directory, every
+/// shader and every include. All errors are returned together so one build shows them all.
+///
+/// The generated module has one `pub const NAME_EXT: &[u32]` per shader and
+/// `pub fn get(name: &str) -> Option<&'static [u32]>`.
+///
+/// # Example:
+///
+/// ```ignore
+/// // build.rs
+/// let out = PathBuf::from(std::env::var("OUT_DIR").unwrap()).join("shaders.rs");
+/// if let Err(errors) = embed_shaders("./shared/shaders", out, &ShaderCompiler::new()) {
+///     for err in errors {
+///         eprintln!("{}", err);
+///     }
+///     std::process::exit(1);
+/// }
+///
+/// // main.rs
+/// mod shaders { include!(concat!(env!("OUT_DIR"), "/shaders.rs")); }
+/// let spv = shaders::TRIANGLE_VERT.to_vec();
+/// ```
+///
+pub fn embed_shaders(
+    src_dir: impl AsRef<Path>,
+    out_file: impl AsRef<Path>,
+    compiler: &ShaderCompiler
+) -> Result<Vec<EmbeddedShader>, Vec<ShaderError>> {
+    embed_shader_variants(src_dir, out_file, compiler, &[])
+}
+
+///
+/// [`embed_shaders`] that also precompiles the declared variants of some shaders
+///
+/// Each variant gets its own constant, e.g. `MESH_FRAG__ALPHA_TEST_NORMAL_MAP`, and the
+/// generated module has `pub fn get_variant(name: &str, defines: &str)` where `defines`
+/// is [`ShaderDefines`] formatted with `to_string()`.
+///
+/// # Example:
+///
+/// ```ignore
+/// let permutations = ShaderDefines::permutations(&["NORMAL_MAP", "ALPHA_TEST"]);
+/// embed_shader_variants("./shared/shaders", out, &ShaderCompiler::new(), &[("mesh.frag", &permutations)])?;
+///
+/// // main.rs
+/// let spv = shaders::get_variant("mesh.frag", &ShaderDefines::new().with("ALPHA_TEST").to_string());
+/// ```
+///
+pub fn embed_shader_variants(
+    src_dir: impl AsRef<Path>,
+    out_file: impl AsRef<Path>,
+    compiler: &ShaderCompiler,
+    variants: &[(&str, &[ShaderDefines])]
+) -> Result<Vec<EmbeddedShader>, Vec<ShaderError>> {
+
+    let src_dir = src_dir.as_ref();
+    println!("cargo:rerun-if-changed={}", src_dir.display());
+
+    let mut files = vec![];
+    collect_files(src_dir, &mut files).map_err(|err| vec![err])?;
+    files.sort();
+
+    let mut shaders = vec![];
+    let mut errors = vec![];
+
+    for file in files {
+
+        println!("cargo:rerun-if-changed={}", file.display());
+
+        match ShaderLanguage::from_path(&file) {
+            Ok(Some(_)) => {},
+            Ok(None) => continue,
+            Err(err) => {
+                errors.push(err);
+                continue;
+            }
+        }
+
+        let name = file.strip_prefix(src_dir)
+            .unwrap_or(&file)
+            .to_string_lossy()
+            .replace('\\', "/");
+
+        // Вариант без дефайнов есть всегда, остальные — только объявленные
+        let mut defines_list = vec![ShaderDefines::new()];
+        for (_, declared) in variants.iter().filter(|(shader, _)| *shader == name) {
+            for defines in declared.iter() {
+                if !defines_list.contains(defines) {
+                    defines_list.push(defines.clone());
+                }
+            }
+        }
+
+        for defines in defines_list {
+            match compiler.clone().with_defines(&defines).compile_file(&file) {
+                Ok(compiled) => {
+                    for dependency in &compiled.dependencies {
+                        println!("cargo:rerun-if-changed={}", dependency.display());
+                    }
+
+                    shaders.push(EmbeddedShader {
+                        const_name: variant_const_name(&name, &defines),
+                        name: name.clone(),
+                        defines,
+                        spv: compiled.spv,
+                        dependencies: compiled.dependencies,
+                    });
+                },
+                Err(err) => errors.push(err),
+            }
+        }
+    }
+
+    for (shader, _) in variants {
+        let compiled = shaders.iter().any(|embedded| embedded.name == *shader);
+        let failed = errors.iter().any(|err| err.path() == src_dir.join(shader));
+
+        if !compiled && !failed {
+            errors.push(ShaderError::Io {
+                path: src_dir.join(shader),
+                message: "variants are declared for a shader that doesn't exist".into(),
+            });
+        }
+    }
+
+    errors.extend(check_const_names(src_dir, &shaders));
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let out_file = out_file.as_ref();
+    fs::write(out_file, generate_module(&shaders)).map_err(|err| {
+        vec![ShaderError::Io { path: out_file.to_path_buf(), message: err.to_string() }]
+    })?;
+
+    Ok(shaders)
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), ShaderError> {
+
+    let io_error = |err: std::io::Error| ShaderError::Io { path: dir.to_path_buf(), message: err.to_string() };
+
+    for entry in fs::read_dir(dir).map_err(io_error)? {
+        let path = entry.map_err(io_error)?.path();
+
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// `mesh.frag` with `ALPHA_TEST` -> `MESH_FRAG__ALPHA_TEST`
+fn variant_const_name(name: &str, defines: &ShaderDefines) -> String {
+    if defines.is_empty() {
+        return const_name(name);
+    }
+
+    let suffix = defines.iter()
+        .map(|(define, value)| if value == "1" { define.to_string() } else { format!("{}_{}", define, value) })
+        .collect::<Vec<_>>()
+        .join("_");
+
+    format!("{}__{}", const_name(name), const_name(&suffix))
+}
+
+/// Different shaders and defines can map to one name, e.g. `MAX_LIGHTS=8` and `MAX_LIGHTS_8`
+fn check_const_names(src_dir: &Path, shaders: &[EmbeddedShader]) -> Vec<ShaderError> {
+
+    let mut errors = vec![];
+    for (index, shader) in shaders.iter().enumerate() {
+        if let Some(other) = shaders[..index].iter().find(|other| other.const_name == shader.const_name) {
+            errors.push(ShaderError::DuplicateConstant {
+                path: src_dir.join(&shader.name),
+                const_name: shader.const_name.clone(),
+                other: match other.defines.is_empty() {
+                    true => other.name.clone(),
+                    false => format!("{} with {}", other.name, other.defines),
+                },
+            });
+        }
+    }
+    errors
+}
+
+/// `ray-tracing.frag` -> `RAY_TRACING_FRAG`
+fn const_name(name: &str) -> String {
+    let mut const_name = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect::<String>();
+
+    if const_name.starts_with(|c: char| c.is_ascii_digit()) {
+        const_name.insert(0, '_');
+    }
+
+    const_name
+}
+
+fn generate_module(shaders: &[EmbeddedShader]) -> String {
+
+    let mut module = String::from("// @generated by fujiya-shaders, do not edit\n\n");
+
+    for shader in shaders {
+        writeln!(module, "/// SPIR-V of `{}`", shader.name).unwrap();
+        writeln!(module, "pub const {}: &[u32] = &[", shader.const_name).unwrap();
+
+        for words in shader.spv.chunks(8) {
+            let line = words.iter().map(|word| format!("{:#010x}", word)).collect::<Vec<_>>().join(", ");
+            writeln!(module, "    {},", line).unwrap();
+        }
+
+        module.push_str("];\n\n");
+    }
+
+    module.push_str("/// Every embedded shader by its path relative to the shader directory\n");
+    module.push_str("pub const SHADERS: &[(&str, &[u32])] = &[\n");
+    for shader in shaders.iter().filter(|shader| shader.defines.is_empty()) {
+        writeln!(module, "    ({:?}, {}),", shader.name, shader.const_name).unwrap();
+    }
+    module.push_str("];\n\n");
+
+    module.push_str("/// Every embedded variant by its path and defines, the shaders themselves have empty defines\n");
+    module.push_str("pub const VARIANTS: &[(&str, &str, &[u32])] = &[\n");
+    for shader in shaders {
+        writeln!(module, "    ({:?}, {:?}, {}),", shader.name, shader.defines.to_string(), shader.const_name).unwrap();
+    }
+    module.push_str("];\n\n");
+
+    module.push_str("pub fn get(name: &str) -> Option<&'static [u32]> {\n");
+    module.push_str("    SHADERS.iter().find(|(shader, _)| *shader == name).map(|(_, spv)| *spv)\n");
+    module.push_str("}\n\n");
+
+    module.push_str("pub fn get_variant(name: &str, defines: &str) -> Option<&'static [u32]> {\n");
+    module.push_str("    VARIANTS.iter().find(|(shader, key, _)| *shader == name && *key == defines).map(|(_, _, spv)| *spv)\n");
+    module.push_str("}\n");
+
+    module
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_variant_constants() {
+        assert_eq!(variant_const_name("mesh.frag", &ShaderDefines::new()), "MESH_FRAG");
+        assert_eq!(
+            variant_const_name("mesh.frag", &ShaderDefines::new().with("NORMAL_MAP").with_value("MAX_LIGHTS", "8")),
+            "MESH_FRAG__MAX_LIGHTS_8_NORMAL_MAP"
+        );
+    }
+
+    #[test]
+    fn reports_colliding_constants() {
+        let shader = |name: &str, defines: ShaderDefines| EmbeddedShader {
+            name: name.into(),
+            const_name: variant_const_name(name, &defines),
+            defines,
+            spv: vec![],
+            dependencies: vec![],
+        };
+
+        let shaders = [
+            shader("mesh.frag", ShaderDefines::new().with_value("MAX_LIGHTS", "8")),
+            shader("mesh.frag", ShaderDefines::new().with("MAX_LIGHTS_8")),
+            shader("ray-tracing.frag", ShaderDefines::new()),
+            shader("ray_tracing.frag", ShaderDefines::new()),
+        ];
+
+        let errors = check_const_names(Path::new("shaders"), &shaders);
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(&errors[0], ShaderError::DuplicateConstant { const_name, .. } if const_name == "MESH_FRAG__MAX_LIGHTS_8"));
+        assert!(errors[1].to_string().contains("already generated for ray-tracing.frag"), "{}", errors[1]);
+    }
+}
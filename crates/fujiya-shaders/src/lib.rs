@@ -0,0 +1,436 @@
+use std::{
+    collections::HashSet,
+    fmt,
+    fs,
+    path::{Path, PathBuf}
+};
+
+use naga::{back::spv, front::{glsl, wgsl}, valid};
+
+pub(crate) mod embed;
+pub(crate) mod variants;
+pub use embed::*;
+pub use variants::*;
+
+pub use naga::ShaderStage;
+
+///
+/// Source language of a shader file
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShaderLanguage {
+    /// Vulkan GLSL, the stage comes from the extension
+    Glsl(ShaderStage),
+    /// WGSL, every entry point of the file ends up in one module
+    Wgsl,
+    /// HLSL, naga has no HLSL frontend
+    Hlsl,
+}
+
+/// Extensions of files next to shaders that are not compiled on their own: includes,
+/// precompiled SPIR-V and build scripts
+pub const SKIPPED_EXTENSIONS: &[&str] = &["glsl", "h", "inc", "hlsli", "spv", "bat", "sh", "md", "txt"];
+
+impl ShaderLanguage {
+
+    ///
+    /// Language of a file by its extension
+    ///
+    /// `Ok(None)` for [`SKIPPED_EXTENSIONS`]. Stages naga can't compile, e.g. `.geom`, and
+    /// unknown extensions are errors, so a shader is never dropped silently.
+    ///
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Option<Self>, ShaderError> {
+        let path = path.as_ref();
+        let unsupported = |reason: &str| ShaderError::Unsupported { path: path.to_path_buf(), reason: reason.into() };
+
+        let language = match path.extension().and_then(|extension| extension.to_str()).unwrap_or_default() {
+            "vert" => Self::Glsl(ShaderStage::Vertex),
+            "frag" => Self::Glsl(ShaderStage::Fragment),
+            "comp" => Self::Glsl(ShaderStage::Compute),
+            "wgsl" => Self::Wgsl,
+            "hlsl" => Self::Hlsl,
+            "geom" | "tesc" | "tese" => return Err(unsupported("geometry and tessellation stages are not supported by naga, compile them with glslc")),
+            extension if SKIPPED_EXTENSIONS.contains(&extension) => return Ok(None),
+            _ => return Err(unsupported("unknown shader extension, expected .vert, .frag, .comp or .wgsl")),
+        };
+        Ok(Some(language))
+    }
+}
+
+///
+/// Error of a shader compilation with diagnostics ready to be printed
+///
+#[derive(Debug, Clone)]
+pub enum ShaderError {
+    Io {
+        path: PathBuf,
+        message: String,
+    },
+    Include {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    Unsupported {
+        path: PathBuf,
+        reason: String,
+    },
+    Parse {
+        path: PathBuf,
+        diagnostics: String,
+    },
+    Validation {
+        path: PathBuf,
+        diagnostics: String,
+    },
+    Output {
+        path: PathBuf,
+        message: String,
+    },
+    /// Two embedded shaders or variants get the same constant name
+    DuplicateConstant {
+        path: PathBuf,
+        const_name: String,
+        /// Shader and defines the constant was generated for first
+        other: String,
+    },
+}
+
+impl ShaderError {
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::Io { path, .. }
+            | Self::Include { path, .. }
+            | Self::Unsupported { path, .. }
+            | Self::Parse { path, .. }
+            | Self::Validation { path, .. }
+            | Self::Output { path, .. }
+            | Self::DuplicateConstant { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io { path, message } => write!(f, "{}: {}", path.display(), message),
+            Self::Include { path, line, message } => write!(f, "{}:{}: {}", path.display(), line, message),
+            Self::Unsupported { path, reason } => write!(f, "{}: {}", path.display(), reason),
+            Self::Parse { path, diagnostics } => write!(f, "failed to parse {}\n{}", path.display(), diagnostics),
+            Self::Validation { path, diagnostics } => write!(f, "{} is not valid\n{}", path.display(), diagnostics),
+            Self::Output { path, message } => write!(f, "failed to write SPIR-V for {}: {}", path.display(), message),
+            Self::DuplicateConstant { path, const_name, other } => write!(
+                f, "{}: constant {} is already generated for {}, rename the shader or the define", path.display(), const_name, other
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ShaderError {}
+
+///
+/// Result of a compilation
+///
+#[derive(Debug, Clone)]
+pub struct CompiledShader {
+    pub spv: Vec<u32>,
+    /// The source file and every file it includes
+    pub dependencies: Vec<PathBuf>,
+}
+
+///
+/// In-process shader compiler built on naga
+///
+/// Supports `#include "file"` (relative to the including file, then the include
+/// directories) for GLSL and WGSL, and `#define`s for GLSL. Geometry and tessellation
+/// stages and HLSL are not supported by naga, compile them with `glslc`/`dxc` instead.
+///
+/// # Example:
+///
+/// ```ignore
+/// let shader = ShaderCompiler::new()
+///     .with_define("ALPHA_TEST", "1")
+///     .with_include_dir("./shared/shaders/include")
+///     .compile_file("./shared/shaders/triangle.frag")?;
+/// ```
+///
+#[derive(Debug, Clone, Default)]
+pub struct ShaderCompiler {
+    defines: Vec<(String, String)>,
+    include_dirs: Vec<PathBuf>,
+}
+
+impl ShaderCompiler {
+
+    pub fn new() -> Self {
+        Self { ..Default::default() }
+    }
+
+    pub fn with_define(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.defines.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn with_defines(mut self, defines: &ShaderDefines) -> Self {
+        self.defines.extend(defines.iter().map(|(name, value)| (name.to_string(), value.to_string())));
+        self
+    }
+
+    pub fn with_include_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.include_dirs.push(dir.into());
+        self
+    }
+
+    pub fn compile_file(&self, path: impl AsRef<Path>) -> Result<CompiledShader, ShaderError> {
+
+        let path = path.as_ref();
+
+        let language = ShaderLanguage::from_path(path)?.ok_or_else(|| ShaderError::Unsupported {
+            path: path.to_path_buf(),
+            reason: "the file is not compiled on its own".into(),
+        })?;
+
+        let source = read_source(path)?;
+        self.compile_source(path, &source, language)
+    }
+
+    /// Compile `source` as if it was read from `path`, includes are resolved relative to it
+    pub fn compile_source(&self, path: &Path, source: &str, language: ShaderLanguage) -> Result<CompiledShader, ShaderError> {
+
+        let mut dependencies = vec![path.to_path_buf()];
+        let mut included = HashSet::new();
+        let line_directives = matches!(language, ShaderLanguage::Glsl(_));
+        let source = self.expand_includes(path, source, line_directives, &mut dependencies, &mut included, &mut vec![])?;
+        let display_path = path.display().to_string();
+
+        let (module, pipeline_options, mut flags) = match language {
+            ShaderLanguage::Glsl(stage) => {
+                let mut options = glsl::Options::from(stage);
+                options.defines.extend(self.defines.iter().cloned());
+
+                let module = glsl::Frontend::default()
+                    .parse(&options, &source)
+                    .map_err(|err| ShaderError::Parse {
+                        path: path.to_path_buf(),
+                        diagnostics: err.emit_to_string_with_path(&source, &display_path),
+                    })?;
+
+                let pipeline_options = spv::PipelineOptions { shader_stage: stage, entry_point: "main".into() };
+
+                // GLSL под Vulkan уже в системе координат Vulkan, Y не переворачиваем
+                (module, Some(pipeline_options), spv::WriterFlags::empty())
+            },
+            ShaderLanguage::Wgsl => {
+                let module = wgsl::parse_str(&source).map_err(|err| ShaderError::Parse {
+                    path: path.to_path_buf(),
+                    diagnostics: err.emit_to_string_with_path(&source, &display_path),
+                })?;
+
+                (module, None, spv::WriterFlags::ADJUST_COORDINATE_SPACE)
+            },
+            ShaderLanguage::Hlsl => return Err(ShaderError::Unsupported {
+                path: path.to_path_buf(),
+                reason: "HLSL is not supported by the naga compiler".into(),
+            }),
+        };
+
+        let info = valid::Validator::new(valid::ValidationFlags::all(), valid::Capabilities::all())
+            .validate(&module)
+            .map_err(|err| ShaderError::Validation {
+                path: path.to_path_buf(),
+                diagnostics: err.emit_to_string_with_path(&source, &display_path),
+            })?;
+
+        flags |= spv::WriterFlags::LABEL_VARYINGS;
+        if cfg!(debug_assertions) {
+            flags |= spv::WriterFlags::DEBUG;
+        }
+
+        let options = spv::Options { flags, ..Default::default() };
+
+        let spv = spv::write_vec(&module, &info, &options, pipeline_options.as_ref())
+            .map_err(|err| ShaderError::Output { path: path.to_path_buf(), message: err.to_string() })?;
+
+        Ok(CompiledShader { spv, dependencies })
+    }
+
+    ///
+    /// Source file and everything it includes, without compiling it
+    ///
+    /// Used to decide which shaders to rebuild when an include changes.
+    ///
+    pub fn dependencies(&self, path: impl AsRef<Path>) -> Result<Vec<PathBuf>, ShaderError> {
+        let path = path.as_ref();
+        let source = read_source(path)?;
+        let mut dependencies = vec![path.to_path_buf()];
+        self.expand_includes(path, &source, false, &mut dependencies, &mut HashSet::new(), &mut vec![])?;
+        Ok(dependencies)
+    }
+
+    ///
+    /// `source` with the includes pasted in
+    ///
+    /// With `line_directives` every included chunk is wrapped in GLSL `#line` directives,
+    /// the source string number is the index of the file in `dependencies`.
+    ///
+    fn expand_includes(
+        &self,
+        path: &Path,
+        source: &str,
+        line_directives: bool,
+        dependencies: &mut Vec<PathBuf>,
+        included: &mut HashSet<PathBuf>,
+        stack: &mut Vec<PathBuf>
+    ) -> Result<String, ShaderError> {
+
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        stack.push(canonical);
+
+        let mut expanded = String::with_capacity(source.len());
+
+        for (index, line) in source.lines().enumerate() {
+
+            let trimmed = line.trim_start();
+
+            // Директивы, которые понимает только glslc
+            if trimmed.starts_with("#pragma once") || trimmed.starts_with("#extension GL_GOOGLE_include_directive") {
+                expanded.push('\n');
+                continue;
+            }
+
+            let Some(include) = trimmed.strip_prefix("#include") else {
+                expanded.push_str(line);
+                expanded.push('\n');
+                continue;
+            };
+
+            let error = |message: String| ShaderError::Include { path: path.to_path_buf(), line: index + 1, message };
+
+            let include = include.trim();
+            let name = include.strip_prefix('"').and_then(|name| name.strip_suffix('"'))
+                .or_else(|| include.strip_prefix('<').and_then(|name| name.strip_suffix('>')))
+                .ok_or_else(|| error(format!("malformed include {}", include)))?;
+
+            let file = self.resolve_include(path, name)
+                .ok_or_else(|| error(format!("{:?} not found", name)))?;
+            let canonical = file.canonicalize().unwrap_or_else(|_| file.clone());
+
+            if stack.contains(&canonical) {
+                return Err(error(format!("{:?} includes itself", name)));
+            }
+
+            if !dependencies.contains(&file) {
+                dependencies.push(file.clone());
+            }
+
+            // Каждый файл включается один раз, как с #pragma once
+            if included.insert(canonical) {
+                let source = read_source(&file)?;
+                let chunk = self.expand_includes(&file, &source, line_directives, dependencies, included, stack)?;
+
+                if line_directives {
+                    let number = |file: &Path| dependencies.iter().position(|dependency| dependency == file).unwrap_or(0);
+                    expanded.push_str(&format!("#line 1 {}\n", number(&file)));
+                    expanded.push_str(&chunk);
+                    expanded.push_str(&format!("#line {} {}\n", index + 2, number(path)));
+                } else {
+                    expanded.push_str(&chunk);
+                }
+            } else {
+                expanded.push('\n');
+            }
+        }
+
+        stack.pop();
+        Ok(expanded)
+    }
+
+    fn resolve_include(&self, path: &Path, name: &str) -> Option<PathBuf> {
+        path.parent()
+            .into_iter()
+            .chain(self.include_dirs.iter().map(|dir| dir.as_path()))
+            .map(|dir| dir.join(name))
+            .find(|file| file.is_file())
+    }
+}
+
+fn read_source(path: &Path) -> Result<String, ShaderError> {
+    fs::read_to_string(path).map_err(|err| ShaderError::Io { path: path.to_path_buf(), message: err.to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shared_shader(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("../../shared/shaders").join(name)
+    }
+
+    #[test]
+    fn compiles_shared_shaders() {
+        for name in ["triangle.vert", "triangle.frag", "ray-tracing.frag"] {
+            let shader = ShaderCompiler::new().compile_file(shared_shader(name)).unwrap();
+            assert_eq!(shader.spv[0], 0x07230203);
+            assert_eq!(shader.dependencies.len(), 1);
+        }
+    }
+
+    #[test]
+    fn expands_includes_and_defines() {
+        let dir = std::env::temp_dir().join(format!("fujiya-shaders-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("color.glsl"), "#pragma once\nvec4 tint() { return vec4(TINT, 1.0); }\n").unwrap();
+
+        let source = "#version 450\n#include \"color.glsl\"\n#include \"color.glsl\"\nlayout(location = 0) out vec4 outColor;\nvoid main() { outColor = tint(); }\n";
+
+        let shader = ShaderCompiler::new()
+            .with_define("TINT", "vec3(1.0)")
+            .compile_source(&dir.join("tint.frag"), source, ShaderLanguage::Glsl(ShaderStage::Fragment))
+            .unwrap();
+
+        assert_eq!(shader.dependencies, [dir.join("tint.frag"), dir.join("color.glsl")]);
+
+        let expanded = ShaderCompiler::new()
+            .expand_includes(&dir.join("tint.frag"), source, true, &mut vec![dir.join("tint.frag")], &mut HashSet::new(), &mut vec![])
+            .unwrap();
+        assert_eq!(expanded.lines().collect::<Vec<_>>(), [
+            "#version 450",
+            "#line 1 1",
+            "",
+            "vec4 tint() { return vec4(TINT, 1.0); }",
+            "#line 3 0",
+            "",
+            "layout(location = 0) out vec4 outColor;",
+            "void main() { outColor = tint(); }",
+        ]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_unsupported_extensions() {
+        assert_eq!(ShaderLanguage::from_path("mesh.frag").unwrap(), Some(ShaderLanguage::Glsl(ShaderStage::Fragment)));
+        assert_eq!(ShaderLanguage::from_path("include/common.glsl").unwrap(), None);
+        assert_eq!(ShaderLanguage::from_path("build.bat").unwrap(), None);
+
+        for path in ["grass.geom", "terrain.tesc", "terrain.tese", "mesh.fragment", "Makefile"] {
+            let err = ShaderLanguage::from_path(path).unwrap_err();
+            assert!(matches!(err, ShaderError::Unsupported { .. }), "{}", err);
+        }
+    }
+
+    #[test]
+    fn reports_readable_errors() {
+        let path = Path::new("broken.frag");
+
+        let err = ShaderCompiler::new()
+            .compile_source(path, "#version 450\nvoid main() { undefined_call(); }\n", ShaderLanguage::Glsl(ShaderStage::Fragment))
+            .unwrap_err();
+        assert!(matches!(err, ShaderError::Parse { .. }));
+        assert!(err.to_string().contains("broken.frag"), "{}", err);
+
+        let err = ShaderCompiler::new()
+            .compile_source(path, "#include \"missing.glsl\"\n", ShaderLanguage::Glsl(ShaderStage::Fragment))
+            .unwrap_err();
+        assert!(matches!(err, ShaderError::Include { line: 1, .. }), "{}", err);
+    }
+}
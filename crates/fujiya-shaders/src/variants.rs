@@ -0,0 +1,218 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    path::{Path, PathBuf}
+};
+
+use crate::{CompiledShader, ShaderCompiler, ShaderError};
+
+///
+/// Set of `#define`s selecting one variant of a shader
+///
+/// Defines are kept sorted, so the order they are added in doesn't create new variants.
+///
+/// # Example:
+///
+/// ```ignore
+/// let defines = ShaderDefines::new()
+///     .with("NORMAL_MAP")
+///     .with_value("MAX_LIGHTS", "8");
+///
+/// assert_eq!(defines.to_string(), "MAX_LIGHTS=8,NORMAL_MAP=1");
+/// ```
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ShaderDefines {
+    defines: BTreeMap<String, String>,
+}
+
+impl ShaderDefines {
+
+    pub fn new() -> Self {
+        Self { ..Default::default() }
+    }
+
+    /// Feature switch, defined as `1`
+    pub fn with(self, name: impl Into<String>) -> Self {
+        self.with_value(name, "1")
+    }
+
+    pub fn with_value(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.defines.insert(name.into(), value.into());
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.defines.is_empty()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.defines.contains_key(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.defines.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    ///
+    /// Every on/off combination of `features`, starting with none of them
+    ///
+    /// `n` features give `2^n` variants, declare only the ones materials really use.
+    ///
+    pub fn permutations(features: &[&str]) -> Vec<Self> {
+        assert!(features.len() < 16, "{} features give too many permutations", features.len());
+
+        (0..1u32 << features.len())
+            .map(|mask| {
+                features.iter()
+                    .enumerate()
+                    .filter(|(bit, _)| mask & (1 << bit) != 0)
+                    .fold(Self::new(), |defines, (_, feature)| defines.with(*feature))
+            })
+            .collect()
+    }
+}
+
+/// `NAME=VALUE` pairs separated by commas, the key of precompiled variants
+impl fmt::Display for ShaderDefines {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, (name, value)) in self.iter().enumerate() {
+            if index > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}={}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+///
+/// Compiled shader variants by source file and defines
+///
+/// Variants are compiled on first request and kept until [`ShaderVariants::invalidate`],
+/// e.g. when the watcher reports that the source or one of its includes changed.
+///
+/// # Example:
+///
+/// ```ignore
+/// let mut variants = ShaderVariants::new(ShaderCompiler::new().with_include_dir("./shared/shaders/include"));
+///
+/// let alpha_test = ShaderDefines::new().with("ALPHA_TEST");
+/// let spv = &variants.compile("./shared/shaders/mesh.frag", &alpha_test)?.spv;
+/// ```
+///
+#[derive(Debug, Default)]
+pub struct ShaderVariants {
+    pub compiler: ShaderCompiler,
+    compiled: HashMap<(PathBuf, ShaderDefines), CompiledShader>,
+}
+
+impl ShaderVariants {
+
+    pub fn new(compiler: ShaderCompiler) -> Self {
+        Self { compiler, compiled: HashMap::new() }
+    }
+
+    pub fn compile(&mut self, path: impl AsRef<Path>, defines: &ShaderDefines) -> Result<&CompiledShader, ShaderError> {
+
+        let key = (path.as_ref().to_path_buf(), defines.clone());
+
+        if !self.compiled.contains_key(&key) {
+            let compiled = self.compiler.clone()
+                .with_defines(defines)
+                .compile_file(&key.0)?;
+
+            self.compiled.insert(key.clone(), compiled);
+        }
+
+        Ok(&self.compiled[&key])
+    }
+
+    pub fn get(&self, path: impl AsRef<Path>, defines: &ShaderDefines) -> Option<&CompiledShader> {
+        self.compiled.get(&(path.as_ref().to_path_buf(), defines.clone()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.compiled.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.compiled.is_empty()
+    }
+
+    ///
+    /// Forget every variant that depends on `changed`, returns how many were dropped
+    ///
+    pub fn invalidate(&mut self, changed: impl AsRef<Path>) -> usize {
+        let changed = changed.as_ref();
+        let before = self.compiled.len();
+
+        self.compiled.retain(|(path, _), compiled| {
+            !same_file(path, changed) && !compiled.dependencies.iter().any(|dependency| same_file(dependency, changed))
+        });
+
+        before - self.compiled.len()
+    }
+}
+
+/// Watchers report canonical paths while shaders are usually registered relative
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    a == b || matches!((a.canonicalize(), b.canonicalize()), (Ok(a), Ok(b)) if a == b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defines_are_order_independent() {
+        let a = ShaderDefines::new().with("SKINNING").with("ALPHA_TEST");
+        let b = ShaderDefines::new().with("ALPHA_TEST").with("SKINNING");
+
+        assert_eq!(a, b);
+        assert_eq!(a.to_string(), "ALPHA_TEST=1,SKINNING=1");
+        assert_eq!(ShaderDefines::new().to_string(), "");
+    }
+
+    #[test]
+    fn permutations_cover_every_combination() {
+        let permutations = ShaderDefines::permutations(&["NORMAL_MAP", "ALPHA_TEST"]);
+
+        assert_eq!(permutations, [
+            ShaderDefines::new(),
+            ShaderDefines::new().with("NORMAL_MAP"),
+            ShaderDefines::new().with("ALPHA_TEST"),
+            ShaderDefines::new().with("NORMAL_MAP").with("ALPHA_TEST"),
+        ]);
+    }
+
+    #[test]
+    fn compiles_variants_once() {
+        let dir = std::env::temp_dir().join(format!("fujiya-variants-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join("tint.frag");
+        std::fs::write(&path, concat!(
+            "#version 450\n",
+            "layout(location = 0) out vec4 outColor;\n",
+            "void main() {\n",
+            "#ifdef RED\n",
+            "    outColor = vec4(1.0, 0.0, 0.0, 1.0);\n",
+            "#else\n",
+            "    outColor = vec4(1.0);\n",
+            "#endif\n",
+            "}\n",
+        )).unwrap();
+
+        let mut variants = ShaderVariants::new(ShaderCompiler::new());
+        let plain = variants.compile(&path, &ShaderDefines::new()).unwrap().spv.clone();
+        let red = variants.compile(&path, &ShaderDefines::new().with("RED")).unwrap().spv.clone();
+        variants.compile(&path, &ShaderDefines::new().with("RED")).unwrap();
+
+        assert_ne!(plain, red);
+        assert_eq!(variants.len(), 2);
+        assert_eq!(variants.invalidate(&path), 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
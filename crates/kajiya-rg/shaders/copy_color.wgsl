@@ -0,0 +1,15 @@
+// Копия первого цветового выхода пасса для RenderDebugHook
+
+@group(0) @binding(0) var input_tex: texture_2d<f32>;
+@group(0) @binding(1) var output_tex: texture_storage_2d<rgba16float, write>;
+
+@compute @workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(output_tex);
+
+    if (id.x >= size.x || id.y >= size.y) {
+        return;
+    }
+
+    textureStore(output_tex, id.xy, textureLoad(input_tex, id.xy, 0));
+}
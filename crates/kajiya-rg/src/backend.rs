@@ -0,0 +1,286 @@
+use std::collections::HashMap;
+
+use ash::vk;
+use fujiya_render::{
+    DescriptorAllocator, DescriptorAllocatorBuilder, DescriptorSet, DescriptorSetLayout, GPUBuffer,
+    GPUImage,
+};
+use parking_lot::Mutex;
+
+pub use fujiya_render::{AccessType, BarrierBatch, BufferDesc, ImageDesc};
+
+pub type BackendError = anyhow::Error;
+
+/// Frames the GPU may still be executing while the next one is recorded
+pub const FRAMES_IN_FLIGHT: usize = 2;
+
+pub struct CommandBuffer {
+    pub raw: vk::CommandBuffer,
+}
+
+struct DeviceFrames {
+    index: usize,
+    descriptor_allocators: Vec<DescriptorAllocator>,
+}
+
+/// fujiya-render's logical device with what the graph needs to create resources
+pub struct Device {
+    pub raw: ash::Device,
+    pub memory_prop: vk::PhysicalDeviceMemoryProperties,
+    frames: Mutex<DeviceFrames>,
+}
+
+impl Device {
+    pub fn new(raw: ash::Device, memory_prop: vk::PhysicalDeviceMemoryProperties) -> Self {
+        let pool_sizes = [
+            vk::DescriptorType::SAMPLER,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            vk::DescriptorType::SAMPLED_IMAGE,
+            vk::DescriptorType::STORAGE_IMAGE,
+            vk::DescriptorType::UNIFORM_BUFFER,
+            vk::DescriptorType::STORAGE_BUFFER,
+            vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+            vk::DescriptorType::STORAGE_BUFFER_DYNAMIC,
+        ]
+        .map(|ty| vk::DescriptorPoolSize::default().ty(ty).descriptor_count(8));
+
+        let descriptor_allocators = (0..FRAMES_IN_FLIGHT)
+            .map(|_| {
+                DescriptorAllocatorBuilder::new()
+                    .with_pool_sizes(&pool_sizes)
+                    .build()
+            })
+            .collect();
+
+        Self {
+            raw,
+            memory_prop,
+            frames: Mutex::new(DeviceFrames {
+                index: 0,
+                descriptor_allocators,
+            }),
+        }
+    }
+
+    /// Start recording a frame. The GPU must have finished the frame
+    /// started `FRAMES_IN_FLIGHT` calls ago.
+    pub fn begin_frame(&self) {
+        let mut frames = self.frames.lock();
+        frames.index = (frames.index + 1) % FRAMES_IN_FLIGHT;
+
+        let index = frames.index;
+        frames.descriptor_allocators[index].reset(&self.raw);
+    }
+
+    /// Descriptor set valid until the current frame slot comes around again
+    pub fn allocate_descriptor_set(
+        &self,
+        layout: &DescriptorSetLayout,
+    ) -> Result<DescriptorSet, BackendError> {
+        let mut frames = self.frames.lock();
+        let index = frames.index;
+        Ok(frames.descriptor_allocators[index].allocate(&self.raw, layout)?)
+    }
+
+    pub fn create_image(&self, desc: ImageDesc) -> Result<Image, BackendError> {
+        Ok(GPUImage::new(&self.raw, &self.memory_prop, desc)?.into())
+    }
+
+    pub fn create_buffer(&self, desc: BufferDesc) -> Result<Buffer, BackendError> {
+        self.create_buffer_with_memory(desc, vk::MemoryPropertyFlags::DEVICE_LOCAL)
+    }
+
+    pub fn create_buffer_with_memory(
+        &self,
+        desc: BufferDesc,
+        memory_flags: vk::MemoryPropertyFlags,
+    ) -> Result<Buffer, BackendError> {
+        let gpu = GPUBuffer::new(
+            &self.raw,
+            &self.memory_prop,
+            desc.size,
+            desc.usage,
+            memory_flags,
+        )?;
+
+        Ok(Buffer {
+            raw: gpu.raw,
+            desc,
+            gpu,
+        })
+    }
+
+    /// Nothing allocated from this device may be in use by the GPU
+    pub fn destroy(&self) {
+        for allocator in &mut self.frames.lock().descriptor_allocators {
+            allocator.destroy(&self.raw);
+        }
+    }
+}
+
+/// View of a subset of an [`Image`], `None` fields cover the whole image
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ImageViewDesc {
+    pub format: Option<vk::Format>,
+    pub aspect_mask: Option<vk::ImageAspectFlags>,
+    pub base_mip_level: u32,
+    pub level_count: Option<u32>,
+}
+
+impl ImageViewDesc {
+    pub fn format(mut self, format: vk::Format) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    pub fn aspect_mask(mut self, aspect_mask: vk::ImageAspectFlags) -> Self {
+        self.aspect_mask = Some(aspect_mask);
+        self
+    }
+
+    pub fn base_mip_level(mut self, base_mip_level: u32) -> Self {
+        self.base_mip_level = base_mip_level;
+        self
+    }
+
+    pub fn level_count(mut self, level_count: u32) -> Self {
+        self.level_count = Some(level_count);
+        self
+    }
+
+    pub fn subresource_range(&self, image_desc: &ImageDesc) -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange::default()
+            .aspect_mask(self.aspect_mask.unwrap_or_else(|| image_desc.aspect()))
+            .base_mip_level(self.base_mip_level)
+            .level_count(
+                self.level_count
+                    .unwrap_or(image_desc.mip_levels - self.base_mip_level),
+            )
+            .base_array_layer(0)
+            .layer_count(1)
+    }
+}
+
+pub struct Image {
+    pub raw: vk::Image,
+    pub desc: ImageDesc,
+    // None for images owned elsewhere, e.g. by the swapchain
+    gpu: Option<GPUImage>,
+    views: Mutex<HashMap<ImageViewDesc, vk::ImageView>>,
+}
+
+impl From<GPUImage> for Image {
+    fn from(gpu: GPUImage) -> Self {
+        let views = HashMap::from([(ImageViewDesc::default(), gpu.view)]);
+
+        Self {
+            raw: gpu.raw,
+            desc: gpu.desc,
+            gpu: Some(gpu),
+            views: Mutex::new(views),
+        }
+    }
+}
+
+impl Image {
+    /// Wrap an image whose memory is owned by someone else, e.g. a swapchain image
+    pub fn new_external(raw: vk::Image, desc: ImageDesc) -> Self {
+        Self {
+            raw,
+            desc,
+            gpu: None,
+            views: Default::default(),
+        }
+    }
+
+    pub fn view(
+        &self,
+        device: &Device,
+        desc: &ImageViewDesc,
+    ) -> Result<vk::ImageView, BackendError> {
+        let mut views = self.views.lock();
+
+        if let Some(view) = views.get(desc) {
+            return Ok(*view);
+        }
+
+        let view_info = vk::ImageViewCreateInfo::default()
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(desc.format.unwrap_or(self.desc.format))
+            .subresource_range(desc.subresource_range(&self.desc))
+            .image(self.raw);
+
+        let view = unsafe { device.raw.create_image_view(&view_info, None)? };
+        views.insert(*desc, view);
+
+        Ok(view)
+    }
+
+    /// The image must not be used by any pending command buffer
+    pub fn destroy(self, device: &Device) {
+        let own_view = self.gpu.as_ref().map(|gpu| gpu.view);
+
+        for view in self.views.into_inner().into_values() {
+            if Some(view) != own_view {
+                unsafe { device.raw.destroy_image_view(view, None) };
+            }
+        }
+
+        if let Some(gpu) = self.gpu {
+            gpu.destroy(&device.raw);
+        }
+    }
+}
+
+pub struct Buffer {
+    pub raw: vk::Buffer,
+    pub desc: BufferDesc,
+    gpu: GPUBuffer,
+}
+
+impl Buffer {
+    pub fn memory(&self) -> vk::DeviceMemory {
+        self.gpu.memory
+    }
+
+    /// The buffer must not be used by any pending command buffer
+    pub fn destroy(mut self, device: &Device) {
+        self.gpu.destroy(&device.raw);
+    }
+}
+
+/// Images and buffers of previous frames, reused by resources with the same desc
+#[derive(Default)]
+pub struct TransientResourceCache {
+    images: HashMap<ImageDesc, Vec<Image>>,
+    buffers: HashMap<BufferDesc, Vec<Buffer>>,
+}
+
+impl TransientResourceCache {
+    pub fn get_image(&mut self, desc: &ImageDesc) -> Option<Image> {
+        self.images.get_mut(desc)?.pop()
+    }
+
+    pub fn insert_image(&mut self, image: Image) {
+        self.images.entry(image.desc).or_default().push(image);
+    }
+
+    pub fn get_buffer(&mut self, desc: &BufferDesc) -> Option<Buffer> {
+        self.buffers.get_mut(desc)?.pop()
+    }
+
+    pub fn insert_buffer(&mut self, buffer: Buffer) {
+        self.buffers.entry(buffer.desc).or_default().push(buffer);
+    }
+
+    /// None of the cached resources may be in use by the GPU
+    pub fn destroy(&mut self, device: &Device) {
+        for image in self.images.drain().flat_map(|(_, images)| images) {
+            image.destroy(device);
+        }
+
+        for buffer in self.buffers.drain().flat_map(|(_, buffers)| buffers) {
+            buffer.destroy(device);
+        }
+    }
+}
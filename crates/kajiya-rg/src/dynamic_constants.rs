@@ -0,0 +1,121 @@
+use ash::vk;
+
+use crate::backend::{BackendError, Buffer, BufferDesc, Device, FRAMES_IN_FLIGHT};
+
+pub const DYNAMIC_CONSTANTS_SIZE_BYTES: usize = 1024 * 1024 * 16;
+pub const DYNAMIC_CONSTANTS_BUFFER_COUNT: usize = FRAMES_IN_FLIGHT;
+
+// Generally supported minimum uniform buffer size across vendors (maxUniformBufferRange)
+// Could be bumped to 65536 if needed.
+pub const MAX_DYNAMIC_CONSTANTS_BYTES_PER_DISPATCH: usize = 16384;
+
+// Must be >= `minUniformBufferOffsetAlignment`. In practice <= 256.
+pub const DYNAMIC_CONSTANTS_ALIGNMENT: usize = 256;
+
+// Sadly we can't have unsized dynamic storage buffers sub-allocated from dynamic constants because WHOLE_SIZE blows up.
+// https://github.com/KhronosGroup/Vulkan-ValidationLayers/issues/2846#issuecomment-851744837
+// For now, just a max size.
+pub const MAX_DYNAMIC_CONSTANTS_STORAGE_BUFFER_BYTES: usize = 1024 * 1024;
+
+/// Host-visible ring of per-frame constants, bound with dynamic offsets
+pub struct DynamicConstants {
+    pub buffer: Buffer,
+    mapped: *mut u8,
+    frame_offset_bytes: usize,
+    frame_parity: usize,
+}
+
+impl DynamicConstants {
+    pub fn new(device: &Device) -> Result<Self, BackendError> {
+        let buffer = device.create_buffer_with_memory(
+            BufferDesc::new((DYNAMIC_CONSTANTS_SIZE_BYTES * DYNAMIC_CONSTANTS_BUFFER_COUNT) as u64)
+                .with_usage(
+                    vk::BufferUsageFlags::UNIFORM_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER,
+                ),
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+
+        let mapped = unsafe {
+            device.raw.map_memory(
+                buffer.memory(),
+                0,
+                vk::WHOLE_SIZE,
+                vk::MemoryMapFlags::empty(),
+            )?
+        } as *mut u8;
+
+        Ok(Self {
+            buffer,
+            mapped,
+            frame_offset_bytes: 0,
+            frame_parity: 0,
+        })
+    }
+
+    pub fn advance_frame(&mut self) {
+        self.frame_parity = (self.frame_parity + 1) % DYNAMIC_CONSTANTS_BUFFER_COUNT;
+        self.frame_offset_bytes = 0;
+    }
+
+    #[inline(always)]
+    pub fn current_offset(&self) -> u32 {
+        (self.frame_parity * DYNAMIC_CONSTANTS_SIZE_BYTES + self.frame_offset_bytes) as u32
+    }
+
+    pub fn push<T: Copy>(&mut self, t: &T) -> u32 {
+        let t_size = std::mem::size_of::<T>();
+        assert!(self.frame_offset_bytes + t_size < DYNAMIC_CONSTANTS_SIZE_BYTES);
+
+        let buffer_offset = self.current_offset() as usize;
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                t as *const T as *const u8,
+                self.mapped.add(buffer_offset),
+                t_size,
+            );
+        }
+
+        let t_size_aligned = aligned_size(t_size);
+        self.frame_offset_bytes += t_size_aligned;
+
+        buffer_offset as u32
+    }
+
+    pub fn push_from_iter<T: Copy, Iter: Iterator<Item = T>>(&mut self, iter: Iter) -> u32 {
+        let t_size = std::mem::size_of::<T>();
+        let t_align = std::mem::align_of::<T>();
+
+        assert!(self.frame_offset_bytes + t_size < DYNAMIC_CONSTANTS_SIZE_BYTES);
+        assert!(DYNAMIC_CONSTANTS_ALIGNMENT.is_multiple_of(t_align));
+
+        let buffer_offset = self.current_offset() as usize;
+        assert!(buffer_offset.is_multiple_of(t_align));
+
+        let mut dst_offset = buffer_offset;
+        for t in iter {
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    &t as *const T as *const u8,
+                    self.mapped.add(dst_offset),
+                    t_size,
+                );
+            }
+            dst_offset += t_size + t_align - 1;
+            dst_offset &= !(t_align - 1);
+        }
+
+        self.frame_offset_bytes += aligned_size(dst_offset - buffer_offset);
+
+        buffer_offset as u32
+    }
+
+    /// The buffer must not be used by any pending command buffer
+    pub fn destroy(self, device: &Device) {
+        unsafe { device.raw.unmap_memory(self.buffer.memory()) };
+        self.buffer.destroy(device);
+    }
+}
+
+fn aligned_size(size: usize) -> usize {
+    (size + DYNAMIC_CONSTANTS_ALIGNMENT - 1) & !(DYNAMIC_CONSTANTS_ALIGNMENT - 1)
+}
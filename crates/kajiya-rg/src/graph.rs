@@ -1,5 +1,3 @@
-#![allow(unused_imports)]
-
 use crate::{renderer::FrameConstantsLayout, resource_registry::PendingRenderResourceInfo};
 
 use super::{
@@ -11,38 +9,20 @@ use super::{
     RenderPassApi,
 };
 
-use kajiya_backend::{
-    ash::{
-        extensions::khr::Swapchain,
-        vk::{self, DebugUtilsLabelEXT},
-    },
+use crate::{
+    backend::{BackendError, CommandBuffer, Device, TransientResourceCache},
     dynamic_constants::DynamicConstants,
     pipeline_cache::{
-        ComputePipelineHandle, PipelineCache, RasterPipelineHandle, RtPipelineHandle,
-    },
-    rspirv_reflect,
-    transient_resource_cache::TransientResourceCache,
-    vk_sync,
-    vulkan::{
-        barrier::{
-            get_access_info, image_aspect_mask_from_access_type_and_format, record_image_barrier,
-            ImageBarrier,
-        },
-        device::{CommandBuffer, Device, VkProfilerData},
-        image::ImageViewDesc,
-        ray_tracing::{RayTracingAcceleration, RayTracingPipelineDesc},
-        shader::{ComputePipelineDesc, PipelineShader, PipelineShaderDesc, RasterPipelineDesc},
+        ComputePipelineDesc, ComputePipelineHandle, PipelineCache, PipelineShaderDesc,
+        RasterPipelineDesc, RasterPipelineHandle,
     },
-    BackendError,
 };
-use parking_lot::Mutex;
+use ash::vk;
+use fujiya_render::{AccessType, BarrierBatch, DescriptorSet, GpuProfiler};
 use std::{
-    collections::{HashMap, VecDeque},
-    ffi::CString,
-    hash::Hash,
+    collections::{BTreeMap, HashMap, VecDeque},
     marker::PhantomData,
-    path::{Path, PathBuf},
-    sync::{Arc, Weak},
+    sync::Arc,
 };
 
 #[derive(Clone)]
@@ -54,15 +34,11 @@ pub(crate) struct GraphResourceCreateInfo {
 pub(crate) enum GraphResourceImportInfo {
     Image {
         resource: Arc<Image>,
-        access_type: vk_sync::AccessType,
+        access_type: AccessType,
     },
     Buffer {
         resource: Arc<Buffer>,
-        access_type: vk_sync::AccessType,
-    },
-    RayTracingAcceleration {
-        resource: Arc<RayTracingAcceleration>,
-        access_type: vk_sync::AccessType,
+        access_type: AccessType,
     },
     SwapchainImage,
 }
@@ -106,18 +82,9 @@ pub(crate) struct RgRasterPipeline {
     pub(crate) desc: RasterPipelineDesc,
 }
 
-#[derive(Clone, Copy)]
-pub struct RgRtPipelineHandle {
-    pub(crate) id: usize,
-}
-
-pub(crate) struct RgRtPipeline {
-    pub(crate) shaders: Vec<PipelineShaderDesc>,
-    pub(crate) desc: RayTracingPipelineDesc,
-}
-
+/// Set that replaces the reflected one in every pipeline of the graph, e.g. frame constants
 pub struct PredefinedDescriptorSet {
-    pub bindings: HashMap<u32, rspirv_reflect::DescriptorInfo>,
+    pub bindings: BTreeMap<u32, vk::DescriptorType>,
 }
 
 #[derive(Clone, PartialEq, Eq, Hash)]
@@ -134,10 +101,9 @@ pub struct GraphDebugHook {
 pub struct RenderGraph {
     passes: Vec<RecordedPass>,
     resources: Vec<GraphResourceInfo>,
-    exported_resources: Vec<(ExportableGraphResource, vk_sync::AccessType)>,
+    exported_resources: Vec<(ExportableGraphResource, AccessType)>,
     pub(crate) compute_pipelines: Vec<RgComputePipeline>,
     pub(crate) raster_pipelines: Vec<RgRasterPipeline>,
-    pub(crate) rt_pipelines: Vec<RgRtPipeline>,
     pub predefined_descriptor_set_layouts: HashMap<u32, PredefinedDescriptorSet>,
 
     pub debug_hook: Option<GraphDebugHook>,
@@ -151,13 +117,13 @@ where
     fn import(
         self: Arc<Self>,
         rg: &mut RenderGraph,
-        access_type_at_import_time: vk_sync::AccessType,
+        access_type_at_import_time: AccessType,
     ) -> Handle<Self>;
 
     fn export(
         resource: Handle<Self>,
         rg: &mut RenderGraph,
-        access_type: vk_sync::AccessType,
+        access_type: AccessType,
     ) -> ExportedHandle<Self>;
 }
 
@@ -165,7 +131,7 @@ impl ImportExportToRenderGraph for Image {
     fn import(
         self: Arc<Self>,
         rg: &mut RenderGraph,
-        access_type_at_import_time: vk_sync::AccessType,
+        access_type_at_import_time: AccessType,
     ) -> Handle<Self> {
         let res = GraphRawResourceHandle {
             id: rg.resources.len() as u32,
@@ -191,7 +157,7 @@ impl ImportExportToRenderGraph for Image {
     fn export(
         resource: Handle<Self>,
         rg: &mut RenderGraph,
-        access_type: vk_sync::AccessType,
+        access_type: AccessType,
     ) -> ExportedHandle<Self> {
         let res = ExportedHandle {
             raw: resource.raw,
@@ -207,7 +173,7 @@ impl ImportExportToRenderGraph for Buffer {
     fn import(
         self: Arc<Self>,
         rg: &mut RenderGraph,
-        access_type_at_import_time: vk_sync::AccessType,
+        access_type_at_import_time: AccessType,
     ) -> Handle<Self> {
         let res = GraphRawResourceHandle {
             id: rg.resources.len() as u32,
@@ -233,7 +199,7 @@ impl ImportExportToRenderGraph for Buffer {
     fn export(
         resource: Handle<Self>,
         rg: &mut RenderGraph,
-        access_type: vk_sync::AccessType,
+        access_type: AccessType,
     ) -> ExportedHandle<Self> {
         let res = ExportedHandle {
             raw: resource.raw,
@@ -245,42 +211,6 @@ impl ImportExportToRenderGraph for Buffer {
     }
 }
 
-impl ImportExportToRenderGraph for RayTracingAcceleration {
-    fn import(
-        self: Arc<Self>,
-        rg: &mut RenderGraph,
-        access_type_at_import_time: vk_sync::AccessType,
-    ) -> Handle<Self> {
-        let res = GraphRawResourceHandle {
-            id: rg.resources.len() as u32,
-            version: 0,
-        };
-
-        let desc = RayTracingAccelerationDesc;
-
-        rg.resources.push(GraphResourceInfo::Imported(
-            GraphResourceImportInfo::RayTracingAcceleration {
-                resource: self,
-                access_type: access_type_at_import_time,
-            },
-        ));
-
-        Handle {
-            raw: res,
-            desc,
-            marker: PhantomData,
-        }
-    }
-
-    fn export(
-        _resource: Handle<Self>,
-        _rg: &mut RenderGraph,
-        _access_type: vk_sync::AccessType,
-    ) -> ExportedHandle<Self> {
-        todo!()
-    }
-}
-
 pub trait TypeEquals {
     type Other;
     fn same(value: Self) -> Self::Other;
@@ -307,19 +237,16 @@ impl RenderGraph {
             exported_resources: Vec::new(),
             compute_pipelines: Vec::new(),
             raster_pipelines: Vec::new(),
-            rt_pipelines: Vec::new(),
             predefined_descriptor_set_layouts: HashMap::new(),
             debug_hook: None,
             debugged_resource: None,
         }
     }
 
-    pub fn create<Desc: ResourceDesc>(
-        &mut self,
-        desc: Desc,
-    ) -> Handle<<Desc as ResourceDesc>::Resource>
+    pub fn create<Desc>(&mut self, desc: Desc) -> Handle<<Desc as ResourceDesc>::Resource>
     where
-        Desc: TypeEquals<Other = <<Desc as ResourceDesc>::Resource as Resource>::Desc>,
+        Desc:
+            ResourceDesc + TypeEquals<Other = <<Desc as ResourceDesc>::Resource as Resource>::Desc>,
     {
         let handle: Handle<<Desc as ResourceDesc>::Resource> = Handle {
             raw: self.create_raw_resource(GraphResourceCreateInfo {
@@ -348,7 +275,7 @@ impl RenderGraph {
     pub fn import<Res: ImportExportToRenderGraph>(
         &mut self,
         resource: Arc<Res>,
-        access_type_at_import_time: vk_sync::AccessType,
+        access_type_at_import_time: AccessType,
     ) -> Handle<Res> {
         ImportExportToRenderGraph::import(resource, self, access_type_at_import_time)
     }
@@ -356,7 +283,7 @@ impl RenderGraph {
     pub fn export<Res: ImportExportToRenderGraph>(
         &mut self,
         resource: Handle<Res>,
-        access_type: vk_sync::AccessType,
+        access_type: AccessType,
     ) -> ExportedHandle<Res> {
         ImportExportToRenderGraph::export(resource, self, access_type)
     }
@@ -374,7 +301,13 @@ impl RenderGraph {
         Handle {
             raw: res,
             // TODO: size
-            desc: ImageDesc::new_2d(vk::Format::R8G8B8A8_UNORM, [1, 1]),
+            desc: ImageDesc::new_2d(
+                vk::Format::R8G8B8A8_UNORM,
+                vk::Extent2D {
+                    width: 1,
+                    height: 1,
+                },
+            ),
             marker: PhantomData,
         }
     }
@@ -395,15 +328,14 @@ struct ResourceInfo {
 pub struct RenderGraphExecutionParams<'a> {
     pub device: &'a Device,
     pub pipeline_cache: &'a mut PipelineCache,
-    pub frame_descriptor_set: vk::DescriptorSet,
+    pub frame_descriptor_set: DescriptorSet,
     pub frame_constants_layout: FrameConstantsLayout,
-    pub profiler_data: &'a VkProfilerData,
+    pub profiler: Option<&'a mut GpuProfiler>,
 }
 
 pub struct RenderGraphPipelines {
     pub(crate) compute: Vec<ComputePipelineHandle>,
     pub(crate) raster: Vec<RasterPipelineHandle>,
-    pub(crate) rt: Vec<RtPipelineHandle>,
 }
 
 pub struct CompiledRenderGraph {
@@ -412,6 +344,12 @@ pub struct CompiledRenderGraph {
     pipelines: RenderGraphPipelines,
 }
 
+// Copies the hooked image into `RenderGraph::debugged_resource`
+const COPY_COLOR_SHADER: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/copy_color.wgsl");
+
+// Must match the storage texture format in `COPY_COLOR_SHADER`
+const DEBUG_IMAGE_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
 struct PendingDebugPass {
     img: Handle<Image>,
 }
@@ -477,7 +415,7 @@ impl RenderGraph {
                         .unwrap_or(pass_idx),
                 );
 
-                let access_mask = get_access_info(res_access.access.access_type).access_mask;
+                let access_type = res_access.access.access_type;
 
                 match &self.resources[resource_index] {
                     // Images
@@ -487,10 +425,8 @@ impl RenderGraph {
                     })
                     | GraphResourceInfo::Imported(GraphResourceImportInfo::Image { .. })
                     | GraphResourceInfo::Imported(GraphResourceImportInfo::SwapchainImage) => {
-                        let image_usage: vk::ImageUsageFlags =
-                            image_access_mask_to_usage_flags(access_mask);
-
-                        image_usage_flags[res_access.handle.id as usize] |= image_usage;
+                        image_usage_flags[res_access.handle.id as usize] |=
+                            access_type.image_usage();
                     }
 
                     // Buffers
@@ -499,23 +435,8 @@ impl RenderGraph {
                         ..
                     })
                     | GraphResourceInfo::Imported(GraphResourceImportInfo::Buffer { .. }) => {
-                        let buffer_usage: vk::BufferUsageFlags =
-                            buffer_access_mask_to_usage_flags(access_mask);
-
-                        buffer_usage_flags[res_access.handle.id as usize] |= buffer_usage;
-                    }
-
-                    // Acceleration structures
-                    GraphResourceInfo::Created(GraphResourceCreateInfo {
-                        desc: GraphResourceDesc::RayTracingAcceleration(_),
-                        ..
-                    }) => {
-                        unimplemented!("Creation of acceleration structures via the render graph is not currently supported");
-                    }
-                    GraphResourceInfo::Imported(
-                        GraphResourceImportInfo::RayTracingAcceleration { .. },
-                    ) => {
-                        // TODO; not currently tracking usage flags for RT acceleration
+                        buffer_usage_flags[res_access.handle.id as usize] |=
+                            access_type.buffer_usage();
                     }
                 };
             }
@@ -525,16 +446,13 @@ impl RenderGraph {
             let raw_id = res.raw().id as usize;
             lifetimes[raw_id].last_access = Some(self.passes.len().saturating_sub(1));
 
-            if *access_type != vk_sync::AccessType::Nothing {
-                let access_mask = get_access_info(*access_type).access_mask;
-
+            if *access_type != AccessType::Nothing {
                 match res {
                     ExportableGraphResource::Image(_) => {
-                        image_usage_flags[raw_id] |= image_access_mask_to_usage_flags(access_mask);
+                        image_usage_flags[raw_id] |= access_type.image_usage();
                     }
                     ExportableGraphResource::Buffer(_) => {
-                        buffer_usage_flags[raw_id] |=
-                            buffer_access_mask_to_usage_flags(access_mask);
+                        buffer_usage_flags[raw_id] |= access_type.buffer_usage();
                     }
                 }
             }
@@ -572,19 +490,12 @@ impl RenderGraph {
             .map(|pipeline| pipeline_cache.register_raster(&pipeline.shaders, &pipeline.desc))
             .collect::<Vec<_>>();
 
-        let rt_pipelines = self
-            .rt_pipelines
-            .iter()
-            .map(|pipeline| pipeline_cache.register_ray_tracing(&pipeline.shaders, &pipeline.desc))
-            .collect::<Vec<_>>();
-
         CompiledRenderGraph {
             rg: self,
             resource_info,
             pipelines: RenderGraphPipelines {
                 compute: compute_pipelines,
                 raster: raster_pipelines,
-                rt: rt_pipelines,
             },
         }
     }
@@ -600,10 +511,10 @@ impl RenderGraph {
             let mut dst = self.create(src_desc);
             let debug_pass = self.add_pass("debug");
 
-            crate::SimpleRenderPass::new_compute(debug_pass, "/shaders/copy_color.hlsl")
+            crate::SimpleRenderPass::new_compute(debug_pass, COPY_COLOR_SHADER)
                 .read(&src_handle)
                 .write(&mut dst)
-                .dispatch(src_desc.extent);
+                .dispatch([src_desc.extent.width, src_desc.extent.height, 1]);
 
             self.debugged_resource = Some(dst);
         }
@@ -614,9 +525,7 @@ impl RenderGraph {
 
         if pass.name == scope_hook.name && pass.idx as u64 == scope_hook.id {
             fn is_debug_compatible(desc: &ImageDesc) -> bool {
-                kajiya_backend::vulkan::barrier::image_aspect_mask_from_format(desc.format)
-                    == vk::ImageAspectFlags::COLOR
-                    && desc.image_type == ImageType::Tex2d
+                desc.aspect() == vk::ImageAspectFlags::COLOR
             }
 
             // Grab the first compatible image written by this pass
@@ -644,9 +553,10 @@ impl RenderGraph {
 
             let src_handle: Handle<Image> = Handle {
                 raw: src_handle,
-                desc: TypeEquals::same(src_desc)
-                    .mip_levels(1)
-                    .format(vk::Format::B10G11R11_UFLOAT_PACK32),
+                desc: ImageDesc {
+                    format: DEBUG_IMAGE_FORMAT,
+                    ..TypeEquals::same(src_desc).with_mip_levels(1)
+                },
                 marker: PhantomData,
             };
 
@@ -657,53 +567,6 @@ impl RenderGraph {
     }
 }
 
-fn image_access_mask_to_usage_flags(access_mask: vk::AccessFlags) -> vk::ImageUsageFlags {
-    match access_mask {
-        vk::AccessFlags::SHADER_READ => vk::ImageUsageFlags::SAMPLED,
-        vk::AccessFlags::SHADER_WRITE => vk::ImageUsageFlags::STORAGE,
-        vk::AccessFlags::COLOR_ATTACHMENT_READ => vk::ImageUsageFlags::COLOR_ATTACHMENT,
-        vk::AccessFlags::COLOR_ATTACHMENT_WRITE => vk::ImageUsageFlags::COLOR_ATTACHMENT,
-        vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ => {
-            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
-        }
-        vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE => {
-            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
-        }
-        vk::AccessFlags::TRANSFER_READ => vk::ImageUsageFlags::TRANSFER_SRC,
-        vk::AccessFlags::TRANSFER_WRITE => vk::ImageUsageFlags::TRANSFER_DST,
-
-        _ if access_mask == vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE => {
-            vk::ImageUsageFlags::STORAGE
-        }
-
-        // Appears with DepthAttachmentWriteStencilReadOnly
-        _ if access_mask
-            == vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
-                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE =>
-        {
-            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
-        }
-        _ => panic!("Invalid image access mask: {:?}", access_mask),
-    }
-}
-
-fn buffer_access_mask_to_usage_flags(access_mask: vk::AccessFlags) -> vk::BufferUsageFlags {
-    match access_mask {
-        vk::AccessFlags::INDIRECT_COMMAND_READ => vk::BufferUsageFlags::INDIRECT_BUFFER,
-        vk::AccessFlags::INDEX_READ => vk::BufferUsageFlags::INDEX_BUFFER,
-        vk::AccessFlags::VERTEX_ATTRIBUTE_READ => vk::BufferUsageFlags::UNIFORM_TEXEL_BUFFER,
-        vk::AccessFlags::UNIFORM_READ => vk::BufferUsageFlags::UNIFORM_BUFFER,
-        vk::AccessFlags::SHADER_READ => vk::BufferUsageFlags::UNIFORM_TEXEL_BUFFER,
-        vk::AccessFlags::SHADER_WRITE => vk::BufferUsageFlags::STORAGE_BUFFER,
-        vk::AccessFlags::TRANSFER_READ => vk::BufferUsageFlags::TRANSFER_SRC,
-        vk::AccessFlags::TRANSFER_WRITE => vk::BufferUsageFlags::TRANSFER_DST,
-        _ if access_mask == vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE => {
-            vk::BufferUsageFlags::STORAGE_BUFFER
-        }
-        _ => panic!("Invalid buffer access mask: {:?}", access_mask),
-    }
-}
-
 impl CompiledRenderGraph {
     #[must_use]
     pub fn begin_execute<'exec_params, 'constants>(
@@ -725,31 +588,25 @@ impl CompiledRenderGraph {
 
                         let image = transient_resource_cache
                             .get_image(&desc)
-                            .unwrap_or_else(|| device.create_image(desc, vec![]).unwrap());
+                            .unwrap_or_else(|| device.create_image(desc).unwrap());
 
                         RegistryResource {
-                            access_type: vk_sync::AccessType::Nothing,
+                            access_type: AccessType::Nothing,
                             resource: AnyRenderResource::OwnedImage(image),
                         }
                     }
                     GraphResourceDesc::Buffer(mut desc) => {
                         desc.usage = self.resource_info.buffer_usage_flags[resource_idx];
 
-                        let buffer =
-                            transient_resource_cache
-                                .get_buffer(&desc)
-                                .unwrap_or_else(|| {
-                                    device.create_buffer(desc, "rg buffer", None).unwrap()
-                                });
+                        let buffer = transient_resource_cache
+                            .get_buffer(&desc)
+                            .unwrap_or_else(|| device.create_buffer(desc).unwrap());
 
                         RegistryResource {
                             resource: AnyRenderResource::OwnedBuffer(buffer),
-                            access_type: vk_sync::AccessType::Nothing,
+                            access_type: AccessType::Nothing,
                         }
                     }
-                    GraphResourceDesc::RayTracingAcceleration(_) => {
-                        unimplemented!();
-                    }
                 },
                 GraphResourceInfo::Imported(import_info) => match import_info {
                     GraphResourceImportInfo::Image {
@@ -766,20 +623,11 @@ impl CompiledRenderGraph {
                         resource: AnyRenderResource::ImportedBuffer(resource.clone()),
                         access_type: *access_type,
                     },
-                    GraphResourceImportInfo::RayTracingAcceleration {
-                        resource,
-                        access_type,
-                    } => RegistryResource {
-                        resource: AnyRenderResource::ImportedRayTracingAcceleration(
-                            resource.clone(),
-                        ),
-                        access_type: *access_type,
-                    },
                     GraphResourceImportInfo::SwapchainImage => RegistryResource {
                         resource: AnyRenderResource::Pending(PendingRenderResourceInfo {
                             resource: resource.clone(),
                         }),
-                        access_type: vk_sync::AccessType::ComputeShaderWrite,
+                        access_type: AccessType::ComputeShaderWrite,
                     },
                 },
             })
@@ -804,7 +652,7 @@ impl CompiledRenderGraph {
 pub struct ExecutingRenderGraph<'exec_params, 'constants> {
     passes: VecDeque<RecordedPass>,
     resources: Vec<GraphResourceInfo>,
-    exported_resources: Vec<(ExportableGraphResource, vk_sync::AccessType)>,
+    exported_resources: Vec<(ExportableGraphResource, AccessType)>,
     resource_registry: ResourceRegistry<'exec_params, 'constants>,
 }
 
@@ -879,7 +727,7 @@ impl<'exec_params, 'constants> ExecutingRenderGraph<'exec_params, 'constants> {
 
         // Transition exported images to the requested access types
         for (resource_idx, access_type) in self.exported_resources {
-            if access_type != vk_sync::AccessType::Nothing {
+            if access_type != AccessType::Nothing {
                 let resource =
                     &mut self.resource_registry.resources[resource_idx.raw().id as usize];
                 Self::transition_resource(
@@ -922,46 +770,22 @@ impl<'exec_params, 'constants> ExecutingRenderGraph<'exec_params, 'constants> {
         resource_registry: &mut ResourceRegistry,
         cb: &CommandBuffer,
     ) {
-        let params = &resource_registry.execution_params;
-
-        // Record a crash marker just before this pass
-        params
-            .device
-            .record_crash_marker(cb, format!("begin render pass {:?}", pass.name));
-
-        if let Some(debug_utils) = params.device.debug_utils() {
-            unsafe {
-                let label: CString = CString::new(pass.name.as_str()).unwrap();
-                let label = DebugUtilsLabelEXT::builder().label_name(&label).build();
-                debug_utils.cmd_begin_debug_utils_label(cb.raw, &label);
-            }
-        }
+        let device = resource_registry.execution_params.device;
 
-        let vk_scope = {
-            let query_id = kajiya_backend::gpu_profiler::profiler().create_scope(&pass.name);
-            params
-                .profiler_data
-                .begin_scope(&params.device.raw, cb.raw, query_id)
-        };
+        let gpu_scope = resource_registry
+            .execution_params
+            .profiler
+            .as_deref_mut()
+            .map(|profiler| profiler.begin_scope(&device.raw, cb.raw, &pass.name));
 
         {
-            let params = &resource_registry.execution_params;
-
             let mut transitions: Vec<(usize, PassResourceAccessType)> = Vec::new();
             for resource_ref in pass.read.iter() {
-                transitions.push((
-                    resource_ref.handle.id as usize,
-                    resource_ref.access,
-                    //format!("read {i}"),
-                ));
+                transitions.push((resource_ref.handle.id as usize, resource_ref.access));
             }
 
             for resource_ref in pass.write.iter() {
-                transitions.push((
-                    resource_ref.handle.id as usize,
-                    resource_ref.access,
-                    //format!("write {i}"),
-                ));
+                transitions.push((resource_ref.handle.id as usize, resource_ref.access));
             }
 
             // TODO: optimize the barriers
@@ -969,15 +793,7 @@ impl<'exec_params, 'constants> ExecutingRenderGraph<'exec_params, 'constants> {
             for (resource_idx, access) in transitions {
                 let resource = &mut resource_registry.resources[resource_idx];
 
-                Self::transition_resource(
-                    params.device,
-                    cb,
-                    resource,
-                    access,
-                    //pass.name == "raster simple",
-                    false,
-                    "",
-                );
+                Self::transition_resource(device, cb, resource, access, false, "");
             }
         }
 
@@ -992,22 +808,12 @@ impl<'exec_params, 'constants> ExecutingRenderGraph<'exec_params, 'constants> {
             }
         }
 
-        let params = &resource_registry.execution_params;
-
-        params
-            .profiler_data
-            .end_scope(&params.device.raw, cb.raw, vk_scope);
-
-        if let Some(debug_utils) = params.device.debug_utils() {
-            unsafe {
-                debug_utils.cmd_end_debug_utils_label(cb.raw);
-            }
+        if let (Some(profiler), Some(gpu_scope)) = (
+            resource_registry.execution_params.profiler.as_deref_mut(),
+            gpu_scope,
+        ) {
+            profiler.end_scope(&device.raw, cb.raw, gpu_scope);
         }
-
-        // Record a crash marker just after this pass
-        params
-            .device
-            .record_crash_marker(cb, format!("end render pass {:?}", pass.name));
     }
 
     fn transition_resource(
@@ -1036,93 +842,34 @@ impl<'exec_params, 'constants> ExecutingRenderGraph<'exec_params, 'constants> {
             );
         }
 
+        let mut barriers = BarrierBatch::new();
+
         match resource.resource.borrow() {
             AnyRenderResourceRef::Image(image) => {
                 if debug {
                     log::info!("\t(image {:?})", image.desc);
                 }
 
-                record_image_barrier(
-                    device,
-                    cb.raw,
-                    ImageBarrier::new(
-                        image.raw,
-                        resource.access_type,
-                        access.access_type,
-                        image_aspect_mask_from_access_type_and_format(
-                            access.access_type,
-                            image.desc.format,
-                        )
-                        .unwrap_or_else(|| {
-                            panic!(
-                                "Invalid image access {:?} :: {:?}",
-                                access.access_type, image.desc
-                            )
-                        }),
-                    ),
+                barriers.add_image(
+                    image.raw,
+                    image.desc.subresource_range(),
+                    &[resource.access_type],
+                    &[access.access_type],
+                    false,
                 );
-
-                resource.access_type = access.access_type;
             }
             AnyRenderResourceRef::Buffer(buffer) => {
                 if debug {
                     log::info!("\t(buffer {:?})", buffer.desc);
                 }
-                //global_barrier(device, cb, &[resource.access_type], &[access.access_type]);
-
-                vk_sync::cmd::pipeline_barrier(
-                    device.raw.fp_v1_0(),
-                    cb.raw,
-                    None,
-                    &[vk_sync::BufferBarrier {
-                        previous_accesses: &[resource.access_type],
-                        next_accesses: &[access.access_type],
-                        src_queue_family_index: device.universal_queue.family.index,
-                        dst_queue_family_index: device.universal_queue.family.index,
-                        buffer: buffer.raw,
-                        offset: 0,
-                        size: buffer.desc.size,
-                    }],
-                    &[],
-                );
 
-                resource.access_type = access.access_type;
-            }
-            AnyRenderResourceRef::RayTracingAcceleration(_) => {
-                if debug {
-                    log::info!("\t(bvh)");
-                }
-                /*global_barrier(
-                    device,
-                    cb,
-                    &[resource.access_type],
-                    &[access_type],
-                );*/
-                // TODO
-
-                resource.access_type = access.access_type;
+                barriers.add_buffer(buffer.raw, &[resource.access_type], &[access.access_type]);
             }
         }
-    }
-}
 
-#[allow(dead_code)]
-fn global_barrier(
-    device: &Device,
-    cb: &CommandBuffer,
-    previous_accesses: &[vk_sync::AccessType],
-    next_accesses: &[vk_sync::AccessType],
-) {
-    vk_sync::cmd::pipeline_barrier(
-        device.raw.fp_v1_0(),
-        cb.raw,
-        Some(vk_sync::GlobalBarrier {
-            previous_accesses,
-            next_accesses,
-        }),
-        &[],
-        &[],
-    );
+        barriers.record(&device.raw, cb.raw);
+        resource.access_type = access.access_type;
+    }
 }
 
 pub struct RetiredRenderGraph {
@@ -1133,7 +880,7 @@ impl RetiredRenderGraph {
     pub fn exported_resource<Res: Resource>(
         &self,
         handle: ExportedHandle<Res>,
-    ) -> (&Res, vk_sync::AccessType) {
+    ) -> (&Res, AccessType) {
         let reg_resource = &self.resources[handle.raw.id as usize];
         (
             <Res as Resource>::borrow_resource(&reg_resource.resource),
@@ -1151,8 +898,7 @@ impl RetiredRenderGraph {
                     transient_resource_cache.insert_buffer(buffer)
                 }
                 AnyRenderResource::ImportedImage(_)
-                | AnyRenderResource::ImportedBuffer(_)
-                | AnyRenderResource::ImportedRayTracingAcceleration(_) => {},
+                | AnyRenderResource::ImportedBuffer(_) => {},
                 AnyRenderResource::Pending { .. } => panic!("RetiredRenderGraph::release_resources called while a resource was in Pending state"),
             }
         }
@@ -1170,12 +916,12 @@ pub enum PassResourceAccessSyncType {
 #[derive(Copy, Clone)]
 pub struct PassResourceAccessType {
     // TODO: multiple
-    access_type: vk_sync::AccessType,
+    access_type: AccessType,
     sync_type: PassResourceAccessSyncType,
 }
 
 impl PassResourceAccessType {
-    pub fn new(access_type: vk_sync::AccessType, sync_type: PassResourceAccessSyncType) -> Self {
+    pub fn new(access_type: AccessType, sync_type: PassResourceAccessSyncType) -> Self {
         Self {
             access_type,
             sync_type,
@@ -1209,3 +955,101 @@ impl RecordedPass {
 }
 
 pub static mut RG_ALLOW_PASS_OVERLAP: bool = true;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline_cache::ShaderSource;
+    use fujiya_render::ShaderReflection;
+
+    fn image_desc() -> ImageDesc {
+        ImageDesc::new_2d(
+            vk::Format::R16G16B16A16_SFLOAT,
+            vk::Extent2D {
+                width: 64,
+                height: 64,
+            },
+        )
+    }
+
+    #[test]
+    fn usage_flags_follow_pass_accesses() {
+        let mut rg = RenderGraph::new();
+        let mut image = rg.create(image_desc());
+        let mut buffer = rg.create(BufferDesc::new(256));
+
+        {
+            let mut pass = rg.add_pass("write");
+            pass.write(&mut image, AccessType::ComputeShaderWrite);
+            pass.write(&mut buffer, AccessType::TransferWrite);
+        }
+
+        {
+            let mut pass = rg.add_pass("read");
+            pass.read(&image, AccessType::FragmentShaderReadSampledImage);
+            pass.read(&buffer, AccessType::IndirectBuffer);
+        }
+
+        let info = rg.calculate_resource_info();
+
+        assert_eq!(
+            info.image_usage_flags[image.raw.id as usize],
+            vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED
+        );
+        assert_eq!(
+            info.buffer_usage_flags[buffer.raw.id as usize],
+            vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::INDIRECT_BUFFER
+        );
+    }
+
+    #[test]
+    fn writes_bump_the_resource_version() {
+        let mut rg = RenderGraph::new();
+        let mut image = rg.create(image_desc());
+
+        let mut pass = rg.add_pass("write");
+        let written = pass.write(&mut image, AccessType::ComputeShaderWrite);
+        let read = pass.read(&image, AccessType::ComputeShaderReadOther);
+        drop(pass);
+
+        assert_eq!(written.handle.version, image.raw.version + 1);
+        assert_eq!(read.handle, image.raw);
+        assert_eq!(rg.passes.len(), 1);
+        assert_eq!(rg.passes[0].write.len(), 1);
+        assert_eq!(rg.passes[0].read.len(), 1);
+    }
+
+    #[test]
+    fn debug_hook_copies_the_first_color_output() {
+        let mut rg = RenderGraph::new();
+        rg.debug_hook = Some(GraphDebugHook {
+            render_debug_hook: RenderDebugHook {
+                name: "lighting".to_owned(),
+                id: 0,
+            },
+        });
+
+        let mut image = rg.create(image_desc().with_mip_levels(4));
+        rg.add_pass("lighting")
+            .write(&mut image, AccessType::ComputeShaderWrite);
+
+        let debugged = rg.debugged_resource.as_ref().expect("debug pass");
+        assert_eq!(debugged.desc().format, DEBUG_IMAGE_FORMAT);
+        assert_eq!(debugged.desc().mip_levels, 1);
+        assert_eq!(rg.passes.len(), 2);
+        assert_eq!(rg.compute_pipelines.len(), 1);
+    }
+
+    #[test]
+    fn copy_color_shader_compiles() {
+        let spv = crate::pipeline_cache::load_spirv(
+            &fujiya_shaders::ShaderCompiler::new(),
+            &ShaderSource::from(COPY_COLOR_SHADER),
+        )
+        .unwrap();
+
+        let reflection = ShaderReflection::from_spv(&spv).unwrap();
+        assert_eq!(reflection.local_size, Some([8, 8, 1]));
+        assert_eq!(reflection.bindings.len(), 2);
+    }
+}
@@ -1,19 +1,11 @@
-use kajiya_backend::{
-    ash::vk,
-    dynamic_constants,
-    vk_sync::AccessType,
-    vulkan::{
-        image::*,
-        ray_tracing::{RayTracingAcceleration, RayTracingPipelineDesc},
-        shader::{ComputePipelineDesc, PipelineShaderDesc, ShaderPipelineStage, ShaderSource},
-    },
-};
+use ash::vk;
+use fujiya_render::AccessType;
 
-use crate::Image;
+use crate::{backend::ImageViewDesc, dynamic_constants, Image};
 
 use super::{
     BindRgRef, Buffer, GpuSrv, GpuUav, Handle, PassBuilder, Ref, RenderPassApi, RenderPassBinding,
-    Resource, RgComputePipelineHandle, RgRtPipelineHandle,
+    Resource, RgComputePipelineHandle,
 };
 
 pub trait ConstBlob {
@@ -116,20 +108,6 @@ impl<'rg> SimpleRenderPass<'rg, RgComputePipelineHandle> {
         }
     }
 
-    pub fn new_compute_rust(mut pass: PassBuilder<'rg>, entry_name: &str) -> Self {
-        let pipeline = pass.register_compute_pipeline_with_desc(
-            ComputePipelineDesc::builder()
-                .compute_rust(entry_name)
-                .build()
-                .unwrap(),
-        );
-
-        Self {
-            pass,
-            state: SimpleRenderPassState::new(pipeline),
-        }
-    }
-
     pub fn dispatch(self, extent: [u32; 3]) {
         let mut state = self.state;
 
@@ -160,108 +138,13 @@ impl<'rg> SimpleRenderPass<'rg, RgComputePipelineHandle> {
     }
 }
 
-impl<'rg> SimpleRenderPass<'rg, RgRtPipelineHandle> {
-    pub fn new_rt(
-        mut pass: PassBuilder<'rg>,
-        rgen: ShaderSource,
-        miss: impl IntoIterator<Item = ShaderSource>,
-        hit: impl IntoIterator<Item = ShaderSource>,
-    ) -> Self {
-        let miss = miss.into_iter();
-        let hit = hit.into_iter();
-
-        let mut shaders = Vec::with_capacity(1 + miss.size_hint().0 + hit.size_hint().0);
-
-        shaders.push(
-            PipelineShaderDesc::builder(ShaderPipelineStage::RayGen)
-                .source(rgen)
-                .build()
-                .unwrap(),
-        );
-        for source in miss {
-            shaders.push(
-                PipelineShaderDesc::builder(ShaderPipelineStage::RayMiss)
-                    .source(source)
-                    .build()
-                    .unwrap(),
-            );
-        }
-
-        for source in hit {
-            shaders.push(
-                PipelineShaderDesc::builder(ShaderPipelineStage::RayClosestHit)
-                    .source(source)
-                    .build()
-                    .unwrap(),
-            );
-        }
-
-        let pipeline = pass.register_ray_tracing_pipeline(
-            &shaders,
-            RayTracingPipelineDesc::default().max_pipeline_ray_recursion_depth(1),
-        );
-
-        Self {
-            pass,
-            state: SimpleRenderPassState::new(pipeline),
-        }
-    }
-
-    pub fn trace_rays(mut self, tlas: &Handle<RayTracingAcceleration>, extent: [u32; 3]) {
-        let tlas_ref = self.pass.read(tlas, AccessType::AnyShaderReadOther);
-        let mut state = self.state;
-
-        self.pass.render(move |api| {
-            state.patch_const_blobs(api);
-
-            let pipeline = api.bind_ray_tracing_pipeline(
-                state
-                    .create_pipeline_binding()
-                    .descriptor_set(3, &[tlas_ref.bind()]),
-            )?;
-
-            pipeline.trace_rays(extent);
-
-            Ok(())
-        });
-    }
-
-    pub fn trace_rays_indirect(
-        mut self,
-        tlas: &Handle<RayTracingAcceleration>,
-        args_buffer: &Handle<Buffer>,
-        args_buffer_offset: u64,
-    ) {
-        let args_buffer_ref = self.pass.read(args_buffer, AccessType::IndirectBuffer);
-        let tlas_ref = self.pass.read(tlas, AccessType::AnyShaderReadOther);
-        let mut state = self.state;
-
-        self.pass.render(move |api| {
-            state.patch_const_blobs(api);
-
-            let pipeline = api.bind_ray_tracing_pipeline(
-                state
-                    .create_pipeline_binding()
-                    .descriptor_set(3, &[tlas_ref.bind()]),
-            )?;
-
-            pipeline.trace_rays_indirect(args_buffer_ref, args_buffer_offset);
-
-            Ok(())
-        });
-    }
-}
-
 impl<'rg, RgPipelineHandle> SimpleRenderPass<'rg, RgPipelineHandle> {
     pub fn read<Res>(mut self, handle: &Handle<Res>) -> Self
     where
         Res: Resource + 'static,
         Ref<Res, GpuSrv>: BindRgRef,
     {
-        let handle_ref = self.pass.read(
-            handle,
-            AccessType::AnyShaderReadSampledImageOrUniformTexelBuffer,
-        );
+        let handle_ref = self.pass.read(handle, Res::SHADER_READ);
 
         self.state.bindings.push(BindRgRef::bind(&handle_ref));
 
@@ -274,10 +157,8 @@ impl<'rg, RgPipelineHandle> SimpleRenderPass<'rg, RgPipelineHandle> {
         let handle_refs = handles
             .iter()
             .map(|handle| {
-                self.pass.read(
-                    handle,
-                    AccessType::AnyShaderReadSampledImageOrUniformTexelBuffer,
-                )
+                self.pass
+                    .read(handle, AccessType::AnyShaderReadSampledImage)
             })
             .collect::<Vec<_>>();
 
@@ -286,11 +167,10 @@ impl<'rg, RgPipelineHandle> SimpleRenderPass<'rg, RgPipelineHandle> {
         self
     }
 
-    pub fn read_view(mut self, handle: &Handle<Image>, view_desc: ImageViewDescBuilder) -> Self {
-        let handle_ref = self.pass.read(
-            handle,
-            AccessType::AnyShaderReadSampledImageOrUniformTexelBuffer,
-        );
+    pub fn read_view(mut self, handle: &Handle<Image>, view_desc: ImageViewDesc) -> Self {
+        let handle_ref = self
+            .pass
+            .read(handle, AccessType::AnyShaderReadSampledImage);
 
         self.state.bindings.push(handle_ref.bind_view(view_desc));
 
@@ -302,14 +182,13 @@ impl<'rg, RgPipelineHandle> SimpleRenderPass<'rg, RgPipelineHandle> {
         handle: &Handle<Image>,
         aspect_mask: vk::ImageAspectFlags,
     ) -> Self {
-        let handle_ref = self.pass.read(
-            handle,
-            AccessType::AnyShaderReadSampledImageOrUniformTexelBuffer,
-        );
+        let handle_ref = self
+            .pass
+            .read(handle, AccessType::AnyShaderReadSampledImage);
 
         self.state
             .bindings
-            .push(handle_ref.bind_view(ImageViewDescBuilder::default().aspect_mask(aspect_mask)));
+            .push(handle_ref.bind_view(ImageViewDesc::default().aspect_mask(aspect_mask)));
 
         self
     }
@@ -338,11 +217,7 @@ impl<'rg, RgPipelineHandle> SimpleRenderPass<'rg, RgPipelineHandle> {
         self
     }
 
-    pub fn write_view(
-        mut self,
-        handle: &mut Handle<Image>,
-        view_desc: ImageViewDescBuilder,
-    ) -> Self {
+    pub fn write_view(mut self, handle: &mut Handle<Image>, view_desc: ImageViewDesc) -> Self {
         let handle_ref = self.pass.write(handle, AccessType::AnyShaderWrite);
 
         self.state.bindings.push(handle_ref.bind_view(view_desc));
@@ -1,5 +1,6 @@
-use crate::{self as rg, RenderGraph};
-use kajiya_backend::{ash::vk, vk_sync::AccessType, vulkan::image::*};
+use crate::{self as rg, Image, RenderGraph};
+use ash::vk;
+use fujiya_render::AccessType;
 
 pub fn clear_depth(rg: &mut RenderGraph, img: &mut rg::Handle<Image>) {
     let mut pass = rg.add_pass("clear depth");
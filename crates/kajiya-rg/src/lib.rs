@@ -6,7 +6,10 @@ mod resource;
 mod resource_registry;
 mod temporal;
 
+pub mod backend;
+pub mod dynamic_constants;
 pub mod imageops;
+pub mod pipeline_cache;
 pub mod renderer;
 
 pub use graph::*;
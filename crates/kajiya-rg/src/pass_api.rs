@@ -1,30 +1,22 @@
-use std::{cell::UnsafeCell, sync::Arc};
-
-use arrayvec::ArrayVec;
+use std::sync::Arc;
 
 use super::{
     Buffer, GpuRt, GpuSrv, GpuUav, GraphRawResourceHandle, Image, Ref, ResourceRegistry,
-    RgComputePipelineHandle, RgRasterPipelineHandle, RgRtPipelineHandle,
+    RgComputePipelineHandle, RgRasterPipelineHandle,
 };
 
-use kajiya_backend::{
-    ash::vk,
-    chunky_list::TempList,
+use crate::{
+    backend::{BackendError, CommandBuffer, Device, ImageViewDesc},
     dynamic_constants::{
         DynamicConstants, MAX_DYNAMIC_CONSTANTS_BYTES_PER_DISPATCH,
         MAX_DYNAMIC_CONSTANTS_STORAGE_BUFFER_BYTES,
     },
-    vulkan::{
-        device::{CommandBuffer, Device},
-        image::*,
-        ray_tracing::{RayTracingAcceleration, RayTracingPipeline},
-        shader::{
-            ComputePipeline, FramebufferCacheKey, RasterPipeline, ShaderPipelineCommon,
-            MAX_COLOR_ATTACHMENTS,
-        },
+    pipeline_cache::{
+        ComputePipeline, FramebufferCacheKey, RasterPipeline, RenderPass, ShaderPipelineCommon,
     },
-    BackendError,
 };
+use ash::vk;
+use fujiya_render::DescriptorSetWriter;
 
 pub struct RenderPassApi<'a, 'exec_params, 'constants> {
     pub cb: &'a CommandBuffer,
@@ -35,7 +27,6 @@ pub enum DescriptorSetBinding {
     Image(vk::DescriptorImageInfo),
     ImageArray(Vec<vk::DescriptorImageInfo>),
     Buffer(vk::DescriptorBufferInfo),
-    RayTracingAcceleration(vk::AccelerationStructureKHR),
     DynamicBuffer {
         buffer: vk::DescriptorBufferInfo,
         offset: u32,
@@ -93,12 +84,6 @@ impl IntoRenderPassPipelineBinding for RgRasterPipelineHandle {
     }
 }
 
-impl IntoRenderPassPipelineBinding for RgRtPipelineHandle {
-    fn into_binding<'a>(self) -> RenderPassPipelineBinding<'a, Self> {
-        RenderPassPipelineBinding::new(self)
-    }
-}
-
 impl<'a, 'exec_params, 'constants> RenderPassApi<'a, 'exec_params, 'constants> {
     pub fn device(&self) -> &Device {
         self.resources.execution_params.device
@@ -138,34 +123,13 @@ impl<'a, 'exec_params, 'constants> RenderPassApi<'a, 'exec_params, 'constants> {
         })
     }
 
-    pub fn bind_ray_tracing_pipeline<'s>(
-        &'s mut self,
-        binding: RenderPassPipelineBinding<'_, RgRtPipelineHandle>,
-    ) -> Result<BoundRayTracingPipeline<'s, 'a, 'exec_params, 'constants>, BackendError> {
-        let device = self.resources.execution_params.device;
-        let pipeline_arc = self.resources.ray_tracing_pipeline(binding.pipeline);
-
-        self.bind_pipeline_common(device, pipeline_arc.as_ref(), &binding.binding)?;
-
-        Ok(BoundRayTracingPipeline {
-            api: self,
-            pipeline: pipeline_arc,
-        })
-    }
-
     fn bind_pipeline_common(
         &self,
         device: &Device,
         pipeline: &ShaderPipelineCommon,
         binding: &RenderPassCommonShaderPipelineBinding,
     ) -> Result<(), BackendError> {
-        unsafe {
-            device.raw.cmd_bind_pipeline(
-                self.cb.raw,
-                pipeline.pipeline_bind_point,
-                pipeline.pipeline,
-            );
-        }
+        pipeline.pipeline.bind(&device.raw, self.cb.raw);
 
         // Bind frame constants
         if pipeline
@@ -177,10 +141,10 @@ impl<'a, 'exec_params, 'constants> RenderPassApi<'a, 'exec_params, 'constants> {
             unsafe {
                 device.raw.cmd_bind_descriptor_sets(
                     self.cb.raw,
-                    pipeline.pipeline_bind_point,
-                    pipeline.pipeline_layout,
+                    pipeline.pipeline.bind_point,
+                    pipeline.pipeline.raw_layout,
                     2,
-                    &[self.resources.execution_params.frame_descriptor_set],
+                    &[self.resources.execution_params.frame_descriptor_set.raw],
                     &[
                         self.resources
                             .execution_params
@@ -210,59 +174,43 @@ impl<'a, 'exec_params, 'constants> RenderPassApi<'a, 'exec_params, 'constants> {
                 .map(|binding| {
                     Ok(match binding {
                         RenderPassBinding::Image(image) => DescriptorSetBinding::Image(
-                            vk::DescriptorImageInfo::builder()
+                            vk::DescriptorImageInfo::default()
                                 .image_layout(image.image_layout)
                                 .image_view(
                                     self.resources.image_view(image.handle, &image.view_desc)?,
-                                )
-                                .build(),
+                                ),
                         ),
                         RenderPassBinding::ImageArray(images) => DescriptorSetBinding::ImageArray(
                             images
                                 .iter()
                                 .map(|image| {
-                                    Ok(vk::DescriptorImageInfo::builder()
+                                    Ok(vk::DescriptorImageInfo::default()
                                         .image_layout(image.image_layout)
                                         .image_view(
                                             self.resources
                                                 .image_view(image.handle, &image.view_desc)?,
-                                        )
-                                        .build())
+                                        ))
                                 })
                                 .collect::<Result<Vec<_>, BackendError>>()?,
                         ),
                         RenderPassBinding::Buffer(buffer) => DescriptorSetBinding::Buffer(
-                            vk::DescriptorBufferInfo::builder()
-                                .buffer(
-                                    self.resources
-                                        .buffer_from_raw_handle::<GpuSrv>(buffer.handle)
-                                        .raw,
-                                )
-                                .range(vk::WHOLE_SIZE)
-                                .build(),
+                            vk::DescriptorBufferInfo::default()
+                                .buffer(self.resources.buffer_from_raw_handle(buffer.handle).raw)
+                                .range(vk::WHOLE_SIZE),
                         ),
-                        RenderPassBinding::RayTracingAcceleration(acc) => {
-                            DescriptorSetBinding::RayTracingAcceleration(
-                                self.resources
-                                    .rt_acceleration_from_raw_handle::<GpuSrv>(acc.handle)
-                                    .raw,
-                            )
-                        }
                         RenderPassBinding::DynamicConstants(offset) => {
                             DescriptorSetBinding::DynamicBuffer {
-                                buffer: vk::DescriptorBufferInfo::builder()
+                                buffer: vk::DescriptorBufferInfo::default()
                                     .buffer(self.resources.dynamic_constants.buffer.raw)
-                                    .range(MAX_DYNAMIC_CONSTANTS_BYTES_PER_DISPATCH as u64)
-                                    .build(),
+                                    .range(MAX_DYNAMIC_CONSTANTS_BYTES_PER_DISPATCH as u64),
                                 offset: *offset,
                             }
                         }
                         RenderPassBinding::DynamicConstantsStorageBuffer(offset) => {
                             DescriptorSetBinding::DynamicStorageBuffer {
-                                buffer: vk::DescriptorBufferInfo::builder()
+                                buffer: vk::DescriptorBufferInfo::default()
                                     .buffer(self.resources.dynamic_constants.buffer.raw)
-                                    .range(MAX_DYNAMIC_CONSTANTS_STORAGE_BUFFER_BYTES as u64)
-                                    .build(),
+                                    .range(MAX_DYNAMIC_CONSTANTS_STORAGE_BUFFER_BYTES as u64),
                                 offset: *offset,
                             }
                         }
@@ -274,10 +222,10 @@ impl<'a, 'exec_params, 'constants> RenderPassApi<'a, 'exec_params, 'constants> {
             bind_descriptor_set(
                 self.resources.execution_params.device,
                 self.cb,
-                &pipeline,
+                pipeline,
                 set_idx,
                 &bindings,
-            );
+            )?;
         }
 
         for (set_idx, binding) in &binding.raw_bindings {
@@ -293,8 +241,8 @@ impl<'a, 'exec_params, 'constants> RenderPassApi<'a, 'exec_params, 'constants> {
                     .raw
                     .cmd_bind_descriptor_sets(
                         self.cb.raw,
-                        pipeline.pipeline_bind_point,
-                        pipeline.pipeline_layout,
+                        pipeline.pipeline.bind_point,
+                        pipeline.pipeline.raw_layout,
                         set_idx,
                         std::slice::from_ref(binding),
                         &[],
@@ -307,47 +255,29 @@ impl<'a, 'exec_params, 'constants> RenderPassApi<'a, 'exec_params, 'constants> {
 
     pub fn begin_render_pass(
         &mut self,
-        render_pass: &kajiya_backend::vulkan::shader::RenderPass,
+        render_pass: &RenderPass,
         dims: [u32; 2],
         color_attachments: &[(Ref<Image, GpuRt>, &ImageViewDesc)],
         depth_attachment: Option<(Ref<Image, GpuRt>, &ImageViewDesc)>,
     ) -> Result<(), BackendError> {
         let device = self.resources.execution_params.device;
 
-        let framebuffer = render_pass
-            .framebuffer_cache
-            .get_or_create(
-                &device.raw,
-                FramebufferCacheKey::new(
-                    dims,
-                    color_attachments.iter().map(|(a, _)| {
-                        &self.resources.image_from_raw_handle::<GpuRt>(a.handle).desc
-                    }),
-                    depth_attachment.as_ref().map(|(a, _)| {
-                        &self.resources.image_from_raw_handle::<GpuRt>(a.handle).desc
-                    }),
-                ),
-            )
-            .unwrap();
-
-        // Bind images to the imageless framebuffer
-        let image_attachments: Result<
-            ArrayVec<[vk::ImageView; MAX_COLOR_ATTACHMENTS + 1]>,
-            BackendError,
-        > = color_attachments
+        let attachments = color_attachments
             .iter()
-            .chain(depth_attachment.as_ref().into_iter())
+            .chain(depth_attachment.as_ref())
             .map(|(img, view)| self.resources.image_view(img.handle, view))
-            .collect();
-        let image_attachments = image_attachments?;
+            .collect::<Result<Vec<_>, BackendError>>()?;
 
-        let mut pass_attachment_desc =
-            vk::RenderPassAttachmentBeginInfoKHR::builder().attachments(&image_attachments);
+        let framebuffer = render_pass.framebuffer_cache.get_or_create(
+            &device.raw,
+            render_pass.raw,
+            FramebufferCacheKey { dims, attachments },
+        )?;
 
         let [width, height] = dims;
 
         //.clear_values(&clear_values)
-        let pass_begin_desc = vk::RenderPassBeginInfo::builder()
+        let pass_begin_desc = vk::RenderPassBeginInfo::default()
             .render_pass(render_pass.raw)
             .framebuffer(framebuffer)
             .render_area(vk::Rect2D {
@@ -356,8 +286,7 @@ impl<'a, 'exec_params, 'constants> RenderPassApi<'a, 'exec_params, 'constants> {
                     width: width as _,
                     height: height as _,
                 },
-            })
-            .push_next(&mut pass_attachment_desc);
+            });
 
         unsafe {
             device.raw.cmd_begin_render_pass(
@@ -422,9 +351,9 @@ impl<'api, 'a, 'exec_params, 'constants> BoundComputePipeline<'api, 'a, 'exec_pa
         unsafe {
             self.api.device().raw.cmd_dispatch(
                 self.api.cb.raw,
-                (threads[0] + group_size[0] - 1) / group_size[0],
-                (threads[1] + group_size[1] - 1) / group_size[1],
-                (threads[2] + group_size[2] - 1) / group_size[2],
+                threads[0].div_ceil(group_size[0]),
+                threads[1].div_ceil(group_size[1]),
+                threads[2].div_ceil(group_size[2]),
             );
         }
     }
@@ -448,7 +377,7 @@ impl<'api, 'a, 'exec_params, 'constants> BoundComputePipeline<'api, 'a, 'exec_pa
                 .raw
                 .cmd_push_constants(
                     command_buffer,
-                    self.pipeline.pipeline_layout,
+                    self.pipeline.pipeline.raw_layout,
                     vk::ShaderStageFlags::COMPUTE,
                     offset,
                     constants,
@@ -478,7 +407,7 @@ impl<'api, 'a, 'exec_params, 'constants> BoundRasterPipeline<'api, 'a, 'exec_par
                 .raw
                 .cmd_push_constants(
                     command_buffer,
-                    self.pipeline.pipeline_layout,
+                    self.pipeline.pipeline.raw_layout,
                     stage_flags,
                     offset,
                     constants,
@@ -497,81 +426,29 @@ pub struct RenderPassBufferBinding {
     handle: GraphRawResourceHandle,
 }
 
-pub struct RenderPassRayTracingAccelerationBinding {
-    handle: GraphRawResourceHandle,
-}
-
 pub enum RenderPassBinding {
     Image(RenderPassImageBinding),
     ImageArray(Vec<RenderPassImageBinding>),
     Buffer(RenderPassBufferBinding),
-    RayTracingAcceleration(RenderPassRayTracingAccelerationBinding),
     DynamicConstants(u32),
     DynamicConstantsStorageBuffer(u32),
 }
 
-pub struct BoundRayTracingPipeline<'api, 'a, 'exec_params, 'constants> {
-    api: &'api mut RenderPassApi<'a, 'exec_params, 'constants>,
-    pipeline: Arc<RayTracingPipeline>,
-}
-
-impl<'api, 'a, 'exec_params, 'constants>
-    BoundRayTracingPipeline<'api, 'a, 'exec_params, 'constants>
-{
-    pub fn trace_rays(&self, threads: [u32; 3]) {
-        unsafe {
-            self.api.device().ray_tracing_pipeline_ext.cmd_trace_rays(
-                self.api.cb.raw,
-                &self.pipeline.sbt.raygen_shader_binding_table,
-                &self.pipeline.sbt.miss_shader_binding_table,
-                &self.pipeline.sbt.hit_shader_binding_table,
-                &self.pipeline.sbt.callable_shader_binding_table,
-                threads[0],
-                threads[1],
-                threads[2],
-            );
-        }
-    }
-
-    pub fn trace_rays_indirect(&self, args_buffer: Ref<Buffer, GpuSrv>, args_buffer_offset: u64) {
-        let indirect_device_address = self
-            .api
-            .resources
-            .buffer(args_buffer)
-            .device_address(self.api.device())
-            + args_buffer_offset;
-
-        unsafe {
-            self.api
-                .device()
-                .ray_tracing_pipeline_ext
-                .cmd_trace_rays_indirect(
-                    self.api.cb.raw,
-                    std::slice::from_ref(&self.pipeline.sbt.raygen_shader_binding_table),
-                    std::slice::from_ref(&self.pipeline.sbt.miss_shader_binding_table),
-                    std::slice::from_ref(&self.pipeline.sbt.hit_shader_binding_table),
-                    std::slice::from_ref(&self.pipeline.sbt.callable_shader_binding_table),
-                    indirect_device_address,
-                );
-        }
-    }
-}
-
 pub trait BindRgRef {
     fn bind(&self) -> RenderPassBinding;
 }
 
 impl BindRgRef for Ref<Image, GpuSrv> {
     fn bind(&self) -> RenderPassBinding {
-        self.bind_view(ImageViewDescBuilder::default())
+        self.bind_view(ImageViewDesc::default())
     }
 }
 
 impl Ref<Image, GpuSrv> {
-    pub fn bind_view(&self, view_desc: ImageViewDescBuilder) -> RenderPassBinding {
+    pub fn bind_view(&self, view_desc: ImageViewDesc) -> RenderPassBinding {
         RenderPassBinding::Image(RenderPassImageBinding {
             handle: self.handle,
-            view_desc: view_desc.build().unwrap(),
+            view_desc,
             image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
         })
     }
@@ -596,15 +473,15 @@ impl BindRgRef for Vec<Ref<Image, GpuSrv>> {
 
 impl BindRgRef for Ref<Image, GpuUav> {
     fn bind(&self) -> RenderPassBinding {
-        self.bind_view(ImageViewDescBuilder::default())
+        self.bind_view(ImageViewDesc::default())
     }
 }
 
 impl Ref<Image, GpuUav> {
-    pub fn bind_view(&self, view_desc: ImageViewDescBuilder) -> RenderPassBinding {
+    pub fn bind_view(&self, view_desc: ImageViewDesc) -> RenderPassBinding {
         RenderPassBinding::Image(RenderPassImageBinding {
             handle: self.handle,
-            view_desc: view_desc.build().unwrap(),
+            view_desc,
             image_layout: vk::ImageLayout::GENERAL,
         })
     }
@@ -626,142 +503,86 @@ impl BindRgRef for Ref<Buffer, GpuUav> {
     }
 }
 
-impl BindRgRef for Ref<RayTracingAcceleration, GpuSrv> {
-    fn bind(&self) -> RenderPassBinding {
-        RenderPassBinding::RayTracingAcceleration(RenderPassRayTracingAccelerationBinding {
-            handle: self.handle,
-        })
-    }
-}
-
 fn bind_descriptor_set(
     device: &Device,
     cb: &CommandBuffer,
-    pipeline: &impl std::ops::Deref<Target = ShaderPipelineCommon>,
+    pipeline: &ShaderPipelineCommon,
     set_index: u32,
     bindings: &[DescriptorSetBinding],
-) {
+) -> Result<(), BackendError> {
     let shader_set_info = if let Some(info) = pipeline.set_layout_info.get(set_index as usize) {
         info
     } else {
-        println!(
+        log::warn!(
             "bind_descriptor_set: set index {} does not exist",
             set_index
         );
-        return;
+        return Ok(());
     };
 
-    let image_info = TempList::new();
-    let buffer_info = TempList::new();
-    let accel_info: TempList<UnsafeCell<vk::WriteDescriptorSetAccelerationStructureKHR>> =
-        TempList::new();
-
-    let raw_device = &device.raw;
+    let descriptor_set =
+        device.allocate_descriptor_set(&pipeline.descriptor_set_layouts[set_index as usize])?;
 
-    let descriptor_pool = {
-        let descriptor_pool_create_info = vk::DescriptorPoolCreateInfo::builder()
-            .max_sets(1)
-            .pool_sizes(&pipeline.descriptor_pool_sizes);
+    let mut dynamic_offsets: Vec<u32> = Vec::new();
+    let mut writer = DescriptorSetWriter::new();
 
-        unsafe { raw_device.create_descriptor_pool(&descriptor_pool_create_info, None) }.unwrap()
-    };
-    device.defer_release(descriptor_pool);
+    // Descriptor types come from the set layout, bindings the shader doesn't use are skipped
+    for (binding_idx, binding) in bindings.iter().enumerate() {
+        let binding_idx = binding_idx as u32;
+        let ty = match shader_set_info.get(&binding_idx) {
+            Some(ty) => *ty,
+            None => continue,
+        };
 
-    let descriptor_set = {
-        let descriptor_set_allocate_info = vk::DescriptorSetAllocateInfo::builder()
-            .descriptor_pool(descriptor_pool)
-            .set_layouts(std::slice::from_ref(
-                &pipeline.descriptor_set_layouts[set_index as usize],
-            ));
+        writer = match binding {
+            DescriptorSetBinding::Image(image) => writer.write_image_element(
+                binding_idx,
+                0,
+                ty,
+                image.image_view,
+                vk::Sampler::null(),
+                image.image_layout,
+            ),
+            DescriptorSetBinding::ImageArray(images) => {
+                assert!(!images.is_empty());
+
+                images
+                    .iter()
+                    .enumerate()
+                    .fold(writer, |writer, (element, image)| {
+                        writer.write_image_element(
+                            binding_idx,
+                            element as u32,
+                            ty,
+                            image.image_view,
+                            vk::Sampler::null(),
+                            image.image_layout,
+                        )
+                    })
+            }
+            DescriptorSetBinding::Buffer(buffer) => {
+                writer.write_buffer(binding_idx, ty, buffer.buffer, buffer.offset, buffer.range)
+            }
+            DescriptorSetBinding::DynamicBuffer { buffer, offset }
+            | DescriptorSetBinding::DynamicStorageBuffer { buffer, offset } => {
+                dynamic_offsets.push(*offset);
+                writer.write_buffer(binding_idx, ty, buffer.buffer, buffer.offset, buffer.range)
+            }
+        };
+    }
 
-        unsafe { raw_device.allocate_descriptor_sets(&descriptor_set_allocate_info) }.unwrap()[0]
-    };
+    writer.update(&device.raw, &descriptor_set);
 
     unsafe {
-        let mut dynamic_offsets: Vec<u32> = Vec::new();
-        let descriptor_writes: Vec<vk::WriteDescriptorSet> =
-            bindings
-                .iter()
-                .enumerate()
-                .filter(|(binding_idx, _)| shader_set_info.contains_key(&(*binding_idx as u32)))
-                .map(|(binding_idx, binding)| {
-                    let write = vk::WriteDescriptorSet::builder()
-                        .dst_set(descriptor_set)
-                        .dst_binding(binding_idx as _)
-                        .dst_array_element(0);
-
-                    match binding {
-                        DescriptorSetBinding::Image(image) => write
-                            .descriptor_type(match image.image_layout {
-                                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL => {
-                                    vk::DescriptorType::SAMPLED_IMAGE
-                                }
-                                vk::ImageLayout::GENERAL => vk::DescriptorType::STORAGE_IMAGE,
-                                _ => unimplemented!("{:?}", image.image_layout),
-                            })
-                            .image_info(std::slice::from_ref(image_info.add(*image)))
-                            .build(),
-                        DescriptorSetBinding::ImageArray(images) => {
-                            assert!(!images.is_empty());
-
-                            write
-                                .descriptor_type(match images[0].image_layout {
-                                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL => {
-                                        vk::DescriptorType::SAMPLED_IMAGE
-                                    }
-                                    vk::ImageLayout::GENERAL => vk::DescriptorType::STORAGE_IMAGE,
-                                    _ => unimplemented!("{:?}", images[0].image_layout),
-                                })
-                                .image_info(images.as_slice())
-                                .build()
-                        }
-                        DescriptorSetBinding::Buffer(buffer) => write
-                            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
-                            .buffer_info(std::slice::from_ref(buffer_info.add(*buffer)))
-                            .build(),
-                        DescriptorSetBinding::DynamicBuffer { buffer, offset } => {
-                            dynamic_offsets.push(*offset);
-                            write
-                                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
-                                .buffer_info(std::slice::from_ref(buffer_info.add(*buffer)))
-                                .build()
-                        }
-                        DescriptorSetBinding::DynamicStorageBuffer { buffer, offset } => {
-                            dynamic_offsets.push(*offset);
-                            write
-                                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER_DYNAMIC)
-                                .buffer_info(std::slice::from_ref(buffer_info.add(*buffer)))
-                                .build()
-                        }
-                        DescriptorSetBinding::RayTracingAcceleration(acc) => {
-                            let mut write = write
-                            .descriptor_type(vk::DescriptorType::ACCELERATION_STRUCTURE_KHR)
-                            .push_next(
-                                accel_info.add(UnsafeCell::new(
-                                    vk::WriteDescriptorSetAccelerationStructureKHR::builder()
-                                        .acceleration_structures(std::slice::from_ref(acc))
-                                        .build(),
-                                )).get().as_mut().unwrap(),
-                            )
-                            .build();
-
-                            // This is only set by the builder for images, buffers, or views; need to set explicitly after
-                            write.descriptor_count = 1;
-                            write
-                        }
-                    }
-                })
-                .collect();
-
-        device.raw.update_descriptor_sets(&descriptor_writes, &[]);
-
         device.raw.cmd_bind_descriptor_sets(
             cb.raw,
-            pipeline.pipeline_bind_point,
-            pipeline.pipeline_layout,
+            pipeline.pipeline.bind_point,
+            pipeline.pipeline.raw_layout,
             set_index,
-            &[descriptor_set],
+            &[descriptor_set.raw],
             dynamic_offsets.as_slice(),
         );
     }
+
+    Ok(())
 }
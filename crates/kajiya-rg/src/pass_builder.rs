@@ -3,17 +3,16 @@ use crate::{PassResourceAccessSyncType, RenderPassApi};
 use super::{
     graph::{
         PassResourceAccessType, PassResourceRef, RecordedPass, RenderGraph, RgComputePipeline,
-        RgComputePipelineHandle, RgRasterPipeline, RgRasterPipelineHandle, RgRtPipeline,
-        RgRtPipelineHandle, TypeEquals,
+        RgComputePipelineHandle, RgRasterPipeline, RgRasterPipelineHandle, TypeEquals,
     },
     resource::*,
 };
 
-use kajiya_backend::{
-    vk_sync::{self, AccessType},
-    vulkan::{ray_tracing::RayTracingPipelineDesc, shader::*},
-    BackendError,
+use crate::{
+    backend::BackendError,
+    pipeline_cache::{ComputePipelineDesc, PipelineShaderDesc, RasterPipelineDesc},
 };
+use fujiya_render::AccessType;
 use std::{marker::PhantomData, path::Path};
 
 pub struct PassBuilder<'rg> {
@@ -30,12 +29,10 @@ impl<'s> Drop for PassBuilder<'s> {
 }
 
 impl<'rg> PassBuilder<'rg> {
-    pub fn create<Desc: ResourceDesc>(
-        &mut self,
-        desc: Desc,
-    ) -> Handle<<Desc as ResourceDesc>::Resource>
+    pub fn create<Desc>(&mut self, desc: Desc) -> Handle<<Desc as ResourceDesc>::Resource>
     where
-        Desc: TypeEquals<Other = <<Desc as ResourceDesc>::Resource as Resource>::Desc>,
+        Desc:
+            ResourceDesc + TypeEquals<Other = <<Desc as ResourceDesc>::Resource as Resource>::Desc>,
     {
         self.rg.create(desc)
     }
@@ -43,7 +40,7 @@ impl<'rg> PassBuilder<'rg> {
     pub fn write_impl<Res: Resource, ViewType: GpuViewType>(
         &mut self,
         handle: &mut Handle<Res>,
-        access_type: vk_sync::AccessType,
+        access_type: AccessType,
         sync_type: PassResourceAccessSyncType,
     ) -> Ref<Res, ViewType> {
         let pass = self.pass.as_mut().unwrap();
@@ -75,14 +72,10 @@ impl<'rg> PassBuilder<'rg> {
     pub fn write<Res: Resource>(
         &mut self,
         handle: &mut Handle<Res>,
-        access_type: vk_sync::AccessType,
+        access_type: AccessType,
     ) -> Ref<Res, GpuUav> {
         match access_type {
-            AccessType::CommandBufferWriteNVX
-            | AccessType::VertexShaderWrite
-            | AccessType::TessellationControlShaderWrite
-            | AccessType::TessellationEvaluationShaderWrite
-            | AccessType::GeometryShaderWrite
+            AccessType::VertexShaderWrite
             | AccessType::FragmentShaderWrite
             | AccessType::ComputeShaderWrite
             | AccessType::AnyShaderWrite
@@ -101,14 +94,10 @@ impl<'rg> PassBuilder<'rg> {
     pub fn write_no_sync<Res: Resource>(
         &mut self,
         handle: &mut Handle<Res>,
-        access_type: vk_sync::AccessType,
+        access_type: AccessType,
     ) -> Ref<Res, GpuUav> {
         match access_type {
-            AccessType::CommandBufferWriteNVX
-            | AccessType::VertexShaderWrite
-            | AccessType::TessellationControlShaderWrite
-            | AccessType::TessellationEvaluationShaderWrite
-            | AccessType::GeometryShaderWrite
+            AccessType::VertexShaderWrite
             | AccessType::FragmentShaderWrite
             | AccessType::ComputeShaderWrite
             | AccessType::AnyShaderWrite
@@ -131,13 +120,10 @@ impl<'rg> PassBuilder<'rg> {
     pub fn raster<Res: Resource>(
         &mut self,
         handle: &mut Handle<Res>,
-        access_type: vk_sync::AccessType,
+        access_type: AccessType,
     ) -> Ref<Res, GpuRt> {
         match access_type {
-            AccessType::ColorAttachmentWrite
-            | AccessType::DepthStencilAttachmentWrite
-            | AccessType::DepthAttachmentWriteStencilReadOnly
-            | AccessType::StencilAttachmentWriteDepthReadOnly => {}
+            AccessType::ColorAttachmentWrite | AccessType::DepthStencilAttachmentWrite => {}
             _ => {
                 panic!("Invalid access type: {:?}", access_type);
             }
@@ -149,38 +135,25 @@ impl<'rg> PassBuilder<'rg> {
     pub fn read<Res: Resource>(
         &mut self,
         handle: &Handle<Res>,
-        access_type: vk_sync::AccessType,
+        access_type: AccessType,
     ) -> Ref<Res, GpuSrv> {
         match access_type {
-            AccessType::CommandBufferReadNVX
-            | AccessType::IndirectBuffer
+            AccessType::IndirectBuffer
             | AccessType::IndexBuffer
             | AccessType::VertexBuffer
             | AccessType::VertexShaderReadUniformBuffer
-            | AccessType::VertexShaderReadSampledImageOrUniformTexelBuffer
+            | AccessType::VertexShaderReadSampledImage
             | AccessType::VertexShaderReadOther
-            | AccessType::TessellationControlShaderReadUniformBuffer
-            | AccessType::TessellationControlShaderReadSampledImageOrUniformTexelBuffer
-            | AccessType::TessellationControlShaderReadOther
-            | AccessType::TessellationEvaluationShaderReadUniformBuffer
-            | AccessType::TessellationEvaluationShaderReadSampledImageOrUniformTexelBuffer
-            | AccessType::TessellationEvaluationShaderReadOther
-            | AccessType::GeometryShaderReadUniformBuffer
-            | AccessType::GeometryShaderReadSampledImageOrUniformTexelBuffer
-            | AccessType::GeometryShaderReadOther
             | AccessType::FragmentShaderReadUniformBuffer
-            | AccessType::FragmentShaderReadSampledImageOrUniformTexelBuffer
+            | AccessType::FragmentShaderReadSampledImage
             | AccessType::FragmentShaderReadColorInputAttachment
-            | AccessType::FragmentShaderReadDepthStencilInputAttachment
             | AccessType::FragmentShaderReadOther
             | AccessType::ColorAttachmentRead
             | AccessType::DepthStencilAttachmentRead
             | AccessType::ComputeShaderReadUniformBuffer
-            | AccessType::ComputeShaderReadSampledImageOrUniformTexelBuffer
+            | AccessType::ComputeShaderReadSampledImage
             | AccessType::ComputeShaderReadOther
-            | AccessType::AnyShaderReadUniformBuffer
-            | AccessType::AnyShaderReadUniformBufferOrVertexBuffer
-            | AccessType::AnyShaderReadSampledImageOrUniformTexelBuffer
+            | AccessType::AnyShaderReadSampledImage
             | AccessType::AnyShaderReadOther
             | AccessType::TransferRead
             | AccessType::HostRead
@@ -216,7 +189,7 @@ impl<'rg> PassBuilder<'rg> {
     pub fn raster_read<Res: Resource>(
         &mut self,
         handle: &Handle<Res>,
-        access_type: vk_sync::AccessType,
+        access_type: AccessType,
     ) -> Ref<Res, GpuRt> {
         match access_type {
             AccessType::ColorAttachmentRead | AccessType::DepthStencilAttachmentRead => {}
@@ -243,10 +216,7 @@ impl<'rg> PassBuilder<'rg> {
     }
 
     pub fn register_compute_pipeline(&mut self, path: impl AsRef<Path>) -> RgComputePipelineHandle {
-        let desc = ComputePipelineDesc::builder()
-            .compute_hlsl(path.as_ref().to_owned())
-            .build()
-            .unwrap();
+        let desc = ComputePipelineDesc::new(path.as_ref().to_owned());
         self.register_compute_pipeline_with_desc(desc)
     }
 
@@ -257,13 +227,8 @@ impl<'rg> PassBuilder<'rg> {
         let id = self.rg.compute_pipelines.len();
 
         for (set_idx, layout) in &self.rg.predefined_descriptor_set_layouts {
-            desc.descriptor_set_opts[*set_idx as usize] = Some((
-                *set_idx,
-                DescriptorSetLayoutOpts::builder()
-                    .replace(layout.bindings.clone())
-                    .build()
-                    .unwrap(),
-            ));
+            desc.descriptor_set_overrides
+                .insert(*set_idx, layout.bindings.clone());
         }
 
         self.rg.compute_pipelines.push(RgComputePipeline { desc });
@@ -274,19 +239,13 @@ impl<'rg> PassBuilder<'rg> {
     pub fn register_raster_pipeline(
         &mut self,
         shaders: &[PipelineShaderDesc],
-        desc: RasterPipelineDescBuilder,
+        mut desc: RasterPipelineDesc,
     ) -> RgRasterPipelineHandle {
         let id = self.rg.raster_pipelines.len();
-        let mut desc = desc.build().unwrap();
 
         for (set_idx, layout) in &self.rg.predefined_descriptor_set_layouts {
-            desc.descriptor_set_opts[*set_idx as usize] = Some((
-                *set_idx,
-                DescriptorSetLayoutOpts::builder()
-                    .replace(layout.bindings.clone())
-                    .build()
-                    .unwrap(),
-            ));
+            desc.descriptor_set_overrides
+                .insert(*set_idx, layout.bindings.clone());
         }
 
         self.rg.raster_pipelines.push(RgRasterPipeline {
@@ -297,31 +256,6 @@ impl<'rg> PassBuilder<'rg> {
         RgRasterPipelineHandle { id }
     }
 
-    pub fn register_ray_tracing_pipeline(
-        &mut self,
-        shaders: &[PipelineShaderDesc],
-        mut desc: RayTracingPipelineDesc,
-    ) -> RgRtPipelineHandle {
-        let id = self.rg.rt_pipelines.len();
-
-        for (set_idx, layout) in &self.rg.predefined_descriptor_set_layouts {
-            desc.descriptor_set_opts[*set_idx as usize] = Some((
-                *set_idx,
-                DescriptorSetLayoutOpts::builder()
-                    .replace(layout.bindings.clone())
-                    .build()
-                    .unwrap(),
-            ));
-        }
-
-        self.rg.rt_pipelines.push(RgRtPipeline {
-            shaders: shaders.to_vec(),
-            desc,
-        });
-
-        RgRtPipelineHandle { id }
-    }
-
     pub fn render(
         mut self,
         render: impl (FnOnce(&mut RenderPassApi) -> Result<(), BackendError>) + 'static,
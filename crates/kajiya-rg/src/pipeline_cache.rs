@@ -0,0 +1,607 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Context;
+use ash::vk;
+use fujiya_render::{
+    ComputePipelineBuilder, DescriptorSetLayout, DescriptorSetLayoutBuilder, PipelineReflection,
+    RenderPassBuilder, RenderPipeline, RenderPipelineBuilder, ShaderProgram, ShaderProgramBuilder,
+};
+use fujiya_shaders::ShaderCompiler;
+use parking_lot::Mutex;
+
+use crate::backend::{BackendError, Device};
+
+pub const MAX_COLOR_ATTACHMENTS: usize = 8;
+
+/// Bindings of descriptor sets that replace the reflected ones, e.g. dynamic frame constants
+pub type DescriptorSetOverrides = BTreeMap<u32, BTreeMap<u32, vk::DescriptorType>>;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ShaderSource {
+    /// `.comp`/`.vert`/`.frag`/`.wgsl` compiled on load, or precompiled `.spv`
+    File(PathBuf),
+    Spirv(Vec<u32>),
+}
+
+impl From<&str> for ShaderSource {
+    fn from(path: &str) -> Self {
+        Self::File(PathBuf::from(path))
+    }
+}
+
+impl From<PathBuf> for ShaderSource {
+    fn from(path: PathBuf) -> Self {
+        Self::File(path)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ShaderPipelineStage {
+    Vertex,
+    Pixel,
+    Compute,
+}
+
+impl ShaderPipelineStage {
+    pub fn flags(self) -> vk::ShaderStageFlags {
+        match self {
+            Self::Vertex => vk::ShaderStageFlags::VERTEX,
+            Self::Pixel => vk::ShaderStageFlags::FRAGMENT,
+            Self::Compute => vk::ShaderStageFlags::COMPUTE,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineShaderDesc {
+    pub stage: ShaderPipelineStage,
+    pub source: ShaderSource,
+    pub entry: String,
+}
+
+impl PipelineShaderDesc {
+    pub fn new(stage: ShaderPipelineStage, source: impl Into<ShaderSource>) -> Self {
+        Self {
+            stage,
+            source: source.into(),
+            entry: "main".to_owned(),
+        }
+    }
+
+    pub fn entry(mut self, entry: &str) -> Self {
+        self.entry = entry.to_owned();
+        self
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ComputePipelineDesc {
+    pub shader: PipelineShaderDesc,
+    pub descriptor_set_overrides: DescriptorSetOverrides,
+}
+
+impl ComputePipelineDesc {
+    pub fn new(source: impl Into<ShaderSource>) -> Self {
+        Self {
+            shader: PipelineShaderDesc::new(ShaderPipelineStage::Compute, source),
+            descriptor_set_overrides: Default::default(),
+        }
+    }
+
+    pub fn entry(mut self, entry: &str) -> Self {
+        self.shader = self.shader.entry(entry);
+        self
+    }
+}
+
+///
+/// Raster pipelines are built with fujiya's `RenderPipelineBuilder`: one color attachment,
+/// no depth test, triangle lists and a fixed viewport of `extent`.
+///
+#[derive(Clone)]
+pub struct RasterPipelineDesc {
+    pub render_pass: Arc<RenderPass>,
+    pub extent: vk::Extent2D,
+    pub descriptor_set_overrides: DescriptorSetOverrides,
+}
+
+impl RasterPipelineDesc {
+    pub fn new(render_pass: Arc<RenderPass>, extent: vk::Extent2D) -> Self {
+        Self {
+            render_pass,
+            extent,
+            descriptor_set_overrides: Default::default(),
+        }
+    }
+}
+
+impl PartialEq for RasterPipelineDesc {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.render_pass, &other.render_pass)
+            && self.extent == other.extent
+            && self.descriptor_set_overrides == other.descriptor_set_overrides
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RenderPassDesc {
+    pub color_attachments: Vec<vk::Format>,
+    pub depth_attachment: Option<vk::Format>,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct FramebufferCacheKey {
+    pub dims: [u32; 2],
+    pub attachments: Vec<vk::ImageView>,
+}
+
+/// Framebuffers by attachment views, which stay alive as long as their images
+#[derive(Default)]
+pub struct FramebufferCache {
+    entries: Mutex<HashMap<FramebufferCacheKey, vk::Framebuffer>>,
+}
+
+impl FramebufferCache {
+    pub fn get_or_create(
+        &self,
+        device: &ash::Device,
+        render_pass: vk::RenderPass,
+        key: FramebufferCacheKey,
+    ) -> Result<vk::Framebuffer, BackendError> {
+        let mut entries = self.entries.lock();
+
+        if let Some(framebuffer) = entries.get(&key) {
+            return Ok(*framebuffer);
+        }
+
+        let [width, height] = key.dims;
+        let framebuffer_info = vk::FramebufferCreateInfo::default()
+            .render_pass(render_pass)
+            .attachments(&key.attachments)
+            .width(width)
+            .height(height)
+            .layers(1);
+
+        let framebuffer = unsafe { device.create_framebuffer(&framebuffer_info, None)? };
+        entries.insert(key, framebuffer);
+
+        Ok(framebuffer)
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        for (_, framebuffer) in self.entries.lock().drain() {
+            unsafe { device.destroy_framebuffer(framebuffer, None) };
+        }
+    }
+}
+
+pub struct RenderPass {
+    pub raw: vk::RenderPass,
+    pub desc: RenderPassDesc,
+    pub framebuffer_cache: FramebufferCache,
+}
+
+impl RenderPass {
+    /// The pass and its framebuffers must not be used by any pending command buffer
+    pub fn destroy(&self, device: &Device) {
+        self.framebuffer_cache.destroy(&device.raw);
+        unsafe { device.raw.destroy_render_pass(self.raw, None) };
+    }
+}
+
+/// Attachments are loaded and stored in the layouts the graph transitions them to
+pub fn create_render_pass(device: &Device, desc: RenderPassDesc) -> Arc<RenderPass> {
+    let attachment = |format, layout| {
+        vk::AttachmentDescription::default()
+            .format(format)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::LOAD)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::LOAD)
+            .stencil_store_op(vk::AttachmentStoreOp::STORE)
+            .initial_layout(layout)
+            .final_layout(layout)
+    };
+
+    let color_refs = (0..desc.color_attachments.len() as u32)
+        .map(|attachment| {
+            vk::AttachmentReference::default()
+                .attachment(attachment)
+                .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        })
+        .collect::<Vec<_>>();
+
+    let depth_ref = vk::AttachmentReference::default()
+        .attachment(desc.color_attachments.len() as u32)
+        .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+    let mut subpass = vk::SubpassDescription::default()
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .color_attachments(&color_refs);
+
+    if desc.depth_attachment.is_some() {
+        subpass = subpass.depth_stencil_attachment(&depth_ref);
+    }
+
+    let mut builder = RenderPassBuilder::new()
+        .with_device(&device.raw)
+        .add_subpass(subpass);
+
+    for format in &desc.color_attachments {
+        builder = builder.add_attachments_desc(attachment(
+            *format,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        ));
+    }
+
+    if let Some(format) = desc.depth_attachment {
+        builder = builder.add_attachments_desc(attachment(
+            format,
+            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        ));
+    }
+
+    Arc::new(RenderPass {
+        raw: builder.build().raw,
+        desc,
+        framebuffer_cache: Default::default(),
+    })
+}
+
+pub struct ShaderPipelineCommon {
+    pub pipeline: RenderPipeline,
+    /// Descriptor type of every binding, per set
+    pub set_layout_info: Vec<HashMap<u32, vk::DescriptorType>>,
+    pub descriptor_set_layouts: Vec<DescriptorSetLayout>,
+}
+
+impl ShaderPipelineCommon {
+    /// The pipeline must not be used by any pending command buffer
+    pub fn destroy(&self, device: &Device) {
+        self.pipeline.destroy(&device.raw);
+
+        for layout in &self.descriptor_set_layouts {
+            unsafe { device.raw.destroy_descriptor_set_layout(layout.raw, None) };
+        }
+    }
+}
+
+pub struct ComputePipeline {
+    pub common: ShaderPipelineCommon,
+    pub group_size: [u32; 3],
+}
+
+impl std::ops::Deref for ComputePipeline {
+    type Target = ShaderPipelineCommon;
+
+    fn deref(&self) -> &Self::Target {
+        &self.common
+    }
+}
+
+pub struct RasterPipeline {
+    pub common: ShaderPipelineCommon,
+}
+
+impl std::ops::Deref for RasterPipeline {
+    type Target = ShaderPipelineCommon;
+
+    fn deref(&self) -> &Self::Target {
+        &self.common
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ComputePipelineHandle(usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RasterPipelineHandle(usize);
+
+struct ComputePipelineEntry {
+    desc: ComputePipelineDesc,
+    pipeline: Option<Arc<ComputePipeline>>,
+}
+
+struct RasterPipelineEntry {
+    shaders: Vec<PipelineShaderDesc>,
+    desc: RasterPipelineDesc,
+    pipeline: Option<Arc<RasterPipeline>>,
+}
+
+///
+/// Pipelines registered by render graphs, built on [`PipelineCache::prepare_frame`]
+///
+/// The same desc registered again returns the same handle, so a graph rebuilt
+/// every frame doesn't rebuild its pipelines.
+///
+pub struct PipelineCache {
+    compiler: ShaderCompiler,
+    compute_entries: Vec<ComputePipelineEntry>,
+    raster_entries: Vec<RasterPipelineEntry>,
+}
+
+impl PipelineCache {
+    pub fn new(compiler: ShaderCompiler) -> Self {
+        Self {
+            compiler,
+            compute_entries: Vec::new(),
+            raster_entries: Vec::new(),
+        }
+    }
+
+    pub fn register_compute(&mut self, desc: &ComputePipelineDesc) -> ComputePipelineHandle {
+        if let Some(index) = self
+            .compute_entries
+            .iter()
+            .position(|entry| entry.desc == *desc)
+        {
+            return ComputePipelineHandle(index);
+        }
+
+        self.compute_entries.push(ComputePipelineEntry {
+            desc: desc.clone(),
+            pipeline: None,
+        });
+        ComputePipelineHandle(self.compute_entries.len() - 1)
+    }
+
+    pub fn register_raster(
+        &mut self,
+        shaders: &[PipelineShaderDesc],
+        desc: &RasterPipelineDesc,
+    ) -> RasterPipelineHandle {
+        if let Some(index) = self
+            .raster_entries
+            .iter()
+            .position(|entry| entry.shaders == shaders && entry.desc == *desc)
+        {
+            return RasterPipelineHandle(index);
+        }
+
+        self.raster_entries.push(RasterPipelineEntry {
+            shaders: shaders.to_vec(),
+            desc: desc.clone(),
+            pipeline: None,
+        });
+        RasterPipelineHandle(self.raster_entries.len() - 1)
+    }
+
+    pub fn get_compute(&self, handle: ComputePipelineHandle) -> Arc<ComputePipeline> {
+        self.compute_entries[handle.0]
+            .pipeline
+            .clone()
+            .expect("compute pipeline is not built, was prepare_frame called?")
+    }
+
+    pub fn get_raster(&self, handle: RasterPipelineHandle) -> Arc<RasterPipeline> {
+        self.raster_entries[handle.0]
+            .pipeline
+            .clone()
+            .expect("raster pipeline is not built, was prepare_frame called?")
+    }
+
+    /// Build every pipeline registered since the last call
+    pub fn prepare_frame(&mut self, device: &Device) -> anyhow::Result<()> {
+        for entry in &mut self.compute_entries {
+            if entry.pipeline.is_none() {
+                let pipeline =
+                    build_compute(device, &self.compiler, &entry.desc).with_context(|| {
+                        format!("Building compute pipeline {:?}", entry.desc.shader.source)
+                    })?;
+                entry.pipeline = Some(Arc::new(pipeline));
+            }
+        }
+
+        for entry in &mut self.raster_entries {
+            if entry.pipeline.is_none() {
+                let pipeline = build_raster(device, &self.compiler, &entry.shaders, &entry.desc)
+                    .with_context(|| format!("Building raster pipeline {:?}", entry.shaders))?;
+                entry.pipeline = Some(Arc::new(pipeline));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// None of the pipelines may be used by any pending command buffer
+    pub fn destroy(&mut self, device: &Device) {
+        for pipeline in self
+            .compute_entries
+            .drain(..)
+            .filter_map(|entry| entry.pipeline)
+        {
+            pipeline.destroy(device);
+        }
+
+        for pipeline in self
+            .raster_entries
+            .drain(..)
+            .filter_map(|entry| entry.pipeline)
+        {
+            pipeline.destroy(device);
+        }
+    }
+}
+
+pub(crate) fn load_spirv(
+    compiler: &ShaderCompiler,
+    source: &ShaderSource,
+) -> anyhow::Result<Vec<u32>> {
+    match source {
+        ShaderSource::Spirv(spv) => Ok(spv.clone()),
+        ShaderSource::File(path) if path.extension() == Some("spv".as_ref()) => {
+            let bytes = std::fs::read(path).with_context(|| format!("Reading {:?}", path))?;
+            Ok(ash::util::read_spv(&mut std::io::Cursor::new(bytes))?)
+        }
+        ShaderSource::File(path) => Ok(compiler.compile_file(Path::new(path))?.spv),
+    }
+}
+
+fn build_program(
+    device: &Device,
+    compiler: &ShaderCompiler,
+    shaders: &[PipelineShaderDesc],
+) -> anyhow::Result<(ShaderProgram, PipelineReflection)> {
+    let mut builder = ShaderProgramBuilder::new().with_device(&device.raw);
+
+    for shader in shaders {
+        let stage = shader.stage.flags();
+        builder = builder
+            .add_stage(stage, load_spirv(compiler, &shader.source)?)
+            .with_entry_point(stage, &shader.entry);
+    }
+
+    let program = builder
+        .try_build()
+        .map_err(|err| anyhow::anyhow!("{}", err))?;
+
+    match program.reflect() {
+        Ok(reflection) => Ok((program, reflection)),
+        Err(err) => {
+            program.destroy(&device.raw);
+            Err(err.into())
+        }
+    }
+}
+
+/// Set layouts from the reflection, with the overridden sets replaced
+fn create_set_layouts(
+    device: &Device,
+    reflection: &PipelineReflection,
+    overrides: &DescriptorSetOverrides,
+) -> (
+    Vec<DescriptorSetLayout>,
+    Vec<HashMap<u32, vk::DescriptorType>>,
+) {
+    (0..reflection.set_count())
+        .map(|set| {
+            let mut bindings = reflection.set_layout_bindings(set);
+
+            if let Some(replacement) = overrides.get(&set).filter(|_| !bindings.is_empty()) {
+                bindings = replacement
+                    .iter()
+                    .map(|(binding, ty)| {
+                        vk::DescriptorSetLayoutBinding::default()
+                            .binding(*binding)
+                            .descriptor_type(*ty)
+                            .descriptor_count(1)
+                            .stage_flags(vk::ShaderStageFlags::ALL)
+                    })
+                    .collect();
+            }
+
+            let info = bindings
+                .iter()
+                .map(|binding| (binding.binding, binding.descriptor_type))
+                .collect();
+
+            let layout = DescriptorSetLayoutBuilder::new()
+                .with_device(&device.raw)
+                .with_bindings(&bindings)
+                .build();
+
+            (layout, info)
+        })
+        .unzip()
+}
+
+fn build_compute(
+    device: &Device,
+    compiler: &ShaderCompiler,
+    desc: &ComputePipelineDesc,
+) -> anyhow::Result<ComputePipeline> {
+    let (program, reflection) =
+        build_program(device, compiler, std::slice::from_ref(&desc.shader))?;
+
+    let group_size = program.stages[0].reflection.local_size.unwrap_or([1, 1, 1]);
+    let (descriptor_set_layouts, set_layout_info) =
+        create_set_layouts(device, &reflection, &desc.descriptor_set_overrides);
+
+    let mut builder = ComputePipelineBuilder::new()
+        .with_device(&device.raw)
+        .with_shader_program(&program);
+
+    for layout in &descriptor_set_layouts {
+        builder = builder.add_set_layout(layout.raw);
+    }
+
+    if let Some(range) = reflection.push_constant_range {
+        builder = builder.add_push_constant_range(range);
+    }
+
+    let pipeline = builder.build();
+    program.destroy(&device.raw);
+
+    Ok(ComputePipeline {
+        common: ShaderPipelineCommon {
+            pipeline,
+            set_layout_info,
+            descriptor_set_layouts,
+        },
+        group_size,
+    })
+}
+
+fn build_raster(
+    device: &Device,
+    compiler: &ShaderCompiler,
+    shaders: &[PipelineShaderDesc],
+    desc: &RasterPipelineDesc,
+) -> anyhow::Result<RasterPipeline> {
+    let pass_desc = &desc.render_pass.desc;
+    anyhow::ensure!(
+        pass_desc.color_attachments.len() == 1 && pass_desc.depth_attachment.is_none(),
+        "raster pipelines support render passes with one color attachment and no depth"
+    );
+
+    let (program, reflection) = build_program(device, compiler, shaders)?;
+
+    let (descriptor_set_layouts, set_layout_info) =
+        create_set_layouts(device, &reflection, &desc.descriptor_set_overrides);
+
+    let (vertex_binding, vertex_attributes) = reflection.vertex_input(0);
+    let vertex_bindings = [vertex_binding];
+
+    let vertex_input = if reflection.vertex_inputs.is_empty() {
+        vk::PipelineVertexInputStateCreateInfo::default()
+    } else {
+        vk::PipelineVertexInputStateCreateInfo::default()
+            .vertex_binding_descriptions(&vertex_bindings)
+            .vertex_attribute_descriptions(&vertex_attributes)
+    };
+
+    let mut builder = RenderPipelineBuilder::new()
+        .with_device(&device.raw)
+        .with_shader_program(&program)
+        .with_render_pass(&desc.render_pass.raw)
+        .with_format(pass_desc.color_attachments[0])
+        .with_resolution(desc.extent)
+        .with_vertex_input_info(vertex_input)
+        .with_input_assembly_info(
+            vk::PipelineInputAssemblyStateCreateInfo::default()
+                .topology(vk::PrimitiveTopology::TRIANGLE_LIST),
+        );
+
+    for layout in &descriptor_set_layouts {
+        builder = builder.add_set_layout(layout.raw);
+    }
+
+    if let Some(range) = reflection.push_constant_range {
+        builder = builder.add_push_constant_range(range);
+    }
+
+    let pipeline = builder.build();
+    program.destroy(&device.raw);
+
+    Ok(RasterPipeline {
+        common: ShaderPipelineCommon {
+            pipeline,
+            set_layout_info,
+            descriptor_set_layouts,
+        },
+    })
+}
@@ -1,23 +1,20 @@
 use crate::{
+    backend::{CommandBuffer, Device, Image, TransientResourceCache},
+    dynamic_constants::*,
+    pipeline_cache::PipelineCache,
     CompiledRenderGraph, ExecutingRenderGraph, ExportedTemporalRenderGraphState,
     PredefinedDescriptorSet, RenderGraphExecutionParams, TemporalRenderGraph,
     TemporalRenderGraphState, TemporalResourceState,
 };
-use kajiya_backend::{
-    ash::vk,
-    dynamic_constants::*,
-    pipeline_cache::*,
-    rspirv_reflect,
-    transient_resource_cache::TransientResourceCache,
-    vk_sync,
-    vulkan::{self, swapchain::Swapchain, RenderBackend},
-    Device,
+use ash::vk;
+use fujiya_render::{
+    AccessType, BarrierBatch, DescriptorPool, DescriptorPoolBuilder, DescriptorSet,
+    DescriptorSetLayout, DescriptorSetLayoutBuilder, DescriptorSetWriter, GpuProfiler,
 };
+use fujiya_shaders::ShaderCompiler;
 #[allow(unused_imports)]
 use log::{debug, error, info, trace, warn};
-use std::{collections::HashMap, sync::Arc};
-use turbosloth::*;
-use vulkan::buffer::{Buffer, BufferDesc};
+use std::{collections::BTreeMap, sync::Arc};
 
 enum TemporalRg {
     Inert(TemporalRenderGraphState),
@@ -36,45 +33,24 @@ pub struct Renderer {
     pipeline_cache: PipelineCache,
     transient_resource_cache: TransientResourceCache,
     dynamic_constants: DynamicConstants,
-    frame_descriptor_set: vk::DescriptorSet,
+    frame_descriptor_set: DescriptorSet,
+    frame_descriptor_set_layout: DescriptorSetLayout,
+    frame_descriptor_pool: DescriptorPool,
 
     compiled_rg: Option<CompiledRenderGraph>,
     temporal_rg_state: TemporalRg,
 }
 
-lazy_static::lazy_static! {
-    static ref FRAME_CONSTANTS_LAYOUT: HashMap<u32, rspirv_reflect::DescriptorInfo> = [
-    // frame_constants
-    (
-        0,
-        rspirv_reflect::DescriptorInfo {
-            ty: rspirv_reflect::DescriptorType::UNIFORM_BUFFER,
-            dimensionality: rspirv_reflect::DescriptorDimensionality::Single,
-            name: Default::default(),
-        },
-    ),
-    // instance_dynamic_parameters_dyn
-    (
-        1,
-        rspirv_reflect::DescriptorInfo {
-            ty: rspirv_reflect::DescriptorType::STORAGE_BUFFER_DYNAMIC,
-            dimensionality: rspirv_reflect::DescriptorDimensionality::Single,
-            name: Default::default(),
-        },
-    ),
-    // triangle_lights_dyn
-    (
-        2,
-        rspirv_reflect::DescriptorInfo {
-            ty: rspirv_reflect::DescriptorType::STORAGE_BUFFER_DYNAMIC,
-            dimensionality: rspirv_reflect::DescriptorDimensionality::Single,
-            name: Default::default(),
-        },
-    ),
-    ]
-    .iter()
-    .cloned()
-    .collect();
+/// Bindings of descriptor set 2, shared by every pipeline of the graph
+fn frame_constants_layout() -> BTreeMap<u32, vk::DescriptorType> {
+    BTreeMap::from([
+        // frame_constants
+        (0, vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC),
+        // instance_dynamic_parameters_dyn
+        (1, vk::DescriptorType::STORAGE_BUFFER_DYNAMIC),
+        // triangle_lights_dyn
+        (2, vk::DescriptorType::STORAGE_BUFFER_DYNAMIC),
+    ])
 }
 
 pub struct FrameConstantsLayout {
@@ -84,28 +60,19 @@ pub struct FrameConstantsLayout {
 }
 
 impl Renderer {
-    pub fn new(backend: &RenderBackend) -> anyhow::Result<Self> {
-        let dynamic_constants = DynamicConstants::new({
-            backend.device.create_buffer(
-                BufferDesc::new_cpu_to_gpu(
-                    DYNAMIC_CONSTANTS_SIZE_BYTES * DYNAMIC_CONSTANTS_BUFFER_COUNT,
-                    vk::BufferUsageFlags::UNIFORM_BUFFER
-                        | vk::BufferUsageFlags::STORAGE_BUFFER
-                        | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
-                ),
-                "dynamic constants buffer",
-                None,
-            )?
-        });
-
-        let frame_descriptor_set =
-            Self::create_frame_descriptor_set(backend, &dynamic_constants.buffer);
+    pub fn new(device: Arc<Device>, compiler: ShaderCompiler) -> anyhow::Result<Self> {
+        let dynamic_constants = DynamicConstants::new(&device)?;
+
+        let (frame_descriptor_set_layout, frame_descriptor_pool, frame_descriptor_set) =
+            Self::create_frame_descriptor_set(&device, &dynamic_constants)?;
 
         Ok(Renderer {
-            device: backend.device.clone(),
+            device,
             dynamic_constants,
             frame_descriptor_set,
-            pipeline_cache: PipelineCache::new(&LazyCache::create()),
+            frame_descriptor_set_layout,
+            frame_descriptor_pool,
+            pipeline_cache: PipelineCache::new(compiler),
             transient_resource_cache: Default::default(),
 
             compiled_rg: None,
@@ -113,10 +80,19 @@ impl Renderer {
         })
     }
 
+    ///
+    /// Record the graph prepared by [`Renderer::prepare_frame`] into `cb`
+    ///
+    /// The caller begins and submits `cb`, waiting for the swapchain image at
+    /// `COMPUTE_SHADER`, and presents. The frame recorded `FRAMES_IN_FLIGHT` calls
+    /// ago must have finished on the GPU. `swapchain_image` is left in `Present`.
+    ///
     pub fn draw_frame<PrepareFrameConstantsFn>(
         &mut self,
         prepare_frame_constants: PrepareFrameConstantsFn,
-        swapchain: &mut Swapchain,
+        cb: &CommandBuffer,
+        swapchain_image: &Arc<Image>,
+        profiler: Option<&mut GpuProfiler>,
     ) where
         PrepareFrameConstantsFn: FnOnce(&mut DynamicConstants) -> FrameConstantsLayout,
     {
@@ -127,169 +103,48 @@ impl Renderer {
         };
 
         let device = &*self.device;
-        let raw_device = &device.raw;
-
-        let current_frame = self.device.begin_frame();
-
-        // Both command buffers are accessible now, so begin recording.
-        for cb in [
-            &current_frame.main_command_buffer,
-            &current_frame.presentation_command_buffer,
-        ] {
-            unsafe {
-                raw_device
-                    .reset_command_buffer(cb.raw, vk::CommandBufferResetFlags::default())
-                    .unwrap();
-
-                raw_device
-                    .begin_command_buffer(
-                        cb.raw,
-                        &vk::CommandBufferBeginInfo::builder()
-                            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
-                    )
-                    .unwrap();
-            }
-        }
+        device.begin_frame();
 
         // Now that we can write to GPU data, prepare global frame constants.
         let frame_constants_layout = prepare_frame_constants(&mut self.dynamic_constants);
 
-        let mut executing_rg: ExecutingRenderGraph;
-
-        // Record and submit the main command buffer
-        {
-            let main_cb = &current_frame.main_command_buffer;
-
-            current_frame
-                .profiler_data
-                .begin_frame(&device.raw, main_cb.raw);
-
-            executing_rg = {
-                puffin::profile_scope!("rg begin_execute");
-
-                rg.begin_execute(
-                    RenderGraphExecutionParams {
-                        device: &self.device,
-                        pipeline_cache: &mut self.pipeline_cache,
-                        frame_descriptor_set: self.frame_descriptor_set,
-                        frame_constants_layout,
-                        profiler_data: &current_frame.profiler_data,
-                    },
-                    &mut self.transient_resource_cache,
-                    &mut self.dynamic_constants,
-                )
-            };
-
-            // Record and submit the main command buffer
-            unsafe {
-                puffin::profile_scope!("main cb");
-
-                {
-                    puffin::profile_scope!("rg::record_main_cb");
-                    executing_rg.record_main_cb(main_cb)
-                }
-
-                raw_device.end_command_buffer(main_cb.raw).unwrap();
-
-                let submit_info = [vk::SubmitInfo::builder()
-                    .command_buffers(std::slice::from_ref(&main_cb.raw))
-                    .build()];
-
-                raw_device
-                    .reset_fences(std::slice::from_ref(&main_cb.submit_done_fence))
-                    .expect("reset_fences");
-
-                puffin::profile_scope!("submit main cb");
-
-                // Try to submit the command buffer to the GPU. We might encounter a GPU crash.
-                raw_device
-                    .queue_submit(
-                        self.device.universal_queue.raw,
-                        &submit_info,
-                        main_cb.submit_done_fence,
-                    )
-                    .map_err(|err| device.report_error(err.into()))
-                    .expect("main queue_submit failed");
-            };
-        }
-
-        // Now that we've done the main submission and the GPU is busy, acquire the presentation image.
-        // This can block, so we're doing it as late as possible.
-
-        let swapchain_image = swapchain
-            .acquire_next_image()
-            .ok()
-            .expect("swapchain image");
-
-        // Execute the rest of the render graph, and submit the presentation command buffer.
-        let retired_rg = {
-            puffin::profile_scope!("presentation cb");
-
-            let presentation_cb = &current_frame.presentation_command_buffer;
-
-            // Transition the swapchain to CS write
-            vulkan::barrier::record_image_barrier(
-                device,
-                presentation_cb.raw,
-                vulkan::barrier::ImageBarrier::new(
-                    swapchain_image.image.raw,
-                    vk_sync::AccessType::Present,
-                    vk_sync::AccessType::ComputeShaderWrite,
-                    vk::ImageAspectFlags::COLOR,
-                )
-                .with_discard(true),
-            );
-
-            let retired_rg =
-                executing_rg.record_presentation_cb(presentation_cb, swapchain_image.image.clone());
-
-            // Transition the swapchain to present
-            vulkan::barrier::record_image_barrier(
+        let mut executing_rg: ExecutingRenderGraph = rg.begin_execute(
+            RenderGraphExecutionParams {
                 device,
-                presentation_cb.raw,
-                vulkan::barrier::ImageBarrier::new(
-                    swapchain_image.image.raw,
-                    vk_sync::AccessType::ComputeShaderWrite,
-                    vk_sync::AccessType::Present,
-                    vk::ImageAspectFlags::COLOR,
-                ),
-            );
-
-            current_frame
-                .profiler_data
-                .end_frame(&device.raw, presentation_cb.raw);
-
-            // Record and submit the presentation command buffer
-            unsafe {
-                raw_device.end_command_buffer(presentation_cb.raw).unwrap();
-
-                let submit_info = [vk::SubmitInfo::builder()
-                    .wait_semaphores(std::slice::from_ref(&swapchain_image.acquire_semaphore))
-                    .signal_semaphores(std::slice::from_ref(
-                        &swapchain_image.rendering_finished_semaphore,
-                    ))
-                    .wait_dst_stage_mask(&[vk::PipelineStageFlags::COMPUTE_SHADER])
-                    .command_buffers(std::slice::from_ref(&presentation_cb.raw))
-                    .build()];
-                raw_device
-                    .reset_fences(std::slice::from_ref(&presentation_cb.submit_done_fence))
-                    .expect("reset_fences");
-
-                puffin::profile_scope!("submit presentation cb");
-                raw_device
-                    .queue_submit(
-                        self.device.universal_queue.raw,
-                        &submit_info,
-                        presentation_cb.submit_done_fence,
-                    )
-                    .map_err(|err| device.report_error(err.into()))
-                    .expect("presentation queue_submit failed");
-            }
+                pipeline_cache: &mut self.pipeline_cache,
+                frame_descriptor_set: self.frame_descriptor_set,
+                frame_constants_layout,
+                profiler,
+            },
+            &mut self.transient_resource_cache,
+            &mut self.dynamic_constants,
+        );
 
-            swapchain.present_image(swapchain_image);
+        executing_rg.record_main_cb(cb);
 
-            retired_rg
-        };
+        // Transition the swapchain to CS write
+        let mut barriers = BarrierBatch::new();
+        barriers.add_image(
+            swapchain_image.raw,
+            swapchain_image.desc.subresource_range(),
+            &[AccessType::Present],
+            &[AccessType::ComputeShaderWrite],
+            true,
+        );
+        barriers.record(&device.raw, cb.raw);
+
+        let retired_rg = executing_rg.record_presentation_cb(cb, swapchain_image.clone());
+
+        // Transition the swapchain to present
+        let mut barriers = BarrierBatch::new();
+        barriers.add_image(
+            swapchain_image.raw,
+            swapchain_image.desc.subresource_range(),
+            &[AccessType::ComputeShaderWrite],
+            &[AccessType::Present],
+            false,
+        );
+        barriers.record(&device.raw, cb.raw);
 
         self.temporal_rg_state = match std::mem::take(&mut self.temporal_rg_state) {
             TemporalRg::Inert(_) => {
@@ -301,60 +156,28 @@ impl Renderer {
         retired_rg.release_resources(&mut self.transient_resource_cache);
 
         self.dynamic_constants.advance_frame();
-        self.device.finish_frame(current_frame);
     }
 
     // Descriptor set for per-frame data
     fn create_frame_descriptor_set(
-        backend: &RenderBackend,
-        dynamic_constants: &Buffer,
-    ) -> vk::DescriptorSet {
-        let device = &backend.device.raw;
-
-        let set_binding_flags = [
-            vk::DescriptorBindingFlags::PARTIALLY_BOUND,
-            vk::DescriptorBindingFlags::PARTIALLY_BOUND,
-            vk::DescriptorBindingFlags::PARTIALLY_BOUND,
-        ];
-
-        let mut binding_flags_create_info =
-            vk::DescriptorSetLayoutBindingFlagsCreateInfo::builder()
-                .binding_flags(&set_binding_flags)
-                .build();
-
-        let descriptor_set_layout = unsafe {
-            device
-                .create_descriptor_set_layout(
-                    &vk::DescriptorSetLayoutCreateInfo::builder()
-                        .bindings(&[
-                            // frame_constants
-                            vk::DescriptorSetLayoutBinding::builder()
-                                .descriptor_count(1)
-                                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
-                                .stage_flags(vk::ShaderStageFlags::ALL)
-                                .binding(0)
-                                .build(),
-                            // instance_dynamic_parameters
-                            vk::DescriptorSetLayoutBinding::builder()
-                                .descriptor_count(1)
-                                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER_DYNAMIC)
-                                .stage_flags(vk::ShaderStageFlags::ALL)
-                                .binding(1)
-                                .build(),
-                            // triangle_lights_dyn
-                            vk::DescriptorSetLayoutBinding::builder()
-                                .descriptor_count(1)
-                                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER_DYNAMIC)
-                                .stage_flags(vk::ShaderStageFlags::ALL)
-                                .binding(2)
-                                .build(),
-                        ])
-                        .push_next(&mut binding_flags_create_info)
-                        .build(),
-                    None,
-                )
-                .unwrap()
-        };
+        device: &Device,
+        dynamic_constants: &DynamicConstants,
+    ) -> anyhow::Result<(DescriptorSetLayout, DescriptorPool, DescriptorSet)> {
+        let bindings = frame_constants_layout()
+            .into_iter()
+            .map(|(binding, ty)| {
+                vk::DescriptorSetLayoutBinding::default()
+                    .binding(binding)
+                    .descriptor_type(ty)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::ALL)
+            })
+            .collect::<Vec<_>>();
+
+        let descriptor_set_layout = DescriptorSetLayoutBuilder::new()
+            .with_device(&device.raw)
+            .with_bindings(&bindings)
+            .build();
 
         let descriptor_sizes = [
             vk::DescriptorPoolSize {
@@ -367,65 +190,43 @@ impl Renderer {
             },
         ];
 
-        let descriptor_pool_info = vk::DescriptorPoolCreateInfo::builder()
-            .pool_sizes(&descriptor_sizes)
-            .max_sets(1);
-
-        let descriptor_pool = unsafe {
-            device
-                .create_descriptor_pool(&descriptor_pool_info, None)
-                .unwrap()
-        };
-
-        let set = unsafe {
-            device
-                .allocate_descriptor_sets(
-                    &vk::DescriptorSetAllocateInfo::builder()
-                        .descriptor_pool(descriptor_pool)
-                        .set_layouts(std::slice::from_ref(&descriptor_set_layout))
-                        .build(),
-                )
-                .unwrap()[0]
-        };
-
-        {
-            let uniform_buffer_info = vk::DescriptorBufferInfo::builder()
-                .buffer(dynamic_constants.raw)
-                .range(MAX_DYNAMIC_CONSTANTS_BYTES_PER_DISPATCH as u64)
-                .build();
-            let storage_buffer_info = vk::DescriptorBufferInfo::builder()
-                .buffer(dynamic_constants.raw)
-                .range(MAX_DYNAMIC_CONSTANTS_STORAGE_BUFFER_BYTES as u64)
-                .build();
-
-            let descriptor_set_writes = [
-                // `frame_constants`
-                vk::WriteDescriptorSet::builder()
-                    .dst_binding(0)
-                    .dst_set(set)
-                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
-                    .buffer_info(std::slice::from_ref(&uniform_buffer_info))
-                    .build(),
-                // `instance_dynamic_parameters_dyn`
-                vk::WriteDescriptorSet::builder()
-                    .dst_binding(1)
-                    .dst_set(set)
-                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER_DYNAMIC)
-                    .buffer_info(std::slice::from_ref(&storage_buffer_info))
-                    .build(),
-                // `triangle_lights_dyn`
-                vk::WriteDescriptorSet::builder()
-                    .dst_binding(2)
-                    .dst_set(set)
-                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER_DYNAMIC)
-                    .buffer_info(std::slice::from_ref(&storage_buffer_info))
-                    .build(),
-            ];
-
-            unsafe { device.update_descriptor_sets(&descriptor_set_writes, &[]) };
-        }
-
-        set
+        let descriptor_pool = DescriptorPoolBuilder::new()
+            .with_device(&device.raw)
+            .with_pool_sizes(&descriptor_sizes)
+            .with_max_sets(1)
+            .build();
+
+        let set = descriptor_pool.allocate(&device.raw, &[descriptor_set_layout.raw])?[0];
+        let buffer = dynamic_constants.buffer.raw;
+
+        DescriptorSetWriter::new()
+            // `frame_constants`
+            .write_buffer(
+                0,
+                vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+                buffer,
+                0,
+                MAX_DYNAMIC_CONSTANTS_BYTES_PER_DISPATCH as u64,
+            )
+            // `instance_dynamic_parameters_dyn`
+            .write_buffer(
+                1,
+                vk::DescriptorType::STORAGE_BUFFER_DYNAMIC,
+                buffer,
+                0,
+                MAX_DYNAMIC_CONSTANTS_STORAGE_BUFFER_BYTES as u64,
+            )
+            // `triangle_lights_dyn`
+            .write_buffer(
+                2,
+                vk::DescriptorType::STORAGE_BUFFER_DYNAMIC,
+                buffer,
+                0,
+                MAX_DYNAMIC_CONSTANTS_STORAGE_BUFFER_BYTES as u64,
+            )
+            .update(&device.raw, &set);
+
+        Ok((descriptor_set_layout, descriptor_pool, set))
     }
 
     pub fn prepare_frame<PrepareRenderGraphFn>(
@@ -448,7 +249,7 @@ impl Renderer {
         rg.predefined_descriptor_set_layouts.insert(
             2,
             PredefinedDescriptorSet {
-                bindings: FRAME_CONSTANTS_LAYOUT.clone(),
+                bindings: frame_constants_layout(),
             },
         );
 
@@ -468,6 +269,7 @@ impl Renderer {
                 // some temporal resources, and we can reuse them in the next attempt.
                 //
                 // Import any new resources into our temporal rg state, but reset their access modes.
+                self.compiled_rg = None;
 
                 let self_temporal_rg_state = match &mut self.temporal_rg_state {
                     TemporalRg::Inert(state) => state,
@@ -484,7 +286,7 @@ impl Renderer {
                             | TemporalResourceState::Exported { resource, .. } => {
                                 TemporalResourceState::Inert {
                                     resource,
-                                    access_type: vk_sync::AccessType::Nothing,
+                                    access_type: AccessType::Nothing,
                                 }
                             }
                         };
@@ -501,4 +303,28 @@ impl Renderer {
     pub fn device(&self) -> &Arc<Device> {
         &self.device
     }
+
+    /// None of the frames recorded by this renderer may be in use by the GPU
+    pub fn destroy(mut self) {
+        let device = self.device.clone();
+
+        // Drops the graph's references to temporal resources
+        self.compiled_rg = None;
+
+        match self.temporal_rg_state {
+            TemporalRg::Inert(state) => state.destroy(&device),
+            TemporalRg::Exported(state) => state.0.destroy(&device),
+        }
+
+        self.pipeline_cache.destroy(&device);
+        self.transient_resource_cache.destroy(&device);
+        self.dynamic_constants.destroy(&device);
+
+        self.frame_descriptor_pool.destroy(&device.raw);
+        unsafe {
+            device
+                .raw
+                .destroy_descriptor_set_layout(self.frame_descriptor_set_layout.raw, None)
+        };
+    }
 }
@@ -1,8 +1,5 @@
-use kajiya_backend::vulkan::ray_tracing::RayTracingAcceleration;
-pub use kajiya_backend::vulkan::{
-    buffer::{Buffer, BufferDesc},
-    image::*,
-};
+pub use crate::backend::{Buffer, BufferDesc, Image, ImageDesc, ImageViewDesc};
+use fujiya_render::AccessType;
 use std::marker::PhantomData;
 
 use super::resource_registry::{AnyRenderResource, AnyRenderResourceRef};
@@ -10,11 +7,15 @@ use super::resource_registry::{AnyRenderResource, AnyRenderResourceRef};
 pub trait Resource {
     type Desc: ResourceDesc;
 
+    /// Access of a plain shader read, see `SimpleRenderPass::read`
+    const SHADER_READ: AccessType;
+
     fn borrow_resource(res: &AnyRenderResource) -> &Self;
 }
 
 impl Resource for Image {
     type Desc = ImageDesc;
+    const SHADER_READ: AccessType = AccessType::AnyShaderReadSampledImage;
 
     fn borrow_resource(res: &AnyRenderResource) -> &Self {
         match res.borrow() {
@@ -26,6 +27,7 @@ impl Resource for Image {
 
 impl Resource for Buffer {
     type Desc = BufferDesc;
+    const SHADER_READ: AccessType = AccessType::AnyShaderReadOther;
 
     fn borrow_resource(res: &AnyRenderResource) -> &Self {
         match res.borrow() {
@@ -35,25 +37,10 @@ impl Resource for Buffer {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
-pub struct RayTracingAccelerationDesc;
-
-impl Resource for RayTracingAcceleration {
-    type Desc = RayTracingAccelerationDesc;
-
-    fn borrow_resource(res: &AnyRenderResource) -> &Self {
-        match res.borrow() {
-            AnyRenderResourceRef::RayTracingAcceleration(inner) => inner,
-            _ => unimplemented!(),
-        }
-    }
-}
-
 #[derive(Clone, Copy, Debug)]
 pub enum GraphResourceDesc {
     Image(ImageDesc),
     Buffer(BufferDesc),
-    RayTracingAcceleration(RayTracingAccelerationDesc),
 }
 
 impl From<ImageDesc> for GraphResourceDesc {
@@ -68,12 +55,6 @@ impl From<BufferDesc> for GraphResourceDesc {
     }
 }
 
-impl From<RayTracingAccelerationDesc> for GraphResourceDesc {
-    fn from(desc: RayTracingAccelerationDesc) -> Self {
-        Self::RayTracingAcceleration(desc)
-    }
-}
-
 pub trait ResourceDesc: Clone + std::fmt::Debug + Into<GraphResourceDesc> {
     type Resource: Resource;
 }
@@ -86,10 +67,6 @@ impl ResourceDesc for BufferDesc {
     type Resource = Buffer;
 }
 
-impl ResourceDesc for RayTracingAccelerationDesc {
-    type Resource = RayTracingAcceleration;
-}
-
 #[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
 pub(crate) struct GraphRawResourceHandle {
     pub(crate) id: u32,
@@ -120,10 +97,7 @@ pub struct ExportedHandle<ResType: Resource> {
 
 impl<ResType: Resource> Clone for ExportedHandle<ResType> {
     fn clone(&self) -> Self {
-        Self {
-            raw: self.raw,
-            marker: PhantomData,
-        }
+        *self
     }
 }
 
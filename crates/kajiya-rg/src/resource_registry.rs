@@ -1,19 +1,15 @@
 use crate::{GraphResourceInfo, RenderGraphPipelines};
 
 use super::{
-    graph::RenderGraphExecutionParams, resource::*, RgComputePipelineHandle,
-    RgRasterPipelineHandle, RgRtPipelineHandle,
+    graph::RenderGraphExecutionParams, resource::*, RgComputePipelineHandle, RgRasterPipelineHandle,
 };
-use kajiya_backend::{
-    ash::vk,
+use crate::{
+    backend::BackendError,
     dynamic_constants::DynamicConstants,
-    vk_sync,
-    vulkan::{
-        ray_tracing::{RayTracingAcceleration, RayTracingPipeline},
-        shader::{ComputePipeline, RasterPipeline},
-    },
-    BackendError,
+    pipeline_cache::{ComputePipeline, RasterPipeline},
 };
+use ash::vk;
+use fujiya_render::AccessType;
 use std::sync::Arc;
 
 pub struct PendingRenderResourceInfo {
@@ -25,7 +21,6 @@ pub enum AnyRenderResource {
     ImportedImage(Arc<Image>),
     OwnedBuffer(Buffer),
     ImportedBuffer(Arc<Buffer>),
-    ImportedRayTracingAcceleration(Arc<RayTracingAcceleration>),
 
     // Must be replaced before access. Used to late-update swapchain resources.
     Pending(PendingRenderResourceInfo),
@@ -33,7 +28,7 @@ pub enum AnyRenderResource {
 
 impl AnyRenderResource {
     #[track_caller]
-    pub fn borrow(&self) -> AnyRenderResourceRef {
+    pub fn borrow(&self) -> AnyRenderResourceRef<'_> {
         match self {
             AnyRenderResource::OwnedImage(inner) => AnyRenderResourceRef::Image(inner),
             AnyRenderResource::ImportedImage(inner) => AnyRenderResourceRef::Image(inner.as_ref()),
@@ -41,9 +36,6 @@ impl AnyRenderResource {
             AnyRenderResource::ImportedBuffer(inner) => {
                 AnyRenderResourceRef::Buffer(inner.as_ref())
             }
-            AnyRenderResource::ImportedRayTracingAcceleration(inner) => {
-                AnyRenderResourceRef::RayTracingAcceleration(inner.as_ref())
-            }
             AnyRenderResource::Pending { .. } => {
                 panic!("AnyRenderResource::borrow called while the resource was in Pending state")
             }
@@ -54,12 +46,11 @@ impl AnyRenderResource {
 pub enum AnyRenderResourceRef<'a> {
     Image(&'a Image),
     Buffer(&'a Buffer),
-    RayTracingAcceleration(&'a RayTracingAcceleration),
 }
 
 pub(crate) struct RegistryResource {
     pub resource: AnyRenderResource,
-    pub access_type: vk_sync::AccessType,
+    pub access_type: AccessType,
 }
 
 pub struct ResourceRegistry<'exec_params, 'constants> {
@@ -71,13 +62,10 @@ pub struct ResourceRegistry<'exec_params, 'constants> {
 
 impl<'exec_params, 'constants> ResourceRegistry<'exec_params, 'constants> {
     pub fn image<ViewType: GpuViewType>(&self, resource: Ref<Image, ViewType>) -> &Image {
-        self.image_from_raw_handle::<ViewType>(resource.handle)
+        self.image_from_raw_handle(resource.handle)
     }
 
-    pub(crate) fn image_from_raw_handle<ViewType: GpuViewType>(
-        &self,
-        handle: GraphRawResourceHandle,
-    ) -> &Image {
+    pub(crate) fn image_from_raw_handle(&self, handle: GraphRawResourceHandle) -> &Image {
         match &self.resources[handle.id as usize].resource.borrow() {
             AnyRenderResourceRef::Image(img) => img,
             _ => panic!(),
@@ -85,36 +73,16 @@ impl<'exec_params, 'constants> ResourceRegistry<'exec_params, 'constants> {
     }
 
     pub fn buffer<ViewType: GpuViewType>(&self, resource: Ref<Buffer, ViewType>) -> &Buffer {
-        self.buffer_from_raw_handle::<ViewType>(resource.handle)
+        self.buffer_from_raw_handle(resource.handle)
     }
 
-    pub(crate) fn buffer_from_raw_handle<ViewType: GpuViewType>(
-        &self,
-        handle: GraphRawResourceHandle,
-    ) -> &Buffer {
+    pub(crate) fn buffer_from_raw_handle(&self, handle: GraphRawResourceHandle) -> &Buffer {
         match &self.resources[handle.id as usize].resource.borrow() {
             AnyRenderResourceRef::Buffer(buffer) => buffer,
             _ => panic!(),
         }
     }
 
-    pub fn rt_acceleration<ViewType: GpuViewType>(
-        &self,
-        resource: Ref<RayTracingAcceleration, ViewType>,
-    ) -> &RayTracingAcceleration {
-        self.rt_acceleration_from_raw_handle::<ViewType>(resource.handle)
-    }
-
-    pub(crate) fn rt_acceleration_from_raw_handle<ViewType: GpuViewType>(
-        &self,
-        handle: GraphRawResourceHandle,
-    ) -> &RayTracingAcceleration {
-        match &self.resources[handle.id as usize].resource.borrow() {
-            AnyRenderResourceRef::RayTracingAcceleration(acc) => acc,
-            _ => panic!(),
-        }
-    }
-
     pub(crate) fn image_view<'a, 's>(
         &'s self,
         resource: GraphRawResourceHandle,
@@ -123,8 +91,6 @@ impl<'exec_params, 'constants> ResourceRegistry<'exec_params, 'constants> {
     where
         's: 'a,
     {
-        let view_desc = view_desc;
-
         let image = match &self.resources[resource.id as usize].resource.borrow() {
             AnyRenderResourceRef::Image(img) => *img,
             _ => panic!(),
@@ -143,9 +109,4 @@ impl<'exec_params, 'constants> ResourceRegistry<'exec_params, 'constants> {
         let handle = self.pipelines.raster[pipeline.id];
         self.execution_params.pipeline_cache.get_raster(handle)
     }
-
-    pub fn ray_tracing_pipeline(&self, pipeline: RgRtPipelineHandle) -> Arc<RayTracingPipeline> {
-        let handle = self.pipelines.rt[pipeline.id];
-        self.execution_params.pipeline_cache.get_ray_tracing(handle)
-    }
 }
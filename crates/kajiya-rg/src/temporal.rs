@@ -5,7 +5,10 @@ use std::{
 
 use anyhow::Context;
 
-use kajiya_backend::{vk_sync::AccessType, Device, Image, ImageDesc};
+use ash::vk;
+use fujiya_render::AccessType;
+
+use crate::backend::{Device, Image, ImageDesc};
 
 use super::{
     Buffer, BufferDesc, ExportableGraphResource, ExportedHandle, Handle, RenderGraph, Resource,
@@ -75,6 +78,31 @@ pub struct TemporalRenderGraphState {
 }
 
 impl TemporalRenderGraphState {
+    /// Destroy the resources no render graph holds anymore.
+    /// None of them may be in use by the GPU.
+    pub fn destroy(self, device: &Device) {
+        for state in self.resources.into_values() {
+            let resource = match state {
+                TemporalResourceState::Inert { resource, .. }
+                | TemporalResourceState::Imported { resource, .. }
+                | TemporalResourceState::Exported { resource, .. } => resource,
+            };
+
+            match resource {
+                TemporalResource::Image(image) => {
+                    if let Ok(image) = Arc::try_unwrap(image) {
+                        image.destroy(device);
+                    }
+                }
+                TemporalResource::Buffer(buffer) => {
+                    if let Ok(buffer) = Arc::try_unwrap(buffer) {
+                        buffer.destroy(device);
+                    }
+                }
+            }
+        }
+    }
+
     pub(crate) fn clone_assuming_inert(&self) -> Self {
         Self {
             resources: self
@@ -202,7 +230,7 @@ impl GetOrCreateTemporal<ImageDesc> for TemporalRenderGraph {
                 let resource = Arc::new(
                     self.device
                         // TODO: Zero-init
-                        .create_image(desc, vec![])
+                        .create_image(desc)
                         .with_context(|| format!("Creating image {:?}", desc))?,
                 );
                 let handle = self.rg.import(resource.clone(), AccessType::Nothing);
@@ -266,17 +294,36 @@ impl GetOrCreateTemporal<BufferDesc> for TemporalRenderGraph {
                 }
             }
             hash_map::Entry::Vacant(entry) => {
-                let resource = Arc::new(self.device.create_buffer(
-                    desc,
-                    &key.0,
-                    // Zero-init
-                    Some(vec![0; desc.size].as_slice()),
-                )?);
-                let handle = self.rg.import(resource.clone(), AccessType::Nothing);
+                let resource = Arc::new(
+                    self.device
+                        .create_buffer(
+                            desc.with_usage(desc.usage | vk::BufferUsageFlags::TRANSFER_DST),
+                        )
+                        .with_context(|| format!("Creating buffer {:?}", key))?,
+                );
+                let mut handle = self.rg.import(resource.clone(), AccessType::Nothing);
                 entry.insert(TemporalResourceState::Imported {
                     resource: TemporalResource::Buffer(resource),
                     handle: ExportableGraphResource::Buffer(handle.clone_unchecked()),
                 });
+
+                // Zero-init
+                let mut pass = self.rg.add_pass("clear temporal buffer");
+                let buffer_ref = pass.write(&mut handle, AccessType::TransferWrite);
+                pass.render(move |api| {
+                    let buffer = api.resources.buffer(buffer_ref);
+                    unsafe {
+                        api.device().raw.cmd_fill_buffer(
+                            api.cb.raw,
+                            buffer.raw,
+                            0,
+                            vk::WHOLE_SIZE,
+                            0,
+                        );
+                    }
+                    Ok(())
+                });
+
                 Ok(handle)
             }
         }
@@ -11,6 +11,11 @@ use winit::{dpi::PhysicalSize, raw_window_handle::HasDisplayHandle};
 use fujiya_render::*;
 use fujiya_macros::Vertex;
 
+#[allow(dead_code)]
+mod shaders {
+    include!(concat!(env!("OUT_DIR"), "/shaders.rs"));
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug)]
 struct Vertex {
@@ -129,65 +134,37 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     uniform_buffer.upload_data(&ctx.graphics_device.device.raw, &[ubo]);
 
-    let layout = DescriptorSetLayoutBuilder::new()
-        .with_device(ctx.graphics_device.raw_device())
-        .with_bindings(&[
-            vk::DescriptorSetLayoutBinding::default()
-                .binding(0)
-                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-                .descriptor_count(1)
-                .stage_flags(vk::ShaderStageFlags::VERTEX)
-            ]
-        )
-        .build();
-
-    let descriptor_pool = DescriptorPoolBuilder::new()
-        .with_device(ctx.graphics_device.raw_device())
-        .with_max_sets(1)
-        .with_pool_sizes(&[
-            vk::DescriptorPoolSize::default()
-                .ty(vk::DescriptorType::UNIFORM_BUFFER)
-                .descriptor_count(1)
-        ])
-        .build();
-
-    // Выделяем Descriptor Set
-    let layout = std::slice::from_ref(&layout.raw);
-    let allocate_info = vk::DescriptorSetAllocateInfo::default()
-        .descriptor_pool(descriptor_pool.raw)
-        .set_layouts(layout);
+    let vertex_shader = shaders::TRIANGLE_VERT.to_vec();
+    let fragment_shader = shaders::TRIANGLE_FRAG.to_vec();
 
-    let descriptor_sets = unsafe {
-        ctx.graphics_device.device.raw
-            .allocate_descriptor_sets(&allocate_info)
-            .unwrap()
-    };
+    // Раскладка дескрипторов и размеры пула берутся из SPIR-V
+    let reflection = PipelineReflection::from_spv(&[&vertex_shader, &fragment_shader])?;
+    let set_layouts = reflection.create_set_layouts(ctx.graphics_device.raw_device());
+    let raw_set_layouts = set_layouts.iter().map(|layout| layout.raw).collect::<Vec<_>>();
 
-    let descriptor_set = descriptor_sets[0];
+    // naga выкидывает неиспользуемые ресурсы, поэтому набора 0 может и не быть
+    if let Some(layout) = set_layouts.first() {
 
-    let buffer_info = [vk::DescriptorBufferInfo::default()
-        .buffer(uniform_buffer.raw)
-        .offset(0)
-        .range(buffer_size)
-    ];
+        let pool_sizes = reflection.descriptor_pool_sizes(0);
+        let mut descriptor_allocator = DescriptorAllocatorBuilder::new()
+            .with_pool_sizes(&pool_sizes)
+            .build();
 
-    let write_descriptor = vk::WriteDescriptorSet::default()
-        .dst_set(descriptor_set)
-        .dst_binding(0)  // Как в layout_binding
-        .dst_array_element(0)
-        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-        .buffer_info(&buffer_info);
+        let descriptor_set = descriptor_allocator
+            .allocate(ctx.graphics_device.raw_device(), layout)
+            .unwrap();
 
-    unsafe {
-        ctx.graphics_device.device.raw
-            .update_descriptor_sets(&[write_descriptor], &[]);
+        DescriptorSetWriter::new()
+            .write_uniform_buffer(0, &uniform_buffer)
+            .update(ctx.graphics_device.raw_device(), &descriptor_set);
     }
 
     let pipeline = StandartPipelineBuilder::new()
         .with_graphics_device(&ctx)
-        .with_fragment_shader(load_spv(r"C:\Users\Oleja\Desktop\d\fujiya\shared\shaders\spv\triangle-frag.spv"))
-        .with_vertex_shader(load_spv(r"C:\Users\Oleja\Desktop\d\fujiya\shared\shaders\spv\triangle-vert.spv"))
-        .build(layout[0]);
+        .with_fragment_shader(fragment_shader)
+        .with_vertex_shader(vertex_shader)
+        .with_set_layouts(&raw_set_layouts)
+        .build();
 
     let (gltf, index) = &load_mesh_data(&open_gltf("./shared/assets/models/box.glb").unwrap())[0];
 
@@ -220,11 +197,6 @@ fn main() -> Result<(), Box<dyn Error>> {
         });
     }
 
-    let command_pool = CommandPoolBuilder::new()
-        .device(&ctx.graphics_device.device.raw)
-        .family_index(ctx.graphics_device.universal_queue.graphics_index())
-        .build();
-
     let gpu_buffer = GPUBuffer::new(
         &ctx.graphics_device.device.raw,
         &ctx.graphics_device.phys_dev.phys_info.memory_prop,
@@ -254,18 +226,33 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     //------------------------------
     let mut graph = RenderGraph::new();
-    graph.register_command_pool("pool", command_pool);
-    graph.register_buffer("buf", gpu_buffer);
-    graph.register_buffer("index_buf", index_buffer);
-    graph.register_pipeline("pipe", pipeline);
-    graph.add_raw_pass("Simple", |res, ctx, image_index| {
+    let vertex_buffer = graph.register_buffer("buf", gpu_buffer);
+    let index_buffer = graph.register_buffer("index_buf", index_buffer);
+    let pipeline = graph.register_pipeline("pipe", pipeline);
+
+    graph.watch_pipeline(pipeline, &["./shared/shaders/triangle.vert", "./shared/shaders/triangle.frag"], move |ctx, spv| {
+        Ok(StandartPipelineBuilder::new()
+            .with_graphics_device(ctx)
+            .with_vertex_shader(spv[0].clone())
+            .with_fragment_shader(spv[1].clone())
+            .with_set_layouts(&raw_set_layouts)
+            .build())
+    });
+
+    if let Err(err) = graph.enable_shader_hot_reload("./shared/shaders") {
+        warn!("Shader hot reload is disabled: {}", err);
+    }
+    graph.add_pass("Simple")
+        .access(vertex_buffer, AccessType::VertexBuffer)
+        .access(index_buffer, AccessType::IndexBuffer)
+        .write(GraphResource::Swapchain)
+        .execute(move |res, ctx, image_index| {
 
         let device = ctx.graphics_device.raw_device();
-        let buffer = res.buffers.get("buf").ok_or("ERR")?;
-        let index_buffer = res.buffers.get("index_buf").ok_or("ERR")?;
-        let pipeline = res.pipeline.get("pipe").ok_or("ERR")?;
-        let command_pool = res.command_pool.get("pool").ok_or("ERR")?;
-        let command_buffer = command_pool.create_command_buffers(device, 1, CommandBufferLevel::PRIMARY)[0];
+        let command_buffer = res.begin_pass_commands(device)?;
+        let buffer = res.buffers.try_get(vertex_buffer)?;
+        let index_buffer = res.buffers.try_get(index_buffer)?;
+        let pipeline = res.pipeline.try_get(pipeline)?;
         let render_pass = &ctx.window_manager.render_pass;
         let current_extent = ctx.window_manager.caps.current_extent;
 
@@ -286,14 +273,8 @@ fn main() -> Result<(), Box<dyn Error>> {
             })
             .clear_values(&clear_values);
 
-        let begin_info = vk::CommandBufferBeginInfo::default()
-            .flags(vk::CommandBufferUsageFlags::SIMULTANEOUS_USE);
-
         unsafe {
 
-            device.begin_command_buffer(command_buffer, &begin_info)
-                .expect("Failed to begin command buffer");
-
             device.cmd_begin_render_pass(
                 command_buffer,
                 &render_pass_begin_info,
@@ -319,11 +300,8 @@ fn main() -> Result<(), Box<dyn Error>> {
 
             //device.cmd_draw(command_buffer, 36, 1, 0, 0);
             device.cmd_end_render_pass(command_buffer);
-            device.end_command_buffer(command_buffer)
-                .expect("Failed to end command buffer");
         }
 
-        res.command_buffers.push(command_buffer);
         Ok(())
     });
 
@@ -339,7 +317,14 @@ fn main() -> Result<(), Box<dyn Error>> {
             },
             winit::event::WindowEvent::CloseRequested => ev_window.exit(),
             winit::event::WindowEvent::RedrawRequested => {
-                graph.execute(&ctx);
+                match graph.execute(&ctx) {
+                    Ok(()) => {},
+                    Err(err) if err.is_fatal() => {
+                        error!("Render graph failed: {}", err);
+                        ev_window.exit();
+                    },
+                    Err(err) => warn!("Frame is skipped, {}", err),
+                }
             },
             winit::event::WindowEvent::Resized(_) => {
 